@@ -0,0 +1,48 @@
+// Copyright (c) 2016 DWANGO Co., Ltd. All Rights Reserved.
+// See the LICENSE file at the top-level directory of this distribution.
+
+extern crate fibers;
+extern crate futures;
+
+#[cfg(unix)]
+fn main() {
+    use fibers::time::timer;
+    use fibers::{signal, Executor, Spawn, ThreadPoolExecutor};
+    use futures::{future, Future};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    let mut executor = ThreadPoolExecutor::new().unwrap();
+    let stopping = Arc::new(AtomicBool::new(false));
+
+    // Spawns a worker which finishes its current step before stopping
+    let worker_stopping = Arc::clone(&stopping);
+    executor.spawn(future::loop_fn(0, move |step| {
+        let stopping = Arc::clone(&worker_stopping);
+        timer::timeout(Duration::from_millis(500))
+            .map_err(|e| panic!("{:?}", e))
+            .map(move |()| {
+                println!("# Step {} finished", step);
+                if stopping.load(Ordering::SeqCst) {
+                    future::Loop::Break(())
+                } else {
+                    future::Loop::Continue(step + 1)
+                }
+            })
+    }));
+
+    println!("# Press Ctrl-C to stop");
+    let monitor = executor.spawn_monitor(signal::ctrl_c());
+    executor.run_fiber(monitor).unwrap().unwrap();
+
+    println!("# SIGINT received: shutting down");
+    stopping.store(true, Ordering::SeqCst);
+    let result = executor.shutdown_timeout(Duration::from_secs(5)).wait();
+    println!("# Shutdown finished: {:?}", result);
+}
+
+#[cfg(not(unix))]
+fn main() {
+    println!("# Signals are not available on this platform");
+}
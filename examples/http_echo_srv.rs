@@ -136,5 +136,5 @@ fn get_content_length(headers: &[httparse::Header]) -> io::Result<usize> {
 }
 
 fn into_io_error<E: std::error::Error + Send + Sync + 'static>(e: E) -> io::Error {
-    io::Error::new(io::ErrorKind::Other, Box::new(e))
+    io::Error::other(Box::new(e))
 }
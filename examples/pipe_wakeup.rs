@@ -0,0 +1,46 @@
+// Copyright (c) 2016 DWANGO Co., Ltd. All Rights Reserved.
+// See the LICENSE file at the top-level directory of this distribution.
+
+extern crate fibers;
+extern crate futures;
+
+#[cfg(unix)]
+fn main() {
+    use fibers::{net, Executor, InPlaceExecutor, Spawn};
+    use futures::{future, Async, Future};
+    use std::io::{self, Read, Write};
+    use std::thread;
+    use std::time::Duration;
+
+    let mut executor = InPlaceExecutor::new().unwrap();
+    let monitor = executor.spawn_monitor(net::pipe());
+    let (mut reader, mut writer) = executor.run_fiber(monitor).unwrap().unwrap();
+
+    // Spawns a fiber which sleeps until a byte is written to the pipe
+    let monitor = executor.spawn_monitor(
+        future::poll_fn(move || {
+            let mut buf = [0; 1];
+            match reader.read(&mut buf) {
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(Async::NotReady),
+                Err(e) => Err(e),
+                Ok(_) => Ok(Async::Ready(buf[0])),
+            }
+        })
+        .map(|byte| println!("# Woken up by {}", byte)),
+    );
+
+    // Wakes the fiber up from another thread
+    let thread = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(100));
+        println!("# Writing a byte to the pipe");
+        writer.write_all(&[1]).unwrap();
+    });
+
+    executor.run_fiber(monitor).unwrap().unwrap();
+    thread.join().unwrap();
+}
+
+#[cfg(not(unix))]
+fn main() {
+    println!("# Pipes are not available on this platform");
+}
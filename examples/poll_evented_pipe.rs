@@ -0,0 +1,52 @@
+// Copyright (c) 2016 DWANGO Co., Ltd. All Rights Reserved.
+// See the LICENSE file at the top-level directory of this distribution.
+
+extern crate fibers;
+extern crate futures;
+#[cfg(unix)]
+extern crate libc;
+
+#[cfg(unix)]
+fn main() {
+    use fibers::io::{self as fibers_io, PollEvented};
+    use fibers::{Executor, InPlaceExecutor, Spawn};
+    use futures::Future;
+    use std::fs::File;
+    use std::io::Write;
+    use std::os::unix::io::FromRawFd;
+    use std::thread;
+    use std::time::Duration;
+
+    // The pipe is created by `libc`, and then driven by the poller of `executor`
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+    let reader = unsafe { File::from_raw_fd(fds[0]) };
+    let mut writer = unsafe { File::from_raw_fd(fds[1]) };
+    let mut executor = InPlaceExecutor::new().unwrap();
+
+    // Spawns a fiber which reads a line from the pipe
+    let monitor = executor.spawn_monitor(
+        PollEvented::register(reader)
+            .and_then(|reader| {
+                fibers_io::BufReader::new(reader)
+                    .read_line()
+                    .map_err(|(_, e)| e)
+            })
+            .map(|(_, line)| println!("# Received: {:?}", line)),
+    );
+
+    // Writes a line from another thread
+    let thread = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(100));
+        println!("# Writing a line to the pipe");
+        writer.write_all(b"hello\n").unwrap();
+    });
+
+    executor.run_fiber(monitor).unwrap().unwrap();
+    thread.join().unwrap();
+}
+
+#[cfg(not(unix))]
+fn main() {
+    println!("# PollEvented is not available on this platform");
+}
@@ -0,0 +1,92 @@
+// Copyright (c) 2016 DWANGO Co., Ltd. All Rights Reserved.
+// See the LICENSE file at the top-level directory of this distribution.
+
+//! Measures the time a poller takes to dispatch the readiness events of many registrants.
+//!
+//! Every registrant is a `mio::Registration`, so no system calls are involved in
+//! making them ready, and the result reflects the bookkeeping cost of the poller.
+//!
+//! Run it in release mode (e.g., `cargo run --release --example poller_dispatch`).
+extern crate clap;
+extern crate fibers;
+extern crate futures;
+extern crate mio;
+
+use clap::{App, Arg};
+use fibers::io::poll::{Interest, Poller};
+use futures::{Async, Future};
+use std::time::{Duration, Instant};
+
+fn main() {
+    let matches = App::new("poller_dispatch")
+        .arg(
+            Arg::with_name("REGISTRANTS")
+                .long("registrants")
+                .takes_value(true)
+                .default_value("100000"),
+        )
+        .arg(
+            Arg::with_name("ITERATIONS")
+                .long("iterations")
+                .takes_value(true)
+                .default_value("10"),
+        )
+        .get_matches();
+    let registrants: usize = value_of(&matches, "REGISTRANTS");
+    let iterations: usize = value_of(&matches, "ITERATIONS");
+
+    let mut poller = Poller::new().expect("Cannot create Poller");
+    poller.set_events_capacity(registrants);
+    poller.set_request_budget(registrants);
+    let mut handle = poller.handle();
+
+    let (registrations, readinesses): (Vec<_>, Vec<_>) =
+        (0..registrants).map(|_| mio::Registration::new2()).unzip();
+    let mut register = handle.register_all(registrations);
+    let handles = loop {
+        poller.poll_once(Some(Duration::from_millis(0))).unwrap();
+        if let Async::Ready(handles) = register.poll().unwrap() {
+            break handles;
+        }
+    };
+
+    let mut elapsed = Duration::from_millis(0);
+    for _ in 0..iterations {
+        let mut monitors = handles
+            .iter()
+            .map(|h| h.monitor(Interest::Read))
+            .collect::<Vec<_>>();
+        poller.poll_once(Some(Duration::from_millis(0))).unwrap();
+        for readiness in &readinesses {
+            readiness.set_readiness(mio::Ready::readable()).unwrap();
+        }
+
+        let start = Instant::now();
+        poller.poll_once(Some(Duration::from_millis(0))).unwrap();
+        elapsed += start.elapsed();
+
+        for monitor in &mut monitors {
+            assert!(
+                monitor.poll().unwrap().is_ready(),
+                "Some events are not dispatched"
+            );
+        }
+        for readiness in &readinesses {
+            readiness.set_readiness(mio::Ready::empty()).unwrap();
+        }
+    }
+    println!(
+        "registrants={} iterations={} dispatch/iteration={:?}",
+        registrants,
+        iterations,
+        elapsed / iterations as u32
+    );
+}
+
+fn value_of(matches: &clap::ArgMatches, name: &str) -> usize {
+    matches
+        .value_of(name)
+        .unwrap()
+        .parse()
+        .unwrap_or_else(|_| panic!("Invalid {}", name))
+}
@@ -0,0 +1,120 @@
+// Copyright (c) 2016 DWANGO Co., Ltd. All Rights Reserved.
+// See the LICENSE file at the top-level directory of this distribution.
+
+//! Measures the echo throughput of many TCP connections for each number of pollers.
+//!
+//! Run it in release mode (e.g., `cargo run --release --example poller_scaling`).
+extern crate clap;
+extern crate fibers;
+extern crate futures;
+
+use clap::{App, Arg};
+use fibers::io::{read_exact, write_all};
+use fibers::net::{TcpListener, TcpStream};
+use fibers::{Executor, Spawn, ThreadPoolExecutor};
+use futures::future::{self, Either, Loop};
+use futures::{Future, Stream};
+use std::net::SocketAddr;
+use std::time::Instant;
+
+const MESSAGE_SIZE: usize = 64;
+
+fn main() {
+    let matches = App::new("poller_scaling")
+        .arg(
+            Arg::with_name("THREADS")
+                .long("threads")
+                .takes_value(true)
+                .default_value("4"),
+        )
+        .arg(
+            Arg::with_name("CONNECTIONS")
+                .long("connections")
+                .takes_value(true)
+                .default_value("256"),
+        )
+        .arg(
+            Arg::with_name("MESSAGES")
+                .long("messages")
+                .takes_value(true)
+                .default_value("100"),
+        )
+        .get_matches();
+    let threads: usize = value_of(&matches, "THREADS");
+    let connections: usize = value_of(&matches, "CONNECTIONS");
+    let messages: usize = value_of(&matches, "MESSAGES");
+
+    let mut pollers = 1;
+    while pollers <= threads {
+        let start = Instant::now();
+        run(threads, pollers, connections, messages);
+        let elapsed = start.elapsed();
+        let round_trips = (connections * messages) as f64;
+        println!(
+            "pollers={:<3} elapsed={:?} round_trips/sec={:.0}",
+            pollers,
+            elapsed,
+            round_trips / elapsed.as_secs_f64()
+        );
+        pollers *= 2;
+    }
+}
+
+fn value_of(matches: &clap::ArgMatches, name: &str) -> usize {
+    matches
+        .value_of(name)
+        .unwrap()
+        .parse()
+        .unwrap_or_else(|_| panic!("Invalid {}", name))
+}
+
+fn run(threads: usize, pollers: usize, connections: usize, messages: usize) {
+    let mut executor = ThreadPoolExecutor::with_thread_and_poller_count(threads, pollers)
+        .expect("Cannot create Executor");
+    let handle = executor.handle();
+
+    let monitor = executor.spawn_monitor(TcpListener::bind("127.0.0.1:0".parse().unwrap()));
+    let listener = executor.run_fiber(monitor).unwrap().unwrap();
+    let addr = listener.local_addr().unwrap();
+    executor.spawn(
+        listener
+            .incoming()
+            .for_each(move |(client, _)| {
+                handle.spawn(client.and_then(echo).then(|_| Ok(())));
+                Ok(())
+            })
+            // NOTE: The listener fails when the executor is dropped at the end of this function
+            .map_err(|_| ()),
+    );
+
+    let clients = (0..connections)
+        .map(|_| executor.spawn_monitor(client(addr, messages)))
+        .collect::<Vec<_>>();
+    for client in clients {
+        executor.run_fiber(client).unwrap().unwrap();
+    }
+}
+
+fn echo(stream: TcpStream) -> impl Future<Item = (), Error = std::io::Error> {
+    future::loop_fn(stream, |stream| {
+        read_exact(stream, [0; MESSAGE_SIZE])
+            .and_then(|(stream, buf)| write_all(stream, buf))
+            .map(|(stream, _)| Loop::<(), _>::Continue(stream))
+            .map_err(|(_, _, e)| e)
+    })
+}
+
+fn client(addr: SocketAddr, messages: usize) -> impl Future<Item = (), Error = std::io::Error> {
+    TcpStream::connect(addr).and_then(move |stream| {
+        future::loop_fn((stream, 0), move |(stream, i)| {
+            if i == messages {
+                return Either::A(future::ok(Loop::Break(())));
+            }
+            let round_trip = write_all(stream, [i as u8; MESSAGE_SIZE])
+                .and_then(|(stream, _)| read_exact(stream, [0; MESSAGE_SIZE]))
+                .map(move |(stream, _)| Loop::Continue((stream, i + 1)))
+                .map_err(|(_, _, e)| e);
+            Either::B(round_trip)
+        })
+    })
+}
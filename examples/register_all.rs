@@ -0,0 +1,88 @@
+// Copyright (c) 2016 DWANGO Co., Ltd. All Rights Reserved.
+// See the LICENSE file at the top-level directory of this distribution.
+
+//! Compares registering evented objects to a poller one by one with `PollerHandle::register`
+//! against registering them at once with `PollerHandle::register_all`.
+//!
+//! Run it in release mode (e.g., `cargo run --release --example register_all`).
+extern crate clap;
+extern crate fibers;
+extern crate futures;
+extern crate mio;
+
+use clap::{App, Arg};
+use fibers::io::poll::Poller;
+use futures::{Async, Future};
+use std::time::{Duration, Instant};
+
+fn main() {
+    let matches = App::new("register_all")
+        .arg(
+            Arg::with_name("REGISTRANTS")
+                .long("registrants")
+                .takes_value(true)
+                .default_value("1000"),
+        )
+        .arg(
+            Arg::with_name("ITERATIONS")
+                .long("iterations")
+                .takes_value(true)
+                .default_value("100"),
+        )
+        .get_matches();
+    let registrants: usize = value_of(&matches, "REGISTRANTS");
+    let iterations: usize = value_of(&matches, "ITERATIONS");
+
+    let mut poller = Poller::new().expect("Cannot create Poller");
+    let mut sequential = Duration::from_millis(0);
+    let mut batch = Duration::from_millis(0);
+    for _ in 0..iterations {
+        let start = Instant::now();
+        let mut handles = Vec::with_capacity(registrants);
+        for registration in registrations(registrants) {
+            let register = poller.handle().register(registration);
+            handles.push(wait(&mut poller, register));
+        }
+        sequential += start.elapsed();
+        drop(handles);
+        poller.poll_once(Some(Duration::from_millis(0))).unwrap();
+
+        let start = Instant::now();
+        let register = poller.handle().register_all(registrations(registrants));
+        let handles = wait(&mut poller, register);
+        batch += start.elapsed();
+        assert_eq!(handles.len(), registrants);
+        drop(handles);
+        poller.poll_once(Some(Duration::from_millis(0))).unwrap();
+    }
+    println!(
+        "registrants={} sequential={:?} batch={:?}",
+        registrants,
+        sequential / iterations as u32,
+        batch / iterations as u32
+    );
+}
+
+fn value_of(matches: &clap::ArgMatches, name: &str) -> usize {
+    matches
+        .value_of(name)
+        .unwrap()
+        .parse()
+        .unwrap_or_else(|_| panic!("Invalid {}", name))
+}
+
+fn registrations(count: usize) -> Vec<mio::Registration> {
+    (0..count).map(|_| mio::Registration::new2().0).collect()
+}
+
+fn wait<F: Future>(poller: &mut Poller, mut future: F) -> F::Item
+where
+    F::Error: std::fmt::Debug,
+{
+    loop {
+        if let Async::Ready(item) = future.poll().unwrap() {
+            return item;
+        }
+        poller.poll_once(Some(Duration::from_millis(0))).unwrap();
+    }
+}
@@ -0,0 +1,21 @@
+// Copyright (c) 2016 DWANGO Co., Ltd. All Rights Reserved.
+// See the LICENSE file at the top-level directory of this distribution.
+
+extern crate fibers;
+extern crate futures;
+
+use fibers::time::timer;
+use fibers::{Executor, InPlaceExecutor, Spawn};
+use futures::Future;
+use std::time::{Duration, Instant};
+
+fn main() {
+    let mut executor = InPlaceExecutor::new().unwrap();
+    let start = Instant::now();
+    println!("# Sleeping for 100ms");
+    let future = timer::sleep(Duration::from_millis(100)).map(move |()| {
+        println!("# Woke up after {:?}", start.elapsed());
+    });
+    let monitor = executor.spawn_monitor(future);
+    executor.run_fiber(monitor).unwrap().unwrap();
+}
@@ -0,0 +1,51 @@
+// Copyright (c) 2016 DWANGO Co., Ltd. All Rights Reserved.
+// See the LICENSE file at the top-level directory of this distribution.
+
+extern crate fibers;
+extern crate futures;
+
+use fibers::fiber::{ContextId, Instrument};
+use fibers::{Executor, Spawn, ThreadPoolExecutor};
+use futures::future;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// A collector which logs the polls taking longer than the threshold.
+#[derive(Debug)]
+struct SlowPollLogger {
+    threshold: Duration,
+}
+impl Instrument for SlowPollLogger {
+    fn on_poll_end(&self, (scheduler_id, fiber_id): ContextId, elapsed: Duration) {
+        if elapsed > self.threshold {
+            println!(
+                "# Slow poll: scheduler={}, fiber={}, elapsed={:?}",
+                scheduler_id, fiber_id, elapsed
+            );
+        }
+    }
+}
+
+fn main() {
+    let logger = SlowPollLogger {
+        threshold: Duration::from_millis(10),
+    };
+    let mut executor = ThreadPoolExecutor::builder()
+        .thread_count(2)
+        .instrument(Arc::new(logger))
+        .build()
+        .unwrap();
+
+    let fibers = (0..4).map(|i| {
+        executor.spawn_monitor(future::lazy(move || {
+            // The odd fibers block the scheduler thread
+            if i % 2 == 1 {
+                thread::sleep(Duration::from_millis(50));
+            }
+            Ok::<_, ()>(())
+        }))
+    });
+    let monitor = executor.spawn_monitor(future::join_all(fibers.collect::<Vec<_>>()));
+    executor.run_fiber(monitor).unwrap().unwrap();
+}
@@ -0,0 +1,73 @@
+// Copyright (c) 2016 DWANGO Co., Ltd. All Rights Reserved.
+// See the LICENSE file at the top-level directory of this distribution.
+
+extern crate fibers;
+extern crate futures;
+extern crate handy_async;
+
+#[cfg(unix)]
+fn main() {
+    use fibers::net::unix::{UnixListener, UnixStream};
+    use fibers::{Executor, Spawn, ThreadPoolExecutor};
+    use futures::{Future, Stream};
+    use handy_async::io::{AsyncRead, AsyncWrite};
+    use std::fs;
+
+    let path = std::env::temp_dir().join("fibers_unix_echo.sock");
+    let _ = fs::remove_file(&path);
+
+    let mut executor = ThreadPoolExecutor::new().unwrap();
+    let handle = executor.handle();
+
+    // Spawns an echo server
+    let monitor = executor.spawn_monitor(UnixListener::bind(&path));
+    let listener = executor.run_fiber(monitor).unwrap().unwrap();
+    println!("# Start listening: {:?}", listener.local_addr().unwrap());
+    executor.spawn(
+        listener
+            .incoming()
+            .for_each(move |(client, addr)| {
+                println!("# Accepted: {:?}", addr);
+                handle.spawn(
+                    client
+                        .and_then(|client| {
+                            client
+                                .async_read(vec![0; 1024])
+                                .map_err(|e| e.into_error())
+                                .and_then(|(client, mut buf, size)| {
+                                    buf.truncate(size);
+                                    client.async_write_all(buf).map_err(|e| e.into_error())
+                                })
+                        })
+                        .then(|r| {
+                            println!("# Client finished: {:?}", r.map(|_| ()));
+                            Ok(())
+                        }),
+                );
+                Ok(())
+            })
+            .map_err(|e| panic!("{:?}", e)),
+    );
+
+    // Spawns a client
+    let monitor = executor.spawn_monitor(UnixStream::connect(&path).and_then(|stream| {
+        stream
+            .async_write_all(b"Hello World!")
+            .map_err(|e| e.into_error())
+            .and_then(|(stream, _)| stream.async_read(vec![0; 32]).map_err(|e| e.into_error()))
+            .map(|(_, mut buf, size)| {
+                buf.truncate(size);
+                buf
+            })
+    }));
+    let buf = executor.run_fiber(monitor).unwrap().unwrap();
+    assert_eq!(buf, b"Hello World!");
+    println!("# Succeeded");
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[cfg(not(unix))]
+fn main() {
+    println!("# Unix domain sockets are not available on this platform");
+}
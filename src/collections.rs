@@ -1,5 +1,9 @@
 //! Crate internal data structures.
+use splay_tree::map::Iter;
 use splay_tree::SplayMap;
+use std::collections::HashMap;
+use std::mem;
+use std::time::{Duration, Instant};
 
 /// A heap (a.k.a, priority queue) which has map like properties.
 ///
@@ -42,13 +46,25 @@ where
     where
         F: FnOnce(&K, &V) -> bool,
     {
-        if self.inner.smallest().map_or(false, |(k, v)| f(k, v)) {
+        if self.inner.smallest().is_some_and(|(k, v)| f(k, v)) {
             self.inner.take_smallest()
         } else {
             None
         }
     }
 
+    /// Makes an iterator which pops the entries in ascending order of their keys
+    /// while the predicate `f` is satisfied.
+    ///
+    /// The entries which are not yielded (e.g., because the iterator is dropped early)
+    /// remain in the heap.
+    pub fn drain_while<F>(&mut self, f: F) -> DrainWhile<'_, K, V, F>
+    where
+        F: FnMut(&K, &V) -> bool,
+    {
+        DrainWhile { heap: self, f }
+    }
+
     /// Peeks the entry which has the smallest key.
     ///
     /// If the heap is empty, this will return `None`.
@@ -56,11 +72,21 @@ where
         self.inner.smallest()
     }
 
+    /// Shrinks the memory region of the heap as much as possible.
+    ///
+    /// The nodes of the underlying tree are never released by removals,
+    /// so the tree is rebuilt from the remaining entries.
+    pub fn shrink_to_fit(&mut self) {
+        let inner = mem::replace(&mut self.inner, SplayMap::new());
+        self.inner = inner.into_iter().collect();
+    }
+
     /// Removes the entry which has `key` from the heap.
     ///
-    /// If such entry exists, this will return `true`, otherwise `false`.
-    pub fn remove(&mut self, key: &K) -> bool {
-        self.inner.remove(key).is_some()
+    /// If such entry exists, this will return the value of it, otherwise `None`
+    /// (e.g., the entry has already been removed or popped).
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        self.inner.remove(key)
     }
 }
 impl<K, V> HeapMap<K, V> {
@@ -69,10 +95,311 @@ impl<K, V> HeapMap<K, V> {
     pub fn len(&self) -> usize {
         self.inner.len()
     }
+
+    /// Returns `true` if the heap has no entries, otherwise `false`.
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// Gets an iterator over the entries of the heap.
+    ///
+    /// The order of the entries is unspecified.
+    /// Removed entries are never yielded.
+    #[allow(dead_code)]
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        self.inner.iter()
+    }
+}
+
+/// An iterator which pops the entries of a heap while a predicate is satisfied.
+///
+/// This is created by calling `HeapMap::drain_while` method.
+#[derive(Debug)]
+pub struct DrainWhile<'a, K: 'a, V: 'a, F> {
+    heap: &'a mut HeapMap<K, V>,
+    f: F,
+}
+impl<'a, K: 'a, V: 'a, F> Iterator for DrainWhile<'a, K, V, F>
+where
+    K: Ord,
+    F: FnMut(&K, &V) -> bool,
+{
+    type Item = (K, V);
+    fn next(&mut self) -> Option<Self::Item> {
+        let f = &mut self.f;
+        self.heap.pop_if(|k, v| f(k, v))
+    }
+}
+
+/// A vector based map of which keys are allocated by itself.
+///
+/// The keys of the removed entries are recycled (the most recently freed one first),
+/// so the keys are kept as small as possible.
+/// Inserting, looking up and removing an entry take constant time.
+#[derive(Debug)]
+pub struct Slab<V> {
+    entries: Vec<Option<V>>,
+    free_keys: Vec<usize>,
+    len: usize,
+}
+impl<V> Slab<V> {
+    /// Makes a new empty slab.
+    pub fn new() -> Self {
+        Slab {
+            entries: Vec::new(),
+            free_keys: Vec::new(),
+            len: 0,
+        }
+    }
+
+    /// Returns the key which will be assigned to the entry inserted next.
+    pub fn next_key(&self) -> usize {
+        self.free_keys.last().cloned().unwrap_or(self.entries.len())
+    }
+
+    /// Inserts `value` and returns the key assigned to it (i.e., `self.next_key()`).
+    pub fn insert(&mut self, value: V) -> usize {
+        self.len += 1;
+        if let Some(key) = self.free_keys.pop() {
+            self.entries[key] = Some(value);
+            key
+        } else {
+            self.entries.push(Some(value));
+            self.entries.len() - 1
+        }
+    }
+
+    /// Returns the mutable reference to the value of the entry identified by `key`.
+    pub fn get_mut(&mut self, key: usize) -> Option<&mut V> {
+        self.entries.get_mut(key).and_then(|e| e.as_mut())
+    }
+
+    /// Removes the entry identified by `key`.
+    ///
+    /// If such entry exists, this will return the value of it, otherwise `None`.
+    pub fn remove(&mut self, key: usize) -> Option<V> {
+        let value = self.entries.get_mut(key)?.take()?;
+        self.free_keys.push(key);
+        self.len -= 1;
+        Some(value)
+    }
+
+    /// Removes all the entries and returns the values of them.
+    pub fn drain(&mut self) -> Vec<V> {
+        self.free_keys.clear();
+        self.len = 0;
+        self.entries.drain(..).flatten().collect()
+    }
+
+    /// Returns the entry count of the slab.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns an iterator visiting all the entries of the slab in the order of the keys.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &V)> {
+        self.entries
+            .iter()
+            .enumerate()
+            .filter_map(|(k, e)| e.as_ref().map(|v| (k, v)))
+    }
+
+    /// Returns the number of the allocated keys (i.e., the largest key ever used plus one).
+    #[allow(dead_code)]
+    pub fn key_count(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Shrinks the memory region of the slab as much as possible.
+    ///
+    /// The trailing vacant keys are released.
+    pub fn shrink_to_fit(&mut self) {
+        while let Some(&None) = self.entries.last() {
+            self.entries.pop();
+        }
+        let key_count = self.entries.len();
+        self.free_keys.retain(|&k| k < key_count);
+        self.entries.shrink_to_fit();
+        self.free_keys.shrink_to_fit();
+    }
+}
+
+/// A hashed timing wheel.
+///
+/// The deadlines of the entries are rounded up to the tick granularity of the wheel,
+/// and each entry is stored in the slot of the tick (modulo the number of slots).
+/// Thus inserting and removing an entry take constant time,
+/// and expiring entries takes time proportional to the number of the elapsed ticks
+/// (plus the entries scheduled for the later rounds of the visited slots).
+#[derive(Debug)]
+pub struct TimerWheel<V> {
+    origin: Instant,
+    tick_nanos: u64,
+    current_tick: u64,
+    slots: Vec<HashMap<usize, (u64, Instant, V)>>,
+    len: usize,
+}
+impl<V> TimerWheel<V> {
+    /// Makes a new wheel which has `slot_count` slots of `tick` granularity.
+    pub fn new(tick: Duration, slot_count: usize) -> Self {
+        assert!(tick.as_nanos() > 0);
+        assert_ne!(slot_count, 0);
+        TimerWheel {
+            origin: Instant::now(),
+            tick_nanos: tick.as_nanos() as u64,
+            current_tick: 0,
+            slots: (0..slot_count).map(|_| HashMap::new()).collect(),
+            len: 0,
+        }
+    }
+
+    /// Returns the tick granularity of the wheel.
+    pub fn tick(&self) -> Duration {
+        Duration::from_nanos(self.tick_nanos)
+    }
+
+    /// Inserts the entry identified by `(deadline, id)`.
+    ///
+    /// If the tick of `deadline` has already passed, the entry is not inserted
+    /// and `value` is returned as `Err`.
+    pub fn insert(&mut self, id: usize, deadline: Instant, value: V) -> Result<(), V> {
+        let tick = self.tick_of(deadline);
+        if tick <= self.current_tick {
+            return Err(value);
+        }
+        let slot = self.slot_of(tick);
+        if self.slots[slot].insert(id, (tick, deadline, value)).is_none() {
+            self.len += 1;
+        }
+        Ok(())
+    }
+
+    /// Removes the entry identified by `(deadline, id)`.
+    ///
+    /// If such entry exists, this will return the value of it, otherwise `None`.
+    pub fn remove(&mut self, id: usize, deadline: Instant) -> Option<V> {
+        let slot = self.slot_of(self.tick_of(deadline));
+        let (_, _, value) = self.slots[slot].remove(&id)?;
+        self.len -= 1;
+        Some(value)
+    }
+
+    /// Advances the wheel to `now` and calls `f` with each of the expired entries.
+    ///
+    /// This will return the number of the expired entries.
+    pub fn expire<F>(&mut self, now: Instant, mut f: F) -> usize
+    where
+        F: FnMut(V),
+    {
+        let target = self.floor_tick_of(now);
+        if target <= self.current_tick {
+            return 0;
+        }
+
+        let mut count = 0;
+        if self.len > 0 {
+            let steps = target - self.current_tick;
+            let slots = if steps >= self.slots.len() as u64 {
+                (0..self.slots.len()).collect::<Vec<_>>()
+            } else {
+                (self.current_tick + 1..=target)
+                    .map(|tick| self.slot_of(tick))
+                    .collect()
+            };
+            for slot in slots {
+                let slot = &mut self.slots[slot];
+                let expired = slot
+                    .iter()
+                    .filter(|&(_, &(tick, _, _))| tick <= target)
+                    .map(|(id, _)| *id)
+                    .collect::<Vec<_>>();
+                for id in expired {
+                    let (_, _, value) = slot.remove(&id).expect("Never fails");
+                    count += 1;
+                    f(value);
+                }
+            }
+            self.len -= count;
+        }
+        self.current_tick = target;
+        count
+    }
+
+    /// Returns the time instant at which the wheel may have expired entries next.
+    ///
+    /// The returned instant never comes after the earliest deadline of the entries,
+    /// but it may come before that (e.g., when the nearest non-empty slot only
+    /// holds the entries for the later rounds).
+    ///
+    /// If the wheel is empty, this will return `None`.
+    pub fn next_expiry(&self) -> Option<Instant> {
+        if self.len == 0 {
+            return None;
+        }
+        let delta = (1..=self.slots.len() as u64)
+            .find(|delta| !self.slots[self.slot_of(self.current_tick + delta)].is_empty())
+            .expect("Never fails");
+        Some(self.instant_of(self.current_tick + delta))
+    }
+
+    /// Returns the entry count of the wheel.
+    #[allow(dead_code)]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Shrinks the memory region of the wheel as much as possible.
+    pub fn shrink_to_fit(&mut self) {
+        for slot in &mut self.slots {
+            slot.shrink_to_fit();
+        }
+    }
+
+    /// Removes all the entries of the wheel and returns the values of them.
+    pub fn drain(&mut self) -> Vec<V> {
+        self.len = 0;
+        self.slots
+            .iter_mut()
+            .flat_map(|slot| slot.drain().map(|(_, (_, _, value))| value))
+            .collect()
+    }
+
+    /// Moves all the entries of the wheel into `other`.
+    ///
+    /// The entries which have already expired in `other` are returned.
+    pub fn move_into(mut self, other: &mut Self) -> Vec<V> {
+        let mut expired = Vec::new();
+        for slot in self.slots.drain(..) {
+            for (id, (_, deadline, value)) in slot {
+                if let Err(value) = other.insert(id, deadline, value) {
+                    expired.push(value);
+                }
+            }
+        }
+        expired
+    }
+
+    fn tick_of(&self, deadline: Instant) -> u64 {
+        let nanos = deadline.saturating_duration_since(self.origin).as_nanos() as u64;
+        nanos.div_ceil(self.tick_nanos)
+    }
+    fn floor_tick_of(&self, now: Instant) -> u64 {
+        let nanos = now.saturating_duration_since(self.origin).as_nanos() as u64;
+        nanos / self.tick_nanos
+    }
+    fn instant_of(&self, tick: u64) -> Instant {
+        self.origin + Duration::from_nanos(tick * self.tick_nanos)
+    }
+    fn slot_of(&self, tick: u64) -> usize {
+        (tick % self.slots.len() as u64) as usize
+    }
 }
 
 #[cfg(test)]
 mod test {
+    use std::time::{Duration, Instant};
+
     use super::*;
 
     #[test]
@@ -94,10 +421,10 @@ mod test {
         // TEST: remove
         assert_eq!(heap.len(), 3);
 
-        assert!(heap.remove(&1));
+        assert_eq!(heap.remove(&1), Some("value-a"));
         assert_eq!(heap.len(), 2);
 
-        assert!(!heap.remove(&1));
+        assert_eq!(heap.remove(&1), None);
         assert_eq!(heap.len(), 2);
 
         // TEST: pop_if
@@ -106,4 +433,213 @@ mod test {
         assert_eq!(heap.pop_if(|key, _| *key == 2), Some((2, "value-c")));
         assert_eq!(heap.pop_if(|_, _| true), None);
     }
+
+    #[test]
+    fn iter_skips_removed_entries() {
+        let mut heap = HeapMap::new();
+        assert!(heap.is_empty());
+        assert_eq!(heap.iter().count(), 0);
+
+        for key in 0..10 {
+            assert!(heap.push_if_absent(key, key * 10));
+        }
+        assert_eq!(heap.remove(&3), Some(30));
+        assert_eq!(heap.remove(&7), Some(70));
+        assert_eq!(heap.pop_if(|key, _| *key == 0), Some((0, 0)));
+        assert!(!heap.is_empty());
+
+        let mut entries = heap.iter().map(|(k, v)| (*k, *v)).collect::<Vec<_>>();
+        entries.sort();
+        assert_eq!(
+            entries,
+            [(1, 10), (2, 20), (4, 40), (5, 50), (6, 60), (8, 80), (9, 90)]
+        );
+        assert_eq!(heap.peek(), Some((&1, &10)));
+    }
+
+    #[test]
+    fn stale_keys_do_not_remove_other_entries() {
+        let deadline = Instant::now() + Duration::from_secs(1);
+        let mut heap = HeapMap::new();
+        assert!(heap.push_if_absent((deadline, 0), "cancelled"));
+        assert_eq!(heap.remove(&(deadline, 0)), Some("cancelled"));
+
+        // A timer reissued with the same deadline has a distinct id,
+        // so removing the stale key (twice or more) never affects it.
+        assert!(heap.push_if_absent((deadline, 1), "reissued"));
+        assert_eq!(heap.remove(&(deadline, 0)), None);
+        assert_eq!(heap.remove(&(deadline, 0)), None);
+        assert_eq!(heap.pop_if(|_, _| true), Some(((deadline, 1), "reissued")));
+
+        // Popped entries cannot be removed either
+        assert_eq!(heap.remove(&(deadline, 1)), None);
+    }
+
+    #[test]
+    fn shrink_to_fit_keeps_entries() {
+        let mut heap = HeapMap::new();
+        for key in 0..10_000 {
+            assert!(heap.push_if_absent(key, key));
+        }
+        for key in 0..10_000 {
+            if key % 1000 != 0 {
+                assert_eq!(heap.remove(&key), Some(key));
+            }
+        }
+
+        heap.shrink_to_fit();
+        assert_eq!(heap.len(), 10);
+        assert_eq!(heap.remove(&5000), Some(5000));
+        let popped = heap.drain_while(|_, _| true).map(|(k, _)| k).collect::<Vec<_>>();
+        assert_eq!(popped, [0, 1000, 2000, 3000, 4000, 6000, 7000, 8000, 9000]);
+    }
+
+    #[test]
+    fn drain_while_pops_only_expired_deadlines() {
+        let now = Instant::now();
+        let mut heap = HeapMap::new();
+        for (id, millis) in [30, 0, 50, 20, 40, 10].iter().enumerate() {
+            let deadline = now + Duration::from_millis(*millis);
+            assert!(heap.push_if_absent((deadline, id), *millis));
+        }
+
+        let threshold = now + Duration::from_millis(20);
+        let drained = heap
+            .drain_while(|k, _| k.0 <= threshold)
+            .map(|(_, millis)| millis)
+            .collect::<Vec<_>>();
+        assert_eq!(drained, [0, 10, 20]);
+        assert_eq!(heap.len(), 3);
+        assert_eq!(heap.drain_while(|k, _| k.0 <= threshold).count(), 0);
+
+        let rest = heap.drain_while(|_, _| true).map(|(_, v)| v).collect::<Vec<_>>();
+        assert_eq!(rest, [30, 40, 50]);
+    }
+
+    #[test]
+    fn deadlines_are_popped_in_ascending_order() {
+        let now = Instant::now();
+        let mut heap = HeapMap::new();
+        for (id, millis) in [30, 10, 50, 20, 40].iter().enumerate() {
+            let deadline = now + Duration::from_millis(*millis);
+            assert!(heap.push_if_absent((deadline, id), *millis));
+        }
+
+        let mut popped = Vec::new();
+        while let Some((_, millis)) = heap.pop_if(|_, _| true) {
+            popped.push(millis);
+        }
+        assert_eq!(popped, [10, 20, 30, 40, 50]);
+    }
+
+    #[test]
+    fn remove_keeps_ordering() {
+        let now = Instant::now();
+        let mut heap = HeapMap::new();
+        let keys = (0..5)
+            .map(|i| (now + Duration::from_millis(i * 10), i as usize))
+            .collect::<Vec<_>>();
+        for (i, key) in keys.iter().enumerate().rev() {
+            assert!(heap.push_if_absent(*key, i));
+        }
+
+        assert_eq!(heap.remove(&keys[2]), Some(2));
+        assert_eq!(heap.peek(), Some((&keys[0], &0)));
+
+        let mut popped = Vec::new();
+        while let Some((_, i)) = heap.pop_if(|_, _| true) {
+            popped.push(i);
+        }
+        assert_eq!(popped, [0, 1, 3, 4]);
+    }
+
+    #[test]
+    fn timer_wheel_works() {
+        let tick = Duration::from_millis(10);
+        let mut wheel = TimerWheel::new(tick, 4);
+        let origin = wheel.origin;
+        assert_eq!(wheel.next_expiry(), None);
+
+        // Deadlines are rounded up to the tick boundaries
+        assert_eq!(wheel.insert(0, origin + Duration::from_millis(15), "a"), Ok(()));
+        assert_eq!(wheel.insert(1, origin + Duration::from_millis(20), "b"), Ok(()));
+        assert_eq!(wheel.insert(2, origin + Duration::from_millis(60), "c"), Ok(()));
+        assert_eq!(wheel.insert(3, origin + Duration::from_millis(95), "d"), Ok(()));
+        assert_eq!(wheel.len(), 4);
+        assert_eq!(wheel.next_expiry(), Some(origin + tick * 2));
+
+        // TEST: remove
+        assert_eq!(wheel.remove(3, origin + Duration::from_millis(95)), Some("d"));
+        assert_eq!(wheel.remove(3, origin + Duration::from_millis(95)), None);
+        assert_eq!(wheel.len(), 3);
+
+        // TEST: expire
+        let mut expired = Vec::new();
+        assert_eq!(wheel.expire(origin + Duration::from_millis(19), |v| expired.push(v)), 0);
+        assert_eq!(wheel.expire(origin + Duration::from_millis(25), |v| expired.push(v)), 2);
+        expired.sort();
+        assert_eq!(expired, ["a", "b"]);
+
+        // "c" is in the same slot as "b", but it is scheduled for the next round
+        assert_eq!(wheel.len(), 1);
+        assert_eq!(wheel.next_expiry(), Some(origin + tick * 6));
+        assert_eq!(wheel.expire(origin + Duration::from_millis(59), |_| ()), 0);
+        assert_eq!(wheel.expire(origin + Duration::from_millis(500), |_| ()), 1);
+        assert_eq!(wheel.len(), 0);
+
+        // Deadlines which have already passed are rejected
+        assert_eq!(wheel.insert(4, origin + Duration::from_millis(500), "e"), Err("e"));
+    }
+
+    #[test]
+    fn slab_works() {
+        let mut slab = Slab::new();
+        assert_eq!(slab.next_key(), 0);
+        assert_eq!(slab.insert("foo"), 0);
+        assert_eq!(slab.insert("bar"), 1);
+        assert_eq!(slab.insert("baz"), 2);
+        assert_eq!(slab.len(), 3);
+
+        assert_eq!(slab.remove(0), Some("foo"));
+        assert_eq!(slab.remove(0), None);
+        assert_eq!(slab.remove(1), Some("bar"));
+        assert_eq!(slab.get_mut(2), Some(&mut "baz"));
+        assert_eq!(slab.len(), 1);
+
+        // The most recently freed key is reused first
+        assert_eq!(slab.next_key(), 1);
+        assert_eq!(slab.insert("qux"), 1);
+        assert_eq!(slab.insert("quux"), 0);
+        assert_eq!(slab.key_count(), 3);
+
+        assert_eq!(
+            slab.iter().collect::<Vec<_>>(),
+            [(0, &"quux"), (1, &"qux"), (2, &"baz")]
+        );
+
+        assert_eq!(slab.remove(2), Some("baz"));
+        assert_eq!(slab.remove(1), Some("qux"));
+        slab.shrink_to_fit();
+        assert_eq!(slab.key_count(), 1);
+        assert_eq!(slab.next_key(), 1);
+
+        assert_eq!(slab.drain(), vec!["quux"]);
+        assert_eq!(slab.len(), 0);
+        assert_eq!(slab.next_key(), 0);
+    }
+
+    #[test]
+    fn timer_wheel_can_be_rebuilt() {
+        let mut wheel = TimerWheel::new(Duration::from_millis(10), 8);
+        let deadline = wheel.origin + Duration::from_secs(1);
+        for id in 0..100 {
+            assert_eq!(wheel.insert(id, deadline, id), Ok(()));
+        }
+
+        let mut other = TimerWheel::new(Duration::from_millis(100), 4);
+        assert!(wheel.move_into(&mut other).is_empty());
+        assert_eq!(other.len(), 100);
+        assert_eq!(other.remove(10, deadline), Some(10));
+        assert_eq!(other.expire(deadline + Duration::from_secs(1), |_| ()), 99);
+    }
 }
@@ -0,0 +1,253 @@
+// Copyright (c) 2016 DWANGO Co., Ltd. All Rights Reserved.
+// See the LICENSE file at the top-level directory of this distribution.
+
+use futures::{Future, Poll};
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicIsize, AtomicUsize, Ordering};
+use std::sync::mpsc::{self as std_mpsc, RecvError};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+
+use sync::oneshot;
+
+/// The default maximum number of the threads of a blocking pool.
+pub const DEFAULT_BLOCKING_THREAD_COUNT: usize = 16;
+
+/// A pool of threads which execute blocking closures.
+///
+/// The threads are spawned lazily (i.e., only when all the existing ones are busy)
+/// up to the maximum count specified on creation,
+/// and they terminate when all the clones of the pool are dropped.
+///
+/// Usually, this is used via `Executor::spawn_blocking` method.
+#[derive(Debug, Clone)]
+pub struct BlockingPool {
+    inner: Arc<Inner>,
+}
+impl BlockingPool {
+    /// Creates a new pool which has at most `max_thread_count` threads.
+    ///
+    /// # Panics
+    ///
+    /// If `max_thread_count` is zero, this function will panic.
+    pub fn new(max_thread_count: usize) -> Self {
+        assert_ne!(max_thread_count, 0);
+        let (job_tx, job_rx) = std_mpsc::channel();
+        BlockingPool {
+            inner: Arc::new(Inner {
+                job_tx: Mutex::new(job_tx),
+                job_rx: Arc::new(Mutex::new(job_rx)),
+                max_thread_count,
+                thread_count: AtomicUsize::new(0),
+                backlog: Arc::new(AtomicIsize::new(0)),
+            }),
+        }
+    }
+
+    /// Returns the maximum number of the threads of the pool.
+    pub fn max_thread_count(&self) -> usize {
+        self.inner.max_thread_count
+    }
+
+    /// Returns the number of the threads spawned by the pool.
+    pub fn thread_count(&self) -> usize {
+        self.inner.thread_count.load(Ordering::SeqCst)
+    }
+
+    /// Executes `f` on a thread of the pool and returns a future which resolves to its result.
+    ///
+    /// If all the threads are busy and no more threads can be spawned,
+    /// `f` is queued until one of them becomes available.
+    pub fn spawn<F, T>(&self, f: F) -> SpawnBlocking<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        let job = Job(Box::new(move || {
+            // NOTE: If `f` panics, `tx` is dropped and the receiver side is notified of that
+            if let Ok(value) = panic::catch_unwind(AssertUnwindSafe(f)) {
+                let _ = tx.send(value);
+            }
+        }));
+        let _ = self.inner.job_tx.lock().expect("Never fails").send(job);
+        if self.inner.backlog.fetch_add(1, Ordering::SeqCst) >= 0 {
+            // NOTE: There are no idle threads which can take the job
+            self.spawn_thread();
+        }
+        SpawnBlocking { rx }
+    }
+
+    fn spawn_thread(&self) {
+        let inner = &self.inner;
+        let mut count = inner.thread_count.load(Ordering::SeqCst);
+        loop {
+            if count == inner.max_thread_count {
+                return;
+            }
+            match inner.thread_count.compare_exchange(
+                count,
+                count + 1,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            ) {
+                Err(current) => count = current,
+                Ok(_) => break,
+            }
+        }
+
+        // NOTE: The new thread is counted as idle from now on,
+        // so that the subsequent jobs do not spawn redundant threads
+        inner.backlog.fetch_sub(1, Ordering::SeqCst);
+        let job_rx = Arc::clone(&inner.job_rx);
+        let backlog = Arc::clone(&inner.backlog);
+        let result = thread::Builder::new()
+            .name(format!("fibers_blocking_{}", count))
+            .spawn(move || loop {
+                let job = job_rx.lock().expect("Never fails").recv();
+                match job {
+                    Err(_) => break,
+                    Ok(job) => (job.0)(),
+                }
+                backlog.fetch_sub(1, Ordering::SeqCst);
+            });
+        if result.is_err() {
+            // NOTE: The queued jobs will be executed by the existing threads (if any)
+            inner.backlog.fetch_add(1, Ordering::SeqCst);
+            inner.thread_count.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+impl Default for BlockingPool {
+    fn default() -> Self {
+        Self::new(DEFAULT_BLOCKING_THREAD_COUNT)
+    }
+}
+
+/// Returns the process-wide pool which has at most `DEFAULT_BLOCKING_THREAD_COUNT` threads.
+///
+/// This is used by the blocking operations which are not tied to a specific executor
+/// (e.g., the ones in `fibers::fs` module).
+pub(crate) fn shared_blocking_pool() -> &'static BlockingPool {
+    static POOL: OnceLock<BlockingPool> = OnceLock::new();
+    POOL.get_or_init(BlockingPool::default)
+}
+
+/// A future which will resolve to the result of a blocking closure.
+///
+/// This fails if the closure panics.
+///
+/// This is created by calling `BlockingPool::spawn` (or `Executor::spawn_blocking`) method.
+#[derive(Debug)]
+pub struct SpawnBlocking<T> {
+    rx: oneshot::Receiver<T>,
+}
+impl<T> Future for SpawnBlocking<T> {
+    type Item = T;
+    type Error = RecvError;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        self.rx.poll()
+    }
+}
+
+#[derive(Debug)]
+struct Inner {
+    job_tx: Mutex<std_mpsc::Sender<Job>>,
+    job_rx: Arc<Mutex<std_mpsc::Receiver<Job>>>,
+    max_thread_count: usize,
+    thread_count: AtomicUsize,
+
+    // The number of the queued jobs minus the number of the idle threads
+    backlog: Arc<AtomicIsize>,
+}
+
+struct Job(Box<dyn FnOnce() + Send + 'static>);
+impl fmt::Debug for Job {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Job(_)")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::Future;
+    use std::sync::Barrier;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use executor::{Executor, InPlaceExecutor};
+    use fiber::Spawn;
+
+    #[test]
+    fn spawn_blocking_works() {
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let start = Instant::now();
+        let blocking = executor.spawn_blocking(|| {
+            thread::sleep(Duration::from_millis(50));
+            42
+        });
+        let monitor = executor.spawn_monitor(blocking.map(|v| v + 1));
+        assert_eq!(executor.run_fiber(monitor).unwrap(), Ok(43));
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn threads_are_spawned_up_to_max_count() {
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let pool = BlockingPool::new(2);
+        assert_eq!(pool.thread_count(), 0);
+
+        let futures = (0..4)
+            .map(|i| {
+                pool.spawn(move || {
+                    thread::sleep(Duration::from_millis(10));
+                    i
+                })
+            })
+            .collect::<Vec<_>>();
+        let monitor = executor.spawn_monitor(futures::future::join_all(futures));
+        assert_eq!(executor.run_fiber(monitor).unwrap(), Ok(vec![0, 1, 2, 3]));
+        assert_eq!(pool.thread_count(), 2);
+    }
+
+    #[test]
+    fn burst_of_jobs_is_executed_concurrently() {
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let pool = BlockingPool::new(4);
+
+        // Makes an idle thread
+        let monitor = executor.spawn_monitor(pool.spawn(|| ()));
+        executor.run_fiber(monitor).unwrap().unwrap();
+        assert_eq!(pool.thread_count(), 1);
+
+        // The jobs can complete only if all of them are running at the same time
+        let barrier = Arc::new(Barrier::new(4));
+        let futures = (0..4)
+            .map(|i| {
+                let barrier = Arc::clone(&barrier);
+                pool.spawn(move || {
+                    barrier.wait();
+                    i
+                })
+            })
+            .collect::<Vec<_>>();
+        let monitor = executor.spawn_monitor(futures::future::join_all(futures));
+        assert_eq!(executor.run_fiber(monitor).unwrap(), Ok(vec![0, 1, 2, 3]));
+        assert_eq!(pool.thread_count(), 4);
+    }
+
+    #[test]
+    fn panicked_closure_fails() {
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let pool = BlockingPool::new(1);
+        let monitor = executor.spawn_monitor(pool.spawn(|| -> usize { panic!() }));
+        assert!(executor.run_fiber(monitor).unwrap().is_err());
+
+        // The thread survives the panic
+        let monitor = executor.spawn_monitor(pool.spawn(|| 1));
+        assert_eq!(executor.run_fiber(monitor).unwrap(), Ok(1));
+        assert_eq!(pool.thread_count(), 1);
+    }
+}
@@ -3,14 +3,18 @@
 
 use futures::Future;
 use std::io;
+use std::sync::Arc;
 use std::time;
 
-use super::Executor;
-use fiber::{self, Spawn};
+use super::{BlockingPool, Executor, SpawnBlocking};
+use fiber::{self, Instrument, Priority, Spawn};
 use io::poll;
 
 /// An executor that executes spawned fibers and I/O event polling on current thread.
 ///
+/// The poller is driven inline between the scheduling rounds (i.e., no helper threads are spawned).
+/// It only blocks the thread when none of the fibers are ready to run.
+///
 /// # Examples
 ///
 /// An example to calculate fibonacci numbers:
@@ -21,7 +25,7 @@ use io::poll;
 /// use fibers::{Spawn, Executor, InPlaceExecutor};
 /// use futures::{Async, Future};
 ///
-/// fn fib<H: Spawn + Clone>(n: usize, handle: H) -> Box<Future<Item=usize, Error=()> + Send> {
+/// fn fib<H: Spawn + Clone>(n: usize, handle: H) -> Box<dyn Future<Item=usize, Error=()> + Send> {
 ///     if n < 2 {
 ///         Box::new(futures::finished(n))
 ///     } else {
@@ -48,16 +52,66 @@ use io::poll;
 pub struct InPlaceExecutor {
     scheduler: fiber::Scheduler,
     poller: poll::Poller,
+    blocking: BlockingPool,
 }
 impl InPlaceExecutor {
     /// Creates a new instance of `InPlaceExecutor`.
     pub fn new() -> io::Result<Self> {
-        let poller = poll::Poller::new()?;
+        Self::builder().build()
+    }
+
+    /// Makes a builder to create an `InPlaceExecutor` instance with the specified settings.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate fibers;
+    /// # extern crate futures;
+    /// use fibers::{Executor, InPlaceExecutor, Spawn};
+    /// use futures::finished;
+    /// use std::time::Duration;
+    ///
+    /// # fn main() {
+    /// let mut executor = InPlaceExecutor::builder()
+    ///     .events_capacity(1024)
+    ///     .request_budget(256)
+    ///     .timer_granularity(Duration::from_millis(50))
+    ///     .build()
+    ///     .unwrap();
+    /// let monitor = executor.spawn_monitor(finished::<_, ()>(42));
+    /// assert_eq!(executor.run_fiber(monitor).unwrap(), Ok(42));
+    /// # }
+    /// ```
+    pub fn builder() -> InPlaceExecutorBuilder {
+        InPlaceExecutorBuilder {
+            poller: poll::Poller::builder(),
+            instrument: None,
+        }
+    }
+
+    fn from_builder(builder: &InPlaceExecutorBuilder) -> io::Result<Self> {
+        let poller = builder.poller.build()?;
+        let mut scheduler = fiber::Scheduler::new(poller.handle());
+        scheduler.set_instrument(builder.instrument.clone());
         Ok(InPlaceExecutor {
-            scheduler: fiber::Scheduler::new(poller.handle()),
+            scheduler,
             poller,
+            blocking: BlockingPool::default(),
         })
     }
+
+    /// Sets the maximum number of the threads used by `Executor::spawn_blocking` method.
+    ///
+    /// The default value is `DEFAULT_BLOCKING_THREAD_COUNT`.
+    /// The closures which have been spawned before calling this method
+    /// are executed by the old threads.
+    ///
+    /// # Panics
+    ///
+    /// If `count` is zero, this method will panic.
+    pub fn set_blocking_thread_count(&mut self, count: usize) {
+        self.blocking = BlockingPool::new(count);
+    }
 }
 impl Executor for InPlaceExecutor {
     type Handle = InPlaceExecutorHandle;
@@ -68,14 +122,91 @@ impl Executor for InPlaceExecutor {
     }
     fn run_once(&mut self) -> io::Result<()> {
         self.scheduler.run_once(false);
-        self.poller.poll(Some(time::Duration::from_millis(1)))?;
+
+        // NOTE: Waiting for I/O events is pointless while there are runnable fibers
+        let timeout = if self.scheduler.run_queue_len() > 0 {
+            time::Duration::from_millis(0)
+        } else {
+            time::Duration::from_millis(1)
+        };
+        self.poller.poll(Some(timeout))?;
         Ok(())
     }
+    fn spawn_blocking<F, T>(&self, f: F) -> SpawnBlocking<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.blocking.spawn(f)
+    }
 }
 impl Spawn for InPlaceExecutor {
-    fn spawn_boxed(&self, fiber: Box<Future<Item = (), Error = ()> + Send>) {
+    fn spawn_boxed(&self, fiber: Box<dyn Future<Item = (), Error = ()> + Send>) {
         self.handle().spawn_boxed(fiber)
     }
+    fn spawn_boxed_with_priority(
+        &self,
+        fiber: Box<dyn Future<Item = (), Error = ()> + Send>,
+        priority: Priority,
+    ) {
+        self.handle().spawn_boxed_with_priority(fiber, priority)
+    }
+}
+
+/// A builder for `InPlaceExecutor`.
+///
+/// This is created by calling `InPlaceExecutor::builder` function.
+#[derive(Debug, Clone)]
+pub struct InPlaceExecutorBuilder {
+    poller: poll::PollerBuilder,
+    instrument: Option<Arc<dyn Instrument>>,
+}
+impl InPlaceExecutorBuilder {
+    /// Sets the collector of the events about the execution of fibers.
+    ///
+    /// See `fibers::fiber::Scheduler::set_instrument` for more details.
+    ///
+    /// By default, no events are collected.
+    pub fn instrument(mut self, instrument: Arc<dyn Instrument>) -> Self {
+        self.instrument = Some(instrument);
+        self
+    }
+
+    /// Sets the capacity of the event buffer of the internal poller.
+    ///
+    /// The default value is `fibers::io::poll::DEFAULT_EVENTS_CAPACITY`.
+    /// See `fibers::io::poll::Poller::with_capacity` for more details.
+    pub fn events_capacity(mut self, capacity: usize) -> Self {
+        self.poller = self.poller.events_capacity(capacity);
+        self
+    }
+
+    /// Sets the maximum number of the requests handled in an iteration of the internal poller.
+    ///
+    /// The default value is `fibers::io::poll::DEFAULT_REQUEST_BUDGET`.
+    /// See `fibers::io::poll::Poller::set_request_budget` for more details.
+    pub fn request_budget(mut self, budget: usize) -> Self {
+        self.poller = self.poller.request_budget(budget);
+        self
+    }
+
+    /// Sets the granularity of the coarse timers of the internal poller.
+    ///
+    /// The default value is `fibers::io::poll::DEFAULT_TIMER_GRANULARITY_MS` milliseconds.
+    /// See `fibers::io::poll::Poller::set_timer_granularity` for more details.
+    pub fn timer_granularity(mut self, granularity: time::Duration) -> Self {
+        self.poller = self.poller.timer_granularity(granularity);
+        self
+    }
+
+    /// Creates an `InPlaceExecutor` instance with the settings.
+    ///
+    /// # Panics
+    ///
+    /// If either the request budget or the timer granularity is zero, this method will panic.
+    pub fn build(&self) -> io::Result<InPlaceExecutor> {
+        InPlaceExecutor::from_builder(self)
+    }
 }
 
 /// A handle of an `InPlaceExecutor` instance.
@@ -84,7 +215,14 @@ pub struct InPlaceExecutorHandle {
     scheduler: fiber::SchedulerHandle,
 }
 impl Spawn for InPlaceExecutorHandle {
-    fn spawn_boxed(&self, fiber: Box<Future<Item = (), Error = ()> + Send>) {
+    fn spawn_boxed(&self, fiber: Box<dyn Future<Item = (), Error = ()> + Send>) {
         self.scheduler.spawn_boxed(fiber)
     }
+    fn spawn_boxed_with_priority(
+        &self,
+        fiber: Box<dyn Future<Item = (), Error = ()> + Send>,
+        priority: Priority,
+    ) {
+        self.scheduler.spawn_boxed_with_priority(fiber, priority)
+    }
 }
@@ -2,16 +2,26 @@
 // See the LICENSE file at the top-level directory of this distribution.
 
 //! The `Executor` trait and its implementations.
-use futures::{Async, Future};
+use futures::{Async, Future, Poll};
+use std::fmt;
 use std::io;
 
-pub use self::in_place::{InPlaceExecutor, InPlaceExecutorHandle};
-pub use self::thread_pool::{ThreadPoolExecutor, ThreadPoolExecutorHandle};
+pub use self::blocking::{BlockingPool, SpawnBlocking, DEFAULT_BLOCKING_THREAD_COUNT};
+pub use self::in_place::{InPlaceExecutor, InPlaceExecutorBuilder, InPlaceExecutorHandle};
+pub use self::scope::{scope, Scope};
+pub use self::thread_pool::{
+    Shutdown, ThreadPoolExecutor, ThreadPoolExecutorBuilder, ThreadPoolExecutorHandle,
+    ThreadPoolMetrics,
+};
+
+pub(crate) use self::blocking::shared_blocking_pool;
 
 use fiber::Spawn;
 use sync::oneshot::{Monitor, MonitorError};
 
+mod blocking;
 mod in_place;
+mod scope;
 mod thread_pool;
 
 /// The `Executor` trait allows for spawning and executing fibers.
@@ -25,6 +35,92 @@ pub trait Executor: Sized {
     /// Runs one one unit of works.
     fn run_once(&mut self) -> io::Result<()>;
 
+    /// Executes the blocking closure `f` on the blocking thread pool of the executor.
+    ///
+    /// The returning future resolves to the result of `f`,
+    /// so a fiber can wait for it without blocking the thread running the fiber.
+    ///
+    /// The default implementation uses a process-wide pool which has at most
+    /// `DEFAULT_BLOCKING_THREAD_COUNT` threads.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate fibers;
+    /// # extern crate futures;
+    /// use fibers::{Executor, InPlaceExecutor, Spawn};
+    /// use std::thread;
+    /// use std::time::Duration;
+    ///
+    /// # fn main() {
+    /// let mut executor = InPlaceExecutor::new().unwrap();
+    /// let future = executor.spawn_blocking(|| {
+    ///     thread::sleep(Duration::from_millis(10));
+    ///     42
+    /// });
+    /// let monitor = executor.spawn_monitor(future);
+    /// assert_eq!(executor.run_fiber(monitor).unwrap(), Ok(42));
+    /// # }
+    /// ```
+    fn spawn_blocking<F, T>(&self, f: F) -> SpawnBlocking<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        shared_blocking_pool().spawn(f)
+    }
+
+    /// Makes a future which executes `future` as a fiber of this executor.
+    ///
+    /// When the returning future is polled for the first time (possibly by a fiber of
+    /// another executor), `future` is spawned on this executor, and the subsequent polls of
+    /// `future` happen there.
+    /// The returning future resolves to the result of `future`,
+    /// and the continuation of it is executed by the executor polling the returning future.
+    /// Thus, for example, a fiber can perform CPU intensive work on a dedicated executor
+    /// and continue I/O on the original one.
+    ///
+    /// If the fiber executing `future` is aborted (e.g., this executor is dropped),
+    /// the returning future will fail with `MonitorError::Aborted`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate fibers;
+    /// # extern crate futures;
+    /// use fibers::{Executor, InPlaceExecutor, Spawn, ThreadPoolExecutor};
+    /// use futures::{Async, Future};
+    /// use std::thread;
+    ///
+    /// # fn main() {
+    /// let mut executor = InPlaceExecutor::new().unwrap();
+    /// let mut workers = ThreadPoolExecutor::with_thread_count(2).unwrap();
+    ///
+    /// let work = futures::lazy(|| Ok::<_, ()>(thread::current().id()));
+    /// let future = workers.bind(work).map(|id| id != thread::current().id());
+    /// let mut monitor = executor.spawn_monitor(future);
+    /// loop {
+    ///     if let Async::Ready(on_worker_thread) = monitor.poll().unwrap() {
+    ///         assert!(on_worker_thread);
+    ///         break;
+    ///     }
+    ///     executor.run_once().unwrap();
+    ///     workers.run_once().unwrap();
+    /// }
+    /// # }
+    /// ```
+    fn bind<F>(&self, future: F) -> BoundFuture<F, Self::Handle>
+    where
+        F: Future + Send + 'static,
+        F::Item: Send + 'static,
+        F::Error: Send + 'static,
+    {
+        BoundFuture {
+            pending: Some((self.handle(), future)),
+            monitor: None,
+        }
+    }
+
     /// Runs until the monitored fiber exits.
     fn run_fiber<T, E>(
         &mut self,
@@ -52,3 +148,111 @@ pub trait Executor: Sized {
         }
     }
 }
+
+/// A future which executes the inner future as a fiber of a specific executor.
+///
+/// This is created by calling `Executor::bind` method.
+pub struct BoundFuture<F: Future, H> {
+    pending: Option<(H, F)>,
+    monitor: Option<Monitor<F::Item, F::Error>>,
+}
+impl<F, H> Future for BoundFuture<F, H>
+where
+    F: Future + Send + 'static,
+    F::Item: Send + 'static,
+    F::Error: Send + 'static,
+    H: Spawn,
+{
+    type Item = F::Item;
+    type Error = MonitorError<F::Error>;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if let Some((handle, future)) = self.pending.take() {
+            self.monitor = Some(handle.spawn_monitor(future));
+        }
+        assert_some!(self.monitor.as_mut()).poll()
+    }
+}
+impl<F: Future, H> fmt::Debug for BoundFuture<F, H> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "BoundFuture {{ spawned: {}, .. }}",
+            self.monitor.is_some()
+        )
+    }
+}
+
+/// Runs `future` on the current thread until it completes, and returns the result.
+///
+/// The future is executed as a fiber of a newly created `InPlaceExecutor`,
+/// so it can use the I/O and timer facilities of this crate.
+///
+/// # Panics
+///
+/// This function panics if the executor fails to run,
+/// or the fiber executing `future` is aborted.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate fibers;
+/// # extern crate futures;
+/// use std::time::Duration;
+/// use fibers::time::timer;
+/// use futures::Future;
+///
+/// # fn main() {
+/// let future = timer::timeout(Duration::from_millis(10)).map(|()| "done");
+/// assert_eq!(fibers::block_on(future).ok(), Some("done"));
+/// # }
+/// ```
+pub fn block_on<F>(future: F) -> Result<F::Item, F::Error>
+where
+    F: Future + Send + 'static,
+    F::Item: Send + 'static,
+    F::Error: Send + 'static,
+{
+    let mut executor = InPlaceExecutor::new().expect("Cannot create an executor");
+    let monitor = executor.spawn_monitor(future);
+    match executor.run_fiber(monitor).expect("Executor failed") {
+        Ok(v) => Ok(v),
+        Err(MonitorError::Failed(e)) => Err(e),
+        Err(MonitorError::Aborted) => panic!("The fiber running the future was aborted"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::mpsc;
+    use std::thread;
+
+    use super::*;
+    use sync::oneshot;
+
+    #[test]
+    fn bind_works() {
+        // Runs another executor on a dedicated thread
+        let (tx, rx) = mpsc::channel();
+        let (stop_tx, stop_rx) = oneshot::channel::<()>();
+        let other = thread::spawn(move || {
+            let mut executor = InPlaceExecutor::new().unwrap();
+            let bound = executor.bind(futures::lazy(|| Ok::<_, ()>(thread::current().id())));
+            tx.send(bound).unwrap();
+            executor.run_future(stop_rx).unwrap().unwrap();
+        });
+        let bound = rx.recv().unwrap();
+
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let future = futures::lazy(|| Ok(thread::current().id()))
+            .and_then(move |before| bound.map(move |inside| (before, inside)))
+            .map(|(before, inside)| (before, inside, thread::current().id()));
+        let monitor = executor.spawn_monitor(future);
+        let (before, inside, after) = executor.run_fiber(monitor).unwrap().unwrap();
+        assert_ne!(before, inside);
+        assert_eq!(before, after);
+        assert_eq!(before, thread::current().id());
+
+        stop_tx.send(()).unwrap();
+        other.join().unwrap();
+    }
+}
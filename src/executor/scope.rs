@@ -0,0 +1,148 @@
+// Copyright (c) 2016 DWANGO Co., Ltd. All Rights Reserved.
+// See the LICENSE file at the top-level directory of this distribution.
+
+use futures::Future;
+use std::cell::RefCell;
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem;
+
+use super::{Executor, InPlaceExecutor, InPlaceExecutorHandle};
+use fiber::Spawn;
+use sync::oneshot::{self, Monitor, MonitorError};
+
+/// Creates a scope for spawning fibers which borrow data on the stack.
+///
+/// The fibers spawned via the `Scope` passed to `f` are not required to be `'static`.
+/// They are executed on the current thread (by a newly created `InPlaceExecutor`)
+/// after `f` returns, and this function does not return until all of them complete.
+///
+/// Note that the fibers spawned by the scoped fibers via other means
+/// (e.g., `fibers::fiber::with_current_context`) are dropped when this function returns,
+/// even if they have not completed yet.
+///
+/// # Panics
+///
+/// This function panics if the executor fails to run, or any of the scoped fibers is aborted.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate fibers;
+/// # extern crate futures;
+/// use futures::{future, Future};
+///
+/// # fn main() {
+/// let numbers = vec![1, 2, 3, 4, 5, 6];
+/// let (left, right) = fibers::scope(|s| {
+///     let left = s.spawn_monitor(future::ok::<_, ()>(numbers[..3].iter().sum::<i32>()));
+///     let right = s.spawn_monitor(future::ok::<_, ()>(numbers[3..].iter().sum::<i32>()));
+///     (left, right)
+/// });
+/// assert_eq!(fibers::block_on(left.join(right)).ok(), Some((6, 15)));
+/// # }
+/// ```
+pub fn scope<'env, F, R>(f: F) -> R
+where
+    F: FnOnce(&Scope<'env>) -> R,
+{
+    let mut executor = InPlaceExecutor::new().expect("Cannot create an executor");
+    let scope = Scope {
+        handle: executor.handle(),
+        fibers: RefCell::new(Vec::new()),
+        _env: PhantomData,
+    };
+    let result = f(&scope);
+
+    // NOTE: If a panic occurs before all the fibers complete,
+    // the remaining fibers are dropped along with the executor while the borrowed data is alive.
+    for fiber in scope.fibers.into_inner() {
+        if let Err(MonitorError::Aborted) = executor.run_fiber(fiber).expect("Executor failed") {
+            panic!("A scoped fiber was aborted");
+        }
+    }
+    result
+}
+
+/// A scope for spawning fibers which borrow data on the stack.
+///
+/// This is created by calling `fibers::scope` function.
+pub struct Scope<'env> {
+    handle: InPlaceExecutorHandle,
+    fibers: RefCell<Vec<Monitor<(), ()>>>,
+
+    // NOTE: `'env` must be invariant, otherwise the fibers could borrow shorter-lived data
+    _env: PhantomData<&'env mut &'env ()>,
+}
+impl<'env> Scope<'env> {
+    /// Spawns a fiber which may borrow data outliving the scope.
+    pub fn spawn<F>(&self, fiber: F)
+    where
+        F: Future<Item = (), Error = ()> + Send + 'env,
+    {
+        let fiber: Box<dyn Future<Item = (), Error = ()> + Send + 'env> = Box::new(fiber);
+
+        // SAFETY: `scope` function does not return until the fiber completes or is dropped,
+        // so the data borrowed by the fiber outlives it.
+        let fiber: Box<dyn Future<Item = (), Error = ()> + Send + 'static> =
+            unsafe { mem::transmute(fiber) };
+        let monitor = self.handle.spawn_monitor(fiber);
+        self.fibers.borrow_mut().push(monitor);
+    }
+
+    /// Spawns a fiber which may borrow data outliving the scope, and returns the monitor of it.
+    ///
+    /// The monitor can be polled after the scope returns.
+    pub fn spawn_monitor<F, T, E>(&self, fiber: F) -> Monitor<T, E>
+    where
+        F: Future<Item = T, Error = E> + Send + 'env,
+        T: Send + 'env,
+        E: Send + 'env,
+    {
+        let (monitored, monitor) = oneshot::monitor();
+        self.spawn(fiber.then(move |r| {
+            monitored.exit(r);
+            Ok(())
+        }));
+        monitor
+    }
+}
+impl<'env> fmt::Debug for Scope<'env> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Scope {{ fibers:{}, .. }}", self.fibers.borrow().len())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::future;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use super::*;
+    use time::timer;
+
+    #[test]
+    fn scope_works() {
+        let items = [1, 2, 3, 4, 5, 6, 7, 8];
+        let sum = AtomicUsize::new(0);
+        let finished = scope(|s| {
+            for (i, chunk) in items.chunks(3).enumerate() {
+                let sum = &sum;
+                s.spawn(
+                    timer::timeout(Duration::from_millis(10 - i as u64 * 3))
+                        .map_err(|_| ())
+                        .map(move |()| {
+                            let chunk_sum: usize = chunk.iter().sum();
+                            sum.fetch_add(chunk_sum, Ordering::SeqCst);
+                        }),
+                );
+            }
+            s.spawn_monitor(future::lazy(|| Ok::<_, ()>(items.len())))
+        });
+
+        // All the fibers have completed before the scope returns
+        assert_eq!(sum.load(Ordering::SeqCst), 36);
+        assert_eq!(finished.wait(), Ok(8));
+    }
+}
@@ -1,19 +1,23 @@
 // Copyright (c) 2016 DWANGO Co., Ltd. All Rights Reserved.
 // See the LICENSE file at the top-level directory of this distribution.
 
-use futures::{Async, Future};
+use futures::task::AtomicTask;
+use futures::{self, Async, Future, Poll};
 use nbchan::mpsc as nb_mpsc;
 use num_cpus;
 use std::io;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::mpsc::TryRecvError;
+use std::sync::Arc;
 use std::thread;
 use std::time;
 
-use super::Executor;
+use super::{BlockingPool, Executor, SpawnBlocking};
 use fiber::Task;
-use fiber::{self, Spawn};
-use io::poll;
+use fiber::{self, Instrument, Priority, Spawn};
+use io::poll::{BalancePolicy, Poller, PollerBuilder, PollerPool};
 use sync::oneshot::{self, Link};
+use time::timer;
 
 /// An executor that executes spawned fibers on pooled threads.
 ///
@@ -27,7 +31,7 @@ use sync::oneshot::{self, Link};
 /// use fibers::{Spawn, Executor, ThreadPoolExecutor};
 /// use futures::{Async, Future};
 ///
-/// fn fib<H: Spawn + Clone>(n: usize, handle: H) -> Box<Future<Item=usize, Error=()> + Send> {
+/// fn fib<H: Spawn + Clone>(n: usize, handle: H) -> Box<dyn Future<Item=usize, Error=()> + Send> {
 ///     if n < 2 {
 ///         Box::new(futures::finished(n))
 ///     } else {
@@ -48,6 +52,7 @@ use sync::oneshot::{self, Link};
 pub struct ThreadPoolExecutor {
     pool: SchedulerPool,
     pollers: PollerPool,
+    blocking: BlockingPool,
     spawn_rx: nb_mpsc::Receiver<Task>,
     spawn_tx: nb_mpsc::Sender<Task>,
     round: usize,
@@ -78,25 +83,257 @@ impl ThreadPoolExecutor {
     /// If any of those threads are aborted, the executor will return an error as
     /// a result of `run_once` method call after that.
     pub fn with_thread_count(count: usize) -> io::Result<Self> {
-        assert!(count > 0);
-        let pollers = PollerPool::new(count)?;
-        let schedulers = SchedulerPool::new(&pollers);
+        Self::with_thread_and_poller_count(count, count)
+    }
+
+    /// Creates a new instance of `ThreadPoolExecutor` which has
+    /// `thread_count` scheduler threads and `poller_count` poller threads.
+    ///
+    /// The `i`-th scheduler is paired with the `i % poller_count`-th poller,
+    /// and the I/O events of the fibers running on the scheduler are dispatched by that poller.
+    /// If both of the counts are equal (as `with_thread_count` does),
+    /// each scheduler has its own poller, which minimizes the contention between them.
+    ///
+    /// # Panics
+    ///
+    /// If either of the counts is zero, this function will panic.
+    pub fn with_thread_and_poller_count(
+        thread_count: usize,
+        poller_count: usize,
+    ) -> io::Result<Self> {
+        ThreadPoolExecutorBuilder::new()
+            .thread_count(thread_count)
+            .poller_count(poller_count)
+            .build()
+    }
+
+    /// Makes a builder to create a `ThreadPoolExecutor` instance with the specified settings.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate fibers;
+    /// # extern crate futures;
+    /// use fibers::{Executor, Spawn, ThreadPoolExecutor};
+    /// use futures::finished;
+    /// use std::time::Duration;
+    ///
+    /// # fn main() {
+    /// let mut executor = ThreadPoolExecutor::builder()
+    ///     .thread_count(2)
+    ///     .poll_budget(Duration::from_millis(10))
+    ///     .build()
+    ///     .unwrap();
+    /// let monitor = executor.spawn_monitor(finished::<_, ()>(42));
+    /// assert_eq!(executor.run_fiber(monitor).unwrap(), Ok(42));
+    /// assert_eq!(executor.metrics().budget_overruns, 0);
+    /// # }
+    /// ```
+    pub fn builder() -> ThreadPoolExecutorBuilder {
+        ThreadPoolExecutorBuilder::new()
+    }
+
+    fn from_builder(builder: &ThreadPoolExecutorBuilder) -> io::Result<Self> {
+        let thread_count = builder.thread_count;
+        assert!(thread_count > 0);
+        let pollers = PollerPool::with_poller_builder(
+            builder.poller_count.unwrap_or(thread_count),
+            BalancePolicy::RoundRobin,
+            &builder.poller,
+        )?;
+        let schedulers = SchedulerPool::new(&pollers, builder)?;
         let (tx, rx) = nb_mpsc::channel();
         Ok(ThreadPoolExecutor {
             pool: schedulers,
             pollers,
+            blocking: BlockingPool::default(),
             spawn_tx: tx,
             spawn_rx: rx,
             round: 0,
             steps: 0,
         })
     }
+
+    /// Sets the maximum number of the threads used by `Executor::spawn_blocking` method.
+    ///
+    /// The blocking threads are independent of the scheduler and poller threads.
+    /// The default value is `DEFAULT_BLOCKING_THREAD_COUNT`.
+    /// The closures which have been spawned before calling this method
+    /// are executed by the old threads.
+    ///
+    /// # Panics
+    ///
+    /// If `count` is zero, this method will panic.
+    pub fn set_blocking_thread_count(&mut self, count: usize) {
+        self.blocking = BlockingPool::new(count);
+    }
+
+    /// Returns a snapshot of the metrics of the executor.
+    ///
+    /// The counters are maintained by each scheduler thread without locking,
+    /// so the values may lag slightly behind the actual state of the schedulers.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate fibers;
+    /// # extern crate futures;
+    /// use fibers::{Executor, Spawn, ThreadPoolExecutor};
+    /// use futures::finished;
+    ///
+    /// # fn main() {
+    /// let mut executor = ThreadPoolExecutor::with_thread_count(2).unwrap();
+    /// let monitor = executor.spawn_monitor(finished::<_, ()>(42));
+    /// assert_eq!(executor.run_fiber(monitor).unwrap(), Ok(42));
+    /// while executor.metrics().finished_fibers == 0 {
+    ///     executor.run_once().unwrap();
+    /// }
+    ///
+    /// let metrics = executor.metrics();
+    /// assert_eq!(metrics.spawned_fibers, 1);
+    /// assert_eq!(metrics.live_fibers, 0);
+    /// assert_eq!(metrics.run_queue_lens.len(), 2);
+    /// # }
+    /// ```
+    pub fn metrics(&self) -> ThreadPoolMetrics {
+        let mut metrics = ThreadPoolMetrics::default();
+        for stats in &self.pool.stats {
+            metrics.live_fibers += stats.fibers.load(Ordering::Relaxed);
+            metrics.spawned_fibers += stats.spawned.load(Ordering::Relaxed);
+            metrics.finished_fibers += stats.finished.load(Ordering::Relaxed);
+            metrics.budget_overruns += stats.budget_overruns.load(Ordering::Relaxed);
+            metrics
+                .run_queue_lens
+                .push(stats.run_queue_len.load(Ordering::Relaxed));
+        }
+        metrics
+    }
+
+    /// Shuts down the executor gracefully.
+    ///
+    /// The fibers which have been spawned before calling this method are executed
+    /// until they finish, as well as the fibers spawned by them after that.
+    /// On the other hand, the fibers spawned from the outside of the executor
+    /// (i.e., not by the fibers running on it) after calling this method are discarded.
+    /// When all the fibers have finished, the threads of the executor are terminated
+    /// and the returning future completes.
+    ///
+    /// The scheduler threads exit as soon as all the fibers have finished,
+    /// and the returning future is woken up by the exits.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate fibers;
+    /// # extern crate futures;
+    /// use std::sync::Arc;
+    /// use std::sync::atomic::{AtomicBool, Ordering};
+    /// use std::time::Duration;
+    /// use fibers::{Spawn, ThreadPoolExecutor};
+    /// use fibers::time::timer;
+    /// use futures::Future;
+    ///
+    /// # fn main() {
+    /// let executor = ThreadPoolExecutor::with_thread_count(2).unwrap();
+    /// let finished = Arc::new(AtomicBool::new(false));
+    /// let finished_clone = Arc::clone(&finished);
+    /// executor.spawn(timer::timeout(Duration::from_millis(10))
+    ///     .map(move |()| finished_clone.store(true, Ordering::SeqCst))
+    ///     .map_err(|_| ()));
+    ///
+    /// executor.shutdown().wait().unwrap();
+    /// assert!(finished.load(Ordering::SeqCst));
+    /// # }
+    /// ```
+    pub fn shutdown(self) -> Shutdown {
+        Shutdown::new(self, None)
+    }
+
+    /// Shuts down the executor gracefully within `timeout`.
+    ///
+    /// This is the same as `shutdown` method except that the fibers which have not finished
+    /// until the timeout expires are forcibly terminated.
+    /// In that case, the returning future fails with an error of which kind is `TimedOut`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate fibers;
+    /// # extern crate futures;
+    /// use std::io;
+    /// use std::time::Duration;
+    /// use fibers::{Spawn, ThreadPoolExecutor};
+    /// use futures::{empty, Future};
+    ///
+    /// # fn main() {
+    /// let executor = ThreadPoolExecutor::with_thread_count(2).unwrap();
+    /// executor.spawn(empty());
+    ///
+    /// let result = executor.shutdown_timeout(Duration::from_millis(10)).wait();
+    /// assert_eq!(result.err().map(|e| e.kind()), Some(io::ErrorKind::TimedOut));
+    /// # }
+    /// ```
+    pub fn shutdown_timeout(self, timeout: time::Duration) -> Shutdown {
+        Shutdown::new(self, Some(time::Instant::now() + timeout))
+    }
+
+    fn check_thread_exit(&mut self, i: usize) -> io::Result<bool> {
+        let exited = match self.pool.links[i].poll() {
+            Err(_) => {
+                return Err(io::Error::other(format!(
+                    "The {}-th scheduler thread is aborted",
+                    i
+                )));
+            }
+            Ok(Async::Ready(())) => true,
+            Ok(Async::NotReady) => false,
+        };
+        let poller_count = self.pollers.len();
+        self.pollers.check_poller(i % poller_count)?;
+        Ok(exited)
+    }
+    fn check_threads(&mut self, i: usize) -> io::Result<()> {
+        if self.pool.links[i].poll().is_err() {
+            return Err(io::Error::other(format!(
+                "The {}-th scheduler thread is aborted",
+                i
+            )));
+        }
+        let poller_count = self.pollers.len();
+        self.pollers.check_poller(i % poller_count)
+    }
+    fn dispatch(&mut self, task: Task) {
+        let i = self.round % self.pool.schedulers.len();
+        self.pool.schedulers[i].spawn_boxed_with_priority(task.0, task.1);
+        self.round = self.round.wrapping_add(1);
+    }
+    fn terminate(self) -> io::Result<()> {
+        let ThreadPoolExecutor { pool, pollers, .. } = self;
+        for (scheduler, link) in pool.schedulers.iter().zip(pool.links) {
+            drop(link);
+
+            // Spawns an empty fiber to wake up the scheduler
+            // which may be blocking until any requests arrive.
+            scheduler.spawn(futures::finished(()));
+        }
+
+        for (i, thread) in pool.threads.into_iter().enumerate() {
+            if thread.join().is_err() {
+                return Err(io::Error::other(format!(
+                    "Cannot join the {}-th thread of the executor",
+                    i
+                )));
+            }
+        }
+        pollers.shutdown()
+    }
 }
 impl Executor for ThreadPoolExecutor {
     type Handle = ThreadPoolExecutorHandle;
     fn handle(&self) -> Self::Handle {
         ThreadPoolExecutorHandle {
             spawn_tx: self.spawn_tx.clone(),
+            state: Arc::clone(&self.pool.state),
         }
     }
     fn run_once(&mut self) -> io::Result<()> {
@@ -105,27 +342,304 @@ impl Executor for ThreadPoolExecutor {
                 thread::sleep(time::Duration::from_millis(1));
             }
             Err(TryRecvError::Disconnected) => unreachable!(),
-            Ok(task) => {
-                let i = self.round % self.pool.schedulers.len();
-                self.pool.schedulers[i].spawn_boxed(task.0);
-                self.round = self.round.wrapping_add(1);
-            }
+            Ok(task) => self.dispatch(task),
         }
         self.steps = self.steps.wrapping_add(1);
         let i = self.steps % self.pool.schedulers.len();
-        if self.pool.links[i].poll().is_err() {
+        self.check_threads(i)
+    }
+    fn spawn_blocking<F, T>(&self, f: F) -> SpawnBlocking<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.blocking.spawn(f)
+    }
+}
+impl Spawn for ThreadPoolExecutor {
+    fn spawn_boxed(&self, fiber: Box<dyn Future<Item = (), Error = ()> + Send>) {
+        self.handle().spawn_boxed(fiber)
+    }
+    fn spawn_boxed_with_priority(
+        &self,
+        fiber: Box<dyn Future<Item = (), Error = ()> + Send>,
+        priority: Priority,
+    ) {
+        self.handle().spawn_boxed_with_priority(fiber, priority)
+    }
+}
+
+/// A future which will complete when a `ThreadPoolExecutor` has been shut down.
+///
+/// This is created by calling `ThreadPoolExecutor::shutdown` or
+/// `ThreadPoolExecutor::shutdown_timeout` method.
+#[derive(Debug)]
+pub struct Shutdown {
+    executor: Option<ThreadPoolExecutor>,
+    deadline: Option<time::Instant>,
+    exited: Vec<bool>,
+    woken: bool,
+}
+impl Shutdown {
+    fn new(mut executor: ThreadPoolExecutor, deadline: Option<time::Instant>) -> Self {
+        executor
+            .pool
+            .state
+            .shutting_down
+            .store(true, Ordering::SeqCst);
+
+        // The fibers which have been spawned until now are executed
+        while let Ok(task) = executor.spawn_rx.try_recv() {
+            executor.dispatch(task);
+        }
+        executor.pool.wake_up_schedulers();
+
+        // Wakes up the future when the deadline expires
+        if let Some(deadline) = deadline {
+            let state = Arc::clone(&executor.pool.state);
+            let timeout = deadline.saturating_duration_since(time::Instant::now());
+            executor.pool.schedulers[0].spawn(timer::timeout(timeout).then(move |_| {
+                state.exit_task.notify();
+                Ok(())
+            }));
+        }
+
+        let exited = vec![false; executor.pool.schedulers.len()];
+        Shutdown {
+            executor: Some(executor),
+            deadline,
+            exited,
+            woken: false,
+        }
+    }
+}
+impl Future for Shutdown {
+    type Item = ();
+    type Error = io::Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let is_quiesced = {
+            let executor = self.executor.as_mut().expect("Cannot poll Shutdown twice");
+            executor.pool.state.exit_task.register();
+
+            // The fibers spawned by the running ones are executed too
+            while let Ok(task) = executor.spawn_rx.try_recv() {
+                executor.dispatch(task);
+            }
+            if !self.woken && executor.pool.state.is_quiesced() {
+                // The idle schedulers may be blocking until any requests arrive
+                executor.pool.wake_up_schedulers();
+                self.woken = true;
+            }
+            for (i, exited) in self.exited.iter_mut().enumerate() {
+                if !*exited {
+                    *exited = executor.check_thread_exit(i)?;
+                }
+            }
+
+            let is_quiesced = self.exited.iter().all(|&exited| exited);
+            let is_expired = self.deadline.is_some_and(|d| d <= time::Instant::now());
+            if !is_quiesced && !is_expired {
+                return Ok(Async::NotReady);
+            }
+            is_quiesced
+        };
+
+        let executor = self.executor.take().expect("Never fails");
+        executor.terminate()?;
+        if is_quiesced {
+            Ok(Async::Ready(()))
+        } else {
             Err(io::Error::new(
-                io::ErrorKind::Other,
-                format!("The {}-th scheduler thread is aborted", i),
+                io::ErrorKind::TimedOut,
+                "Some fibers have not finished until the shutdown timeout expired",
             ))
-        } else {
-            Ok(())
         }
     }
 }
-impl Spawn for ThreadPoolExecutor {
-    fn spawn_boxed(&self, fiber: Box<Future<Item = (), Error = ()> + Send>) {
-        self.handle().spawn_boxed(fiber)
+
+/// Metrics of a `ThreadPoolExecutor`.
+///
+/// This is created by calling `ThreadPoolExecutor::metrics` method.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ThreadPoolMetrics {
+    /// The number of the alive fibers in the executor.
+    pub live_fibers: usize,
+
+    /// The total number of the fibers spawned in the schedulers of the executor.
+    pub spawned_fibers: usize,
+
+    /// The total number of the fibers finished in the schedulers of the executor.
+    pub finished_fibers: usize,
+
+    /// The length of the run queue of each scheduler (thread).
+    pub run_queue_lens: Vec<usize>,
+
+    /// The total number of the fiber polls which exceeded the poll budget of the executor.
+    ///
+    /// This is always `0` unless the budget is set by `ThreadPoolExecutorBuilder::poll_budget`.
+    pub budget_overruns: usize,
+}
+
+/// A builder for `ThreadPoolExecutor`.
+///
+/// This is created by calling `ThreadPoolExecutor::builder` function.
+#[derive(Debug, Clone)]
+pub struct ThreadPoolExecutorBuilder {
+    thread_count: usize,
+    poller_count: Option<usize>,
+    poll_budget: Option<time::Duration>,
+    instrument: Option<Arc<dyn Instrument>>,
+    thread_name_prefix: String,
+    cpu_affinity: Option<Vec<usize>>,
+    poller: PollerBuilder,
+}
+impl ThreadPoolExecutorBuilder {
+    fn new() -> Self {
+        ThreadPoolExecutorBuilder {
+            thread_count: num_cpus::get() * 2,
+            poller_count: None,
+            poll_budget: None,
+            instrument: None,
+            thread_name_prefix: "fibers-worker-".to_owned(),
+            cpu_affinity: None,
+            poller: Poller::builder(),
+        }
+    }
+
+    /// Sets the number of the scheduler threads.
+    ///
+    /// The default value is `num_cpus::get() * 2`.
+    pub fn thread_count(mut self, count: usize) -> Self {
+        self.thread_count = count;
+        self
+    }
+
+    /// Sets the number of the poller threads.
+    ///
+    /// The default value is the same as the number of the scheduler threads.
+    /// See `ThreadPoolExecutor::with_thread_and_poller_count` for more details.
+    pub fn poller_count(mut self, count: usize) -> Self {
+        self.poller_count = Some(count);
+        self
+    }
+
+    /// Sets the time budget of a poll of a fiber.
+    ///
+    /// The polls exceeding the budget are counted in `ThreadPoolMetrics::budget_overruns`.
+    /// See `fibers::fiber::Scheduler::set_poll_budget` for more details.
+    ///
+    /// By default, the polls are not measured.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate fibers;
+    /// # extern crate futures;
+    /// use fibers::{Executor, Spawn, ThreadPoolExecutor};
+    /// use futures::lazy;
+    /// use std::thread;
+    /// use std::time::Duration;
+    ///
+    /// # fn main() {
+    /// let mut executor = ThreadPoolExecutor::builder()
+    ///     .thread_count(1)
+    ///     .poll_budget(Duration::from_millis(1))
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// // A misbehaving fiber which blocks the scheduler thread
+    /// executor.spawn(lazy(|| {
+    ///     thread::sleep(Duration::from_millis(10));
+    ///     Ok(())
+    /// }));
+    /// while executor.metrics().budget_overruns == 0 {
+    ///     executor.run_once().unwrap();
+    /// }
+    /// assert_eq!(executor.metrics().budget_overruns, 1);
+    /// # }
+    /// ```
+    pub fn poll_budget(mut self, budget: time::Duration) -> Self {
+        self.poll_budget = Some(budget);
+        self
+    }
+
+    /// Sets the collector of the events about the execution of fibers.
+    ///
+    /// The collector is shared by all the scheduler threads.
+    /// See `fibers::fiber::Scheduler::set_instrument` for more details.
+    ///
+    /// By default, no events are collected.
+    pub fn instrument(mut self, instrument: Arc<dyn Instrument>) -> Self {
+        self.instrument = Some(instrument);
+        self
+    }
+
+    /// Sets the prefix of the names of the scheduler threads.
+    ///
+    /// The threads are named by appending their indices to the prefix
+    /// (e.g., `fibers-worker-0`, `fibers-worker-1`, ...), which helps to identify them
+    /// in profilers, debuggers and `top -H`.
+    ///
+    /// The default value is `"fibers-worker-"`.
+    pub fn thread_name_prefix(mut self, prefix: &str) -> Self {
+        self.thread_name_prefix = prefix.to_owned();
+        self
+    }
+
+    /// Sets the CPUs to which the scheduler threads are pinned.
+    ///
+    /// The `i`-th scheduler thread is pinned to the CPU `cpus[i % cpus.len()]`.
+    ///
+    /// This is a best-effort setting: it is only supported on Linux,
+    /// and the failures (e.g., nonexistent CPUs) are ignored.
+    /// On the other platforms, this setting has no effect.
+    ///
+    /// By default, the threads are not pinned.
+    ///
+    /// # Panics
+    ///
+    /// If `cpus` is empty, this method will panic.
+    pub fn cpu_affinity(mut self, cpus: Vec<usize>) -> Self {
+        assert!(!cpus.is_empty());
+        self.cpu_affinity = Some(cpus);
+        self
+    }
+
+    /// Sets the capacity of the event buffer of each poller.
+    ///
+    /// The default value is `fibers::io::poll::DEFAULT_EVENTS_CAPACITY`.
+    /// See `fibers::io::poll::Poller::with_capacity` for more details.
+    pub fn events_capacity(mut self, capacity: usize) -> Self {
+        self.poller = self.poller.events_capacity(capacity);
+        self
+    }
+
+    /// Sets the maximum number of the requests handled in an iteration of each poller.
+    ///
+    /// The default value is `fibers::io::poll::DEFAULT_REQUEST_BUDGET`.
+    /// See `fibers::io::poll::Poller::set_request_budget` for more details.
+    pub fn request_budget(mut self, budget: usize) -> Self {
+        self.poller = self.poller.request_budget(budget);
+        self
+    }
+
+    /// Sets the granularity of the coarse timers of each poller.
+    ///
+    /// The default value is `fibers::io::poll::DEFAULT_TIMER_GRANULARITY_MS` milliseconds.
+    /// See `fibers::io::poll::Poller::set_timer_granularity` for more details.
+    pub fn timer_granularity(mut self, granularity: time::Duration) -> Self {
+        self.poller = self.poller.timer_granularity(granularity);
+        self
+    }
+
+    /// Creates a `ThreadPoolExecutor` instance with the settings.
+    ///
+    /// # Panics
+    ///
+    /// If either of the thread counts, the request budget or the timer granularity is zero,
+    /// this method will panic.
+    pub fn build(&self) -> io::Result<ThreadPoolExecutor> {
+        ThreadPoolExecutor::from_builder(self)
     }
 }
 
@@ -133,38 +647,75 @@ impl Spawn for ThreadPoolExecutor {
 #[derive(Debug, Clone)]
 pub struct ThreadPoolExecutorHandle {
     spawn_tx: nb_mpsc::Sender<Task>,
+    state: Arc<PoolState>,
 }
 impl Spawn for ThreadPoolExecutorHandle {
-    fn spawn_boxed(&self, fiber: Box<Future<Item = (), Error = ()> + Send>) {
-        let _ = self.spawn_tx.send(Task(fiber));
+    fn spawn_boxed(&self, fiber: Box<dyn Future<Item = (), Error = ()> + Send>) {
+        self.spawn_boxed_with_priority(fiber, Priority::Normal)
+    }
+    fn spawn_boxed_with_priority(
+        &self,
+        fiber: Box<dyn Future<Item = (), Error = ()> + Send>,
+        priority: Priority,
+    ) {
+        let state = &self.state;
+        if state.shutting_down.load(Ordering::SeqCst) && !state.is_in_pool() {
+            // The executor is shutting down
+            return;
+        }
+
+        state.live_fibers.fetch_add(1, Ordering::SeqCst);
+        let fiber = PoolFiber {
+            fiber,
+            state: Arc::clone(state),
+        };
+        let _ = self.spawn_tx.send(Task(Box::new(fiber), priority));
+        if state.shutting_down.load(Ordering::SeqCst) {
+            // Lets `Shutdown` dispatch the fiber
+            state.exit_task.notify();
+        }
     }
 }
 
+/// The state shared by the schedulers and the handles of a `ThreadPoolExecutor`.
 #[derive(Debug)]
-struct PollerPool {
-    pollers: Vec<poll::PollerHandle>,
-    links: Vec<Link<(), io::Error>>,
+struct PoolState {
+    scheduler_ids: Vec<fiber::SchedulerId>,
+    live_fibers: AtomicUsize,
+    shutting_down: AtomicBool,
+    exit_task: AtomicTask,
 }
-impl PollerPool {
-    pub fn new(pool_size: usize) -> io::Result<Self> {
-        let mut pollers = Vec::new();
-        let mut links = Vec::new();
-        for _ in 0..pool_size {
-            let (link0, mut link1) = oneshot::link();
-            let mut poller = poll::Poller::new()?;
-            links.push(link0);
-            pollers.push(poller.handle());
-            thread::spawn(move || {
-                while let Ok(Async::NotReady) = link1.poll() {
-                    let timeout = time::Duration::from_millis(1);
-                    if let Err(e) = poller.poll(Some(timeout)) {
-                        link1.exit(Err(e));
-                        return;
-                    }
-                }
-            });
+impl PoolState {
+    fn is_in_pool(&self) -> bool {
+        fiber::with_current_context(|c| self.scheduler_ids.contains(&c.context_id().0))
+            .unwrap_or(false)
+    }
+    fn is_quiesced(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst) && self.live_fibers.load(Ordering::SeqCst) == 0
+    }
+}
+
+/// A fiber spawned via `ThreadPoolExecutorHandle`.
+///
+/// This is counted as a live fiber of the executor until it is dropped.
+struct PoolFiber {
+    fiber: Box<dyn Future<Item = (), Error = ()> + Send>,
+    state: Arc<PoolState>,
+}
+impl Future for PoolFiber {
+    type Item = ();
+    type Error = ();
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        self.fiber.poll()
+    }
+}
+impl Drop for PoolFiber {
+    fn drop(&mut self) {
+        if self.state.live_fibers.fetch_sub(1, Ordering::SeqCst) == 1
+            && self.state.shutting_down.load(Ordering::SeqCst)
+        {
+            self.state.exit_task.notify();
         }
-        Ok(PollerPool { pollers, links })
     }
 }
 
@@ -172,22 +723,267 @@ impl PollerPool {
 struct SchedulerPool {
     schedulers: Vec<fiber::SchedulerHandle>,
     links: Vec<Link<(), ()>>,
+    stats: Vec<Arc<SchedulerStats>>,
+    state: Arc<PoolState>,
+    threads: Vec<thread::JoinHandle<()>>,
 }
 impl SchedulerPool {
-    pub fn new(poller_pool: &PollerPool) -> Self {
+    pub fn new(poller_pool: &PollerPool, builder: &ThreadPoolExecutorBuilder) -> io::Result<Self> {
         let mut schedulers = Vec::new();
         let mut links = Vec::new();
-        for poller in &poller_pool.pollers {
+        let mut stats = Vec::new();
+        let mut threads = Vec::new();
+        let pollers = poller_pool.handles();
+        let new_schedulers = pollers
+            .iter()
+            .cycle()
+            .take(builder.thread_count)
+            .map(|poller| fiber::Scheduler::new(poller.clone()))
+            .collect::<Vec<_>>();
+        let state = Arc::new(PoolState {
+            scheduler_ids: new_schedulers.iter().map(|s| s.scheduler_id()).collect(),
+            live_fibers: AtomicUsize::new(0),
+            shutting_down: AtomicBool::new(false),
+            exit_task: AtomicTask::new(),
+        });
+        for (i, mut scheduler) in new_schedulers.into_iter().enumerate() {
             let (link0, mut link1) = oneshot::link();
-            let mut scheduler = fiber::Scheduler::new(poller.clone());
+            scheduler.set_poll_budget(builder.poll_budget);
+            scheduler.set_instrument(builder.instrument.clone());
+            let scheduler_stats = Arc::new(SchedulerStats::default());
+            let scheduler_state = Arc::clone(&state);
+            let exit_notifier = ExitNotifier(Arc::clone(&state));
+            let cpu = builder
+                .cpu_affinity
+                .as_ref()
+                .map(|cpus| cpus[i % cpus.len()]);
             links.push(link0);
             schedulers.push(scheduler.handle());
-            thread::spawn(move || {
-                while let Ok(Async::NotReady) = link1.poll() {
-                    scheduler.run_once(true);
-                }
-            });
+            stats.push(Arc::clone(&scheduler_stats));
+            let thread = thread::Builder::new()
+                .name(format!("{}{}", builder.thread_name_prefix, i))
+                .spawn(move || {
+                    let _exit_notifier = exit_notifier;
+                    if let Some(cpu) = cpu {
+                        let _ = set_current_thread_affinity(cpu);
+                    }
+                    while let Ok(Async::NotReady) = link1.poll() {
+                        scheduler.run_once(true);
+                        scheduler_stats.update(&scheduler);
+
+                        // NOTE: Once the executor is quiesced, no fibers will be spawned via
+                        // the handles, and the remaining ones are internal (e.g., the alarm
+                        // of `Shutdown`), so they can be discarded
+                        if scheduler_state.is_quiesced() {
+                            link1.exit(Ok(()));
+                            break;
+                        }
+                    }
+                })?;
+            threads.push(thread);
+        }
+        Ok(SchedulerPool {
+            schedulers,
+            links,
+            stats,
+            state,
+            threads,
+        })
+    }
+
+    fn wake_up_schedulers(&self) {
+        for scheduler in &self.schedulers {
+            // Spawns an empty fiber to wake up the scheduler
+            // which may be blocking until any requests arrive.
+            scheduler.spawn(futures::finished(()));
+        }
+    }
+}
+
+/// Notifies the exit of a scheduler thread (including the one by panic) to `Shutdown`.
+struct ExitNotifier(Arc<PoolState>);
+impl Drop for ExitNotifier {
+    fn drop(&mut self) {
+        self.0.exit_task.notify();
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_current_thread_affinity(cpu: usize) -> io::Result<()> {
+    use libc;
+    use std::mem;
+    if cpu >= libc::CPU_SETSIZE as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("CPU index out of range: {}", cpu),
+        ));
+    }
+    unsafe {
+        let mut set: libc::cpu_set_t = mem::zeroed();
+        libc::CPU_SET(cpu, &mut set);
+        if libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_current_thread_affinity(_cpu: usize) -> io::Result<()> {
+    Ok(())
+}
+
+#[derive(Debug, Default)]
+struct SchedulerStats {
+    fibers: AtomicUsize,
+    run_queue_len: AtomicUsize,
+    spawned: AtomicUsize,
+    finished: AtomicUsize,
+    budget_overruns: AtomicUsize,
+}
+impl SchedulerStats {
+    fn update(&self, scheduler: &fiber::Scheduler) {
+        self.fibers
+            .store(scheduler.fiber_count(), Ordering::Relaxed);
+        self.run_queue_len
+            .store(scheduler.run_queue_len(), Ordering::Relaxed);
+        self.spawned
+            .store(scheduler.spawned_count(), Ordering::Relaxed);
+        self.finished
+            .store(scheduler.finished_count(), Ordering::Relaxed);
+        self.budget_overruns
+            .store(scheduler.budget_overrun_count(), Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::future;
+
+    use super::*;
+
+    #[test]
+    fn thread_names_work() {
+        let mut executor = ThreadPoolExecutor::builder()
+            .thread_count(2)
+            .cpu_affinity(vec![0])
+            .build()
+            .unwrap();
+        let monitors = (0..8)
+            .map(|_| {
+                executor.spawn_monitor(future::lazy(|| {
+                    Ok::<_, ()>(thread::current().name().map(|s| s.to_owned()))
+                }))
+            })
+            .collect::<Vec<_>>();
+        let names = executor
+            .run_future(future::join_all(monitors))
+            .unwrap()
+            .unwrap();
+        for name in names {
+            let name = name.unwrap();
+            assert!(
+                name == "fibers-worker-0" || name == "fibers-worker-1",
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn out_of_range_cpu_affinity_is_ignored() {
+        let mut executor = ThreadPoolExecutor::builder()
+            .thread_count(1)
+            .cpu_affinity(vec![usize::MAX])
+            .build()
+            .unwrap();
+        let monitor = executor.spawn_monitor(future::ok::<_, ()>(1));
+        assert_eq!(executor.run_fiber(monitor).unwrap(), Ok(1));
+    }
+
+    #[test]
+    fn shutdown_executes_fibers_spawned_by_running_ones() {
+        let executor = ThreadPoolExecutor::with_thread_count(2).unwrap();
+        let handle = executor.handle();
+        let child_finished = Arc::new(AtomicBool::new(false));
+        let outsider_finished = Arc::new(AtomicBool::new(false));
+
+        let (tx, rx) = oneshot::channel::<()>();
+        let child_finished_clone = Arc::clone(&child_finished);
+        executor.spawn(rx.map_err(|_| ()).map(move |()| {
+            let child = timer::timeout(time::Duration::from_millis(10))
+                .map(move |()| child_finished_clone.store(true, Ordering::SeqCst))
+                .map_err(|_| ());
+            handle.spawn(child);
+        }));
+
+        let outsider = executor.handle();
+        let shutdown = executor.shutdown();
+
+        // Spawned from the outside of the executor after the shutdown started
+        let outsider_finished_clone = Arc::clone(&outsider_finished);
+        outsider.spawn(future::lazy(move || {
+            outsider_finished_clone.store(true, Ordering::SeqCst);
+            Ok(())
+        }));
+
+        tx.send(()).unwrap();
+        shutdown.wait().unwrap();
+        assert!(child_finished.load(Ordering::SeqCst));
+        assert!(!outsider_finished.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn shutdown_timeout_works() {
+        let executor = ThreadPoolExecutor::with_thread_count(1).unwrap();
+        executor.spawn(future::empty());
+
+        let start = time::Instant::now();
+        let result = executor
+            .shutdown_timeout(time::Duration::from_millis(50))
+            .wait();
+        assert_eq!(
+            result.err().map(|e| e.kind()),
+            Some(io::ErrorKind::TimedOut)
+        );
+        assert!(start.elapsed() >= time::Duration::from_millis(50));
+    }
+
+    #[test]
+    fn shutdown_is_woken_up_by_thread_exits() {
+        struct Counter(AtomicUsize);
+        impl futures::executor::Notify for Counter {
+            fn notify(&self, _id: usize) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let executor = ThreadPoolExecutor::with_thread_count(1).unwrap();
+        let (tx, rx) = oneshot::channel::<()>();
+        executor.spawn(rx.map_err(|_| ()));
+
+        let counter = Arc::new(Counter(AtomicUsize::new(0)));
+        let mut shutdown = futures::executor::spawn(executor.shutdown());
+        assert!(shutdown
+            .poll_future_notify(&counter, 0)
+            .unwrap()
+            .is_not_ready());
+
+        // Not woken up until the fiber finishes
+        thread::sleep(time::Duration::from_millis(50));
+        assert_eq!(counter.0.load(Ordering::SeqCst), 0);
+
+        tx.send(()).unwrap();
+        let start = time::Instant::now();
+        loop {
+            while counter.0.load(Ordering::SeqCst) == 0 {
+                assert!(start.elapsed() < time::Duration::from_secs(5));
+                thread::sleep(time::Duration::from_millis(1));
+            }
+            counter.0.store(0, Ordering::SeqCst);
+            if shutdown.poll_future_notify(&counter, 0).unwrap().is_ready() {
+                break;
+            }
         }
-        SchedulerPool { schedulers, links }
     }
 }
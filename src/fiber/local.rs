@@ -0,0 +1,181 @@
+// Copyright (c) 2016 DWANGO Co., Ltd. All Rights Reserved.
+// See the LICENSE file at the top-level directory of this distribution.
+
+use std::fmt;
+
+use super::schedule;
+
+/// Declares a new fiber-local storage key of type `fibers::fiber::FiberLocal`.
+///
+/// The syntax is the same as `std::thread_local!` macro's one,
+/// except that only a single declaration is accepted at a time.
+///
+/// # Examples
+///
+/// ```
+/// #[macro_use]
+/// extern crate fibers;
+/// extern crate futures;
+///
+/// use fibers::{fiber, Executor, InPlaceExecutor, Spawn};
+/// use futures::Future;
+/// use std::cell::Cell;
+///
+/// local_key!(static REQUEST_ID: Cell<u64> = Cell::new(0));
+///
+/// # fn main() {
+/// let mut executor = InPlaceExecutor::new().unwrap();
+/// let future = futures::lazy(|| {
+///     REQUEST_ID.with(|id| id.set(42));
+///     fiber::yield_now()
+/// })
+/// .map(|()| REQUEST_ID.with(|id| id.get()));
+/// let monitor = executor.spawn_monitor(future);
+/// assert_eq!(executor.run_fiber(monitor).unwrap(), Ok(42));
+/// # }
+/// ```
+#[macro_export]
+macro_rules! local_key {
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty = $init:expr $(;)?) => {
+        $(#[$attr])*
+        $vis static $name: $crate::fiber::FiberLocal<$t> = {
+            fn __init() -> $t {
+                $init
+            }
+            $crate::fiber::FiberLocal::new(__init)
+        };
+    };
+}
+
+/// A key of a fiber-local storage.
+///
+/// Unlike thread-local storage, the value of a fiber-local storage is owned by
+/// the currently running fiber, rather than by the thread shared by all the fibers
+/// of a scheduler.
+/// The value is lazily initialized when the fiber accesses it for the first time,
+/// and dropped when the fiber finishes.
+///
+/// This is created by `local_key!` macro.
+pub struct FiberLocal<T: 'static> {
+    init: fn() -> T,
+}
+impl<T: Send + 'static> FiberLocal<T> {
+    #[doc(hidden)]
+    pub const fn new(init: fn() -> T) -> Self {
+        FiberLocal { init }
+    }
+
+    /// Calls `f` with a reference to the value of this key for the current fiber.
+    ///
+    /// # Panics
+    ///
+    /// If this method is called on the outside of a fiber, it will panic.
+    pub fn with<F, R>(&'static self, f: F) -> R
+    where
+        F: FnOnce(&T) -> R,
+    {
+        self.try_with(f)
+            .expect("Cannot access a fiber-local storage on the outside of a fiber")
+    }
+
+    /// Calls `f` with a reference to the value of this key for the current fiber.
+    ///
+    /// If this method is called on the outside of a fiber, it will ignore `f` and return `None`.
+    pub fn try_with<F, R>(&'static self, f: F) -> Option<R>
+    where
+        F: FnOnce(&T) -> R,
+    {
+        let fiber = schedule::current_fiber()?;
+        let key = self as *const Self as usize;
+
+        // NOTE: `init` is called without borrowing the fiber state,
+        // because it may access other fiber-local storages
+        if !unsafe { &*fiber }.locals.contains_key(&key) {
+            let value = (self.init)();
+            unsafe { &mut *fiber }
+                .locals
+                .entry(key)
+                .or_insert_with(|| Box::new(value));
+        }
+
+        // NOTE: The values are boxed and never removed until the fiber finishes,
+        // so the reference is valid while `f` is executed
+        let value = assert_some!(unsafe { &*fiber }.locals.get(&key));
+        let value = assert_some!(value.downcast_ref::<T>()) as *const T;
+        Some(f(unsafe { &*value }))
+    }
+}
+impl<T: 'static> fmt::Debug for FiberLocal<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "FiberLocal {{ .. }}")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::{self, Future};
+    use std::cell::{Cell, RefCell};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    use executor::{Executor, InPlaceExecutor, ThreadPoolExecutor};
+    use fiber::{self, Spawn};
+    use sync::oneshot;
+
+    local_key!(static VALUE: Cell<usize> = Cell::new(0));
+    local_key!(static GUARD: RefCell<Option<DropFlag>> = RefCell::new(None));
+
+    struct DropFlag(Arc<AtomicBool>);
+    impl Drop for DropFlag {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn fiber_local_works() {
+        let mut executor = ThreadPoolExecutor::with_thread_count(4).unwrap();
+        let monitors = (1..=20)
+            .map(|i| {
+                let future = futures::lazy(move || {
+                    assert_eq!(VALUE.with(|v| v.get()), 0);
+                    VALUE.with(|v| v.set(i));
+                    fiber::yield_now()
+                })
+                .and_then(|()| fiber::yield_now())
+                .map(|()| VALUE.with(|v| v.get()));
+                executor.spawn_monitor(future)
+            })
+            .collect::<Vec<_>>();
+        let values = executor
+            .run_future(futures::future::join_all(monitors))
+            .unwrap()
+            .unwrap();
+        assert_eq!(values, (1..=20).collect::<Vec<_>>());
+        assert_eq!(VALUE.try_with(|v| v.get()), None);
+    }
+
+    #[test]
+    fn fiber_local_is_dropped_when_fiber_finishes() {
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let dropped = Arc::new(AtomicBool::new(false));
+        let flag = DropFlag(Arc::clone(&dropped));
+        let (tx, rx) = oneshot::channel::<()>();
+        executor.spawn(
+            futures::lazy(move || {
+                GUARD.with(|g| *g.borrow_mut() = Some(flag));
+                rx
+            })
+            .map_err(|_| ()),
+        );
+        for _ in 0..10 {
+            executor.run_once().unwrap();
+        }
+        assert!(!dropped.load(Ordering::SeqCst));
+
+        tx.send(()).unwrap();
+        while !dropped.load(Ordering::SeqCst) {
+            executor.run_once().unwrap();
+        }
+    }
+}
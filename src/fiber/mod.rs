@@ -7,15 +7,22 @@
 //! So, usual users do not need to be conscious.
 use futures::future::Either;
 use futures::{self, Async, Future, IntoFuture, Poll};
+use std::any::Any;
+use std::collections::HashMap;
 use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::atomic::{self, AtomicUsize};
 use std::sync::Arc;
 
-pub use self::schedule::{with_current_context, yield_poll, Context};
-pub use self::schedule::{Scheduler, SchedulerHandle, SchedulerId};
+pub use self::local::FiberLocal;
+pub use self::schedule::{with_current_context, yield_now, yield_poll, Context, YieldNow};
+pub use self::schedule::{Instrument, Scheduler, SchedulerHandle, SchedulerId};
+pub use self::schedule::{StreamExt, WithBudget};
 
 use sync::oneshot::{self, Link, Monitor};
 
+#[macro_use]
+mod local;
 mod schedule;
 
 /// The identifier of a fiber.
@@ -26,10 +33,40 @@ pub type FiberId = usize;
 /// The identifier of an execution context.
 pub type ContextId = (SchedulerId, FiberId);
 
+/// The scheduling priority of a fiber.
+///
+/// A scheduler runs the runnable fibers with higher priority first.
+/// To avoid starvation, a fiber which has waited in the run queue for a long time
+/// is run ahead of the higher priority fibers.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Priority {
+    /// The priority for latency-critical fibers.
+    High,
+
+    /// The default priority.
+    #[default]
+    Normal,
+
+    /// The priority for background fibers.
+    Low,
+}
+
 /// The `Spawn` trait allows for spawning fibers.
 pub trait Spawn {
     /// Spawns a fiber which will execute given boxed future.
-    fn spawn_boxed(&self, fiber: Box<Future<Item = (), Error = ()> + Send>);
+    fn spawn_boxed(&self, fiber: Box<dyn Future<Item = (), Error = ()> + Send>);
+
+    /// Spawns a fiber which will execute given boxed future with the specified priority.
+    ///
+    /// The default implementation ignores `priority` and calls `spawn_boxed` method.
+    fn spawn_boxed_with_priority(
+        &self,
+        fiber: Box<dyn Future<Item = (), Error = ()> + Send>,
+        priority: Priority,
+    ) {
+        let _ = priority;
+        self.spawn_boxed(fiber);
+    }
 
     /// Spawns a fiber which will execute given future.
     fn spawn<F>(&self, fiber: F)
@@ -39,6 +76,32 @@ pub trait Spawn {
         self.spawn_boxed(Box::new(fiber));
     }
 
+    /// Spawns a fiber which will execute given future with the specified priority.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate fibers;
+    /// # extern crate futures;
+    /// use fibers::fiber::Priority;
+    /// use fibers::{Executor, InPlaceExecutor, Spawn};
+    /// use fibers::sync::oneshot;
+    ///
+    /// # fn main() {
+    /// let mut executor = InPlaceExecutor::new().unwrap();
+    /// let (tx, rx) = oneshot::channel();
+    /// let fiber = futures::lazy(move || tx.send(42).map_err(|_| ()));
+    /// executor.spawn_with_priority(fiber, Priority::High);
+    /// assert_eq!(executor.run_future(rx).unwrap(), Ok(42));
+    /// # }
+    /// ```
+    fn spawn_with_priority<F>(&self, fiber: F, priority: Priority)
+    where
+        F: Future<Item = (), Error = ()> + Send + 'static,
+    {
+        self.spawn_boxed_with_priority(Box::new(fiber), priority);
+    }
+
     /// Equivalent to `self.spawn(futures::lazy(|| f()))`.
     fn spawn_fn<F, T>(&self, f: F)
     where
@@ -50,6 +113,24 @@ pub trait Spawn {
     }
 
     /// Spawns a fiber and returns a future to monitor it's execution result.
+    ///
+    /// If the fiber is aborted before exiting (e.g., the executor is dropped),
+    /// the returning future will fail with `MonitorError::Aborted`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate fibers;
+    /// # extern crate futures;
+    /// use fibers::{Executor, InPlaceExecutor, Spawn};
+    /// use futures::{Future, lazy};
+    ///
+    /// # fn main() {
+    /// let mut executor = InPlaceExecutor::new().unwrap();
+    /// let monitor = executor.spawn_monitor(lazy(|| -> Result<_, ()> { Ok(42) }));
+    /// assert_eq!(executor.run_fiber(monitor).unwrap(), Ok(42));
+    /// # }
+    /// ```
     fn spawn_monitor<F, T, E>(&self, f: F) -> Monitor<T, E>
     where
         F: Future<Item = T, Error = E> + Send + 'static,
@@ -115,22 +196,118 @@ pub trait Spawn {
         link0
     }
 
+    /// Spawns a fiber which can be aborted via the returning `AbortHandle`.
+    ///
+    /// When `AbortHandle::abort` is called, the future of the fiber is dropped
+    /// (without being polled again) the next time the fiber is scheduled.
+    /// Dropping the handle without calling `abort` simply detaches the fiber.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate fibers;
+    /// # extern crate futures;
+    /// use fibers::sync::oneshot;
+    /// use fibers::{Executor, InPlaceExecutor, Spawn};
+    /// use futures::{Future, empty};
+    ///
+    /// # fn main() {
+    /// let mut executor = InPlaceExecutor::new().unwrap();
+    /// let (tx, rx) = oneshot::channel();
+    /// let fiber = empty().and_then(move |()| tx.send(()).map_err(|_| ()));
+    ///
+    /// // Spawns `fiber` and aborts it.
+    /// let handle = executor.spawn_abortable(fiber);
+    /// handle.abort();
+    ///
+    /// // Channel `rx` is disconnected (e.g., `fiber` was dropped).
+    /// assert!(executor.run_future(rx).unwrap().is_err());
+    /// # }
+    /// ```
+    fn spawn_abortable<F>(&self, fiber: F) -> AbortHandle
+    where
+        F: Future<Item = (), Error = ()> + Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        self.spawn(Abortable {
+            future: fiber,
+            abort_rx: Some(rx),
+        });
+        AbortHandle { abort_tx: tx }
+    }
+
+    /// Spawns a fiber which calls `handler` if the execution of the given future panics.
+    ///
+    /// The panic is caught by `std::panic::catch_unwind` and
+    /// the payload of it is passed to `handler`.
+    /// After that, the fiber exits without affecting the other fibers
+    /// running on the same scheduler.
+    ///
+    /// Note that the panic hook (e.g., the default one printing the panic message)
+    /// is still invoked before `handler` is called.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate fibers;
+    /// # extern crate futures;
+    /// use fibers::sync::oneshot;
+    /// use fibers::{Executor, InPlaceExecutor, Spawn};
+    /// use futures::lazy;
+    ///
+    /// # fn main() {
+    /// let mut executor = InPlaceExecutor::new().unwrap();
+    /// let (tx, rx) = oneshot::channel();
+    /// executor.spawn_with_panic_handler(
+    ///     lazy(|| -> Result<(), ()> { panic!("Oops!") }),
+    ///     move |payload| {
+    ///         let message = *payload.downcast::<&str>().unwrap();
+    ///         let _ = tx.send(message);
+    ///     },
+    /// );
+    /// assert_eq!(executor.run_future(rx).unwrap(), Ok("Oops!"));
+    ///
+    /// // Other fibers keep running
+    /// let monitor = executor.spawn_monitor(lazy(|| -> Result<_, ()> { Ok(42) }));
+    /// assert_eq!(executor.run_fiber(monitor).unwrap(), Ok(42));
+    /// # }
+    /// ```
+    fn spawn_with_panic_handler<F, H>(&self, fiber: F, handler: H)
+    where
+        F: Future<Item = (), Error = ()> + Send + 'static,
+        H: FnOnce(Box<dyn Any + Send>) + Send + 'static,
+    {
+        self.spawn(CatchUnwind {
+            future: fiber,
+            handler: Some(handler),
+        });
+    }
+
     /// Converts this instance into a boxed object.
     fn boxed(self) -> BoxSpawn
     where
         Self: Sized + Send + 'static,
     {
-        BoxSpawn(Box::new(move |fiber| self.spawn_boxed(fiber)))
+        BoxSpawn(Box::new(move |fiber, priority| {
+            self.spawn_boxed_with_priority(fiber, priority)
+        }))
     }
 }
 
-type BoxFn = Box<Fn(Box<Future<Item = (), Error = ()> + Send>) + Send + 'static>;
+type BoxFn = Box<dyn Fn(Box<dyn Future<Item = (), Error = ()> + Send>, Priority) + Send + 'static>;
 
 /// Boxed `Spawn` object.
 pub struct BoxSpawn(BoxFn);
 impl Spawn for BoxSpawn {
-    fn spawn_boxed(&self, fiber: Box<Future<Item = (), Error = ()> + Send>) {
-        (self.0)(fiber);
+    fn spawn_boxed(&self, fiber: Box<dyn Future<Item = (), Error = ()> + Send>) {
+        (self.0)(fiber, Priority::Normal);
+    }
+    fn spawn_boxed_with_priority(
+        &self,
+        fiber: Box<dyn Future<Item = (), Error = ()> + Send>,
+        priority: Priority,
+    ) {
+        (self.0)(fiber, priority);
     }
     fn boxed(self) -> BoxSpawn
     where
@@ -145,6 +322,22 @@ impl fmt::Debug for BoxSpawn {
     }
 }
 
+/// A handle to abort a fiber.
+///
+/// This is created by calling `Spawn::spawn_abortable` method.
+#[derive(Debug)]
+pub struct AbortHandle {
+    abort_tx: oneshot::Sender<()>,
+}
+impl AbortHandle {
+    /// Aborts the fiber associated with this handle.
+    ///
+    /// If the fiber has already exited, this has no effect.
+    pub fn abort(self) {
+        let _ = self.abort_tx.send(());
+    }
+}
+
 #[derive(Debug)]
 struct FiberState {
     pub fiber_id: FiberId,
@@ -152,6 +345,7 @@ struct FiberState {
     parks: usize,
     unparks: Arc<AtomicUsize>,
     pub in_run_queue: bool,
+    locals: HashMap<usize, Box<dyn Any + Send>>,
 }
 impl FiberState {
     pub fn new(fiber_id: FiberId, task: Task) -> Self {
@@ -161,6 +355,7 @@ impl FiberState {
             parks: 0,
             unparks: Arc::new(AtomicUsize::new(0)),
             in_run_queue: false,
+            locals: HashMap::new(),
         }
     }
     pub fn run_once(&mut self) -> bool {
@@ -168,11 +363,10 @@ impl FiberState {
             self.parks -= 1;
             self.unparks.fetch_sub(1, atomic::Ordering::SeqCst);
         }
-        if let Ok(Async::NotReady) = self.task.0.poll() {
-            false
-        } else {
-            true
-        }
+        !matches!(self.task.0.poll(), Ok(Async::NotReady))
+    }
+    pub fn priority(&self) -> Priority {
+        self.task.1
     }
     pub fn is_runnable(&self) -> bool {
         self.parks == 0 || self.unparks.load(atomic::Ordering::SeqCst) > 0
@@ -223,12 +417,12 @@ impl Drop for Unpark {
     }
 }
 
-pub(crate) type FiberFuture = Box<Future<Item = (), Error = ()> + Send>;
+pub(crate) type FiberFuture = Box<dyn Future<Item = (), Error = ()> + Send>;
 
-pub(crate) struct Task(pub FiberFuture);
+pub(crate) struct Task(pub FiberFuture, pub Priority);
 impl fmt::Debug for Task {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Task(_)")
+        write!(f, "Task(_, {:?})", self.1)
     }
 }
 
@@ -257,3 +451,213 @@ impl<A: Future, B: Future> Future for SelectEither<A, B> {
         Ok(Async::NotReady)
     }
 }
+
+struct Abortable<F> {
+    future: F,
+    abort_rx: Option<oneshot::Receiver<()>>,
+}
+impl<F> Future for Abortable<F>
+where
+    F: Future<Item = (), Error = ()>,
+{
+    type Item = ();
+    type Error = ();
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if let Some(mut abort_rx) = self.abort_rx.take() {
+            match abort_rx.poll() {
+                Ok(Async::Ready(())) => return Ok(Async::Ready(())),
+                Ok(Async::NotReady) => self.abort_rx = Some(abort_rx),
+                Err(_) => {
+                    // Detached by dropping `AbortHandle`
+                }
+            }
+        }
+        self.future.poll()
+    }
+}
+
+struct CatchUnwind<F, H> {
+    future: F,
+    handler: Option<H>,
+}
+impl<F, H> Future for CatchUnwind<F, H>
+where
+    F: Future<Item = (), Error = ()>,
+    H: FnOnce(Box<dyn Any + Send>),
+{
+    type Item = ();
+    type Error = ();
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let future = &mut self.future;
+        match panic::catch_unwind(AssertUnwindSafe(|| future.poll())) {
+            Ok(result) => result,
+            Err(payload) => {
+                let handler = self.handler.take().expect("Cannot poll CatchUnwind twice");
+                handler(payload);
+                Ok(Async::Ready(()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::{self, Future, Stream};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use super::*;
+    use executor::{Executor, InPlaceExecutor};
+    use sync::{mpsc, oneshot};
+
+    struct DropFlag(Arc<AtomicBool>);
+    impl Drop for DropFlag {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn abort_works() {
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let (tx, rx) = mpsc::channel::<()>();
+        let dropped = Arc::new(AtomicBool::new(false));
+        let flag = DropFlag(Arc::clone(&dropped));
+        let handle = executor.spawn_abortable(rx.for_each(move |()| {
+            let _ = &flag;
+            Ok(())
+        }));
+        for _ in 0..10 {
+            executor.run_once().unwrap();
+            tx.send(()).unwrap();
+        }
+        assert!(!dropped.load(Ordering::SeqCst));
+
+        handle.abort();
+        while !dropped.load(Ordering::SeqCst) {
+            executor.run_once().unwrap();
+        }
+        assert!(tx.send(()).is_err());
+    }
+
+    #[test]
+    fn dropping_abort_handle_detaches_fiber() {
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let (tx, rx) = oneshot::channel();
+        let handle = executor.spawn_abortable(rx.map_err(|_| ()));
+        std::mem::drop(handle);
+        executor.run_once().unwrap();
+
+        let monitor = executor.spawn_monitor(futures::lazy(move || tx.send(())));
+        assert_eq!(executor.run_fiber(monitor).unwrap(), Ok(()));
+    }
+
+    #[test]
+    fn priority_works() {
+        fn busy_loop(
+            iterations: usize,
+            progress: Arc<AtomicUsize>,
+        ) -> impl Future<Item = (), Error = ()> {
+            futures::future::loop_fn(0, move |i| {
+                progress.fetch_add(1, Ordering::SeqCst);
+                yield_now().map(move |()| {
+                    if i < iterations {
+                        futures::future::Loop::Continue(i + 1)
+                    } else {
+                        futures::future::Loop::Break(())
+                    }
+                })
+            })
+        }
+
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let low_progress = Arc::new(AtomicUsize::new(0));
+        for _ in 0..10 {
+            let fiber = busy_loop(10_000, Arc::clone(&low_progress));
+            executor.spawn_with_priority(fiber, Priority::Low);
+        }
+        while low_progress.load(Ordering::SeqCst) < 100 {
+            executor.run_once().unwrap();
+        }
+
+        // The high priority fiber is hardly delayed by the busy low priority fibers
+        let high_progress = Arc::new(AtomicUsize::new(0));
+        let (monitored, monitor) = oneshot::monitor();
+        let start = low_progress.load(Ordering::SeqCst);
+        let fiber = busy_loop(200, high_progress).then(move |r| {
+            monitored.exit(r);
+            Ok(())
+        });
+        executor.spawn_with_priority(fiber, Priority::High);
+        executor.run_fiber(monitor).unwrap().unwrap();
+        let low_progress_during_high = low_progress.load(Ordering::SeqCst) - start;
+
+        // Yet the low priority fibers still make progress
+        assert!(low_progress_during_high > 0);
+        assert!(
+            low_progress_during_high < 200,
+            "{}",
+            low_progress_during_high
+        );
+    }
+
+    #[test]
+    fn instrument_works() {
+        #[derive(Debug, Default)]
+        struct Collector(Mutex<Vec<(&'static str, FiberId)>>);
+        impl Instrument for Collector {
+            fn on_poll_begin(&self, (_, fiber_id): ContextId) {
+                self.0.lock().unwrap().push(("begin", fiber_id));
+            }
+            fn on_poll_end(&self, (_, fiber_id): ContextId, _elapsed: Duration) {
+                self.0.lock().unwrap().push(("end", fiber_id));
+            }
+            fn on_complete(&self, (_, fiber_id): ContextId) {
+                self.0.lock().unwrap().push(("complete", fiber_id));
+            }
+        }
+
+        let collector = Arc::new(Collector::default());
+        let mut executor = InPlaceExecutor::builder()
+            .instrument(collector.clone())
+            .build()
+            .unwrap();
+        let future = yield_now().and_then(|()| yield_now());
+        let monitor = executor.spawn_monitor(future);
+        executor.run_fiber(monitor).unwrap().unwrap();
+
+        let events = collector.0.lock().unwrap();
+        let fiber_id = events[0].1;
+        let expected = ["begin", "end", "begin", "end", "begin", "end", "complete"];
+        assert_eq!(
+            *events,
+            expected.iter().map(|&e| (e, fiber_id)).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn stream_budget_works() {
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let consumed = Arc::new(AtomicUsize::new(0));
+        let consumed_clone = Arc::clone(&consumed);
+        executor.spawn(
+            futures::stream::repeat(1)
+                .take(1_000_000)
+                .with_budget(10)
+                .for_each(move |n| {
+                    consumed_clone.fetch_add(n, Ordering::SeqCst);
+                    Ok(())
+                }),
+        );
+
+        // The other fiber gets a turn while the always-ready stream is consumed
+        let consumed_clone = Arc::clone(&consumed);
+        let monitor = executor.spawn_monitor(futures::lazy(move || {
+            Ok::<_, ()>(consumed_clone.load(Ordering::SeqCst))
+        }));
+        let consumed_before = executor.run_fiber(monitor).unwrap().unwrap();
+        assert!(consumed_before < 1_000_000);
+        assert_eq!(consumed_before % 10, 0);
+    }
+}
@@ -1,17 +1,24 @@
 // Copyright (c) 2016 DWANGO Co., Ltd. All Rights Reserved.
 // See the LICENSE file at the top-level directory of this distribution.
 
-use futures::{Async, Future, Poll};
+use futures::{Async, Future, Poll, Stream};
 use std::cell::RefCell;
 use std::collections::{HashMap, VecDeque};
+use std::fmt;
 use std::sync::atomic;
 use std::sync::mpsc as std_mpsc;
+use std::sync::Arc;
+use std::time;
 
-use super::{FiberState, Spawn};
+use super::{ContextId, FiberState, Priority, Spawn};
 use fiber::{self, Task};
 use io::poll;
 
-static mut NEXT_SCHEDULER_ID: atomic::AtomicUsize = atomic::ATOMIC_USIZE_INIT;
+static NEXT_SCHEDULER_ID: atomic::AtomicUsize = atomic::AtomicUsize::new(0);
+
+/// The number of dispatches after which a fiber waiting in the run queue
+/// is run ahead of the higher priority fibers.
+const STARVATION_THRESHOLD: u64 = 64;
 
 thread_local! {
     static CURRENT_CONTEXT: RefCell<InnerContext> = {
@@ -25,6 +32,32 @@ type RequestReceiver = std_mpsc::Receiver<Request>;
 /// The identifier of a scheduler.
 pub type SchedulerId = usize;
 
+/// A collector of the events about the execution of fibers.
+///
+/// The methods are invoked by the scheduler thread around each poll of a fiber,
+/// so they should return quickly.
+/// Each of them does nothing by default.
+///
+/// See `Scheduler::set_instrument` method for how to install a collector.
+pub trait Instrument: fmt::Debug + Send + Sync {
+    /// Called right before the fiber identified by `context_id` is polled.
+    fn on_poll_begin(&self, context_id: ContextId) {
+        let _ = context_id;
+    }
+
+    /// Called right after the fiber identified by `context_id` is polled.
+    ///
+    /// `elapsed` is the time taken by the poll.
+    fn on_poll_end(&self, context_id: ContextId, elapsed: time::Duration) {
+        let _ = (context_id, elapsed);
+    }
+
+    /// Called when the fiber identified by `context_id` completes.
+    fn on_complete(&self, context_id: ContextId) {
+        let _ = context_id;
+    }
+}
+
 /// Scheduler of spawned fibers.
 ///
 /// Scheduler manages spawned fibers state.
@@ -32,32 +65,48 @@ pub type SchedulerId = usize;
 /// the scheduler will push the fiber in it's run queue.
 /// When `run_once` method is called, the first fiber (i.e., future) in the queue
 /// will be poped and executed (i.e., `Future::poll` method is called).
+///
+/// The run queue consists of a queue per `Priority`, and the queue of higher priority
+/// is drained first. However, a fiber which has waited for more than a certain number of
+/// dispatches is popped ahead of the higher priority ones (i.e., aging),
+/// so the low priority fibers still make progress under load.
 /// If the future of a fiber moves to readied state,
 /// it will be removed from the scheduler.
-
 /// For efficiency reasons, it is recommended to run a scheduler on a dedicated thread.
 #[derive(Debug)]
 pub struct Scheduler {
     scheduler_id: SchedulerId,
     next_fiber_id: fiber::FiberId,
     fibers: HashMap<fiber::FiberId, fiber::FiberState>,
-    run_queue: VecDeque<fiber::FiberId>,
+    run_queues: [VecDeque<(fiber::FiberId, u64)>; 3],
+    dispatches: u64,
     request_tx: RequestSender,
     request_rx: RequestReceiver,
     poller: poll::PollerHandle,
+    spawned_count: usize,
+    finished_count: usize,
+    poll_budget: Option<time::Duration>,
+    budget_overrun_count: usize,
+    instrument: Option<Arc<dyn Instrument>>,
 }
 impl Scheduler {
     /// Creates a new scheduler instance.
     pub fn new(poller: poll::PollerHandle) -> Self {
         let (request_tx, request_rx) = std_mpsc::channel();
         Scheduler {
-            scheduler_id: unsafe { NEXT_SCHEDULER_ID.fetch_add(1, atomic::Ordering::SeqCst) },
+            scheduler_id: NEXT_SCHEDULER_ID.fetch_add(1, atomic::Ordering::SeqCst),
             next_fiber_id: 0,
             fibers: HashMap::new(),
-            run_queue: VecDeque::new(),
+            run_queues: [VecDeque::new(), VecDeque::new(), VecDeque::new()],
+            dispatches: 0,
             request_tx,
             request_rx,
             poller,
+            spawned_count: 0,
+            finished_count: 0,
+            poll_budget: None,
+            budget_overrun_count: 0,
+            instrument: None,
         }
     }
 
@@ -68,7 +117,7 @@ impl Scheduler {
 
     /// Returns the length of the run queue of this scheduler.
     pub fn run_queue_len(&self) -> usize {
-        self.run_queue.len()
+        self.run_queues.iter().map(|q| q.len()).sum()
     }
 
     /// Returns the count of alive fibers (i.e., not readied futures) in this scheduler.
@@ -76,6 +125,46 @@ impl Scheduler {
         self.fibers.len()
     }
 
+    /// Returns the total count of the fibers spawned in this scheduler.
+    pub fn spawned_count(&self) -> usize {
+        self.spawned_count
+    }
+
+    /// Returns the total count of the fibers finished (i.e., readied futures) in this scheduler.
+    pub fn finished_count(&self) -> usize {
+        self.finished_count
+    }
+
+    /// Returns the time budget of a poll of a fiber in this scheduler.
+    pub fn poll_budget(&self) -> Option<time::Duration> {
+        self.poll_budget
+    }
+
+    /// Sets the time budget of a poll of a fiber in this scheduler.
+    ///
+    /// Fibers cannot be preempted, so a poll exceeding the budget is not interrupted.
+    /// Instead, the scheduler counts such polls (see `budget_overrun_count` method),
+    /// which is useful to find the fibers monopolizing the thread.
+    /// A fiber which is still runnable after the poll is put at the back of the run queue
+    /// (as with the other fibers), so the rest of the queue gets a turn before it.
+    ///
+    /// The default value is `None` (i.e., the polls are not measured).
+    pub fn set_poll_budget(&mut self, budget: Option<time::Duration>) {
+        self.poll_budget = budget;
+    }
+
+    /// Returns the total count of the fiber polls which exceeded the time budget of this scheduler.
+    pub fn budget_overrun_count(&self) -> usize {
+        self.budget_overrun_count
+    }
+
+    /// Sets the collector of the events about the execution of the fibers in this scheduler.
+    ///
+    /// The default value is `None` (i.e., no events are collected and the polls are not measured).
+    pub fn set_instrument(&mut self, instrument: Option<Arc<dyn Instrument>>) {
+        self.instrument = instrument;
+    }
+
     /// Returns a handle of this scheduler.
     pub fn handle(&self) -> SchedulerHandle {
         SchedulerHandle {
@@ -125,6 +214,7 @@ impl Scheduler {
     }
     fn spawn_fiber(&mut self, task: Task) {
         let fiber_id = self.next_fiber_id();
+        self.spawned_count = self.spawned_count.wrapping_add(1);
         self.fibers
             .insert(fiber_id, fiber::FiberState::new(fiber_id, task));
         self.schedule(fiber_id);
@@ -137,7 +227,7 @@ impl Scheduler {
                 if context
                     .scheduler
                     .as_ref()
-                    .map_or(true, |s| s.id != self.scheduler_id)
+                    .is_none_or(|s| s.id != self.scheduler_id)
                 {
                     context.switch(self);
                 }
@@ -154,14 +244,34 @@ impl Scheduler {
                 context.fiber = Some(fiber as _);
             });
             let fiber = assert_some!(self.fibers.get_mut(&fiber_id));
-            finished = fiber.run_once();
+            if self.poll_budget.is_some() || self.instrument.is_some() {
+                let context_id = (self.scheduler_id, fiber_id);
+                if let Some(ref instrument) = self.instrument {
+                    instrument.on_poll_begin(context_id);
+                }
+                let start = time::Instant::now();
+                finished = fiber.run_once();
+                let elapsed = start.elapsed();
+                if self.poll_budget.is_some_and(|budget| elapsed > budget) {
+                    self.budget_overrun_count = self.budget_overrun_count.wrapping_add(1);
+                }
+                if let Some(ref instrument) = self.instrument {
+                    instrument.on_poll_end(context_id, elapsed);
+                }
+            } else {
+                finished = fiber.run_once();
+            }
             CURRENT_CONTEXT.with(|context| {
                 context.borrow_mut().fiber = None;
             });
             fiber.is_runnable()
         };
         if finished {
+            self.finished_count = self.finished_count.wrapping_add(1);
             self.fibers.remove(&fiber_id);
+            if let Some(ref instrument) = self.instrument {
+                instrument.on_complete((self.scheduler_id, fiber_id));
+            }
         } else if is_runnable {
             self.schedule(fiber_id);
         }
@@ -178,18 +288,30 @@ impl Scheduler {
     fn schedule(&mut self, fiber_id: fiber::FiberId) {
         let fiber = assert_some!(self.fibers.get_mut(&fiber_id));
         if !fiber.in_run_queue {
-            self.run_queue.push_back(fiber_id);
+            let queue = &mut self.run_queues[priority_level(fiber.priority())];
+            queue.push_back((fiber_id, self.dispatches));
             fiber.in_run_queue = true;
         }
     }
     fn next_runnable(&mut self) -> Option<fiber::FiberId> {
-        while let Some(fiber_id) = self.run_queue.pop_front() {
+        loop {
+            let dispatches = self.dispatches;
+            let starving = self
+                .run_queues
+                .iter()
+                .enumerate()
+                .filter_map(|(level, q)| q.front().map(|&(_, enqueued)| (level, enqueued)))
+                .filter(|&(_, enqueued)| dispatches - enqueued >= STARVATION_THRESHOLD)
+                .min_by_key(|&(_, enqueued)| enqueued)
+                .map(|(level, _)| level);
+            let level = starving.or_else(|| self.run_queues.iter().position(|q| !q.is_empty()))?;
+            let (fiber_id, _) = assert_some!(self.run_queues[level].pop_front());
             if let Some(fiber) = self.fibers.get_mut(&fiber_id) {
                 fiber.in_run_queue = false;
+                self.dispatches += 1;
                 return Some(fiber_id);
             }
         }
-        None
     }
 }
 
@@ -207,8 +329,23 @@ impl SchedulerHandle {
     }
 }
 impl Spawn for SchedulerHandle {
-    fn spawn_boxed(&self, fiber: Box<Future<Item = (), Error = ()> + Send>) {
-        let _ = self.request_tx.send(Request::Spawn(Task(fiber)));
+    fn spawn_boxed(&self, fiber: Box<dyn Future<Item = (), Error = ()> + Send>) {
+        self.spawn_boxed_with_priority(fiber, Priority::Normal)
+    }
+    fn spawn_boxed_with_priority(
+        &self,
+        fiber: Box<dyn Future<Item = (), Error = ()> + Send>,
+        priority: Priority,
+    ) {
+        let _ = self.request_tx.send(Request::Spawn(Task(fiber, priority)));
+    }
+}
+
+fn priority_level(priority: Priority) -> usize {
+    match priority {
+        Priority::High => 0,
+        Priority::Normal => 1,
+        Priority::Low => 2,
     }
 }
 
@@ -303,6 +440,144 @@ pub fn yield_poll<T, E>() -> Poll<T, E> {
     Ok(Async::NotReady)
 }
 
+/// Makes a future which cooperatively gives up the execution of the current fiber once.
+///
+/// The future returns `Async::NotReady` at the first poll (after arranging the fiber to be
+/// rescheduled immediately), and `Async::Ready(())` at the subsequent polls.
+/// This is a convenient way to use `yield_poll` function within combinators.
+///
+/// It must be polled in a fiber context.
+/// Otherwise, no one will reschedule the current task.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate fibers;
+/// # extern crate futures;
+/// use fibers::{fiber, Executor, InPlaceExecutor, Spawn};
+/// use futures::{future, Future};
+///
+/// # fn main() {
+/// let mut executor = InPlaceExecutor::new().unwrap();
+/// let future = future::loop_fn(0, |i| {
+///     // Yields the execution to other fibers at each iteration.
+///     fiber::yield_now().map(move |()| {
+///         if i < 10 {
+///             future::Loop::Continue(i + 1)
+///         } else {
+///             future::Loop::Break(i)
+///         }
+///     })
+/// });
+/// let monitor = executor.spawn_monitor(future);
+/// assert_eq!(executor.run_fiber(monitor).unwrap(), Ok(10));
+/// # }
+/// ```
+pub fn yield_now() -> YieldNow {
+    YieldNow { yielded: false }
+}
+
+/// A future which cooperatively gives up the execution of the current fiber once.
+///
+/// This is created by calling `yield_now` function.
+#[derive(Debug)]
+pub struct YieldNow {
+    yielded: bool,
+}
+impl Future for YieldNow {
+    type Item = ();
+    type Error = ();
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if self.yielded {
+            Ok(Async::Ready(()))
+        } else {
+            self.yielded = true;
+            yield_poll()
+        }
+    }
+}
+
+/// A cooperation related extension of the `Stream` trait.
+pub trait StreamExt: Sized + Stream {
+    /// Makes a stream which gives up the execution of the current fiber
+    /// after every `budget` items which were ready in a row.
+    ///
+    /// Consuming an always-ready stream (e.g., by `Stream::for_each`) never returns
+    /// `Async::NotReady`, so it can monopolize the scheduler thread within a single poll.
+    /// The returning stream yields (as with `yield_poll` function) when the budget is exhausted,
+    /// so that the other fibers get a turn.
+    /// The budget is refilled when the stream is polled again or the inner stream is not ready.
+    ///
+    /// As with `yield_now` function, the stream must be polled in a fiber context.
+    ///
+    /// # Panics
+    ///
+    /// If `budget` is zero, this method will panic.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate fibers;
+    /// # extern crate futures;
+    /// use fibers::fiber::StreamExt;
+    /// use fibers::{Executor, InPlaceExecutor, Spawn};
+    /// use futures::{stream, Stream};
+    ///
+    /// # fn main() {
+    /// let mut executor = InPlaceExecutor::new().unwrap();
+    /// let stream = stream::repeat::<_, ()>(1).take(100).with_budget(10);
+    /// let future = stream.fold(0, |a, b| Ok(a + b));
+    /// let monitor = executor.spawn_monitor(future);
+    /// assert_eq!(executor.run_fiber(monitor).unwrap(), Ok(100));
+    /// # }
+    /// ```
+    fn with_budget(self, budget: usize) -> WithBudget<Self> {
+        assert_ne!(budget, 0);
+        WithBudget {
+            stream: self,
+            budget,
+            remaining: budget,
+        }
+    }
+}
+impl<S: Stream> StreamExt for S {}
+
+/// A stream which gives up the execution of the current fiber after a certain number of items.
+///
+/// This is created by calling `StreamExt::with_budget` method.
+#[derive(Debug)]
+pub struct WithBudget<S> {
+    stream: S,
+    budget: usize,
+    remaining: usize,
+}
+impl<S: Stream> Stream for WithBudget<S> {
+    type Item = S::Item;
+    type Error = S::Error;
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if self.remaining == 0 {
+            self.remaining = self.budget;
+            return yield_poll();
+        }
+        let polled = self.stream.poll();
+        if let Ok(Async::Ready(Some(_))) = polled {
+            self.remaining -= 1;
+        } else {
+            self.remaining = self.budget;
+        }
+        polled
+    }
+}
+
+/// Returns the state of the currently running fiber.
+///
+/// Unlike `with_current_context`, the returned pointer does not borrow the context,
+/// so it is possible to call `with_current_context` while using it.
+/// The pointer is valid until the current poll of the fiber finishes.
+pub(crate) fn current_fiber() -> Option<*mut FiberState> {
+    CURRENT_CONTEXT.with(|inner_context| inner_context.borrow().fiber)
+}
+
 // TODO: rename
 #[derive(Debug)]
 struct InnerContext {
@@ -323,7 +598,7 @@ impl InnerContext {
             poller: scheduler.poller.clone(),
         })
     }
-    pub fn as_context(&mut self) -> Option<Context> {
+    pub fn as_context(&mut self) -> Option<Context<'_>> {
         if let Some(scheduler) = self.scheduler.as_mut() {
             if let Some(fiber) = self.fiber {
                 let fiber = unsafe { &mut *fiber };
@@ -0,0 +1,229 @@
+// Copyright (c) 2016 DWANGO Co., Ltd. All Rights Reserved.
+// See the LICENSE file at the top-level directory of this distribution.
+
+//! Filesystem manipulation operations.
+//!
+//! # Implementation Details
+//!
+//! The pollers (e.g., epoll) cannot watch the readiness of regular files,
+//! so the operations in this module are offloaded to the process-wide blocking pool
+//! (the one used by the default implementation of `Executor::spawn_blocking`).
+//! Thus the worker threads of executors are never blocked by them.
+use futures::{Async, Future, Poll};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use executor::{shared_blocking_pool, SpawnBlocking};
+
+/// A reference to an open file on the filesystem.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate fibers;
+/// # extern crate futures;
+/// use fibers::{Executor, InPlaceExecutor, Spawn};
+/// use fibers::fs::File;
+/// use futures::Future;
+///
+/// # fn main() {
+/// let path = std::env::temp_dir().join(format!("fibers_fs_doc_{}", std::process::id()));
+/// let mut executor = InPlaceExecutor::new().unwrap();
+/// let future = File::create(&path)
+///     .and_then(|file| file.write(b"hello"))
+///     .and_then(|(file, _, _)| file.metadata())
+///     .map(|metadata| metadata.len());
+/// let monitor = executor.spawn_monitor(future);
+/// assert_eq!(executor.run_fiber(monitor).unwrap().unwrap(), 5);
+/// # std::fs::remove_file(path).unwrap();
+/// # }
+/// ```
+pub struct File {
+    inner: Arc<fs::File>,
+}
+impl File {
+    /// Makes a future to open a file in read-only mode.
+    ///
+    /// See `std::fs::File::open` for more details.
+    pub fn open<P: AsRef<Path>>(path: P) -> Open {
+        let path = path.as_ref().to_path_buf();
+        Open::new(path, |path| fs::File::open(path))
+    }
+
+    /// Makes a future to open a file in write-only mode.
+    ///
+    /// The file is created if it does not exist, and truncated if it does.
+    /// See `std::fs::File::create` for more details.
+    pub fn create<P: AsRef<Path>>(path: P) -> Open {
+        let path = path.as_ref().to_path_buf();
+        Open::new(path, |path| fs::File::create(path))
+    }
+
+    /// Makes a future to read some bytes from the file into `buf`.
+    ///
+    /// The future will complete with the file, the buffer and the number of the bytes read.
+    /// `0` means that the file has reached EOF.
+    pub fn read<B>(self, mut buf: B) -> Read<B>
+    where
+        B: AsMut<[u8]> + Send + 'static,
+    {
+        let file = self.inner;
+        Read(blocking(move || {
+            let size = io::Read::read(&mut &*file, buf.as_mut())?;
+            Ok((File { inner: file }, buf, size))
+        }))
+    }
+
+    /// Makes a future to write some bytes in `buf` into the file.
+    ///
+    /// The future will complete with the file, the buffer and the number of the bytes written.
+    pub fn write<B>(self, buf: B) -> Write<B>
+    where
+        B: AsRef<[u8]> + Send + 'static,
+    {
+        let file = self.inner;
+        Write(blocking(move || {
+            let size = io::Write::write(&mut &*file, buf.as_ref())?;
+            Ok((File { inner: file }, buf, size))
+        }))
+    }
+
+    /// Makes a future to query the metadata of the file.
+    pub fn metadata(&self) -> GetMetadata {
+        let file = Arc::clone(&self.inner);
+        GetMetadata(blocking(move || file.metadata()))
+    }
+}
+impl fmt::Debug for File {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "File {{ inner: {:?} }}", self.inner)
+    }
+}
+
+/// A future which will open a file.
+///
+/// This is created by calling `File::open` or `File::create` function.
+/// It is permitted to move the future across fibers.
+#[derive(Debug)]
+pub struct Open(SpawnBlocking<io::Result<File>>);
+impl Open {
+    fn new<F>(path: PathBuf, f: F) -> Self
+    where
+        F: FnOnce(&Path) -> io::Result<fs::File> + Send + 'static,
+    {
+        Open(blocking(move || {
+            f(&path).map(|file| File {
+                inner: Arc::new(file),
+            })
+        }))
+    }
+}
+impl Future for Open {
+    type Item = File;
+    type Error = io::Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        poll_blocking(&mut self.0)
+    }
+}
+
+/// A future which will read some bytes from a file into a buffer `B`.
+///
+/// This is created by calling `File::read` method.
+/// It is permitted to move the future across fibers.
+#[derive(Debug)]
+pub struct Read<B>(SpawnBlocking<io::Result<(File, B, usize)>>);
+impl<B> Future for Read<B> {
+    type Item = (File, B, usize);
+    type Error = io::Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        poll_blocking(&mut self.0)
+    }
+}
+
+/// A future which will write some bytes in a buffer `B` into a file.
+///
+/// This is created by calling `File::write` method.
+/// It is permitted to move the future across fibers.
+#[derive(Debug)]
+pub struct Write<B>(SpawnBlocking<io::Result<(File, B, usize)>>);
+impl<B> Future for Write<B> {
+    type Item = (File, B, usize);
+    type Error = io::Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        poll_blocking(&mut self.0)
+    }
+}
+
+/// A future which will query the metadata of a file.
+///
+/// This is created by calling `File::metadata` method.
+/// It is permitted to move the future across fibers.
+#[derive(Debug)]
+pub struct GetMetadata(SpawnBlocking<io::Result<fs::Metadata>>);
+impl Future for GetMetadata {
+    type Item = fs::Metadata;
+    type Error = io::Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        poll_blocking(&mut self.0)
+    }
+}
+
+fn blocking<F, T>(f: F) -> SpawnBlocking<io::Result<T>>
+where
+    F: FnOnce() -> io::Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    shared_blocking_pool().spawn(f)
+}
+
+fn poll_blocking<T>(future: &mut SpawnBlocking<io::Result<T>>) -> Poll<T, io::Error> {
+    match future.poll() {
+        Err(_) => Err(io::Error::other("File operation panicked")),
+        Ok(Async::NotReady) => Ok(Async::NotReady),
+        Ok(Async::Ready(result)) => result.map(Async::Ready),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::process;
+
+    use super::*;
+    use executor::{Executor, ThreadPoolExecutor};
+    use fiber::Spawn;
+
+    #[test]
+    fn round_trip_works() {
+        let path = env::temp_dir().join(format!("fibers_fs_test_{}", process::id()));
+        let mut executor = ThreadPoolExecutor::with_thread_count(2).unwrap();
+
+        let read_path = path.clone();
+        let future = File::create(&path)
+            .and_then(|file| file.write(b"hello world".to_vec()))
+            .and_then(move |(_, _, written)| {
+                File::open(read_path).and_then(move |file| {
+                    file.read(vec![0; 32])
+                        .map(move |(file, buf, read)| (file, buf, read, written))
+                })
+            })
+            .and_then(|(file, mut buf, read, written)| {
+                buf.truncate(read);
+                file.metadata()
+                    .map(move |metadata| (buf, written, metadata))
+            });
+        let monitor = executor.spawn_monitor(future);
+        let (buf, written, metadata) = executor.run_fiber(monitor).unwrap().unwrap();
+        assert_eq!(written, 11);
+        assert_eq!(buf, b"hello world");
+        assert_eq!(metadata.len(), 11);
+        assert!(metadata.is_file());
+
+        let monitor = executor.spawn_monitor(File::open(path.join("nonexistent")));
+        assert!(executor.run_fiber(monitor).unwrap().is_err());
+        fs::remove_file(path).unwrap();
+    }
+}
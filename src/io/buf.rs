@@ -0,0 +1,515 @@
+// Copyright (c) 2016 DWANGO Co., Ltd. All Rights Reserved.
+// See the LICENSE file at the top-level directory of this distribution.
+
+use futures::{Async, Future, Poll};
+use std::fmt;
+use std::io::{self, BufRead, Read, Write};
+
+const DEFAULT_BUFFER_SIZE: usize = 8 * 1024;
+
+/// A buffered reader which can be used with non-blocking streams.
+///
+/// Like `std::io::BufReader`, this adds an internal buffer to a reader.
+/// In addition, it provides the futures to read delimited data
+/// (i.e., `read_until` and `read_line`).
+///
+/// If the inner reader returns `io::ErrorKind::WouldBlock`, the error is passed to the caller
+/// and the data buffered so far is preserved.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate fibers;
+/// # extern crate futures;
+/// use fibers::io::BufReader;
+/// use futures::Future;
+///
+/// # fn main() {
+/// let reader = BufReader::new(&b"foo\nbar"[..]);
+/// let (reader, line) = reader.read_line().wait().unwrap();
+/// assert_eq!(line, "foo\n");
+/// let (_, line) = reader.read_line().wait().unwrap();
+/// assert_eq!(line, "bar");
+/// # }
+/// ```
+pub struct BufReader<R> {
+    inner: R,
+    buf: Box<[u8]>,
+    pos: usize,
+    cap: usize,
+}
+impl<R: Read> BufReader<R> {
+    /// Makes a new `BufReader` with the default buffer capacity (8 KiB).
+    pub fn new(inner: R) -> Self {
+        Self::with_capacity(DEFAULT_BUFFER_SIZE, inner)
+    }
+
+    /// Makes a new `BufReader` with the specified buffer capacity.
+    ///
+    /// # Panics
+    ///
+    /// If `capacity` is `0`, this function will panic.
+    pub fn with_capacity(capacity: usize, inner: R) -> Self {
+        assert!(capacity > 0);
+        BufReader {
+            inner,
+            buf: vec![0; capacity].into_boxed_slice(),
+            pos: 0,
+            cap: 0,
+        }
+    }
+
+    /// Makes a future which reads bytes until the delimiter `byte` or EOF is reached.
+    ///
+    /// The future will complete with the bytes read, including the delimiter (if found).
+    /// If the reader has already reached EOF, the bytes will be empty.
+    pub fn read_until(self, byte: u8) -> ReadUntil<R> {
+        ReadUntil(Some(ReadUntilInner {
+            reader: self,
+            delimiter: byte,
+            bytes: Vec::new(),
+        }))
+    }
+
+    /// Makes a future which reads a line terminated by `'\n'` (or EOF).
+    ///
+    /// The future will complete with the line, including the terminator (if found).
+    /// If the line is not valid UTF-8,
+    /// the future will fail with an error of the kind `io::ErrorKind::InvalidData`.
+    pub fn read_line(self) -> ReadLine<R> {
+        ReadLine(self.read_until(b'\n'))
+    }
+}
+impl<R> BufReader<R> {
+    /// Returns a reference to the inner reader.
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner reader.
+    ///
+    /// Reading directly from the inner reader may cause data loss.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Returns the data currently buffered in this reader.
+    pub fn buffer(&self) -> &[u8] {
+        &self.buf[self.pos..self.cap]
+    }
+
+    /// Unwraps this `BufReader`, returning the inner reader.
+    ///
+    /// Any data currently buffered will be lost.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+impl<R: Read> Read for BufReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // Bypasses the internal buffer if it is empty and the request is large enough
+        if self.pos == self.cap && buf.len() >= self.buf.len() {
+            return self.inner.read(buf);
+        }
+        let size = {
+            let mut available = self.fill_buf()?;
+            available.read(buf)?
+        };
+        self.consume(size);
+        Ok(size)
+    }
+}
+impl<R: Read> BufRead for BufReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.cap {
+            self.cap = self.inner.read(&mut self.buf)?;
+            self.pos = 0;
+        }
+        Ok(&self.buf[self.pos..self.cap])
+    }
+    fn consume(&mut self, amt: usize) {
+        self.pos = ::std::cmp::min(self.pos + amt, self.cap);
+    }
+}
+impl<R: fmt::Debug> fmt::Debug for BufReader<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "BufReader {{ inner: {:?}, buffered: {}, capacity: {} }}",
+            self.inner,
+            self.cap - self.pos,
+            self.buf.len()
+        )
+    }
+}
+
+/// A future which will read bytes until a delimiter is reached.
+///
+/// This is created by calling `BufReader::read_until` method.
+pub struct ReadUntil<R>(Option<ReadUntilInner<R>>);
+impl<R: Read> Future for ReadUntil<R> {
+    type Item = (BufReader<R>, Vec<u8>);
+    type Error = (BufReader<R>, io::Error);
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut inner = self.0.take().expect("Cannot poll ReadUntil twice");
+        let delimiter = inner.delimiter;
+        loop {
+            let (is_done, size) = match inner.reader.fill_buf() {
+                Err(e) => match e.kind() {
+                    io::ErrorKind::Interrupted => continue,
+                    io::ErrorKind::WouldBlock => {
+                        self.0 = Some(inner);
+                        return Ok(Async::NotReady);
+                    }
+                    _ => return Err((inner.reader, e)),
+                },
+                Ok(available) => {
+                    if let Some(i) = available.iter().position(|b| *b == delimiter) {
+                        inner.bytes.extend_from_slice(&available[..=i]);
+                        (true, i + 1)
+                    } else {
+                        inner.bytes.extend_from_slice(available);
+                        (available.is_empty(), available.len())
+                    }
+                }
+            };
+            inner.reader.consume(size);
+            if is_done {
+                return Ok(Async::Ready((inner.reader, inner.bytes)));
+            }
+        }
+    }
+}
+impl<R> fmt::Debug for ReadUntil<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref inner) = self.0 {
+            write!(
+                f,
+                "ReadUntil {{ delimiter: {}, read: {}, .. }}",
+                inner.delimiter,
+                inner.bytes.len()
+            )
+        } else {
+            write!(f, "ReadUntil(_)")
+        }
+    }
+}
+
+struct ReadUntilInner<R> {
+    reader: BufReader<R>,
+    delimiter: u8,
+    bytes: Vec<u8>,
+}
+
+/// A future which will read a line.
+///
+/// This is created by calling `BufReader::read_line` method.
+pub struct ReadLine<R>(ReadUntil<R>);
+impl<R: Read> Future for ReadLine<R> {
+    type Item = (BufReader<R>, String);
+    type Error = (BufReader<R>, io::Error);
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if let Async::Ready((reader, bytes)) = self.0.poll()? {
+            match String::from_utf8(bytes) {
+                Ok(line) => Ok(Async::Ready((reader, line))),
+                Err(e) => Err((reader, io::Error::new(io::ErrorKind::InvalidData, e))),
+            }
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+}
+impl<R> fmt::Debug for ReadLine<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ReadLine({:?})", self.0)
+    }
+}
+
+/// A buffered writer which can be used with non-blocking streams.
+///
+/// Like `std::io::BufWriter`, this coalesces small writes into larger ones.
+/// The buffered data is written to the inner writer when the buffer becomes full
+/// or the writer is explicitly flushed.
+///
+/// If the inner writer returns `io::ErrorKind::WouldBlock` in the middle of flushing,
+/// the data which has not been written yet is kept in the buffer.
+///
+/// When dropped, the writer attempts to flush the buffered data once,
+/// ignoring any errors (including `WouldBlock`).
+/// To ensure that all data is written, use `BufWriter::flush` future before dropping.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate fibers;
+/// # extern crate futures;
+/// use fibers::io::BufWriter;
+/// use futures::Future;
+/// use std::io::Write;
+///
+/// # fn main() {
+/// let mut writer = BufWriter::new(Vec::new());
+/// writer.write_all(b"foo").unwrap();
+/// writer.write_all(b"bar").unwrap();
+/// assert!(writer.get_ref().is_empty());
+///
+/// let writer = writer.flush().wait().unwrap();
+/// assert_eq!(writer.get_ref(), b"foobar");
+/// # }
+/// ```
+pub struct BufWriter<W: Write> {
+    inner: Option<W>,
+    buf: Vec<u8>,
+    capacity: usize,
+}
+impl<W: Write> BufWriter<W> {
+    /// Makes a new `BufWriter` with the default buffer capacity (8 KiB).
+    pub fn new(inner: W) -> Self {
+        Self::with_capacity(DEFAULT_BUFFER_SIZE, inner)
+    }
+
+    /// Makes a new `BufWriter` with the specified buffer capacity.
+    ///
+    /// # Panics
+    ///
+    /// If `capacity` is `0`, this function will panic.
+    pub fn with_capacity(capacity: usize, inner: W) -> Self {
+        assert!(capacity > 0);
+        BufWriter {
+            inner: Some(inner),
+            buf: Vec::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Makes a future which writes all the buffered data and flushes the inner writer.
+    ///
+    /// Note that this method shadows `std::io::Write::flush` when called with the method syntax.
+    pub fn flush(self) -> Flush<W> {
+        Flush(Some(self))
+    }
+
+    /// Returns a reference to the inner writer.
+    pub fn get_ref(&self) -> &W {
+        self.inner.as_ref().expect("Never fails")
+    }
+
+    /// Returns a mutable reference to the inner writer.
+    ///
+    /// Writing directly to the inner writer may cause data reordering.
+    pub fn get_mut(&mut self) -> &mut W {
+        self.inner.as_mut().expect("Never fails")
+    }
+
+    /// Returns the data currently buffered in this writer.
+    pub fn buffer(&self) -> &[u8] {
+        &self.buf
+    }
+
+    /// Unwraps this `BufWriter`, returning the inner writer.
+    ///
+    /// Any data currently buffered will be discarded without being written.
+    pub fn into_inner(mut self) -> W {
+        self.buf.clear();
+        self.inner.take().expect("Never fails")
+    }
+
+    fn flush_buf(&mut self) -> io::Result<()> {
+        let mut written = 0;
+        let mut result = Ok(());
+        {
+            let inner = self.inner.as_mut().expect("Never fails");
+            while written < self.buf.len() {
+                match inner.write(&self.buf[written..]) {
+                    Ok(0) => {
+                        let e = io::Error::new(
+                            io::ErrorKind::WriteZero,
+                            "Failed to write buffered data",
+                        );
+                        result = Err(e);
+                        break;
+                    }
+                    Ok(size) => written += size,
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => {
+                        result = Err(e);
+                        break;
+                    }
+                }
+            }
+        }
+
+        // Keeps the data which has not been written yet
+        self.buf.drain(..written);
+        result
+    }
+}
+impl<W: Write> Write for BufWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.buf.len() + buf.len() > self.capacity {
+            self.flush_buf()?;
+        }
+        if buf.len() >= self.capacity {
+            self.get_mut().write(buf)
+        } else {
+            self.buf.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_buf()?;
+        self.get_mut().flush()
+    }
+}
+impl<W: Write> Drop for BufWriter<W> {
+    fn drop(&mut self) {
+        if self.inner.is_some() && !self.buf.is_empty() {
+            let _ = self.flush_buf();
+        }
+    }
+}
+impl<W: Write + fmt::Debug> fmt::Debug for BufWriter<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "BufWriter {{ inner: {:?}, buffered: {}, capacity: {} }}",
+            self.inner,
+            self.buf.len(),
+            self.capacity
+        )
+    }
+}
+
+/// A future which will flush a buffered writer.
+///
+/// This is created by calling `BufWriter::flush` method.
+pub struct Flush<W: Write>(Option<BufWriter<W>>);
+impl<W: Write> Future for Flush<W> {
+    type Item = BufWriter<W>;
+    type Error = (BufWriter<W>, io::Error);
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut writer = self.0.take().expect("Cannot poll Flush twice");
+        loop {
+            match Write::flush(&mut writer) {
+                Ok(()) => return Ok(Async::Ready(writer)),
+                Err(e) => match e.kind() {
+                    io::ErrorKind::Interrupted => {}
+                    io::ErrorKind::WouldBlock => {
+                        self.0 = Some(writer);
+                        return Ok(Async::NotReady);
+                    }
+                    _ => return Err((writer, e)),
+                },
+            }
+        }
+    }
+}
+impl<W: Write + fmt::Debug> fmt::Debug for Flush<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Flush({:?})", self.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::{Async, Future};
+    use std::io::{self, Read, Write};
+
+    use super::*;
+
+    /// A reader which returns the chunks one by one, interleaved with `WouldBlock` errors.
+    struct Chunks(Vec<&'static [u8]>);
+    impl Read for Chunks {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() {
+                return Ok(0);
+            }
+            let chunk = self.0.remove(0);
+            if chunk.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::WouldBlock, "would block"));
+            }
+            buf[..chunk.len()].copy_from_slice(chunk);
+            Ok(chunk.len())
+        }
+    }
+
+    fn wait<F: Future>(mut future: F) -> Result<F::Item, F::Error> {
+        loop {
+            if let Async::Ready(item) = future.poll()? {
+                return Ok(item);
+            }
+        }
+    }
+
+    #[test]
+    fn read_until_works() {
+        let chunks = Chunks(vec![b"fo", b"", b"o,b", b"", b"ar,", b"baz"]);
+        let reader = BufReader::with_capacity(4, chunks);
+        let (reader, bytes) = wait(reader.read_until(b',')).ok().unwrap();
+        assert_eq!(bytes, b"foo,");
+        let (reader, bytes) = wait(reader.read_until(b',')).ok().unwrap();
+        assert_eq!(bytes, b"bar,");
+        let (reader, bytes) = wait(reader.read_until(b',')).ok().unwrap();
+        assert_eq!(bytes, b"baz");
+        let (_, bytes) = wait(reader.read_until(b',')).ok().unwrap();
+        assert_eq!(bytes, b"");
+    }
+
+    /// A writer which accepts at most 10 bytes per call, interleaved with `WouldBlock` errors.
+    #[derive(Default)]
+    struct Choppy {
+        data: Vec<u8>,
+        writes: usize,
+        blocked: bool,
+    }
+    impl Write for Choppy {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.blocked = !self.blocked;
+            if self.blocked {
+                return Err(io::Error::new(io::ErrorKind::WouldBlock, "would block"));
+            }
+            let size = ::std::cmp::min(buf.len(), 10);
+            self.data.extend_from_slice(&buf[..size]);
+            self.writes += 1;
+            Ok(size)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn small_writes_are_coalesced() {
+        let mut writer = BufWriter::with_capacity(64, Choppy::default());
+        let mut expected = Vec::new();
+        for i in 0..100u8 {
+            let bytes = [i, i, i];
+            loop {
+                match writer.write(&bytes) {
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                    result => {
+                        assert_eq!(result.unwrap(), 3);
+                        break;
+                    }
+                }
+            }
+            expected.extend_from_slice(&bytes);
+        }
+        let writer = wait(writer.flush()).ok().unwrap();
+        assert_eq!(writer.get_ref().data, expected);
+
+        // Each flush of the full buffer needs at most 7 writes of the inner writer
+        assert!(writer.get_ref().writes <= 7 * (300 / 64 + 1));
+    }
+
+    #[test]
+    fn read_line_rejects_invalid_utf8() {
+        let reader = BufReader::new(Chunks(vec![b"\xff\n", b"ok\n"]));
+        let (reader, e) = wait(reader.read_line()).err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+
+        let (mut reader, line) = wait(reader.read_line()).ok().unwrap();
+        assert_eq!(line, "ok\n");
+        assert_eq!(reader.read(&mut [0; 4]).unwrap(), 0);
+    }
+}
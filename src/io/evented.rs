@@ -0,0 +1,267 @@
+// Copyright (c) 2016 DWANGO Co., Ltd. All Rights Reserved.
+// See the LICENSE file at the top-level directory of this distribution.
+
+use futures::{Async, Future, Poll};
+use libc;
+use mio::unix::EventedFd;
+use mio::{self, Evented};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use fiber;
+use io::poll::{EventedHandle, Interest, Register};
+use sync::oneshot::Monitor;
+
+/// An I/O object driven by the I/O poller of the current fiber.
+///
+/// This wraps an arbitrary object which has a file descriptor (e.g., serial ports, tun/tap devices,
+/// or the sockets created by third-party libraries), and registers the descriptor to the poller.
+/// The object is switched to non-blocking mode when it is registered.
+///
+/// # Note
+///
+/// If a read (or write) would block, it returns the `std::io::ErrorKind::WouldBlock` error and
+/// current fiber is suspended until the object becomes readable (or writable).
+/// So the futures in `fibers::io` module (e.g., `read_exact`) can be used with this object.
+///
+/// # Examples
+///
+/// ```
+/// // See also: fibers/examples/poll_evented_pipe.rs
+/// # extern crate fibers;
+/// # extern crate futures;
+/// use fibers::{Executor, InPlaceExecutor, Spawn};
+/// use fibers::io::{self, PollEvented};
+/// use futures::Future;
+/// use std::os::unix::net::UnixStream;
+///
+/// # fn main() {
+/// let (left, right) = UnixStream::pair().unwrap();
+///
+/// let mut executor = InPlaceExecutor::new().unwrap();
+/// let future = PollEvented::register(left)
+///     .join(PollEvented::register(right))
+///     .and_then(|(left, right)| {
+///         io::write_all(left, b"hello")
+///             .map_err(|(_, _, e)| e)
+///             .and_then(|_| io::read_exact(right, [0; 5]).map_err(|(_, _, e)| e))
+///             .map(|(_, buf)| buf)
+///     });
+/// let monitor = executor.spawn_monitor(future);
+/// assert_eq!(&executor.run_fiber(monitor).unwrap().unwrap(), b"hello");
+/// # }
+/// ```
+pub struct PollEvented<S> {
+    handle: EventedHandle<EventedSource<S>>,
+    read_monitor: Option<Monitor<(), io::Error>>,
+    write_monitor: Option<Monitor<(), io::Error>>,
+}
+impl<S: AsRawFd + Send + 'static> PollEvented<S> {
+    /// Makes a future to register `io` to the I/O poller of the current fiber.
+    pub fn register(io: S) -> PollEventedRegister<S> {
+        PollEventedRegister {
+            io: Some(io),
+            register: None,
+        }
+    }
+}
+impl<S: AsRawFd> PollEvented<S> {
+    /// Calls `f` with the reference to the inner object.
+    ///
+    /// # Note
+    ///
+    /// Operations performed on the inner object should not break
+    /// the registration state managed by the I/O poller
+    /// (e.g., replacing the file descriptor, or switching it back to blocking mode).
+    /// Otherwise, the readiness events of the object may be lost,
+    /// and the fibers waiting for them may never be woken up.
+    pub fn with_inner<F, T>(&self, f: F) -> T
+    where
+        F: FnOnce(&mut S) -> T,
+    {
+        f(&mut self.handle.inner().0)
+    }
+
+    fn operate<F, T>(&mut self, interest: Interest, mut f: F) -> io::Result<T>
+    where
+        F: FnMut(&mut S) -> io::Result<T>,
+    {
+        let monitor = if interest == Interest::Read {
+            &mut self.read_monitor
+        } else {
+            &mut self.write_monitor
+        };
+        self.handle
+            .operate(monitor, interest, |inner| f(&mut inner.0))
+    }
+}
+impl<S: AsRawFd> AsRawFd for PollEvented<S> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.handle.inner().0.as_raw_fd()
+    }
+}
+impl<S: AsRawFd + Read> Read for PollEvented<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.operate(Interest::Read, |inner| inner.read(buf))
+    }
+}
+impl<S: AsRawFd + Write> Write for PollEvented<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.operate(Interest::Write, |inner| inner.write(buf))
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.operate(Interest::Write, |inner| inner.flush())
+    }
+}
+impl<S: AsRawFd> fmt::Debug for PollEvented<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PollEvented {{ token:{:?}, .. }}", self.handle.token())
+    }
+}
+
+/// A future which will register an I/O object to the I/O poller of the current fiber.
+///
+/// This is created by calling `PollEvented::register` function.
+/// It is permitted to move the future across fibers.
+///
+/// # Panics
+///
+/// If the future is polled on the outside of a fiber, it may crash.
+pub struct PollEventedRegister<S> {
+    io: Option<S>,
+    register: Option<Register<EventedSource<S>>>,
+}
+impl<S: AsRawFd + Send + 'static> Future for PollEventedRegister<S> {
+    type Item = PollEvented<S>;
+    type Error = io::Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if let Some(io) = self.io.take() {
+            set_nonblocking(io.as_raw_fd())?;
+            let evented = EventedSource(io);
+            let register = |mut c: fiber::Context| c.poller().register(evented);
+            self.register = Some(assert_some!(fiber::with_current_context(register)));
+        }
+        let register = self
+            .register
+            .as_mut()
+            .expect("Cannot poll PollEventedRegister twice");
+        if let Async::Ready(handle) = register.poll()? {
+            self.register = None;
+            Ok(Async::Ready(PollEvented {
+                handle,
+                read_monitor: None,
+                write_monitor: None,
+            }))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+}
+impl<S> fmt::Debug for PollEventedRegister<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PollEventedRegister {{ .. }}")
+    }
+}
+
+struct EventedSource<S>(S);
+impl<S: AsRawFd> Evented for EventedSource<S> {
+    fn register(
+        &self,
+        poll: &mio::Poll,
+        token: mio::Token,
+        interest: mio::Ready,
+        opts: mio::PollOpt,
+    ) -> io::Result<()> {
+        EventedFd(&self.0.as_raw_fd()).register(poll, token, interest, opts)
+    }
+    fn reregister(
+        &self,
+        poll: &mio::Poll,
+        token: mio::Token,
+        interest: mio::Ready,
+        opts: mio::PollOpt,
+    ) -> io::Result<()> {
+        EventedFd(&self.0.as_raw_fd()).reregister(poll, token, interest, opts)
+    }
+    fn deregister(&self, poll: &mio::Poll) -> io::Result<()> {
+        EventedFd(&self.0.as_raw_fd()).deregister(poll)
+    }
+}
+
+pub(crate) fn set_nonblocking(fd: RawFd) -> io::Result<()> {
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFL);
+        if flags == -1 || libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use futures::future;
+    use std::os::unix::net::UnixStream;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+    use executor::{Executor, InPlaceExecutor};
+    use fiber::Spawn;
+    use io::{read_exact, write_all};
+
+    #[test]
+    fn read_and_write_work() {
+        let (left, right) = UnixStream::pair().unwrap();
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let monitor =
+            executor.spawn_monitor(PollEvented::register(left).join(PollEvented::register(right)));
+        let (left, right) = executor.run_fiber(monitor).unwrap().unwrap();
+
+        // Echoes back the received bytes
+        let echo = read_exact(right, [0; 4]).and_then(|(right, buf)| write_all(right, buf));
+        let client = write_all(left, *b"ping").and_then(|(left, _)| read_exact(left, [0; 4]));
+        let echo = executor.spawn_monitor(echo);
+        let client = executor.spawn_monitor(client);
+        executor.run_fiber(echo).unwrap().unwrap();
+        let (_, buf) = executor.run_fiber(client).unwrap().unwrap();
+        assert_eq!(&buf, b"ping");
+    }
+
+    #[test]
+    fn inner_object_is_accessible() {
+        let (left, _right) = UnixStream::pair().unwrap();
+        let fd = left.as_raw_fd();
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let monitor = executor.spawn_monitor(PollEvented::register(left));
+        let left = executor.run_fiber(monitor).unwrap().unwrap();
+
+        assert_eq!(left.as_raw_fd(), fd);
+        assert_eq!(left.with_inner(|inner| inner.as_raw_fd()), fd);
+        assert!(left.with_inner(|inner| inner.peer_addr()).is_ok());
+    }
+
+    #[test]
+    fn write_from_another_thread_wakes_up_reader() {
+        let (reader, mut writer) = UnixStream::pair().unwrap();
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let monitor = executor.spawn_monitor(PollEvented::register(reader));
+        let mut reader = executor.run_fiber(monitor).unwrap().unwrap();
+
+        let monitor = executor.spawn_monitor(future::poll_fn(move || {
+            let mut buf = [0; 1];
+            match reader.read(&mut buf) {
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(Async::NotReady),
+                Err(e) => Err(e),
+                Ok(size) => Ok(Async::Ready((size, buf[0]))),
+            }
+        }));
+        let thread = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            writer.write_all(&[42]).unwrap();
+        });
+        assert_eq!(executor.run_fiber(monitor).unwrap().unwrap(), (1, 42));
+        thread.join().unwrap();
+    }
+}
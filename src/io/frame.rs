@@ -0,0 +1,284 @@
+// Copyright (c) 2016 DWANGO Co., Ltd. All Rights Reserved.
+// See the LICENSE file at the top-level directory of this distribution.
+
+//! Length-prefixed framing over byte streams.
+//!
+//! Each frame consists of a 32-bit big-endian length header followed by the payload.
+//!
+//! # Examples
+//!
+//! ```
+//! # extern crate fibers;
+//! # extern crate futures;
+//! use fibers::io::frame::{FramedRead, FramedWrite};
+//! use futures::{Future, Sink, Stream};
+//!
+//! # fn main() {
+//! let writer = FramedWrite::new(Vec::new());
+//! let writer = writer.send(b"foo".to_vec()).wait().unwrap();
+//! let writer = writer.send(b"bar".to_vec()).wait().unwrap();
+//! let bytes = writer.into_inner();
+//! assert_eq!(bytes, b"\x00\x00\x00\x03foo\x00\x00\x00\x03bar");
+//!
+//! let frames = FramedRead::new(&bytes[..]).collect().wait().unwrap();
+//! assert_eq!(frames, vec![b"foo".to_vec(), b"bar".to_vec()]);
+//! # }
+//! ```
+use futures::{Async, AsyncSink, Poll, Sink, StartSend, Stream};
+use std::io::{self, Read, Write};
+
+const HEADER_SIZE: usize = 4;
+
+/// The default value of `FramedRead::max_frame_len`.
+pub const DEFAULT_MAX_FRAME_LEN: usize = 8 * 1024 * 1024;
+
+/// A stream of the frames read from a reader.
+///
+/// Partially read headers and payloads are preserved across `io::ErrorKind::WouldBlock` errors.
+#[derive(Debug)]
+pub struct FramedRead<R> {
+    inner: R,
+    header: [u8; HEADER_SIZE],
+    header_offset: usize,
+    payload: Option<(Vec<u8>, usize)>,
+    max_frame_len: usize,
+}
+impl<R: Read> FramedRead<R> {
+    /// Makes a new `FramedRead` which reads frames from `inner`.
+    pub fn new(inner: R) -> Self {
+        FramedRead {
+            inner,
+            header: [0; HEADER_SIZE],
+            header_offset: 0,
+            payload: None,
+            max_frame_len: DEFAULT_MAX_FRAME_LEN,
+        }
+    }
+
+    /// Sets the maximum length of the payload of a frame.
+    ///
+    /// If a header declares a longer payload, the stream will fail with an error of
+    /// the kind `io::ErrorKind::InvalidData` before allocating the buffer for it.
+    ///
+    /// The default value is `DEFAULT_MAX_FRAME_LEN`.
+    pub fn max_frame_len(mut self, max_frame_len: usize) -> Self {
+        self.max_frame_len = max_frame_len;
+        self
+    }
+}
+impl<R> FramedRead<R> {
+    /// Returns a reference to the inner reader.
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner reader.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Unwraps this `FramedRead`, returning the inner reader.
+    ///
+    /// The frame being read (if any) will be lost.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+impl<R: Read> Stream for FramedRead<R> {
+    type Item = Vec<u8>;
+    type Error = io::Error;
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            let is_frame_boundary = self.payload.is_none() && self.header_offset == 0;
+            let (buf, offset) = if let Some((ref mut payload, ref mut offset)) = self.payload {
+                if *offset == payload.len() {
+                    break;
+                }
+                (&mut payload[..], offset)
+            } else if self.header_offset < HEADER_SIZE {
+                (&mut self.header[..], &mut self.header_offset)
+            } else {
+                let len = u32::from_be_bytes(self.header) as usize;
+                if len > self.max_frame_len {
+                    let e = io::Error::new(io::ErrorKind::InvalidData, "Too large frame");
+                    return Err(e);
+                }
+                self.payload = Some((vec![0; len], 0));
+                continue;
+            };
+            match self.inner.read(&mut buf[*offset..]) {
+                Ok(0) => {
+                    if is_frame_boundary {
+                        return Ok(Async::Ready(None));
+                    }
+                    let e = io::Error::new(io::ErrorKind::UnexpectedEof, "Truncated frame");
+                    return Err(e);
+                }
+                Ok(size) => *offset += size,
+                Err(e) => match e.kind() {
+                    io::ErrorKind::Interrupted => {}
+                    io::ErrorKind::WouldBlock => return Ok(Async::NotReady),
+                    _ => return Err(e),
+                },
+            }
+        }
+
+        let (payload, _) = self.payload.take().expect("Never fails");
+        self.header_offset = 0;
+        Ok(Async::Ready(Some(payload)))
+    }
+}
+
+/// A sink which writes frames to a writer.
+///
+/// An encoded frame is kept in an internal buffer until it is written completely,
+/// thus at most one frame is buffered at a time.
+#[derive(Debug)]
+pub struct FramedWrite<W> {
+    inner: W,
+    buf: Vec<u8>,
+    offset: usize,
+}
+impl<W: Write> FramedWrite<W> {
+    /// Makes a new `FramedWrite` which writes frames to `inner`.
+    pub fn new(inner: W) -> Self {
+        FramedWrite {
+            inner,
+            buf: Vec::new(),
+            offset: 0,
+        }
+    }
+
+    fn poll_write(&mut self) -> Poll<(), io::Error> {
+        while self.offset < self.buf.len() {
+            match self.inner.write(&self.buf[self.offset..]) {
+                Ok(0) => {
+                    let e = io::Error::new(io::ErrorKind::WriteZero, "Failed to write frame");
+                    return Err(e);
+                }
+                Ok(size) => self.offset += size,
+                Err(e) => match e.kind() {
+                    io::ErrorKind::Interrupted => {}
+                    io::ErrorKind::WouldBlock => return Ok(Async::NotReady),
+                    _ => return Err(e),
+                },
+            }
+        }
+        self.buf.clear();
+        self.offset = 0;
+        Ok(Async::Ready(()))
+    }
+}
+impl<W> FramedWrite<W> {
+    /// Returns a reference to the inner writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner writer.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Unwraps this `FramedWrite`, returning the inner writer.
+    ///
+    /// The frame being written (if any) will be lost.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+impl<W: Write> Sink for FramedWrite<W> {
+    type SinkItem = Vec<u8>;
+    type SinkError = io::Error;
+    fn start_send(&mut self, item: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        if item.len() > u32::MAX as usize {
+            let e = io::Error::new(io::ErrorKind::InvalidInput, "Too large frame");
+            return Err(e);
+        }
+        if let Async::NotReady = self.poll_write()? {
+            return Ok(AsyncSink::NotReady(item));
+        }
+        self.buf
+            .extend_from_slice(&(item.len() as u32).to_be_bytes());
+        self.buf.extend_from_slice(&item);
+        Ok(AsyncSink::Ready)
+    }
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        if let Async::NotReady = self.poll_write()? {
+            return Ok(Async::NotReady);
+        }
+        match self.inner.flush() {
+            Ok(()) => Ok(Async::Ready(())),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(Async::NotReady),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::{self, Future, Sink, Stream};
+
+    use super::*;
+    use executor::{Executor, InPlaceExecutor};
+    use fiber::Spawn;
+    use net::{TcpListener, TcpStream};
+
+    #[test]
+    fn frames_over_tcp_work() {
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let monitor = executor.spawn_monitor(TcpListener::bind("127.0.0.1:0".parse().unwrap()));
+        let listener = executor.run_fiber(monitor).unwrap().unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let frames: Vec<Vec<u8>> =
+            vec![vec![], vec![1], vec![2; 1000], vec![3; 100_000], vec![4; 7]];
+        let server = listener
+            .incoming()
+            .into_future()
+            .map_err(|(e, _)| e)
+            .and_then(|(client, _)| client.unwrap().0)
+            .and_then(|stream| FramedRead::new(stream).collect());
+        let client = TcpStream::connect(addr).and_then({
+            let frames = frames.clone();
+            move |stream| {
+                let writer = FramedWrite::new(stream);
+                writer.send_all(futures::stream::iter_ok::<_, io::Error>(frames))
+            }
+        });
+        let server = executor.spawn_monitor(server);
+        let client = executor.spawn_monitor(client.map(|_| ()));
+        executor.run_fiber(client).unwrap().unwrap();
+        assert_eq!(executor.run_fiber(server).unwrap().unwrap(), frames);
+    }
+
+    #[test]
+    fn truncated_frame_is_rejected() {
+        let mut reader = FramedRead::new(&b"\x00\x00\x00\x05abc"[..]);
+        assert_eq!(
+            reader.poll().err().map(|e| e.kind()),
+            Some(io::ErrorKind::UnexpectedEof)
+        );
+    }
+
+    #[test]
+    fn too_large_frame_is_rejected() {
+        let mut reader = FramedRead::new(&b"\xff\xff\xff\xffabc"[..]);
+        assert_eq!(
+            reader.poll().err().map(|e| e.kind()),
+            Some(io::ErrorKind::InvalidData)
+        );
+
+        let mut reader = FramedRead::new(&b"\x00\x00\x00\x04abcd"[..]).max_frame_len(3);
+        assert_eq!(
+            reader.poll().err().map(|e| e.kind()),
+            Some(io::ErrorKind::InvalidData)
+        );
+
+        let mut reader = FramedRead::new(&b"\x00\x00\x00\x03abc"[..]).max_frame_len(3);
+        assert_eq!(
+            reader.poll().ok(),
+            Some(Async::Ready(Some(b"abc".to_vec())))
+        );
+    }
+}
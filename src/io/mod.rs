@@ -2,7 +2,23 @@
 // See the LICENSE file at the top-level directory of this distribution.
 
 //! I/O related functionalities.
+pub use self::buf::{BufReader, BufWriter, Flush, ReadLine, ReadUntil};
+pub use self::read_write::{
+    copy, read_exact, read_to_end, write_all, Copy, ReadExact, ReadToEnd, WriteAll,
+};
 pub use self::stdio::{stdin, Stdin};
 
+#[cfg(unix)]
+pub use self::evented::{PollEvented, PollEventedRegister};
+
+pub mod frame;
 pub mod poll;
+
+mod buf;
+#[cfg(unix)]
+mod evented;
+mod read_write;
 mod stdio;
+
+#[cfg(unix)]
+pub(crate) use self::evented::set_nonblocking;
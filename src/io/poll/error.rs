@@ -0,0 +1,92 @@
+// Copyright (c) 2016 DWANGO Co., Ltd. All Rights Reserved.
+// See the LICENSE file at the top-level directory of this distribution.
+
+use std::error;
+use std::fmt;
+use std::io;
+
+use sync::oneshot::MonitorError;
+
+/// The error type of the futures provided by pollers.
+#[derive(Debug)]
+pub enum Error {
+    /// The poller has been shut down or dropped before completing the operation.
+    PollerGone,
+
+    /// The evented object has been deregistered before becoming ready.
+    Deregistered,
+
+    /// An I/O error.
+    Io(io::Error),
+}
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        if let Error::Io(ref e) = *self {
+            Some(e)
+        } else {
+            None
+        }
+    }
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::PollerGone => write!(f, "Poller has been shut down or dropped"),
+            Error::Deregistered => write!(
+                f,
+                "Evented object has been deregistered before becoming ready"
+            ),
+            Error::Io(ref e) => write!(f, "I/O error: {}", e),
+        }
+    }
+}
+impl From<io::Error> for Error {
+    /// Converts `io::Error` to `Error`.
+    ///
+    /// If `e` has been converted from an `Error` (i.e., `io::Error::from(Error::PollerGone)`),
+    /// the original value is restored.
+    fn from(e: io::Error) -> Self {
+        if e.get_ref().is_some_and(|inner| inner.is::<Error>()) {
+            let inner = e.into_inner().expect("Never fails");
+            *inner.downcast::<Error>().expect("Never fails")
+        } else {
+            Error::Io(e)
+        }
+    }
+}
+impl From<MonitorError<io::Error>> for Error {
+    fn from(e: MonitorError<io::Error>) -> Self {
+        match e {
+            MonitorError::Aborted => Error::PollerGone,
+            MonitorError::Failed(e) => Error::from(e),
+        }
+    }
+}
+impl From<Error> for io::Error {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::PollerGone => io::Error::new(io::ErrorKind::ConnectionAborted, e),
+            Error::Deregistered => io::Error::other(e),
+            Error::Io(e) => e,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn conversion_works() {
+        let e = io::Error::from(Error::PollerGone);
+        assert_eq!(e.kind(), io::ErrorKind::ConnectionAborted);
+        assert!(matches!(Error::from(e), Error::PollerGone));
+
+        let e = io::Error::from(Error::Deregistered);
+        assert!(matches!(Error::from(e), Error::Deregistered));
+
+        let e = Error::from(io::Error::new(io::ErrorKind::NotFound, "foo"));
+        assert!(matches!(e, Error::Io(ref e) if e.kind() == io::ErrorKind::NotFound));
+        assert_eq!(io::Error::from(e).kind(), io::ErrorKind::NotFound);
+    }
+}
@@ -14,13 +14,24 @@ use std::io;
 use std::ops;
 use std::sync::Arc;
 
-pub use self::poller::{EventedHandle, Poller, PollerHandle};
-pub use self::poller::{Register, DEFAULT_EVENTS_CAPACITY};
+pub use self::error::Error;
+pub use self::poller::{
+    EventedHandle, MonitorBoth, PollOutcome, Poller, PollerBuilder, PollerHandle, PollerMetrics,
+    ReadinessStream, RegistrantInfo, ShutdownFuture,
+};
+pub use self::poller::{
+    Migrate, Register, RegisterAll, Timeout, DEFAULT_EVENTS_CAPACITY, DEFAULT_REQUEST_BUDGET,
+    DEFAULT_TIMER_GRANULARITY_MS, MAX_REGISTRANTS,
+};
+pub use self::pool::{BalancePolicy, PollerPool};
 
 use sync_atomic::{AtomicBorrowMut, AtomicCell};
 
 pub(crate) mod poller;
 
+mod error;
+mod pool;
+
 #[derive(Debug)]
 pub(crate) struct SharableEvented<T>(Arc<AtomicCell<T>>);
 impl<T> SharableEvented<T>
@@ -30,7 +41,12 @@ where
     pub fn new(inner: T) -> Self {
         SharableEvented(Arc::new(AtomicCell::new(inner)))
     }
-    pub fn lock(&self) -> EventedLock<T> {
+    pub fn try_unwrap(self) -> Result<T, Self> {
+        Arc::try_unwrap(self.0)
+            .map(AtomicCell::into_inner)
+            .map_err(SharableEvented)
+    }
+    pub fn lock(&self) -> EventedLock<'_, T> {
         loop {
             // NOTE: We assumes conflictions are very rare.
             // (But should be refined in future releases)
@@ -77,12 +93,12 @@ pub struct EventedLock<'a, T: 'a>(AtomicBorrowMut<'a, T>);
 impl<'a, T: 'a> ops::Deref for EventedLock<'a, T> {
     type Target = T;
     fn deref(&self) -> &T {
-        &*self.0
+        &self.0
     }
 }
 impl<'a, T: 'a> ops::DerefMut for EventedLock<'a, T> {
     fn deref_mut(&mut self) -> &mut T {
-        &mut *self.0
+        &mut self.0
     }
 }
 
@@ -1,27 +1,45 @@
 // Copyright (c) 2016 DWANGO Co., Ltd. All Rights Reserved.
 // See the LICENSE file at the top-level directory of this distribution.
 
-use futures::{self, Future};
+use futures::{self, Future, Stream};
 use mio;
 use nbchan::mpsc as nb_mpsc;
-use std::collections::HashMap;
+use std::cmp;
 use std::fmt;
 use std::io;
+use std::mem;
 use std::sync::atomic::{self, AtomicUsize};
-use std::sync::mpsc::{RecvError, TryRecvError};
+use std::sync::mpsc::{SendError, TryRecvError};
 use std::sync::Arc;
+use std::thread;
 use std::time;
 
-use super::{EventedLock, Interest, SharableEvented};
-use collections::HeapMap;
+use super::{Error, EventedLock, Interest, SharableEvented};
+use collections::{HeapMap, Slab, TimerWheel};
 use sync::oneshot;
 
-type RequestSender = nb_mpsc::Sender<Request>;
 type RequestReceiver = nb_mpsc::Receiver<Request>;
+type TimeoutNotifier = oneshot::Sender<io::Result<()>>;
 
 /// The default capacity of the event buffer of a poller.
 pub const DEFAULT_EVENTS_CAPACITY: usize = 128;
 
+/// The default maximum number of the requests handled by a poller in an iteration.
+pub const DEFAULT_REQUEST_BUDGET: usize = 64;
+
+/// The default granularity of the coarse timers of a poller (i.e., 10 milliseconds).
+pub const DEFAULT_TIMER_GRANULARITY_MS: u64 = 10;
+
+const TIMER_WHEEL_SLOTS: usize = 512;
+
+/// The maximum number of the evented objects which can be registered in a poller at the same time.
+///
+/// Each registered object occupies a token, and the tokens are recycled after deregistration.
+pub const MAX_REGISTRANTS: usize = usize::MAX - 1;
+
+// NOTE: `Token(usize::MAX)` is reserved by mio itself
+const WAKEUP_TOKEN: mio::Token = mio::Token(MAX_REGISTRANTS);
+
 struct MioEvents(mio::Events);
 impl fmt::Debug for MioEvents {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -33,16 +51,31 @@ impl fmt::Debug for MioEvents {
 struct Registrant {
     is_first: bool,
     evented: BoxEvented,
+    options: mio::PollOpt,
+
+    // The interest and options with which the object is currently registered to mio
+    armed: Option<(mio::Ready, mio::PollOpt)>,
+
     read_waitings: Vec<oneshot::Monitored<(), io::Error>>,
     write_waitings: Vec<oneshot::Monitored<(), io::Error>>,
+
+    // The error which occurred when (re)registering the object to mio
+    error: Option<io::Error>,
+
+    // The readiness reported while no monitors were waiting for it
+    readiness_hint: mio::Ready,
 }
 impl Registrant {
-    pub fn new(evented: BoxEvented) -> Self {
+    pub fn new(evented: BoxEvented, options: mio::PollOpt) -> Self {
         Registrant {
             is_first: true,
             evented,
+            options,
+            armed: None,
             read_waitings: Vec::new(),
             write_waitings: Vec::new(),
+            error: None,
+            readiness_hint: mio::Ready::empty(),
         }
     }
     pub fn mio_interest(&self) -> mio::Ready {
@@ -65,10 +98,15 @@ pub struct Poller {
     events: MioEvents,
     request_tx: RequestSender,
     request_rx: RequestReceiver,
-    next_token: usize,
+    wakeup: mio::Registration,
+    token_capacity: usize,
     next_timeout_id: Arc<AtomicUsize>,
-    registrants: HashMap<mio::Token, Registrant>,
-    timeout_queue: HeapMap<(time::Instant, usize), oneshot::Sender<()>>,
+    registrants: Slab<Registrant>,
+    shared_registrant_count: Arc<AtomicUsize>,
+    timeout_queue: HeapMap<(time::Instant, usize), TimeoutNotifier>,
+    coarse_timeout_queue: TimerWheel<TimeoutNotifier>,
+    metrics: PollerMetrics,
+    request_budget: usize,
 }
 impl Poller {
     /// Creates a new poller.
@@ -78,6 +116,11 @@ impl Poller {
         Self::with_capacity(DEFAULT_EVENTS_CAPACITY)
     }
 
+    /// Makes a builder to create a `Poller` instance with the specified settings.
+    pub fn builder() -> PollerBuilder {
+        PollerBuilder::new()
+    }
+
     /// Creates a new poller which has an event buffer of which capacity is `capacity`.
     ///
     /// For the detailed meaning of the `capacity` value,
@@ -86,18 +129,128 @@ impl Poller {
     pub fn with_capacity(capacity: usize) -> io::Result<Self> {
         let poll = mio::Poll::new()?;
         let (tx, rx) = nb_mpsc::channel();
+        let (wakeup, readiness) = mio::Registration::new2();
+        poll.register(
+            &wakeup,
+            WAKEUP_TOKEN,
+            mio::Ready::readable(),
+            mio::PollOpt::edge(),
+        )?;
         Ok(Poller {
             poll,
             events: MioEvents(mio::Events::with_capacity(capacity)),
-            request_tx: tx,
+            request_tx: RequestSender { tx, readiness },
             request_rx: rx,
-            next_token: 0,
+            wakeup,
+            token_capacity: MAX_REGISTRANTS,
             next_timeout_id: Arc::new(AtomicUsize::new(0)),
-            registrants: HashMap::new(),
+            registrants: Slab::new(),
+            shared_registrant_count: Arc::new(AtomicUsize::new(0)),
             timeout_queue: HeapMap::new(),
+            coarse_timeout_queue: TimerWheel::new(
+                time::Duration::from_millis(DEFAULT_TIMER_GRANULARITY_MS),
+                TIMER_WHEEL_SLOTS,
+            ),
+            metrics: PollerMetrics::default(),
+            request_budget: DEFAULT_REQUEST_BUDGET,
         })
     }
 
+    /// Returns the capacity of the event buffer of the poller.
+    pub fn events_capacity(&self) -> usize {
+        self.events.0.capacity()
+    }
+
+    /// Replaces the event buffer of the poller with new one of which capacity is `capacity`.
+    ///
+    /// This method can be called at any time between `poll` method calls.
+    /// A larger buffer reduces the number of system calls under bursty load,
+    /// and a smaller one simply releases the unused capacity.
+    pub fn set_events_capacity(&mut self, capacity: usize) {
+        self.events = MioEvents(mio::Events::with_capacity(capacity));
+    }
+
+    /// Returns the maximum number of the requests handled in an iteration of the poller.
+    pub fn request_budget(&self) -> usize {
+        self.request_budget
+    }
+
+    /// Sets the maximum number of the requests handled in an iteration of the poller.
+    ///
+    /// The remaining requests are handled in the subsequent iterations.
+    /// A larger value reduces the latency of bursty registrations,
+    /// and a smaller one prevents them from delaying the dispatching of I/O events.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `budget` is `0`.
+    pub fn set_request_budget(&mut self, budget: usize) {
+        assert_ne!(budget, 0);
+        self.request_budget = budget;
+    }
+
+    /// Returns the granularity of the coarse timers of the poller.
+    ///
+    /// See `PollerHandle::set_coarse_timeout` for more details.
+    pub fn timer_granularity(&self) -> time::Duration {
+        self.coarse_timeout_queue.tick()
+    }
+
+    /// Sets the granularity of the coarse timers of the poller.
+    ///
+    /// The pending coarse timers are moved to the new granularity,
+    /// and the ones which have already expired are notified immediately.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `granularity` is zero.
+    pub fn set_timer_granularity(&mut self, granularity: time::Duration) {
+        let new_queue = TimerWheel::new(granularity, TIMER_WHEEL_SLOTS);
+        let queue = mem::replace(&mut self.coarse_timeout_queue, new_queue);
+        for notifier in queue.move_into(&mut self.coarse_timeout_queue) {
+            self.metrics.expired_timeouts += 1;
+            let _ = notifier.send(Ok(()));
+        }
+    }
+
+    /// Shrinks the memory regions of the internal queues of the poller as much as possible.
+    ///
+    /// The queues never release their memory by themselves,
+    /// so this is useful after a burst of registrations or timers subsides.
+    pub fn shrink_to_fit(&mut self) {
+        self.registrants.shrink_to_fit();
+        self.timeout_queue.shrink_to_fit();
+        self.coarse_timeout_queue.shrink_to_fit();
+    }
+
+    /// Returns the number of the evented objects registered in the poller.
+    pub fn registrant_count(&self) -> usize {
+        self.registrants.len()
+    }
+
+    /// Returns a snapshot of the metrics of the poller.
+    pub fn metrics(&self) -> PollerMetrics {
+        self.metrics
+    }
+
+    /// Returns a snapshot of the evented objects registered in the poller.
+    ///
+    /// The entries are sorted by their tokens.
+    /// This is intended for diagnostics (e.g., finding leaked objects or stuck monitors),
+    /// and takes time proportional to the number of the registered objects.
+    pub fn registrants_snapshot(&self) -> Vec<RegistrantInfo> {
+        self.registrants
+            .iter()
+            .map(|(token, r)| RegistrantInfo {
+                token: mio::Token(token),
+                read_waitings: r.read_waitings.len(),
+                write_waitings: r.write_waitings.len(),
+                interest: r.mio_interest(),
+                options: r.options,
+            })
+            .collect()
+    }
+
     /// Makes a future to register new evented object to the poller.
     pub fn register<E>(&mut self, evented: E) -> Register<E>
     where
@@ -106,35 +259,61 @@ impl Poller {
         self.handle().register(evented)
     }
 
+    /// Makes a future to register new evented object to the poller with the specified options.
+    ///
+    /// See `PollerHandle::register_with_opts` for more details.
+    pub fn register_with_opts<E>(&mut self, evented: E, options: mio::PollOpt) -> Register<E>
+    where
+        E: mio::Evented + Send + 'static,
+    {
+        self.handle().register_with_opts(evented, options)
+    }
+
     /// Blocks the current thread and wait until any events happen or `timeout` expires.
     ///
     /// On the former case, the poller notifies the fibers waiting on those events.
+    ///
+    /// Requests sent via the handles of the poller also interrupt the blocking,
+    /// so it is safe to pass `None` as `timeout` (e.g., when the poller has been idle for a while).
+    ///
+    /// This is equivalent to `poll_once` except that the outcome is discarded.
     pub fn poll(&mut self, timeout: Option<time::Duration>) -> io::Result<()> {
-        let mut did_something = false;
+        self.poll_once(timeout).map(|_| ())
+    }
+
+    /// Runs one iteration of the polling loop and reports what has been done in it.
+    ///
+    /// The semantics of `timeout` is the same as `poll` method.
+    ///
+    /// The returned outcome can be used by an embedding event loop to decide
+    /// whether it should back off (e.g., when `PollOutcome::is_idle` returns `true`).
+    ///
+    /// # Fairness
+    ///
+    /// All the events reported by mio in an iteration are dispatched,
+    /// so the order of them does not matter as long as the event buffer is large enough.
+    /// If more objects are ready than the capacity of the buffer (see `Poller::with_capacity`),
+    /// the remaining ones are reported in the subsequent iterations.
+    /// Since an object is deregistered from mio (or disarmed in the case of oneshot)
+    /// once its waiting monitors are notified, an always-ready object cannot occupy
+    /// the buffer until it is monitored again, and thus it never starves the others.
+    pub fn poll_once(&mut self, timeout: Option<time::Duration>) -> io::Result<PollOutcome> {
+        let mut outcome = PollOutcome::default();
 
         // Request
-        match self.request_rx.try_recv() {
-            Err(TryRecvError::Empty) => {}
-            Err(TryRecvError::Disconnected) => unreachable!(),
-            Ok(r) => {
-                did_something = true;
-                self.handle_request(r)?;
-            }
-        }
+        self.handle_requests(&mut outcome)?;
 
         // Timeout
         let now = time::Instant::now();
-        while let Some((_, notifier)) = self.timeout_queue.pop_if(|k, _| k.0 <= now) {
-            let _ = notifier.send(());
-        }
+        outcome.timeouts += self.expire_timeouts(now);
 
         // I/O event
-        let timeout = if did_something {
+        let timeout = if outcome.requests > 0 {
             Some(time::Duration::from_millis(0))
-        } else if let Some((k, _)) = self.timeout_queue.peek() {
-            let duration_until_next_expiry_time = k.0 - now;
+        } else if let Some(expiry_time) = self.next_expiry_time() {
+            // NOTE: `Instant` subtraction saturates to zero if the deadline has passed
+            let duration_until_next_expiry_time = expiry_time - now;
             if let Some(timeout) = timeout {
-                use std::cmp;
                 Some(cmp::min(timeout, duration_until_next_expiry_time))
             } else {
                 Some(duration_until_next_expiry_time)
@@ -143,18 +322,116 @@ impl Poller {
             timeout
         };
         let _ = self.poll.poll(&mut self.events.0, timeout)?;
+        let mut is_woken_up = false;
         for e in self.events.0.iter() {
-            let r = assert_some!(self.registrants.get_mut(&e.token()));
-            if e.readiness().is_readable() {
+            if e.token() == WAKEUP_TOKEN {
+                let _ = self.request_tx.readiness.set_readiness(mio::Ready::empty());
+                is_woken_up = true;
+                continue;
+            }
+            let r = assert_some!(self.registrants.get_mut(e.token().0));
+            let readiness = e.readiness();
+            outcome.events += 1;
+            self.metrics.events += 1;
+            if readiness.is_readable() {
+                self.metrics.readable_events += 1;
+            }
+            if readiness.is_writable() {
+                self.metrics.writable_events += 1;
+            }
+
+            // NOTE: Edge-triggered events are never reported again for the same readiness,
+            // so the events which no monitors are waiting for are remembered
+            // in order to notify the subsequent monitors immediately.
+            let is_broken = is_error(readiness) || is_hup(readiness);
+            if (readiness.is_readable() || is_broken) && r.read_waitings.is_empty() {
+                r.readiness_hint.insert(mio::Ready::readable());
+            }
+            if (readiness.is_writable() || is_broken) && r.write_waitings.is_empty() {
+                r.readiness_hint.insert(mio::Ready::writable());
+            }
+            if is_error(readiness) {
+                // NOTE: The waiters retry their operations, which report the actual error
+                for tx in r.read_waitings.drain(..).chain(r.write_waitings.drain(..)) {
+                    tx.exit(Ok(()));
+                }
+            } else if is_hup(readiness) {
+                // NOTE: Readers are notified normally so that they can consume the remaining data
                 for _ in r.read_waitings.drain(..).map(|tx| tx.exit(Ok(()))) {}
+                for tx in r.write_waitings.drain(..) {
+                    let e = io::Error::new(io::ErrorKind::BrokenPipe, "Peer hung up");
+                    tx.exit(Err(e));
+                }
+            } else {
+                if readiness.is_readable() {
+                    for _ in r.read_waitings.drain(..).map(|tx| tx.exit(Ok(()))) {}
+                }
+                if readiness.is_writable() {
+                    for _ in r.write_waitings.drain(..).map(|tx| tx.exit(Ok(()))) {}
+                }
             }
-            if e.readiness().is_writable() {
-                for _ in r.write_waitings.drain(..).map(|tx| tx.exit(Ok(()))) {}
+            Self::mio_register_or_fail(&self.poll, &mut self.metrics, e.token(), r);
+        }
+
+        // Requests which arrived while waiting for I/O events
+        if is_woken_up {
+            self.handle_requests(&mut outcome)?;
+        }
+
+        // Timeouts which expired while waiting for I/O events
+        outcome.timeouts += self.expire_timeouts(time::Instant::now());
+
+        Ok(outcome)
+    }
+
+    /// Makes a future to shut down the poller.
+    ///
+    /// When the future is polled, the pending requests are discarded and
+    /// all the registered objects are deregistered.
+    /// Waiting monitors, registrations and timeouts are notified of the shutdown by an error
+    /// (i.e., `Error::PollerGone`), so that the fibers which depend on them can terminate cleanly.
+    ///
+    /// After the future completes, requests sent via the handles of the poller will fail.
+    pub fn shutdown(self) -> ShutdownFuture {
+        ShutdownFuture { poller: Some(self) }
+    }
+
+    fn shutdown_now(mut self) -> io::Result<()> {
+        while let Ok(request) = self.request_rx.try_recv() {
+            match request {
+                Request::Register(_, _, mut reply) => (reply.0)(Err(shutdown_error())),
+                Request::RegisterBatch(_, _, mut reply) => (reply.0)(Err(shutdown_error())),
+                Request::Monitor(_, _, _, notifier) => notifier.exit(Err(shutdown_error())),
+                Request::SetTimeout(_, _, notifier) | Request::SetCoarseTimeout(_, _, notifier) => {
+                    let _ = notifier.send(Err(shutdown_error()));
+                }
+                Request::Deregister(..)
+                | Request::CancelTimeout(..)
+                | Request::CancelCoarseTimeout(..) => {}
             }
-            Self::mio_register(&self.poll, e.token(), r)?;
+        }
+        let timeouts = self.timeout_queue.drain_while(|_, _| true).map(|(_, n)| n);
+        for notifier in timeouts.collect::<Vec<_>>() {
+            let _ = notifier.send(Err(shutdown_error()));
+        }
+        for notifier in self.coarse_timeout_queue.drain() {
+            let _ = notifier.send(Err(shutdown_error()));
         }
 
-        Ok(())
+        let mut result = Ok(());
+        for mut r in self.registrants.drain() {
+            if !r.is_first {
+                if let Err(e) = self.poll.deregister(&*r.evented.0) {
+                    result = result.and(Err(e));
+                }
+            }
+            for tx in r.read_waitings.drain(..).chain(r.write_waitings.drain(..)) {
+                tx.exit(Err(shutdown_error()));
+            }
+        }
+        self.update_shared_registrant_count();
+        let _ = self.poll.deregister(&self.wakeup);
+        result
     }
 
     /// Makes a handle of the poller.
@@ -162,34 +439,110 @@ impl Poller {
         PollerHandle {
             request_tx: self.request_tx.clone(),
             next_timeout_id: Arc::clone(&self.next_timeout_id),
+            registrant_count: Arc::clone(&self.shared_registrant_count),
             is_alive: true,
         }
     }
 
+    fn handle_requests(&mut self, outcome: &mut PollOutcome) -> io::Result<()> {
+        while outcome.requests < self.request_budget {
+            match self.request_rx.try_recv() {
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => unreachable!(),
+                Ok(r) => {
+                    outcome.requests += 1;
+                    self.handle_request(r)?;
+                }
+            }
+        }
+        Ok(())
+    }
     fn handle_request(&mut self, request: Request) -> io::Result<()> {
         match request {
-            Request::Register(evented, mut reply) => {
-                let token = self.next_token();
-                self.registrants.insert(token, Registrant::new(evented));
-                (reply.0)(token);
+            Request::Register(evented, options, mut reply) => {
+                self.metrics.register_requests += 1;
+                let result = self.add_registrant(evented, options);
+                (reply.0)(result);
+            }
+            Request::RegisterBatch(evented, options, mut reply) => {
+                let mut tokens = Vec::with_capacity(evented.len());
+                for evented in evented {
+                    self.metrics.register_requests += 1;
+                    match self.add_registrant(evented, options) {
+                        Ok(token) => tokens.push(token),
+                        Err(e) => {
+                            // Rolls back so that the batch is registered all or nothing
+                            for token in tokens {
+                                let r = assert_some!(self.registrants.remove(token.0));
+                                if !r.is_first {
+                                    let _ = self.poll.deregister(&*r.evented.0);
+                                }
+                            }
+                            self.update_shared_registrant_count();
+                            (reply.0)(Err(e));
+                            return Ok(());
+                        }
+                    }
+                }
+                (reply.0)(Ok(tokens));
             }
-            Request::Deregister(token) => {
-                let r = assert_some!(self.registrants.remove(&token));
+            Request::Deregister(token, reply) => {
+                self.metrics.deregister_requests += 1;
+                let mut r = assert_some!(self.registrants.remove(token.0));
+                self.update_shared_registrant_count();
+                for tx in r.read_waitings.drain(..).chain(r.write_waitings.drain(..)) {
+                    tx.exit(Err(deregistered_error()));
+                }
                 if !r.is_first {
-                    self.poll.deregister(&*r.evented.0)?;
+                    // NOTE: The object may be already broken (e.g., its file descriptor is closed),
+                    // but it is no longer our concern.
+                    let _ = self.poll.deregister(&*r.evented.0);
+                }
+                if let Some(reply) = reply {
+                    let _ = reply.send((r.evented, r.options));
                 }
             }
-            Request::Monitor(token, interest, notifier) => {
-                let r = assert_some!(self.registrants.get_mut(&token));
+            Request::Monitor(token, interest, options, notifier) => {
+                self.metrics.monitor_requests += 1;
+                let r = assert_some!(self.registrants.get_mut(token.0));
+                if let Some(ref e) = r.error {
+                    notifier.exit(Err(copy_error(e)));
+                    return Ok(());
+                }
+                if let Some(options) = options {
+                    if options != r.options {
+                        if !r.read_waitings.is_empty() || !r.write_waitings.is_empty() {
+                            let e = io::Error::new(
+                                io::ErrorKind::InvalidInput,
+                                "Cannot change the poll options while other monitors are waiting",
+                            );
+                            notifier.exit(Err(e));
+                            return Ok(());
+                        }
+                        r.options = options;
+                    }
+                }
+                let ready = match interest {
+                    Interest::Read => mio::Ready::readable(),
+                    Interest::Write => mio::Ready::writable(),
+                };
+                if r.readiness_hint.contains(ready) {
+                    // The readiness may be stale, but a spurious wakeup is harmless
+                    // (the caller will retry the operation and monitor again if it would block).
+                    r.readiness_hint.remove(ready);
+                    notifier.exit(Ok(()));
+                    return Ok(());
+                }
                 match interest {
                     Interest::Read => r.read_waitings.push(notifier),
                     Interest::Write => r.write_waitings.push(notifier),
                 }
                 if r.read_waitings.len() == 1 || r.write_waitings.len() == 1 {
-                    Self::mio_register(&self.poll, token, r)?;
+                    Self::mio_register_or_fail(&self.poll, &mut self.metrics, token, r);
                 }
             }
             Request::SetTimeout(timeout_id, expiry_time, reply) => {
+                self.metrics.timeout_requests += 1;
                 assert!(
                     self.timeout_queue
                         .push_if_absent((expiry_time, timeout_id), reply,)
@@ -198,31 +551,368 @@ impl Poller {
             Request::CancelTimeout(timeout_id, expiry_time) => {
                 self.timeout_queue.remove(&(expiry_time, timeout_id));
             }
+            Request::SetCoarseTimeout(timeout_id, expiry_time, reply) => {
+                self.metrics.timeout_requests += 1;
+                let queue = &mut self.coarse_timeout_queue;
+                if let Err(reply) = queue.insert(timeout_id, expiry_time, reply) {
+                    self.metrics.expired_timeouts += 1;
+                    let _ = reply.send(Ok(()));
+                }
+            }
+            Request::CancelCoarseTimeout(timeout_id, expiry_time) => {
+                self.coarse_timeout_queue.remove(timeout_id, expiry_time);
+            }
         }
         Ok(())
     }
-    fn mio_register(poll: &mio::Poll, token: mio::Token, r: &mut Registrant) -> io::Result<()> {
+    fn add_registrant(
+        &mut self,
+        evented: BoxEvented,
+        options: mio::PollOpt,
+    ) -> io::Result<mio::Token> {
+        // NOTE: The object is registered to mio eagerly (with empty interest),
+        // so that an invalid registration (e.g., the same file descriptor is registered twice,
+        // which is detected only by epoll) is reported to the registering side
+        // rather than to the caller of `Poller::poll`.
+        let token = self.next_token()?;
+        let mut r = Registrant::new(evented, options);
+        let mut result = self
+            .poll
+            .register(&*r.evented.0, token, mio::Ready::empty(), options)
+            .map_err(|e| {
+                if e.kind() == io::ErrorKind::AlreadyExists {
+                    let message = "The evented object has already been registered to the poller";
+                    io::Error::new(io::ErrorKind::AlreadyExists, message)
+                } else {
+                    e
+                }
+            });
+        if result.is_ok() {
+            r.is_first = false;
+            result = Self::mio_register(&self.poll, &mut self.metrics, token, &mut r);
+        }
+        if let Err(e) = result {
+            if !r.is_first {
+                let _ = self.poll.deregister(&*r.evented.0);
+            }
+            return Err(e);
+        }
+        let key = self.registrants.insert(r);
+        debug_assert_eq!(key, token.0);
+        self.update_shared_registrant_count();
+        Ok(token)
+    }
+    fn update_shared_registrant_count(&self) {
+        self.shared_registrant_count
+            .store(self.registrants.len(), atomic::Ordering::SeqCst);
+    }
+    fn expire_timeouts(&mut self, now: time::Instant) -> usize {
+        let mut count = 0;
+        for (_, notifier) in self.timeout_queue.drain_while(|k, _| k.0 <= now) {
+            count += 1;
+            let _ = notifier.send(Ok(()));
+        }
+        self.metrics.expired_timeouts += count as u64;
+
+        let coarse_count = self.coarse_timeout_queue.expire(now, |notifier| {
+            let _ = notifier.send(Ok(()));
+        });
+        self.metrics.expired_timeouts += coarse_count as u64;
+        count + coarse_count
+    }
+    fn next_expiry_time(&mut self) -> Option<time::Instant> {
+        let precise = self.timeout_queue.peek().map(|(k, _)| k.0);
+        let coarse = self.coarse_timeout_queue.next_expiry();
+        match (precise, coarse) {
+            (Some(a), Some(b)) => Some(cmp::min(a, b)),
+            (a, b) => a.or(b),
+        }
+    }
+    fn mio_register_or_fail(
+        poll: &mio::Poll,
+        metrics: &mut PollerMetrics,
+        token: mio::Token,
+        r: &mut Registrant,
+    ) {
+        // NOTE: A broken object (e.g., its file descriptor has been closed by the owner)
+        // must not take down the other objects served by the poller.
+        // The entry is kept until all the handles are dropped, so that the token is not reused
+        // while the handles refer to it.
+        if let Err(e) = Self::mio_register(poll, metrics, token, r) {
+            metrics.failed_registrations += 1;
+            if !r.is_first {
+                let _ = poll.deregister(&*r.evented.0);
+                r.is_first = true;
+            }
+            r.armed = None;
+            for tx in r.read_waitings.drain(..).chain(r.write_waitings.drain(..)) {
+                tx.exit(Err(copy_error(&e)));
+            }
+            r.error = Some(e);
+        }
+    }
+    fn mio_register(
+        poll: &mio::Poll,
+        metrics: &mut PollerMetrics,
+        token: mio::Token,
+        r: &mut Registrant,
+    ) -> io::Result<()> {
         let interest = r.mio_interest();
         if interest != mio::Ready::empty() {
-            let options = mio::PollOpt::edge() | mio::PollOpt::oneshot();
+            let armed = Some((interest, r.options));
             if r.is_first {
                 r.is_first = false;
-                poll.register(&*r.evented.0, token, interest, options)?;
-            } else {
-                poll.reregister(&*r.evented.0, token, interest, options)?;
+                poll.register(&*r.evented.0, token, interest, r.options)?;
+            } else if r.options.is_oneshot() || r.armed != armed {
+                // NOTE: Unlike oneshot registrations, the other ones are still armed
+                // if neither the interest nor the options have changed.
+                metrics.reregistrations += 1;
+                poll.reregister(&*r.evented.0, token, interest, r.options)?;
+            }
+            r.armed = armed;
+        } else if !r.is_first && !r.options.is_oneshot() {
+            // Unlike oneshot registrations, the other ones keep reporting events
+            // even if there are no waiting monitors.
+            // They are parked by an empty oneshot registration rather than deregistered,
+            // so that the object is never unregistered while the handles are alive.
+            let parked = Some((mio::Ready::empty(), mio::PollOpt::oneshot()));
+            if r.armed != parked {
+                metrics.reregistrations += 1;
+                poll.reregister(
+                    &*r.evented.0,
+                    token,
+                    mio::Ready::empty(),
+                    mio::PollOpt::oneshot(),
+                )?;
+                r.armed = parked;
             }
         }
         Ok(())
     }
-    fn next_token(&mut self) -> mio::Token {
-        loop {
-            let token = self.next_token;
-            self.next_token = token.wrapping_add(1);
-            if self.registrants.contains_key(&mio::Token(token)) {
-                continue;
-            }
-            return mio::Token(token);
+    fn next_token(&self) -> io::Result<mio::Token> {
+        // NOTE: The token is the key of the registrant in the slab
+        let key = self.registrants.next_key();
+        if key < self.token_capacity {
+            Ok(mio::Token(key))
+        } else {
+            Err(io::Error::other("Too many evented objects are registered"))
+        }
+    }
+}
+
+/// A builder for `Poller`.
+///
+/// This is created by calling `Poller::builder` function.
+/// The builder is also used by the executors to tune their internal pollers
+/// (e.g., `InPlaceExecutorBuilder`).
+#[derive(Debug, Clone)]
+pub struct PollerBuilder {
+    events_capacity: usize,
+    request_budget: usize,
+    timer_granularity: time::Duration,
+}
+impl PollerBuilder {
+    fn new() -> Self {
+        PollerBuilder {
+            events_capacity: DEFAULT_EVENTS_CAPACITY,
+            request_budget: DEFAULT_REQUEST_BUDGET,
+            timer_granularity: time::Duration::from_millis(DEFAULT_TIMER_GRANULARITY_MS),
+        }
+    }
+
+    /// Sets the capacity of the event buffer of the poller.
+    ///
+    /// The default value is `DEFAULT_EVENTS_CAPACITY`.
+    /// See `Poller::with_capacity` for more details.
+    pub fn events_capacity(mut self, capacity: usize) -> Self {
+        self.events_capacity = capacity;
+        self
+    }
+
+    /// Sets the maximum number of the requests handled in an iteration of the poller.
+    ///
+    /// The default value is `DEFAULT_REQUEST_BUDGET`.
+    /// See `Poller::set_request_budget` for more details.
+    pub fn request_budget(mut self, budget: usize) -> Self {
+        self.request_budget = budget;
+        self
+    }
+
+    /// Sets the granularity of the coarse timers of the poller.
+    ///
+    /// The default value is `DEFAULT_TIMER_GRANULARITY_MS` milliseconds.
+    /// See `Poller::set_timer_granularity` for more details.
+    pub fn timer_granularity(mut self, granularity: time::Duration) -> Self {
+        self.timer_granularity = granularity;
+        self
+    }
+
+    /// Creates a `Poller` instance with the settings.
+    ///
+    /// # Panics
+    ///
+    /// If either the request budget or the timer granularity is zero, this method will panic.
+    pub fn build(&self) -> io::Result<Poller> {
+        let mut poller = Poller::with_capacity(self.events_capacity)?;
+        poller.set_request_budget(self.request_budget);
+        if poller.timer_granularity() != self.timer_granularity {
+            poller.set_timer_granularity(self.timer_granularity);
         }
+        Ok(poller)
+    }
+}
+
+/// Metrics of a poller.
+///
+/// This is created by calling `Poller::metrics` method.
+/// Each field is a cumulative count since the poller was created.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PollerMetrics {
+    /// The number of the I/O events processed by the poller.
+    pub events: u64,
+
+    /// The number of the I/O events which indicate read readiness.
+    pub readable_events: u64,
+
+    /// The number of the I/O events which indicate write readiness.
+    pub writable_events: u64,
+
+    /// The number of the handled register requests.
+    pub register_requests: u64,
+
+    /// The number of the handled deregister requests.
+    pub deregister_requests: u64,
+
+    /// The number of the handled monitor requests.
+    pub monitor_requests: u64,
+
+    /// The number of the re-registrations of the evented objects to the underlying `mio::Poll`.
+    pub reregistrations: u64,
+
+    /// The number of the evented objects which failed to be (re)registered
+    /// to the underlying `mio::Poll`.
+    pub failed_registrations: u64,
+
+    /// The number of the handled timeout setting requests.
+    pub timeout_requests: u64,
+
+    /// The number of the timeouts which have expired.
+    pub expired_timeouts: u64,
+}
+
+/// Information about an evented object registered in a poller.
+///
+/// This is created by calling `Poller::registrants_snapshot` method.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegistrantInfo {
+    /// The token which identifies the object in the poller (see `EventedHandle::token`).
+    pub token: mio::Token,
+
+    /// The number of the monitors waiting for the object to become readable.
+    pub read_waitings: usize,
+
+    /// The number of the monitors waiting for the object to become writable.
+    pub write_waitings: usize,
+
+    /// The interest in which the object is registered to the underlying `mio::Poll`.
+    ///
+    /// This is empty if there are no waiting monitors.
+    pub interest: mio::Ready,
+
+    /// The poll options of the object.
+    pub options: mio::PollOpt,
+}
+
+/// A future which will shut down a poller.
+///
+/// This is created by calling `Poller::shutdown` method.
+#[derive(Debug)]
+pub struct ShutdownFuture {
+    poller: Option<Poller>,
+}
+impl Future for ShutdownFuture {
+    type Item = ();
+    type Error = io::Error;
+    fn poll(&mut self) -> futures::Poll<Self::Item, Self::Error> {
+        let poller = self
+            .poller
+            .take()
+            .expect("Cannot poll ShutdownFuture twice");
+        poller.shutdown_now().map(futures::Async::Ready)
+    }
+}
+
+/// The outcome of an iteration of a poller.
+///
+/// This is returned by `Poller::poll_once` method.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PollOutcome {
+    /// The number of the I/O events dispatched in the iteration.
+    pub events: usize,
+
+    /// The number of the requests handled in the iteration.
+    pub requests: usize,
+
+    /// The number of the timeouts which expired in the iteration.
+    pub timeouts: usize,
+}
+impl PollOutcome {
+    /// Returns `true` if nothing was done in the iteration, otherwise `false`.
+    pub fn is_idle(&self) -> bool {
+        self.events == 0 && self.requests == 0 && self.timeouts == 0
+    }
+}
+
+fn shutdown_error() -> io::Error {
+    Error::PollerGone.into()
+}
+
+fn deregistered_error() -> io::Error {
+    Error::Deregistered.into()
+}
+
+fn copy_error(e: &io::Error) -> io::Error {
+    if let Some(code) = e.raw_os_error() {
+        io::Error::from_raw_os_error(code)
+    } else {
+        io::Error::new(e.kind(), e.to_string())
+    }
+}
+
+#[cfg(unix)]
+fn is_error(readiness: mio::Ready) -> bool {
+    mio::unix::UnixReady::from(readiness).is_error()
+}
+#[cfg(not(unix))]
+fn is_error(_readiness: mio::Ready) -> bool {
+    false
+}
+
+#[cfg(unix)]
+fn is_hup(readiness: mio::Ready) -> bool {
+    mio::unix::UnixReady::from(readiness).is_hup()
+}
+#[cfg(not(unix))]
+fn is_hup(_readiness: mio::Ready) -> bool {
+    false
+}
+
+/// The sending side of the request channel of a poller.
+///
+/// Each request wakes up the poller, in case it is blocked waiting for I/O events.
+#[derive(Debug, Clone)]
+struct RequestSender {
+    tx: nb_mpsc::Sender<Request>,
+    readiness: mio::SetReadiness,
+}
+impl RequestSender {
+    fn send(&self, request: Request) -> Result<(), SendError<Request>> {
+        self.tx.send(request)?;
+        self.wakeup();
+        Ok(())
+    }
+    fn wakeup(&self) {
+        let _ = self.readiness.set_readiness(mio::Ready::readable());
     }
 }
 
@@ -231,6 +921,7 @@ impl Poller {
 pub struct PollerHandle {
     request_tx: RequestSender,
     next_timeout_id: Arc<AtomicUsize>,
+    registrant_count: Arc<AtomicUsize>,
     is_alive: bool,
 }
 impl PollerHandle {
@@ -239,25 +930,76 @@ impl PollerHandle {
         self.is_alive
     }
 
+    /// Returns the number of the evented objects registered in the poller.
+    ///
+    /// Note that the registrations and deregistrations are reflected to the value
+    /// after the poller handles the corresponding requests.
+    pub fn registrant_count(&self) -> usize {
+        self.registrant_count.load(atomic::Ordering::SeqCst)
+    }
+
+    /// Wakes up the poller if it is blocked waiting for I/O events.
+    pub(crate) fn wakeup(&self) {
+        self.request_tx.wakeup();
+    }
+
     /// Makes a future to register new evented object to the poller.
+    ///
+    /// The object will be registered with the edge-triggered and oneshot options.
     pub fn register<E>(&mut self, evented: E) -> Register<E>
+    where
+        E: mio::Evented + Send + 'static,
+    {
+        self.register_with_opts(evented, mio::PollOpt::edge() | mio::PollOpt::oneshot())
+    }
+
+    /// Makes a future to register new evented object to the poller with the specified options.
+    ///
+    /// For example, `mio::PollOpt::level()` can be used for
+    /// the objects which are easier to handle in level-triggered fashion.
+    ///
+    /// Whatever options are specified, the waiting monitors of the object are notified
+    /// only once per event (i.e., `EventedHandle::monitor` should be called again to
+    /// wait for subsequent events).
+    /// The options can be changed later by `EventedHandle::monitor_with` method.
+    pub fn register_with_opts<E>(&mut self, evented: E, options: mio::PollOpt) -> Register<E>
     where
         E: mio::Evented + Send + 'static,
     {
         let evented = SharableEvented::new(evented);
         let box_evented = BoxEvented(Box::new(evented.clone()));
+        self.register_sharable(evented, box_evented, options)
+    }
+
+    fn register_sharable<E>(
+        &mut self,
+        evented: SharableEvented<E>,
+        box_evented: BoxEvented,
+        options: mio::PollOpt,
+    ) -> Register<E>
+    where
+        E: mio::Evented + Send + 'static,
+    {
         let request_tx = self.request_tx.clone();
         let (tx, rx) = oneshot::channel();
-        let mut reply = Some(move |token| {
-            let handle = EventedHandle::new(evented, request_tx, token);
-            let _ = tx.send(handle);
+        let mut reply = Some(move |result: io::Result<mio::Token>| {
+            // NOTE: On failure, `evented` is released before replying
+            // so that the registering side can take back the object (see `Migrate`)
+            let result = match result {
+                Ok(token) => Ok(EventedHandle::new(evented, request_tx, token)),
+                Err(e) => {
+                    mem::drop(evented);
+                    Err(e)
+                }
+            };
+            let _ = tx.send(result);
         });
-        let reply = RegisterReplyFn(Box::new(move |token| {
+        let reply = RegisterReplyFn(Box::new(move |result| {
             let reply = reply.take().unwrap();
-            reply(token)
+            reply(result)
         }));
         if self.request_tx
-            .send(Request::Register(box_evented, reply))
+            .send(Request::Register(box_evented, options, reply))
             .is_err()
         {
             self.is_alive = false;
@@ -265,7 +1007,76 @@ impl PollerHandle {
         Register { rx }
     }
 
-    fn set_timeout(&self, delay_from_now: time::Duration) -> Timeout {
+    /// Makes a future to register the evented objects to the poller at once.
+    ///
+    /// This is more efficient than calling `register` method for each of the objects,
+    /// because all of them are registered by a single request.
+    ///
+    /// The resulting handles are in the same order as the objects.
+    pub fn register_all<I, E>(&mut self, evented: I) -> RegisterAll<E>
+    where
+        I: IntoIterator<Item = E>,
+        E: mio::Evented + Send + 'static,
+    {
+        let evented = evented
+            .into_iter()
+            .map(SharableEvented::new)
+            .collect::<Vec<_>>();
+        let box_evented = evented
+            .iter()
+            .map(|e| BoxEvented(Box::new(e.clone())))
+            .collect();
+        let request_tx = self.request_tx.clone();
+        let (tx, rx) = oneshot::channel();
+        let mut reply = Some(move |result: io::Result<Vec<mio::Token>>| {
+            let result = result.map(|tokens| {
+                evented
+                    .into_iter()
+                    .zip(tokens)
+                    .map(|(e, token)| EventedHandle::new(e, request_tx.clone(), token))
+                    .collect()
+            });
+            let _ = tx.send(result);
+        });
+        let reply = RegisterBatchReplyFn(Box::new(move |result| {
+            let reply = reply.take().unwrap();
+            reply(result)
+        }));
+        let options = mio::PollOpt::edge() | mio::PollOpt::oneshot();
+        if self.request_tx
+            .send(Request::RegisterBatch(box_evented, options, reply))
+            .is_err()
+        {
+            self.is_alive = false;
+        }
+        RegisterAll { rx }
+    }
+
+    /// Sets a timer which will expire after `delay_from_now`.
+    ///
+    /// The returning future will be notified by the poller when the timer expires.
+    /// If it is dropped before the expiration, the timer will be cancelled.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate fibers;
+    /// # extern crate futures;
+    /// use fibers::io::poll::Poller;
+    /// use futures::Future;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// # fn main() {
+    /// let mut poller = Poller::new().unwrap();
+    /// let start = Instant::now();
+    /// let mut timeout = poller.handle().set_timeout(Duration::from_millis(50));
+    /// while timeout.poll().unwrap().is_not_ready() {
+    ///     poller.poll(Some(Duration::from_millis(10))).unwrap();
+    /// }
+    /// assert!(start.elapsed() >= Duration::from_millis(50));
+    /// # }
+    /// ```
+    pub fn set_timeout(&self, delay_from_now: time::Duration) -> Timeout {
         let (tx, rx) = oneshot::channel();
         let expiry_time = time::Instant::now() + delay_from_now;
         let timeout_id = self.next_timeout_id.fetch_add(1, atomic::Ordering::SeqCst);
@@ -275,27 +1086,56 @@ impl PollerHandle {
             cancel: Some(CancelTimeout {
                 timeout_id,
                 expiry_time,
+                is_coarse: false,
                 request_tx: self.request_tx.clone(),
             }),
             rx,
         }
     }
-}
-
-pub fn set_timeout(poller: &PollerHandle, delay_from_now: time::Duration) -> Timeout {
-    poller.set_timeout(delay_from_now)
-}
 
-#[derive(Debug)]
-struct CancelTimeout {
-    timeout_id: usize,
-    expiry_time: time::Instant,
-    request_tx: RequestSender,
-}
-impl CancelTimeout {
+    /// Sets a coarse timer which will expire after `delay_from_now`.
+    ///
+    /// Unlike `set_timeout`, the timer is managed by a hashed timing wheel,
+    /// so setting, cancelling and expiring it take amortized constant time.
+    /// This suits a large number of timeouts which do not need precision
+    /// (e.g., an idle timeout per connection).
+    ///
+    /// The expiration time is rounded up to the granularity of the poller
+    /// (see `Poller::set_timer_granularity`), thus the timer never expires early
+    /// but it may expire up to one granularity late.
+    pub fn set_coarse_timeout(&self, delay_from_now: time::Duration) -> Timeout {
+        let (tx, rx) = oneshot::channel();
+        let expiry_time = time::Instant::now() + delay_from_now;
+        let timeout_id = self.next_timeout_id.fetch_add(1, atomic::Ordering::SeqCst);
+        let request = Request::SetCoarseTimeout(timeout_id, expiry_time, tx);
+        let _ = self.request_tx.send(request);
+        Timeout {
+            cancel: Some(CancelTimeout {
+                timeout_id,
+                expiry_time,
+                is_coarse: true,
+                request_tx: self.request_tx.clone(),
+            }),
+            rx,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct CancelTimeout {
+    timeout_id: usize,
+    expiry_time: time::Instant,
+    is_coarse: bool,
+    request_tx: RequestSender,
+}
+impl CancelTimeout {
     pub fn cancel(self) {
-        let _ = self.request_tx
-            .send(Request::CancelTimeout(self.timeout_id, self.expiry_time));
+        let request = if self.is_coarse {
+            Request::CancelCoarseTimeout(self.timeout_id, self.expiry_time)
+        } else {
+            Request::CancelTimeout(self.timeout_id, self.expiry_time)
+        };
+        let _ = self.request_tx.send(request);
     }
 }
 
@@ -307,14 +1147,14 @@ impl CancelTimeout {
 #[derive(Debug)]
 pub struct Timeout {
     cancel: Option<CancelTimeout>,
-    rx: oneshot::Receiver<()>,
+    rx: oneshot::Receiver<io::Result<()>>,
 }
 impl Future for Timeout {
     type Item = ();
-    type Error = RecvError;
+    type Error = Error;
     fn poll(&mut self) -> futures::Poll<Self::Item, Self::Error> {
-        let result = self.rx.poll();
-        if result != Ok(futures::Async::NotReady) {
+        let result = poll_reply(&mut self.rx);
+        if !matches!(result, Ok(futures::Async::NotReady)) {
             self.cancel = None;
         }
         result
@@ -329,15 +1169,46 @@ impl Drop for Timeout {
 }
 
 /// A future which will register a new evented object to a poller.
+///
+/// This fails if the object cannot be registered to the poller
+/// or the poller has been dropped before handling the registration.
+///
+/// On Linux, registering the same file descriptor twice is also detected
+/// (it fails with `io::ErrorKind::AlreadyExists`).
+/// On the other platforms, the duplicate registration may succeed,
+/// and then the readiness events of the descriptor are reported to only one of the handles.
 #[derive(Debug)]
 pub struct Register<T> {
-    rx: oneshot::Receiver<EventedHandle<T>>,
+    rx: oneshot::Receiver<io::Result<EventedHandle<T>>>,
 }
 impl<T> Future for Register<T> {
     type Item = EventedHandle<T>;
-    type Error = RecvError;
+    type Error = Error;
     fn poll(&mut self) -> futures::Poll<Self::Item, Self::Error> {
-        self.rx.poll()
+        poll_reply(&mut self.rx)
+    }
+}
+
+/// A future which will register the evented objects to a poller at once.
+///
+/// This is created by calling `PollerHandle::register_all` method.
+#[derive(Debug)]
+pub struct RegisterAll<T> {
+    rx: oneshot::Receiver<io::Result<Vec<EventedHandle<T>>>>,
+}
+impl<T> Future for RegisterAll<T> {
+    type Item = Vec<EventedHandle<T>>;
+    type Error = Error;
+    fn poll(&mut self) -> futures::Poll<Self::Item, Self::Error> {
+        poll_reply(&mut self.rx)
+    }
+}
+
+fn poll_reply<T>(rx: &mut oneshot::Receiver<io::Result<T>>) -> futures::Poll<T, Error> {
+    match rx.poll() {
+        Err(_) => Err(Error::PollerGone),
+        Ok(futures::Async::NotReady) => Ok(futures::Async::NotReady),
+        Ok(futures::Async::Ready(result)) => result.map(futures::Async::Ready).map_err(Error::from),
     }
 }
 
@@ -363,17 +1234,181 @@ impl<T: mio::Evented> EventedHandle<T> {
     }
 
     /// Monitors occurrence of an event specified by `interest`.
+    ///
+    /// If the poller has been dropped, the returning future will fail with
+    /// `MonitorError::Aborted` instead of waiting forever.
+    /// If all the handles of the object are dropped before the event occurs,
+    /// it will fail with `MonitorError::Failed` carrying an error which explains that.
+    ///
+    /// # Error and hang-up events
+    ///
+    /// Only a hang-up surfaces as an error: the write monitors of a hung up object fail with
+    /// an error of the kind `io::ErrorKind::BrokenPipe`, while the read monitors succeed
+    /// so that the remaining data can be consumed.
+    ///
+    /// An error event (i.e., `mio::unix::UnixReady::is_error`) completes the monitors
+    /// successfully. The poller only holds the object as a `mio::Evented`, so it cannot fetch
+    /// the pending error (`SO_ERROR`) of it, and a generic error would hide the actual cause.
+    /// Instead, the retried operation (e.g., `recv`) reports the actual error.
     pub fn monitor(&self, interest: Interest) -> oneshot::Monitor<(), io::Error> {
-        let (monitored, monitor) = oneshot::monitor();
-        let _ = self.request_tx
-            .send(Request::Monitor(self.token, interest, monitored));
-        monitor
+        self.send_monitor_request(interest, None)
+    }
+
+    /// Monitors occurrence of an event specified by `interest` with the poll options `options`.
+    ///
+    /// The options replace the ones specified at the registration, and
+    /// are also used by the subsequent `monitor` calls.
+    /// For example, a level-triggered object (i.e., `mio::PollOpt::level()`) keeps armed
+    /// while monitors are waiting, so it is not re-registered for each event.
+    ///
+    /// Note that the options are shared by the read and write interests of the object.
+    /// Thus, if other monitors with different options are waiting,
+    /// the returning future will fail with an error of the kind `io::ErrorKind::InvalidInput`.
+    pub fn monitor_with(
+        &self,
+        interest: Interest,
+        options: mio::PollOpt,
+    ) -> oneshot::Monitor<(), io::Error> {
+        self.send_monitor_request(interest, Some(options))
+    }
+
+    /// Monitors occurrence of either a read readiness event or a write readiness event.
+    ///
+    /// The returning future will complete with the interest which became ready first.
+    pub fn monitor_both(&self) -> MonitorBoth {
+        MonitorBoth {
+            read: self.monitor(Interest::Read),
+            write: self.monitor(Interest::Write),
+        }
+    }
+
+    /// Makes a stream which yields an item each time an event specified by `interest` occurs.
+    ///
+    /// The stream keeps the object registered, and re-arms the monitor
+    /// when it is polled after yielding an item.
+    /// Thus, the caller should retry the operation (e.g., `accept`) until it would block
+    /// before polling the stream again, as with the monitor returned by `monitor` method.
+    ///
+    /// The stream never terminates, but fails if the poller has been shut down or dropped.
+    pub fn readiness_stream(self, interest: Interest) -> ReadinessStream<T> {
+        ReadinessStream {
+            handle: self,
+            interest,
+            monitor: None,
+        }
+    }
+
+    /// Makes a future to move the evented object to the poller of `new_handle`.
+    ///
+    /// The object is deregistered from the current poller, and then
+    /// registered to the new one with the same poll options.
+    /// The future will resolve to the handle of the object in the new poller.
+    ///
+    /// The handle must not have clones,
+    /// otherwise the future will fail with an error of the kind `io::ErrorKind::InvalidInput`.
+    /// If the migration fails for the other reasons,
+    /// the evented object is returned to the caller along with the error.
+    ///
+    /// Note that the network types of mio (e.g., `mio::net::TcpStream`) refuse to be registered
+    /// to a `mio::Poll` other than the first one even after deregistration.
+    /// Thus, the migration of those objects fails.
+    /// The objects which are registered via `mio::unix::EventedFd`
+    /// (e.g., `fibers::net::UnixStream`) can be migrated.
+    ///
+    /// # Pending Monitors
+    ///
+    /// The monitors which are waiting on the current poller will fail with
+    /// `Error::Deregistered` (as if the handle is dropped).
+    /// After the migration completes, the callers should retry their operations
+    /// and monitor the object via the new handle.
+    pub fn migrate(self, new_handle: &mut PollerHandle) -> Migrate<T>
+    where
+        T: Send + 'static,
+    {
+        if self.shared_count.load(atomic::Ordering::SeqCst) != 1 {
+            let e = io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Cannot migrate an evented handle which has clones",
+            );
+            return Migrate {
+                phase: MigratePhase::Failed(Some(Error::Io(e))),
+                inner: None,
+            };
+        }
+
+        let (tx, rx) = oneshot::channel();
+        let _ = self
+            .request_tx
+            .send(Request::Deregister(self.token, Some(tx)));
+
+        // NOTE: This prevents the handle from sending another deregistration request on drop
+        self.shared_count.fetch_add(1, atomic::Ordering::SeqCst);
+
+        Migrate {
+            phase: MigratePhase::Deregister(rx, new_handle.clone()),
+            inner: Some(self.inner.clone()),
+        }
+    }
+
+    /// Returns the token which identifies the evented object in the poller.
+    ///
+    /// The token is unique among the objects registered in the same poller,
+    /// and shared by all the clones of this handle.
+    /// It may be reused after all of them are dropped.
+    pub fn token(&self) -> mio::Token {
+        self.token
     }
 
     /// Returns the locked reference to the inner evented object.
-    pub fn inner(&self) -> EventedLock<T> {
+    pub fn inner(&self) -> EventedLock<'_, T> {
         self.inner.lock()
     }
+
+    /// Performs the non-blocking operation `f` on the inner evented object.
+    ///
+    /// If `f` would block, a monitor of `interest` is stored in `monitor` and
+    /// the `WouldBlock` error is returned.
+    /// The subsequent calls retry `f` only after the monitor has been notified.
+    pub(crate) fn operate<F, U>(
+        &self,
+        monitor: &mut Option<oneshot::Monitor<(), io::Error>>,
+        interest: Interest,
+        mut f: F,
+    ) -> io::Result<U>
+    where
+        F: FnMut(&mut T) -> io::Result<U>,
+    {
+        loop {
+            if let Some(mut m) = monitor.take() {
+                let polled = m.poll().map_err(|e| {
+                    e.unwrap_or_else(|| io::Error::other("Monitor channel disconnected"))
+                });
+                if let futures::Async::NotReady = polled? {
+                    *monitor = Some(m);
+                    return Err(mio::would_block());
+                }
+            } else {
+                match f(&mut self.inner()) {
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        *monitor = Some(self.monitor(interest));
+                    }
+                    result => return result,
+                }
+            }
+        }
+    }
+
+    fn send_monitor_request(
+        &self,
+        interest: Interest,
+        options: Option<mio::PollOpt>,
+    ) -> oneshot::Monitor<(), io::Error> {
+        let (monitored, monitor) = oneshot::monitor();
+        let _ = self
+            .request_tx
+            .send(Request::Monitor(self.token, interest, options, monitored));
+        monitor
+    }
 }
 impl<T> Clone for EventedHandle<T> {
     fn clone(&self) -> Self {
@@ -389,30 +1424,1089 @@ impl<T> Clone for EventedHandle<T> {
 impl<T> Drop for EventedHandle<T> {
     fn drop(&mut self) {
         if 1 == self.shared_count.fetch_sub(1, atomic::Ordering::SeqCst) {
-            let _ = self.request_tx.send(Request::Deregister(self.token));
+            let _ = self.request_tx.send(Request::Deregister(self.token, None));
+        }
+    }
+}
+
+/// A future which will move an evented object from a poller to another.
+///
+/// This is created by calling `EventedHandle::migrate` method.
+///
+/// If the migration fails, the future returns the evented object along with the error
+/// (the object is `None` if the handle has clones).
+#[derive(Debug)]
+pub struct Migrate<T> {
+    phase: MigratePhase<T>,
+    inner: Option<SharableEvented<T>>,
+}
+impl<T> Migrate<T>
+where
+    T: mio::Evented,
+{
+    fn reclaim(&mut self) -> Option<T> {
+        let mut inner = self.inner.take()?;
+        loop {
+            // NOTE: The other references are held by the pollers only while they are handling
+            // the requests, and are released just after the failure is reported
+            match inner.try_unwrap() {
+                Ok(evented) => return Some(evented),
+                Err(shared) => inner = shared,
+            }
+            thread::yield_now();
+        }
+    }
+}
+impl<T> Future for Migrate<T>
+where
+    T: mio::Evented + Send + 'static,
+{
+    type Item = EventedHandle<T>;
+    type Error = (Option<T>, Error);
+    fn poll(&mut self) -> futures::Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.phase {
+                MigratePhase::Deregister(ref mut rx, ref mut poller) => match rx.poll() {
+                    Err(_) => MigratePhase::Failed(Some(Error::PollerGone)),
+                    Ok(futures::Async::NotReady) => return Ok(futures::Async::NotReady),
+                    Ok(futures::Async::Ready((box_evented, options))) => {
+                        let inner = assert_some!(self.inner.clone());
+                        let future = poller.register_sharable(inner, box_evented, options);
+                        MigratePhase::Register(future)
+                    }
+                },
+                MigratePhase::Register(ref mut future) => match future.poll() {
+                    Err(e) => MigratePhase::Failed(Some(e)),
+                    Ok(futures::Async::NotReady) => return Ok(futures::Async::NotReady),
+                    Ok(futures::Async::Ready(handle)) => {
+                        self.inner = None;
+                        return Ok(futures::Async::Ready(handle));
+                    }
+                },
+                MigratePhase::Failed(ref mut e) => {
+                    let e = e.take().expect("Cannot poll Migrate twice");
+                    return Err((self.reclaim(), e));
+                }
+            };
+            self.phase = next;
+        }
+    }
+}
+
+#[derive(Debug)]
+enum MigratePhase<T> {
+    Deregister(oneshot::Receiver<(BoxEvented, mio::PollOpt)>, PollerHandle),
+    Register(Register<T>),
+    Failed(Option<Error>),
+}
+
+/// A future which will complete when the evented object becomes readable or writable.
+///
+/// This is created by calling `EventedHandle::monitor_both` method.
+#[derive(Debug)]
+pub struct MonitorBoth {
+    read: oneshot::Monitor<(), io::Error>,
+    write: oneshot::Monitor<(), io::Error>,
+}
+impl Future for MonitorBoth {
+    type Item = Interest;
+    type Error = Error;
+    fn poll(&mut self) -> futures::Poll<Self::Item, Self::Error> {
+        if let futures::Async::Ready(()) = self.read.poll()? {
+            return Ok(futures::Async::Ready(Interest::Read));
+        }
+        if let futures::Async::Ready(()) = self.write.poll()? {
+            return Ok(futures::Async::Ready(Interest::Write));
+        }
+        Ok(futures::Async::NotReady)
+    }
+}
+
+/// A stream which yields an item each time an evented object becomes ready.
+///
+/// This is created by calling `EventedHandle::readiness_stream` method.
+#[derive(Debug)]
+pub struct ReadinessStream<T> {
+    handle: EventedHandle<T>,
+    interest: Interest,
+    monitor: Option<oneshot::Monitor<(), io::Error>>,
+}
+impl<T: mio::Evented> ReadinessStream<T> {
+    /// Returns the handle of the monitored object.
+    pub fn handle(&self) -> &EventedHandle<T> {
+        &self.handle
+    }
+
+    /// Returns the interest monitored by this stream.
+    pub fn interest(&self) -> Interest {
+        self.interest
+    }
+}
+impl<T: mio::Evented> Stream for ReadinessStream<T> {
+    type Item = ();
+    type Error = Error;
+    fn poll(&mut self) -> futures::Poll<Option<Self::Item>, Self::Error> {
+        let mut monitor = self
+            .monitor
+            .take()
+            .unwrap_or_else(|| self.handle.monitor(self.interest));
+        if let futures::Async::Ready(()) = monitor.poll()? {
+            Ok(futures::Async::Ready(Some(())))
+        } else {
+            self.monitor = Some(monitor);
+            Ok(futures::Async::NotReady)
         }
     }
 }
 
-struct BoxEvented(Box<mio::Evented + Send + 'static>);
+struct BoxEvented(Box<dyn mio::Evented + Send + 'static>);
 impl fmt::Debug for BoxEvented {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "BoxEvented(_)")
     }
 }
 
-struct RegisterReplyFn(Box<FnMut(mio::Token) + Send + 'static>);
+struct RegisterReplyFn(Box<dyn FnMut(io::Result<mio::Token>) + Send + 'static>);
 impl fmt::Debug for RegisterReplyFn {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "RegisterReplyFn(_)")
     }
 }
 
+struct RegisterBatchReplyFn(Box<dyn FnMut(io::Result<Vec<mio::Token>>) + Send + 'static>);
+impl fmt::Debug for RegisterBatchReplyFn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RegisterBatchReplyFn(_)")
+    }
+}
+
 #[derive(Debug)]
 enum Request {
-    Register(BoxEvented, RegisterReplyFn),
-    Deregister(mio::Token),
-    Monitor(mio::Token, Interest, oneshot::Monitored<(), io::Error>),
-    SetTimeout(usize, time::Instant, oneshot::Sender<()>),
+    Register(BoxEvented, mio::PollOpt, RegisterReplyFn),
+    RegisterBatch(Vec<BoxEvented>, mio::PollOpt, RegisterBatchReplyFn),
+    Deregister(
+        mio::Token,
+        Option<oneshot::Sender<(BoxEvented, mio::PollOpt)>>,
+    ),
+    Monitor(
+        mio::Token,
+        Interest,
+        Option<mio::PollOpt>,
+        oneshot::Monitored<(), io::Error>,
+    ),
+    SetTimeout(usize, time::Instant, TimeoutNotifier),
     CancelTimeout(usize, time::Instant),
+    SetCoarseTimeout(usize, time::Instant, TimeoutNotifier),
+    CancelCoarseTimeout(usize, time::Instant),
+}
+
+#[cfg(test)]
+mod test {
+    use futures::{Async, Future};
+    #[cfg(unix)]
+    use mio::unix::EventedFd;
+    #[cfg(unix)]
+    use std::os::unix::io::{AsRawFd, RawFd};
+    use std::time::Duration;
+
+    use super::*;
+
+    #[cfg(unix)]
+    #[derive(Debug)]
+    struct RawFdEvented(RawFd);
+    #[cfg(unix)]
+    impl mio::Evented for RawFdEvented {
+        fn register(
+            &self,
+            poll: &mio::Poll,
+            token: mio::Token,
+            interest: mio::Ready,
+            opts: mio::PollOpt,
+        ) -> io::Result<()> {
+            EventedFd(&self.0).register(poll, token, interest, opts)
+        }
+        fn reregister(
+            &self,
+            poll: &mio::Poll,
+            token: mio::Token,
+            interest: mio::Ready,
+            opts: mio::PollOpt,
+        ) -> io::Result<()> {
+            EventedFd(&self.0).reregister(poll, token, interest, opts)
+        }
+        fn deregister(&self, poll: &mio::Poll) -> io::Result<()> {
+            EventedFd(&self.0).deregister(poll)
+        }
+    }
+
+    #[test]
+    fn set_timeout_works() {
+        let mut poller = Poller::new().unwrap();
+        let mut timeout = poller.handle().set_timeout(Duration::from_millis(10));
+        assert_eq!(timeout.poll().ok(), Some(Async::NotReady));
+
+        poller.poll(Some(Duration::from_millis(0))).unwrap();
+        assert_eq!(poller.timeout_queue.len(), 1);
+
+        while timeout.poll().ok() == Some(Async::NotReady) {
+            poller.poll(Some(Duration::from_millis(1))).unwrap();
+        }
+        assert_eq!(poller.timeout_queue.len(), 0);
+    }
+
+    #[test]
+    fn poll_wakes_up_at_nearest_expiry_time() {
+        let mut poller = Poller::new().unwrap();
+        let mut timeout = poller.handle().set_timeout(Duration::from_millis(20));
+        poller.poll(Some(Duration::from_millis(0))).unwrap();
+
+        // Without the expiry time of the timer, this call would block forever.
+        poller.poll(None).unwrap();
+        assert_eq!(timeout.poll().ok(), Some(Async::Ready(())));
+    }
+
+    #[test]
+    fn requests_wake_up_blocking_poll() {
+        let mut poller = Poller::new().unwrap();
+        let mut handle = poller.handle();
+        let (tx, rx) = std::sync::mpsc::channel();
+        let thread = std::thread::spawn(move || {
+            let mut outcome = PollOutcome::default();
+            while outcome.requests == 0 {
+                outcome = poller.poll_once(Some(Duration::from_secs(10))).unwrap();
+            }
+            tx.send(outcome).unwrap();
+            poller
+        });
+
+        // Lets the poller start blocking
+        std::thread::sleep(Duration::from_millis(50));
+
+        let start = time::Instant::now();
+        let socket = mio::net::UdpSocket::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let mut register = handle.register(socket);
+        let outcome = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(outcome.requests, 1);
+        assert_eq!(outcome.events, 0);
+        assert!(register.poll().unwrap().is_ready());
+
+        let poller = thread.join().unwrap();
+        assert_eq!(poller.registrant_count(), 1);
+    }
+
+    #[test]
+    fn monitor_both_works() {
+        let mut poller = Poller::new().unwrap();
+        let socket = mio::net::UdpSocket::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let mut register = poller.register(socket);
+        poller.poll(Some(Duration::from_millis(0))).unwrap();
+        let handle = match register.poll() {
+            Ok(Async::Ready(handle)) => handle,
+            other => panic!("{:?}", other),
+        };
+
+        // An idle UDP socket is writable but not readable.
+        let mut monitor = handle.monitor_both();
+        loop {
+            match monitor.poll() {
+                Ok(Async::NotReady) => poller.poll(Some(Duration::from_millis(1))).unwrap(),
+                Ok(Async::Ready(interest)) => {
+                    assert_eq!(interest, Interest::Write);
+                    break;
+                }
+                Err(e) => panic!("{:?}", e),
+            }
+        }
+    }
+
+    #[test]
+    fn monitor_fails_if_poller_is_dropped() {
+        let mut poller = Poller::new().unwrap();
+        let socket = mio::net::UdpSocket::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let mut register = poller.register(socket);
+        poller.poll(Some(Duration::from_millis(0))).unwrap();
+        let handle = match register.poll() {
+            Ok(Async::Ready(handle)) => handle,
+            other => panic!("{:?}", other),
+        };
+
+        std::mem::drop(poller);
+        let mut monitor = handle.monitor(Interest::Read);
+        match monitor.poll() {
+            Err(oneshot::MonitorError::Aborted) => {}
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn monitor_fails_if_handle_is_dropped() {
+        let mut poller = Poller::new().unwrap();
+        let socket = mio::net::UdpSocket::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let mut register = poller.register(socket);
+        poller.poll(Some(Duration::from_millis(0))).unwrap();
+        let handle = match register.poll() {
+            Ok(Async::Ready(handle)) => handle,
+            other => panic!("{:?}", other),
+        };
+
+        let mut monitor = handle.monitor(Interest::Read);
+        std::mem::drop(handle);
+        poller.poll(Some(Duration::from_millis(0))).unwrap();
+        match monitor.poll() {
+            Err(oneshot::MonitorError::Failed(e)) => {
+                assert!(matches!(Error::from(e), Error::Deregistered));
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn metrics_works() {
+        let mut poller = Poller::new().unwrap();
+        let socket = mio::net::UdpSocket::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let mut register = poller.register(socket);
+        poller.poll(Some(Duration::from_millis(0))).unwrap();
+        assert!(register.poll().unwrap().is_ready());
+        assert_eq!(poller.registrant_count(), 1);
+
+        let mut timeout = poller.handle().set_timeout(Duration::from_millis(0));
+        while timeout.poll().unwrap().is_not_ready() {
+            poller.poll(Some(Duration::from_millis(1))).unwrap();
+        }
+
+        let metrics = poller.metrics();
+        assert_eq!(metrics.register_requests, 1);
+        assert_eq!(metrics.timeout_requests, 1);
+        assert_eq!(metrics.expired_timeouts, 1);
+        assert_eq!(metrics.events, 0);
+    }
+
+    #[test]
+    fn poll_once_works() {
+        let mut poller = Poller::new().unwrap();
+        let outcome = poller.poll_once(Some(Duration::from_millis(0))).unwrap();
+        assert!(outcome.is_idle());
+
+        let socket = mio::net::UdpSocket::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let mut register = poller.register(socket);
+        let outcome = poller.poll_once(Some(Duration::from_millis(0))).unwrap();
+        assert_eq!(outcome.requests, 1);
+        assert!(!outcome.is_idle());
+        let handle = match register.poll() {
+            Ok(Async::Ready(handle)) => handle,
+            other => panic!("{:?}", other),
+        };
+
+        let mut monitor = handle.monitor(Interest::Write);
+        let mut events = 0;
+        while monitor.poll().unwrap().is_not_ready() {
+            events += poller.poll_once(Some(Duration::from_millis(1))).unwrap().events;
+        }
+        assert_eq!(events, 1);
+    }
+
+    #[test]
+    fn builder_works() {
+        let poller = Poller::builder().build().unwrap();
+        assert_eq!(poller.events.0.capacity(), DEFAULT_EVENTS_CAPACITY);
+        assert_eq!(poller.request_budget(), DEFAULT_REQUEST_BUDGET);
+        assert_eq!(
+            poller.timer_granularity(),
+            Duration::from_millis(DEFAULT_TIMER_GRANULARITY_MS)
+        );
+
+        let poller = Poller::builder()
+            .events_capacity(16)
+            .request_budget(8)
+            .timer_granularity(Duration::from_millis(100))
+            .build()
+            .unwrap();
+        assert_eq!(poller.events.0.capacity(), 16);
+        assert_eq!(poller.request_budget(), 8);
+        assert_eq!(poller.timer_granularity(), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn requests_are_drained_up_to_budget() {
+        let mut poller = Poller::new().unwrap();
+        assert_eq!(poller.request_budget(), DEFAULT_REQUEST_BUDGET);
+
+        let mut registers = (0..100)
+            .map(|_| {
+                let socket = mio::net::UdpSocket::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+                poller.register(socket)
+            })
+            .collect::<Vec<_>>();
+        let outcome = poller.poll_once(Some(Duration::from_millis(0))).unwrap();
+        assert_eq!(outcome.requests, DEFAULT_REQUEST_BUDGET);
+        let outcome = poller.poll_once(Some(Duration::from_millis(0))).unwrap();
+        assert_eq!(outcome.requests, 100 - DEFAULT_REQUEST_BUDGET);
+        assert_eq!(poller.registrant_count(), 100);
+        for r in &mut registers {
+            assert!(r.poll().unwrap().is_ready());
+        }
+
+        poller.set_request_budget(1);
+        let _ = poller.handle().set_timeout(Duration::from_secs(1));
+        let _ = poller.handle().set_timeout(Duration::from_secs(1));
+        let outcome = poller.poll_once(Some(Duration::from_millis(0))).unwrap();
+        assert_eq!(outcome.requests, 1);
+    }
+
+    #[test]
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn double_registration_fails() {
+        #[derive(Clone)]
+        struct SharedSocket(Arc<mio::net::UdpSocket>);
+        impl mio::Evented for SharedSocket {
+            fn register(
+                &self,
+                poll: &mio::Poll,
+                token: mio::Token,
+                interest: mio::Ready,
+                opts: mio::PollOpt,
+            ) -> io::Result<()> {
+                self.0.register(poll, token, interest, opts)
+            }
+            fn reregister(
+                &self,
+                poll: &mio::Poll,
+                token: mio::Token,
+                interest: mio::Ready,
+                opts: mio::PollOpt,
+            ) -> io::Result<()> {
+                self.0.reregister(poll, token, interest, opts)
+            }
+            fn deregister(&self, poll: &mio::Poll) -> io::Result<()> {
+                self.0.deregister(poll)
+            }
+        }
+
+        let mut poller = Poller::new().unwrap();
+        let socket = mio::net::UdpSocket::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let socket = SharedSocket(Arc::new(socket));
+        let mut first = poller.register(socket.clone());
+        let mut second = poller.register(socket.clone());
+        poller.poll(Some(Duration::from_millis(0))).unwrap();
+        poller.poll(Some(Duration::from_millis(0))).unwrap();
+        let handle = match first.poll() {
+            Ok(Async::Ready(handle)) => handle,
+            other => panic!("{:?}", other.map(|_| ())),
+        };
+        match second.poll() {
+            Err(Error::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::AlreadyExists),
+            other => panic!("{:?}", other.map(|_| ())),
+        }
+        assert_eq!(poller.registrant_count(), 1);
+
+        let mut all = poller.handle().register_all(vec![socket.clone()]);
+        poller.poll(Some(Duration::from_millis(0))).unwrap();
+        assert!(all.poll().is_err());
+        assert_eq!(poller.registrant_count(), 1);
+
+        // The detection works even after all the level-triggered monitors are notified
+        let mut monitor = handle.monitor_with(Interest::Write, mio::PollOpt::level());
+        while monitor.poll().unwrap().is_not_ready() {
+            poller.poll(Some(Duration::from_millis(1))).unwrap();
+        }
+        poller.poll(Some(Duration::from_millis(0))).unwrap();
+        let mut third = poller.register(socket.clone());
+        poller.poll(Some(Duration::from_millis(0))).unwrap();
+        match third.poll() {
+            Err(Error::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::AlreadyExists),
+            other => panic!("{:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn always_ready_objects_do_not_starve_each_other() {
+        // Only one event can be dispatched per iteration
+        let mut poller = Poller::with_capacity(1).unwrap();
+        let sender = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut registers = (0..2)
+            .map(|_| {
+                let socket = mio::net::UdpSocket::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+
+                // The datagram is never received, so the socket is always readable
+                sender.send_to(b"foo", socket.local_addr().unwrap()).unwrap();
+                poller.register_with_opts(socket, mio::PollOpt::level())
+            })
+            .collect::<Vec<_>>();
+        poller.poll(Some(Duration::from_millis(0))).unwrap();
+        let handles = registers
+            .iter_mut()
+            .map(|r| match r.poll() {
+                Ok(Async::Ready(handle)) => handle,
+                other => panic!("{:?}", other.map(|_| ())),
+            })
+            .collect::<Vec<_>>();
+
+        let mut served = [0, 0];
+        let mut monitors = handles
+            .iter()
+            .map(|h| h.monitor(Interest::Read))
+            .collect::<Vec<_>>();
+        for _ in 0..20 {
+            poller.poll(Some(Duration::from_millis(10))).unwrap();
+            for (i, monitor) in monitors.iter_mut().enumerate() {
+                if monitor.poll().unwrap().is_ready() {
+                    served[i] += 1;
+                    *monitor = handles[i].monitor(Interest::Read);
+                }
+            }
+        }
+        assert!(served[0] >= 5, "{:?}", served);
+        assert!(served[1] >= 5, "{:?}", served);
+    }
+
+    #[test]
+    fn level_triggered_registration_works() {
+        let mut poller = Poller::new().unwrap();
+        let socket = mio::net::UdpSocket::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let mut register = poller.register_with_opts(socket, mio::PollOpt::level());
+        poller.poll(Some(Duration::from_millis(0))).unwrap();
+        let handle = match register.poll() {
+            Ok(Async::Ready(handle)) => handle,
+            other => panic!("{:?}", other),
+        };
+
+        for _ in 0..3 {
+            let mut monitor = handle.monitor(Interest::Write);
+            while monitor.poll().unwrap().is_not_ready() {
+                poller.poll(Some(Duration::from_millis(1))).unwrap();
+            }
+        }
+        assert_eq!(poller.metrics().writable_events, 3);
+    }
+
+    #[test]
+    fn readiness_stream_works() {
+        let mut poller = Poller::new().unwrap();
+        let socket = mio::net::UdpSocket::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = socket.local_addr().unwrap();
+        let mut register = poller.register(socket);
+        poller.poll(Some(Duration::from_millis(0))).unwrap();
+        let handle = match register.poll() {
+            Ok(Async::Ready(handle)) => handle,
+            other => panic!("{:?}", other),
+        };
+
+        let mut stream = handle.readiness_stream(Interest::Read);
+        let sender = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut buf = [0; 8];
+        for _ in 0..3 {
+            poller.poll(Some(Duration::from_millis(0))).unwrap();
+            assert_eq!(stream.poll().ok(), Some(Async::NotReady));
+
+            sender.send_to(b"foo", addr).unwrap();
+            loop {
+                match stream.poll() {
+                    Ok(Async::NotReady) => poller.poll(Some(Duration::from_millis(1))).unwrap(),
+                    Ok(Async::Ready(item)) => {
+                        assert_eq!(item, Some(()));
+                        break;
+                    }
+                    Err(e) => panic!("{:?}", e),
+                }
+            }
+            let received = stream.handle().inner().recv_from(&mut buf).ok();
+            assert_eq!(received, Some((3, sender.local_addr().unwrap())));
+        }
+
+        std::mem::drop(poller);
+        assert!(matches!(stream.poll(), Err(Error::PollerGone)));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn broken_registrant_does_not_affect_others() {
+        use std::os::unix::io::IntoRawFd;
+
+        let mut poller = Poller::new().unwrap();
+        let fd = std::net::UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .into_raw_fd();
+        let socket = mio::net::UdpSocket::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = socket.local_addr().unwrap();
+        let mut handle = poller.handle();
+        let mut register = handle
+            .register(RawFdEvented(fd))
+            .join(handle.register(socket));
+        poller.poll(Some(Duration::from_millis(0))).unwrap();
+        let (broken, healthy) = match register.poll() {
+            Ok(Async::Ready(handles)) => handles,
+            other => panic!("{:?}", other.map(|_| ())),
+        };
+
+        // Closes the file descriptor behind the poller's back
+        unsafe {
+            libc::close(fd);
+        }
+        let mut monitor = broken.monitor(Interest::Read);
+        poller.poll(Some(Duration::from_millis(0))).unwrap();
+        match monitor.poll() {
+            Err(oneshot::MonitorError::Failed(_)) => {}
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(poller.metrics().failed_registrations, 1);
+
+        // The subsequent monitors fail immediately
+        let mut monitor = broken.monitor(Interest::Write);
+        poller.poll(Some(Duration::from_millis(0))).unwrap();
+        assert!(monitor.poll().is_err());
+
+        // The other objects keep working
+        let mut monitor = healthy.monitor(Interest::Read);
+        let sender = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        sender.send_to(b"foo", addr).unwrap();
+        while monitor.poll().unwrap().is_not_ready() {
+            poller.poll(Some(Duration::from_millis(1))).unwrap();
+        }
+
+        std::mem::drop(broken);
+        poller.poll(Some(Duration::from_millis(0))).unwrap();
+        assert_eq!(poller.registrant_count(), 1);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn readiness_hint_works() {
+        use std::io::Write;
+        use std::os::unix::net::UnixStream;
+
+        let mut poller = Poller::new().unwrap();
+        let (a, b) = UnixStream::pair().unwrap();
+        a.set_nonblocking(true).unwrap();
+        let fd = a.as_raw_fd();
+        let mut register = poller.register(RawFdEvented(fd));
+        poller.poll(Some(Duration::from_millis(0))).unwrap();
+        let handle = match register.poll() {
+            Ok(Async::Ready(handle)) => handle,
+            other => panic!("{:?}", other),
+        };
+
+        // Fills the send buffer so that the write monitor keeps waiting
+        while (&a).write(&[0; 1024]).is_ok() {}
+        let mut write_monitor = handle.monitor(Interest::Write);
+        poller.poll(Some(Duration::from_millis(0))).unwrap();
+        assert_eq!(write_monitor.poll().ok(), Some(Async::NotReady));
+
+        // The hang-up is reported while no read monitors are waiting
+        std::mem::drop(b);
+        while write_monitor.poll().is_ok() {
+            poller.poll(Some(Duration::from_millis(1))).unwrap();
+        }
+
+        // The readiness is notified to the next read monitor without polling I/O events
+        let mut read_monitor = handle.monitor(Interest::Read);
+        poller.handle_requests(&mut PollOutcome::default()).unwrap();
+        assert_eq!(read_monitor.poll().ok(), Some(Async::Ready(())));
+        std::mem::drop(a);
+    }
+
+    #[test]
+    fn edge_triggered_reads_are_not_lost() {
+        use std::io::{Read, Write};
+        use std::thread;
+
+        const TOTAL_BYTES: usize = 1024 * 1024;
+
+        let mut poller = Poller::new().unwrap();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut writer = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let reader = mio::net::TcpStream::from_stream(listener.accept().unwrap().0).unwrap();
+        let mut register = poller.register_with_opts(reader, mio::PollOpt::edge());
+        poller.poll(Some(Duration::from_millis(0))).unwrap();
+        let handle = match register.poll() {
+            Ok(Async::Ready(handle)) => handle,
+            other => panic!("{:?}", other),
+        };
+
+        let writer = thread::spawn(move || {
+            let mut written = 0;
+            for i in 0.. {
+                let size = cmp::min(1 + (i * 7919) % 4096, TOTAL_BYTES - written);
+                if size == 0 {
+                    break;
+                }
+                writer.write_all(&vec![0; size]).unwrap();
+                written += size;
+                if i % 16 == 0 {
+                    thread::yield_now();
+                }
+            }
+        });
+
+        let mut buf = [0; 1024];
+        let mut read = 0;
+        while read < TOTAL_BYTES {
+            match handle.inner().read(&mut buf) {
+                Ok(0) => panic!("Unexpected EOF"),
+                Ok(size) => {
+                    read += size;
+                    continue;
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => panic!("{:?}", e),
+            }
+
+            // Each monitor must be woken up by the data which has arrived after the last read
+            let mut monitor = handle.monitor(Interest::Read);
+            let start = time::Instant::now();
+            while monitor.poll().unwrap().is_not_ready() {
+                assert!(start.elapsed() < Duration::from_secs(5), "read={}", read);
+                poller.poll(Some(Duration::from_millis(10))).unwrap();
+            }
+        }
+        writer.join().unwrap();
+        assert_eq!(read, TOTAL_BYTES);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn migrate_works() {
+        let mut old_poller = Poller::new().unwrap();
+        let mut new_poller = Poller::new().unwrap();
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_nonblocking(true).unwrap();
+        let addr = socket.local_addr().unwrap();
+        let mut register = old_poller.register(RawFdEvented(socket.as_raw_fd()));
+        old_poller.poll(Some(Duration::from_millis(0))).unwrap();
+        let handle = match register.poll() {
+            Ok(Async::Ready(handle)) => handle,
+            other => panic!("{:?}", other),
+        };
+
+        // A handle which has clones cannot be migrated
+        let mut migrate = handle.clone().migrate(&mut new_poller.handle());
+        match migrate.poll() {
+            Err((None, Error::Io(e))) => assert_eq!(e.kind(), io::ErrorKind::InvalidInput),
+            other => panic!("{:?}", other),
+        }
+
+        let mut pending_monitor = handle.monitor(Interest::Read);
+        let mut migrate = handle.migrate(&mut new_poller.handle());
+        old_poller.poll(Some(Duration::from_millis(0))).unwrap();
+        assert_eq!(old_poller.registrant_count(), 0);
+        match pending_monitor.poll() {
+            Err(oneshot::MonitorError::Failed(e)) => {
+                assert!(matches!(Error::from(e), Error::Deregistered));
+            }
+            other => panic!("{:?}", other),
+        }
+
+        assert!(migrate.poll().unwrap().is_not_ready());
+        new_poller.poll(Some(Duration::from_millis(0))).unwrap();
+        let handle = match migrate.poll() {
+            Ok(Async::Ready(handle)) => handle,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(new_poller.registrant_count(), 1);
+
+        // The object is served by the new poller
+        let mut monitor = handle.monitor(Interest::Read);
+        let sender = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        sender.send_to(b"foo", addr).unwrap();
+        while monitor.poll().unwrap().is_not_ready() {
+            new_poller.poll(Some(Duration::from_millis(1))).unwrap();
+        }
+        old_poller.poll(Some(Duration::from_millis(0))).unwrap();
+        assert_eq!(old_poller.metrics().events, 0);
+    }
+
+    #[test]
+    fn failed_migration_returns_object() {
+        let mut old_poller = Poller::new().unwrap();
+        let mut new_poller = Poller::new().unwrap();
+        let socket = mio::net::UdpSocket::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = socket.local_addr().unwrap();
+        let mut register = old_poller.register(socket);
+        old_poller.poll(Some(Duration::from_millis(0))).unwrap();
+        let handle = match register.poll() {
+            Ok(Async::Ready(handle)) => handle,
+            other => panic!("{:?}", other),
+        };
+
+        // The network types of mio cannot be registered to another `mio::Poll`
+        let mut migrate = handle.migrate(&mut new_poller.handle());
+        old_poller.poll(Some(Duration::from_millis(0))).unwrap();
+        assert!(migrate.poll().unwrap().is_not_ready());
+        new_poller.poll(Some(Duration::from_millis(0))).unwrap();
+        let socket = match migrate.poll() {
+            Err((Some(socket), Error::Io(_))) => socket,
+            other => panic!("{:?}", other.map(|_| ())),
+        };
+        assert_eq!(socket.local_addr().unwrap(), addr);
+        assert_eq!(old_poller.registrant_count(), 0);
+        assert_eq!(new_poller.registrant_count(), 0);
+    }
+
+    #[test]
+    fn monitor_with_works() {
+        let mut poller = Poller::new().unwrap();
+        let socket = mio::net::UdpSocket::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = socket.local_addr().unwrap();
+        let mut register = poller.register(socket);
+        poller.poll(Some(Duration::from_millis(0))).unwrap();
+        let handle = match register.poll() {
+            Ok(Async::Ready(handle)) => handle,
+            other => panic!("{:?}", other),
+        };
+
+        // The unread datagram keeps the socket readable
+        let sender = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        sender.send_to(b"foo", addr).unwrap();
+        for _ in 0..3 {
+            let mut monitor = handle.monitor_with(Interest::Read, mio::PollOpt::level());
+            while monitor.poll().unwrap().is_not_ready() {
+                poller.poll(Some(Duration::from_millis(1))).unwrap();
+            }
+        }
+        assert_eq!(poller.metrics().readable_events, 3);
+
+        // While level-triggered monitors are waiting, the registration is kept as it is
+        let monitor0 = handle.monitor(Interest::Write);
+        let monitor1 = handle.monitor(Interest::Read);
+        poller.handle_requests(&mut PollOutcome::default()).unwrap();
+        let reregistrations = poller.metrics().reregistrations;
+        let monitor2 = handle.monitor(Interest::Read);
+        poller.handle_requests(&mut PollOutcome::default()).unwrap();
+        assert_eq!(poller.metrics().reregistrations, reregistrations);
+
+        // The options cannot be changed while monitors are waiting
+        let mut monitor4 = handle.monitor_with(Interest::Write, mio::PollOpt::edge());
+        poller.poll(Some(Duration::from_millis(0))).unwrap();
+        match monitor4.poll() {
+            Err(oneshot::MonitorError::Failed(e)) => {
+                assert_eq!(e.kind(), io::ErrorKind::InvalidInput)
+            }
+            other => panic!("{:?}", other),
+        }
+        for monitor in &mut [monitor0, monitor1, monitor2] {
+            while monitor.poll().unwrap().is_not_ready() {
+                poller.poll(Some(Duration::from_millis(1))).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn registrants_snapshot_works() {
+        let mut poller = Poller::new().unwrap();
+        let sockets = (0..2)
+            .map(|_| mio::net::UdpSocket::bind(&"127.0.0.1:0".parse().unwrap()).unwrap())
+            .collect::<Vec<_>>();
+        let mut register = poller.handle().register_all(sockets);
+        poller.poll(Some(Duration::from_millis(0))).unwrap();
+        let handles = match register.poll() {
+            Ok(Async::Ready(handles)) => handles,
+            other => panic!("{:?}", other.map(|_| ())),
+        };
+
+        // Nothing will be sent to the sockets, so these monitors never become ready
+        let _monitors = (
+            handles[1].monitor(Interest::Read),
+            handles[1].monitor(Interest::Read),
+        );
+        poller.poll(Some(Duration::from_millis(0))).unwrap();
+
+        let options = mio::PollOpt::edge() | mio::PollOpt::oneshot();
+        assert_eq!(
+            poller.registrants_snapshot(),
+            [
+                RegistrantInfo {
+                    token: handles[0].token(),
+                    read_waitings: 0,
+                    write_waitings: 0,
+                    interest: mio::Ready::empty(),
+                    options,
+                },
+                RegistrantInfo {
+                    token: handles[1].token(),
+                    read_waitings: 2,
+                    write_waitings: 0,
+                    interest: mio::Ready::readable(),
+                    options,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn register_all_works() {
+        let mut poller = Poller::new().unwrap();
+        let sockets = (0..10)
+            .map(|_| mio::net::UdpSocket::bind(&"127.0.0.1:0".parse().unwrap()).unwrap())
+            .collect::<Vec<_>>();
+        let addrs = sockets
+            .iter()
+            .map(|s| s.local_addr().unwrap())
+            .collect::<Vec<_>>();
+
+        let mut register = poller.handle().register_all(sockets);
+        poller.poll(Some(Duration::from_millis(0))).unwrap();
+        let handles = match register.poll() {
+            Ok(Async::Ready(handles)) => handles,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(poller.registrant_count(), 10);
+        assert_eq!(poller.metrics().register_requests, 10);
+
+        let registered_addrs = handles
+            .iter()
+            .map(|h| h.inner().local_addr().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(registered_addrs, addrs);
+
+        let mut tokens = handles.iter().map(|h| h.token()).collect::<Vec<_>>();
+        tokens.sort();
+        tokens.dedup();
+        assert_eq!(tokens.len(), 10);
+        assert_eq!(handles[0].clone().token(), handles[0].token());
+    }
+
+    #[test]
+    fn tokens_are_recycled() {
+        let mut poller = Poller::new().unwrap();
+        poller.token_capacity = 4;
+
+        let register = |poller: &mut Poller| {
+            let socket = mio::net::UdpSocket::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+            let mut register = poller.register(socket);
+            poller.poll(Some(Duration::from_millis(0))).unwrap();
+            register.poll()
+        };
+        for _ in 0..100 {
+            let handles = (0..4)
+                .map(|_| match register(&mut poller) {
+                    Ok(Async::Ready(handle)) => handle,
+                    other => panic!("{:?}", other),
+                })
+                .collect::<Vec<_>>();
+            assert!(handles.iter().all(|h| h.token().0 < 4));
+
+            // The token space is exhausted
+            assert!(register(&mut poller).is_err());
+
+            mem::drop(handles);
+            poller.poll(Some(Duration::from_millis(0))).unwrap();
+            assert_eq!(poller.registrant_count(), 0);
+        }
+        assert_eq!(poller.registrants.key_count(), 4);
+    }
+
+    #[test]
+    fn copy_error_works() {
+        let e = copy_error(&io::Error::from_raw_os_error(1));
+        assert_eq!(e.raw_os_error(), Some(1));
+
+        let e = copy_error(&io::Error::new(io::ErrorKind::InvalidInput, "foo"));
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(e.raw_os_error(), None);
+        assert_eq!(e.to_string(), "foo");
+    }
+
+    #[test]
+    fn shutdown_works() {
+        let mut poller = Poller::new().unwrap();
+        let socket = mio::net::UdpSocket::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let mut register = poller.register(socket);
+        poller.poll(Some(Duration::from_millis(0))).unwrap();
+        let handle = match register.poll() {
+            Ok(Async::Ready(handle)) => handle,
+            other => panic!("{:?}", other),
+        };
+
+        // Nothing will be sent to the socket, so this monitor never becomes ready.
+        let mut monitor = handle.monitor(Interest::Read);
+        poller.poll(Some(Duration::from_millis(0))).unwrap();
+        assert!(monitor.poll().unwrap().is_not_ready());
+
+        let mut queued_timeout = poller.handle().set_timeout(Duration::from_secs(60));
+        let mut queued_coarse_timeout = poller.handle().set_coarse_timeout(Duration::from_secs(60));
+        poller.poll(Some(Duration::from_millis(0))).unwrap();
+        assert!(queued_timeout.poll().unwrap().is_not_ready());
+        assert!(queued_coarse_timeout.poll().unwrap().is_not_ready());
+
+        // These requests are never handled by the poller.
+        let mut pending_timeout = poller.handle().set_timeout(Duration::from_secs(60));
+        let socket = mio::net::UdpSocket::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let mut pending_register = poller.handle().register(socket);
+
+        poller.shutdown().wait().unwrap();
+        for timeout in [
+            &mut queued_timeout,
+            &mut queued_coarse_timeout,
+            &mut pending_timeout,
+        ] {
+            assert!(matches!(timeout.poll(), Err(Error::PollerGone)));
+        }
+        assert!(matches!(pending_register.poll(), Err(Error::PollerGone)));
+        match monitor.poll() {
+            Err(oneshot::MonitorError::Failed(e)) => {
+                assert!(matches!(Error::from(e), Error::PollerGone))
+            }
+            other => panic!("{:?}", other),
+        }
+
+        let mut monitor = handle.monitor(Interest::Read);
+        assert!(monitor.poll().is_err());
+    }
+
+    #[test]
+    fn cancel_timeout_works() {
+        let mut poller = Poller::new().unwrap();
+        let timeout = poller.handle().set_timeout(Duration::from_secs(60));
+        poller.poll(Some(Duration::from_millis(0))).unwrap();
+        assert_eq!(poller.timeout_queue.len(), 1);
+
+        std::mem::drop(timeout);
+        poller.poll(Some(Duration::from_millis(0))).unwrap();
+        assert_eq!(poller.timeout_queue.len(), 0);
+    }
+
+    #[test]
+    fn completed_early_timeouts_do_not_accumulate() {
+        let mut poller = Poller::new().unwrap();
+        let handle = poller.handle();
+        for _ in 0..10 {
+            let timeouts = (0..1000)
+                .map(|_| handle.set_timeout(Duration::from_secs(60)))
+                .collect::<Vec<_>>();
+            while !poller.poll_once(Some(Duration::from_millis(0))).unwrap().is_idle() {}
+            assert_eq!(poller.timeout_queue.len(), 1000);
+
+            // Drops the timeouts as if the futures guarded by them have completed
+            std::mem::drop(timeouts);
+            while !poller.poll_once(Some(Duration::from_millis(0))).unwrap().is_idle() {}
+            assert_eq!(poller.timeout_queue.len(), 0);
+        }
+    }
+
+    #[test]
+    fn coarse_timeouts_work() {
+        let mut poller = Poller::new().unwrap();
+        poller.set_timer_granularity(Duration::from_millis(20));
+        let handle = poller.handle();
+
+        let start = time::Instant::now();
+        let mut timeout = handle.set_coarse_timeout(Duration::from_millis(30));
+        let cancelled = (0..1000)
+            .map(|_| handle.set_coarse_timeout(Duration::from_secs(60)))
+            .collect::<Vec<_>>();
+        while !poller.poll_once(Some(Duration::from_millis(0))).unwrap().is_idle() {}
+        assert_eq!(poller.coarse_timeout_queue.len(), 1001);
+
+        std::mem::drop(cancelled);
+        while !poller.poll_once(Some(Duration::from_millis(0))).unwrap().is_idle() {}
+        assert_eq!(poller.coarse_timeout_queue.len(), 1);
+
+        // Without the expiry time of the wheel, this call would block forever.
+        while timeout.poll().ok() == Some(Async::NotReady) {
+            poller.poll(None).unwrap();
+        }
+        assert!(start.elapsed() >= Duration::from_millis(30));
+        assert_eq!(poller.coarse_timeout_queue.len(), 0);
+        assert_eq!(poller.metrics().expired_timeouts, 1);
+    }
 }
@@ -0,0 +1,361 @@
+// Copyright (c) 2016 DWANGO Co., Ltd. All Rights Reserved.
+// See the LICENSE file at the top-level directory of this distribution.
+
+use futures::{Async, Future};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time;
+
+use super::{Poller, PollerBuilder, PollerHandle};
+use sync::oneshot::{self, Link, MonitorError};
+
+/// The number of the consecutive idle iterations after which a poller thread
+/// blocks until any events happen (instead of waking up periodically).
+const IDLE_POLLS_BEFORE_SLEEP: usize = 16;
+
+/// The policy to choose a poller from a `PollerPool`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BalancePolicy {
+    /// The pollers are chosen in round robin fashion.
+    RoundRobin,
+
+    /// The poller which has the fewest registered objects is chosen.
+    ///
+    /// The ties are broken in round robin fashion.
+    /// This reduces the imbalance between the pollers
+    /// when the lifetimes of the registered objects vary widely.
+    LeastLoaded,
+
+    /// The poller is chosen by the hash value of the key passed to `PollerPool::handle_for`
+    /// (e.g., the file descriptor of the object to be registered).
+    ///
+    /// `PollerPool::handle` falls back to round robin under this policy.
+    FdHash,
+}
+
+/// A pool of pollers each of which runs on a dedicated thread.
+///
+/// I/O events of the objects registered via different pollers are dispatched in parallel,
+/// so a pool can be used to spread the cost of event dispatching across cores.
+///
+/// If the pool is dropped, the threads of the pollers will terminate shortly.
+///
+/// A poller thread which has been idle for a while blocks until an I/O event happens
+/// or a request is sent via a handle of the poller, so idle pools do not consume CPU.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate fibers;
+/// # extern crate futures;
+/// use fibers::io::poll::PollerPool;
+/// use fibers::{Executor, InPlaceExecutor, Spawn};
+/// use std::time::{Duration, Instant};
+///
+/// # fn main() {
+/// let pool = PollerPool::new(2).unwrap();
+/// let start = Instant::now();
+/// let timeouts = (0..4)
+///     .map(|_| pool.handle().set_timeout(Duration::from_millis(10)))
+///     .collect::<Vec<_>>();
+///
+/// let mut executor = InPlaceExecutor::new().unwrap();
+/// let monitor = executor.spawn_monitor(futures::future::join_all(timeouts));
+/// executor.run_fiber(monitor).unwrap().unwrap();
+/// assert!(start.elapsed() >= Duration::from_millis(10));
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct PollerPool {
+    handles: Vec<PollerHandle>,
+    links: Vec<Link<(), io::Error>>,
+    threads: Vec<thread::JoinHandle<()>>,
+    round: AtomicUsize,
+    policy: BalancePolicy,
+}
+impl PollerPool {
+    /// Creates a new pool which has `pool_size` pollers.
+    ///
+    /// This is equivalent to `PollerPool::with_policy(pool_size, BalancePolicy::RoundRobin)`.
+    ///
+    /// # Panics
+    ///
+    /// If `pool_size` is zero, this function will panic.
+    pub fn new(pool_size: usize) -> io::Result<Self> {
+        Self::with_policy(pool_size, BalancePolicy::RoundRobin)
+    }
+
+    /// Creates a new pool which has `pool_size` pollers chosen by `policy`.
+    ///
+    /// # Panics
+    ///
+    /// If `pool_size` is zero, this function will panic.
+    pub fn with_policy(pool_size: usize, policy: BalancePolicy) -> io::Result<Self> {
+        Self::with_poller_builder(pool_size, policy, &Poller::builder())
+    }
+
+    /// Creates a new pool which has `pool_size` pollers created by `builder`.
+    ///
+    /// # Panics
+    ///
+    /// If `pool_size` is zero, this function will panic.
+    pub fn with_poller_builder(
+        pool_size: usize,
+        policy: BalancePolicy,
+        builder: &PollerBuilder,
+    ) -> io::Result<Self> {
+        assert_ne!(pool_size, 0);
+        let mut handles = Vec::new();
+        let mut links = Vec::new();
+        let mut threads = Vec::new();
+        for i in 0..pool_size {
+            let (link0, mut link1) = oneshot::link();
+            let mut poller = builder.build()?;
+            links.push(link0);
+            handles.push(poller.handle());
+            let thread = thread::Builder::new()
+                .name(format!("fibers_poller_{}", i))
+                .spawn(move || {
+                    let mut idle_polls = 0;
+                    while let Ok(Async::NotReady) = link1.poll() {
+                        let timeout = if idle_polls < IDLE_POLLS_BEFORE_SLEEP {
+                            Some(time::Duration::from_millis(1))
+                        } else {
+                            None
+                        };
+                        match poller.poll_once(timeout) {
+                            Err(e) => {
+                                link1.exit(Err(e));
+                                return;
+                            }
+                            Ok(outcome) => {
+                                if outcome.is_idle() {
+                                    idle_polls += 1;
+                                } else {
+                                    idle_polls = 0;
+                                }
+                            }
+                        }
+                    }
+                })?;
+            threads.push(thread);
+        }
+        Ok(PollerPool {
+            handles,
+            links,
+            threads,
+            round: AtomicUsize::new(0),
+            policy,
+        })
+    }
+
+    /// Returns the number of the pollers in the pool.
+    pub fn len(&self) -> usize {
+        self.handles.len()
+    }
+
+    /// Returns `true` if the pool has no pollers, otherwise `false`.
+    pub fn is_empty(&self) -> bool {
+        self.handles.is_empty()
+    }
+
+    /// Returns the policy to choose pollers.
+    pub fn policy(&self) -> BalancePolicy {
+        self.policy
+    }
+
+    /// Returns the handle of a poller in the pool.
+    ///
+    /// The poller is chosen according to the policy of the pool,
+    /// so the objects registered via the returned handles are spread across the pool.
+    pub fn handle(&self) -> PollerHandle {
+        let i = match self.policy {
+            BalancePolicy::RoundRobin | BalancePolicy::FdHash => self.next_round(),
+            BalancePolicy::LeastLoaded => self.least_loaded(),
+        };
+        self.handles[i].clone()
+    }
+
+    /// Returns the handle of a poller in the pool for the object identified by `key`.
+    ///
+    /// Under `BalancePolicy::FdHash`, the same poller is always chosen for the same key.
+    /// Under the other policies, `key` is ignored and this is equivalent to `handle` method.
+    pub fn handle_for(&self, key: usize) -> PollerHandle {
+        if self.policy != BalancePolicy::FdHash {
+            return self.handle();
+        }
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let i = (hasher.finish() % self.handles.len() as u64) as usize;
+        self.handles[i].clone()
+    }
+
+    /// Returns the handles of all the pollers in the pool.
+    ///
+    /// The `i`-th handle corresponds to the `i`-th poller.
+    pub fn handles(&self) -> &[PollerHandle] {
+        &self.handles
+    }
+
+    /// Checks whether the thread of the `i`-th poller is alive.
+    ///
+    /// If the poller has failed, this will return the error which caused the failure.
+    ///
+    /// # Panics
+    ///
+    /// If `i` is out of the range of the pool, this method will panic.
+    pub fn check_poller(&mut self, i: usize) -> io::Result<()> {
+        match self.links[i].poll() {
+            Err(MonitorError::Failed(e)) => Err(e),
+            Err(MonitorError::Aborted) => Err(io::Error::other(format!(
+                "The {}-th poller thread is aborted",
+                i
+            ))),
+            Ok(_) => Ok(()),
+        }
+    }
+
+    /// Stops all the pollers in the pool and waits until their threads terminate.
+    pub fn shutdown(mut self) -> io::Result<()> {
+        self.stop();
+        let threads = mem::take(&mut self.threads);
+        for (i, thread) in threads.into_iter().enumerate() {
+            if thread.join().is_err() {
+                return Err(io::Error::other(format!(
+                    "Cannot join the {}-th poller thread",
+                    i
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn next_round(&self) -> usize {
+        self.round.fetch_add(1, Ordering::Relaxed) % self.handles.len()
+    }
+
+    fn least_loaded(&self) -> usize {
+        let start = self.next_round();
+        let n = self.handles.len();
+        (start..start + n)
+            .map(|i| i % n)
+            .min_by_key(|&i| self.handles[i].registrant_count())
+            .expect("Never fails")
+    }
+
+    fn stop(&mut self) {
+        // NOTE: The links must be dropped before waking up the (possibly sleeping) threads
+        self.links.clear();
+        for handle in &self.handles {
+            handle.wakeup();
+        }
+    }
+}
+impl Drop for PollerPool {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::future;
+    use mio;
+    use std::time::Duration;
+
+    use super::*;
+    use executor::{Executor, InPlaceExecutor};
+    use fiber::Spawn;
+
+    #[test]
+    fn pollers_in_pool_work() {
+        let mut pool = PollerPool::new(3).unwrap();
+        assert_eq!(pool.len(), 3);
+
+        let timeouts = (0..6)
+            .map(|_| pool.handle().set_timeout(Duration::from_millis(5)))
+            .collect::<Vec<_>>();
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let monitor = executor.spawn_monitor(future::join_all(timeouts));
+        executor.run_fiber(monitor).unwrap().unwrap();
+        for i in 0..pool.len() {
+            pool.check_poller(i).unwrap();
+        }
+        pool.shutdown().unwrap();
+    }
+
+    #[test]
+    fn least_loaded_policy_works() {
+        let pool = PollerPool::with_policy(4, BalancePolicy::LeastLoaded).unwrap();
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let mut register = |pool: &PollerPool| {
+            let socket = mio::net::UdpSocket::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+            let monitor = executor.spawn_monitor(pool.handle().register(socket));
+            executor.run_fiber(monitor).unwrap().unwrap()
+        };
+        let counts = |pool: &PollerPool| {
+            let counts = pool
+                .handles()
+                .iter()
+                .map(|h| h.registrant_count())
+                .collect::<Vec<_>>();
+            (counts.iter().sum::<usize>(), counts)
+        };
+
+        // Only the objects registered in the first poller are long-lived
+        let mut handles = (0..100).map(|_| register(&pool)).collect::<Vec<_>>();
+        let mut i = 0;
+        handles.retain(|_| {
+            i += 1;
+            i % 4 == 1
+        });
+        while counts(&pool).0 != 25 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(counts(&pool).1, [25, 0, 0, 0]);
+
+        // New objects are registered in the other pollers first
+        handles.extend((0..103).map(|_| register(&pool)));
+        let (total, counts) = counts(&pool);
+        assert_eq!(total, 128);
+        let spread = counts.iter().max().unwrap() - counts.iter().min().unwrap();
+        assert!(spread <= 1, "{:?}", counts);
+        pool.shutdown().unwrap();
+    }
+
+    #[test]
+    fn fd_hash_policy_works() {
+        let pool = PollerPool::with_policy(4, BalancePolicy::FdHash).unwrap();
+        for key in 0..16 {
+            let mut h0 = pool.handle_for(key);
+            let mut h1 = pool.handle_for(key);
+            let s0 = mio::net::UdpSocket::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+            let s1 = mio::net::UdpSocket::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+            let mut executor = InPlaceExecutor::new().unwrap();
+            let monitor = executor.spawn_monitor(h0.register(s0).join(h1.register(s1)));
+            let _handles = executor.run_fiber(monitor).unwrap().unwrap();
+            assert_eq!(h0.registrant_count(), 2);
+        }
+        pool.shutdown().unwrap();
+    }
+
+    #[test]
+    fn idle_pollers_are_woken_up_by_requests() {
+        let pool = PollerPool::new(1).unwrap();
+
+        // Lets the poller fall asleep
+        thread::sleep(Duration::from_millis(100));
+
+        let start = time::Instant::now();
+        let timeout = pool.handle().set_timeout(Duration::from_millis(5));
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let monitor = executor.spawn_monitor(timeout);
+        executor.run_fiber(monitor).unwrap().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(1));
+        pool.shutdown().unwrap();
+    }
+}
@@ -0,0 +1,554 @@
+// Copyright (c) 2016 DWANGO Co., Ltd. All Rights Reserved.
+// See the LICENSE file at the top-level directory of this distribution.
+
+use futures::{Async, Future, Poll};
+use std::cmp;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+macro_rules! try_nb {
+    ($e:expr) => {
+        match $e.kind() {
+            io::ErrorKind::Interrupted => continue,
+            io::ErrorKind::WouldBlock => return Ok(Async::NotReady),
+            _ => return Err($e),
+        }
+    };
+}
+
+/// Makes a future which reads exactly `buf.as_mut().len()` bytes from `stream`.
+///
+/// The future will complete with the stream and the filled buffer.
+/// If the stream reaches EOF before the buffer is filled,
+/// it will fail with an error of the kind `io::ErrorKind::UnexpectedEof`.
+///
+/// If a read operation returns `io::ErrorKind::WouldBlock`,
+/// the future will be `NotReady` and retry reading at the next polling.
+/// The streams in `fibers::net` module suspend the current fiber until they become readable
+/// in such cases, so reading them does not spin.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate fibers;
+/// # extern crate futures;
+/// use fibers::io::read_exact;
+/// use futures::Future;
+///
+/// # fn main() {
+/// let (_, buf) = read_exact(&b"hello world"[..], vec![0; 5]).wait().unwrap();
+/// assert_eq!(buf, b"hello");
+/// # }
+/// ```
+pub fn read_exact<R: Read, B: AsMut<[u8]>>(stream: R, buf: B) -> ReadExact<R, B> {
+    ReadExact(Some(ReadExactInner {
+        stream,
+        buf,
+        offset: 0,
+    }))
+}
+
+/// A future which will read exactly the number of bytes which fills a buffer.
+///
+/// This is created by calling `fibers::io::read_exact` function.
+pub struct ReadExact<R, B>(Option<ReadExactInner<R, B>>);
+impl<R: Read, B: AsMut<[u8]>> Future for ReadExact<R, B> {
+    type Item = (R, B);
+    type Error = (R, B, io::Error);
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut inner = self.0.take().expect("Cannot poll ReadExact twice");
+        loop {
+            if inner.offset == inner.buf.as_mut().len() {
+                return Ok(Async::Ready((inner.stream, inner.buf)));
+            }
+            let offset = inner.offset;
+            match inner.stream.read(&mut inner.buf.as_mut()[offset..]) {
+                Ok(0) => {
+                    let e = io::Error::new(io::ErrorKind::UnexpectedEof, "Unexpected EOF");
+                    return Err((inner.stream, inner.buf, e));
+                }
+                Ok(size) => inner.offset += size,
+                Err(e) => match e.kind() {
+                    io::ErrorKind::Interrupted => {}
+                    io::ErrorKind::WouldBlock => {
+                        self.0 = Some(inner);
+                        return Ok(Async::NotReady);
+                    }
+                    _ => return Err((inner.stream, inner.buf, e)),
+                },
+            }
+        }
+    }
+}
+impl<R, B> fmt::Debug for ReadExact<R, B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref inner) = self.0 {
+            write!(f, "ReadExact {{ offset: {}, .. }}", inner.offset)
+        } else {
+            write!(f, "ReadExact(_)")
+        }
+    }
+}
+
+struct ReadExactInner<R, B> {
+    stream: R,
+    buf: B,
+    offset: usize,
+}
+
+/// Makes a future which reads all the bytes from `stream` until EOF and appends them to `buf`.
+///
+/// The future will complete with the stream and the buffer after `stream` reaches EOF.
+/// The buffer is grown as needed.
+/// To bound the memory consumed by a misbehaving peer,
+/// the maximum length of the buffer can be set by `ReadToEnd::max_size` method.
+///
+/// As with `read_exact`, `io::ErrorKind::WouldBlock` errors make the future `NotReady`.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate fibers;
+/// # extern crate futures;
+/// use fibers::io::read_to_end;
+/// use futures::Future;
+///
+/// # fn main() {
+/// let (_, buf) = read_to_end(&b"hello world"[..], Vec::new()).wait().unwrap();
+/// assert_eq!(buf, b"hello world");
+///
+/// let future = read_to_end(&b"hello world"[..], Vec::new()).max_size(5);
+/// assert!(future.wait().is_err());
+/// # }
+/// ```
+pub fn read_to_end<R: Read>(stream: R, buf: Vec<u8>) -> ReadToEnd<R> {
+    ReadToEnd(Some(ReadToEndInner {
+        stream,
+        buf,
+        max_size: None,
+    }))
+}
+
+const READ_TO_END_MIN_CHUNK_SIZE: usize = 32;
+
+/// A future which will read all the bytes until EOF.
+///
+/// This is created by calling `fibers::io::read_to_end` function.
+pub struct ReadToEnd<R>(Option<ReadToEndInner<R>>);
+impl<R: Read> ReadToEnd<R> {
+    /// Sets the maximum length of the buffer.
+    ///
+    /// If the length of the buffer exceeds `max_size` before reaching EOF,
+    /// the future will fail with an error of the kind `io::ErrorKind::InvalidData`.
+    ///
+    /// The default value is `None` (i.e., unlimited).
+    ///
+    /// # Panics
+    ///
+    /// If this is called after the future is completed, it will panic.
+    pub fn max_size(mut self, max_size: usize) -> Self {
+        assert_some!(self.0.as_mut()).max_size = Some(max_size);
+        self
+    }
+}
+impl<R: Read> Future for ReadToEnd<R> {
+    type Item = (R, Vec<u8>);
+    type Error = (R, Vec<u8>, io::Error);
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut inner = self.0.take().expect("Cannot poll ReadToEnd twice");
+        loop {
+            let len = inner.buf.len();
+            if let Some(max_size) = inner.max_size {
+                if len > max_size {
+                    let e = io::Error::new(io::ErrorKind::InvalidData, "Too large data");
+                    return Err((inner.stream, inner.buf, e));
+                }
+            }
+
+            // NOTE: Reads one more byte than `max_size` to detect the excess
+            let mut chunk_size = cmp::max(len, READ_TO_END_MIN_CHUNK_SIZE);
+            if let Some(max_size) = inner.max_size {
+                chunk_size = cmp::min(chunk_size, max_size - len + 1);
+            }
+            inner.buf.resize(len + chunk_size, 0);
+            let result = inner.stream.read(&mut inner.buf[len..]);
+            inner.buf.truncate(len + *result.as_ref().unwrap_or(&0));
+            match result {
+                Ok(0) => return Ok(Async::Ready((inner.stream, inner.buf))),
+                Ok(_) => {}
+                Err(e) => match e.kind() {
+                    io::ErrorKind::Interrupted => {}
+                    io::ErrorKind::WouldBlock => {
+                        self.0 = Some(inner);
+                        return Ok(Async::NotReady);
+                    }
+                    _ => return Err((inner.stream, inner.buf, e)),
+                },
+            }
+        }
+    }
+}
+impl<R> fmt::Debug for ReadToEnd<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref inner) = self.0 {
+            write!(
+                f,
+                "ReadToEnd {{ len: {}, max_size: {:?}, .. }}",
+                inner.buf.len(),
+                inner.max_size
+            )
+        } else {
+            write!(f, "ReadToEnd(_)")
+        }
+    }
+}
+
+struct ReadToEndInner<R> {
+    stream: R,
+    buf: Vec<u8>,
+    max_size: Option<usize>,
+}
+
+/// Makes a future which writes the entire contents of `buf` to `stream`.
+///
+/// The future will complete with the stream and the buffer after `stream` is flushed.
+/// If a write operation returns `Ok(0)`,
+/// it will fail with an error of the kind `io::ErrorKind::WriteZero`.
+///
+/// As with `read_exact`, `io::ErrorKind::WouldBlock` errors make the future `NotReady`.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate fibers;
+/// # extern crate futures;
+/// use fibers::io::write_all;
+/// use futures::Future;
+///
+/// # fn main() {
+/// let (stream, _) = write_all(Vec::new(), b"hello").wait().unwrap();
+/// assert_eq!(stream, b"hello");
+/// # }
+/// ```
+pub fn write_all<W: Write, B: AsRef<[u8]>>(stream: W, buf: B) -> WriteAll<W, B> {
+    WriteAll(Some(WriteAllInner {
+        stream,
+        buf,
+        offset: 0,
+    }))
+}
+
+/// A future which will write the entire contents of a buffer.
+///
+/// This is created by calling `fibers::io::write_all` function.
+pub struct WriteAll<W, B>(Option<WriteAllInner<W, B>>);
+impl<W: Write, B: AsRef<[u8]>> Future for WriteAll<W, B> {
+    type Item = (W, B);
+    type Error = (W, B, io::Error);
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut inner = self.0.take().expect("Cannot poll WriteAll twice");
+        loop {
+            let result = if inner.offset == inner.buf.as_ref().len() {
+                match inner.stream.flush() {
+                    Ok(()) => return Ok(Async::Ready((inner.stream, inner.buf))),
+                    Err(e) => Err(e),
+                }
+            } else {
+                inner.stream.write(&inner.buf.as_ref()[inner.offset..])
+            };
+            match result {
+                Ok(0) => {
+                    let e =
+                        io::Error::new(io::ErrorKind::WriteZero, "Failed to write whole buffer");
+                    return Err((inner.stream, inner.buf, e));
+                }
+                Ok(size) => inner.offset += size,
+                Err(e) => match e.kind() {
+                    io::ErrorKind::Interrupted => {}
+                    io::ErrorKind::WouldBlock => {
+                        self.0 = Some(inner);
+                        return Ok(Async::NotReady);
+                    }
+                    _ => return Err((inner.stream, inner.buf, e)),
+                },
+            }
+        }
+    }
+}
+impl<W, B> fmt::Debug for WriteAll<W, B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref inner) = self.0 {
+            write!(f, "WriteAll {{ offset: {}, .. }}", inner.offset)
+        } else {
+            write!(f, "WriteAll(_)")
+        }
+    }
+}
+
+struct WriteAllInner<W, B> {
+    stream: W,
+    buf: B,
+    offset: usize,
+}
+
+/// Makes a future which copies all the bytes read from `reader` to `writer`.
+///
+/// The future reads into an internal fixed-size buffer and writes it out in a loop,
+/// so it never buffers more than the size of the buffer.
+/// When `reader` reaches EOF, the future flushes `writer` and
+/// completes with the streams and the total number of the copied bytes.
+///
+/// As with `read_exact`, `io::ErrorKind::WouldBlock` errors make the future `NotReady`.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate fibers;
+/// # extern crate futures;
+/// use fibers::io::copy;
+/// use futures::Future;
+///
+/// # fn main() {
+/// let (_, writer, size) = copy(&b"hello"[..], Vec::new()).wait().unwrap();
+/// assert_eq!(writer, b"hello");
+/// assert_eq!(size, 5);
+/// # }
+/// ```
+pub fn copy<R: Read, W: Write>(reader: R, writer: W) -> Copy<R, W> {
+    Copy(Some(CopyInner {
+        reader,
+        writer,
+        buf: vec![0; COPY_BUFFER_SIZE].into_boxed_slice(),
+        start: 0,
+        end: 0,
+        is_eos: false,
+        copied: 0,
+    }))
+}
+
+const COPY_BUFFER_SIZE: usize = 8 * 1024;
+
+/// A future which will copy all the bytes from a reader to a writer.
+///
+/// This is created by calling `fibers::io::copy` function.
+pub struct Copy<R, W>(Option<CopyInner<R, W>>);
+impl<R: Read, W: Write> Future for Copy<R, W> {
+    type Item = (R, W, u64);
+    type Error = (R, W, io::Error);
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut inner = self.0.take().expect("Cannot poll Copy twice");
+        match inner.poll_copy() {
+            Err(e) => Err((inner.reader, inner.writer, e)),
+            Ok(Async::NotReady) => {
+                self.0 = Some(inner);
+                Ok(Async::NotReady)
+            }
+            Ok(Async::Ready(())) => Ok(Async::Ready((inner.reader, inner.writer, inner.copied))),
+        }
+    }
+}
+impl<R, W> fmt::Debug for Copy<R, W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref inner) = self.0 {
+            write!(f, "Copy {{ copied: {}, .. }}", inner.copied)
+        } else {
+            write!(f, "Copy(_)")
+        }
+    }
+}
+
+struct CopyInner<R, W> {
+    reader: R,
+    writer: W,
+    buf: Box<[u8]>,
+    start: usize,
+    end: usize,
+    is_eos: bool,
+    copied: u64,
+}
+impl<R: Read, W: Write> CopyInner<R, W> {
+    fn poll_copy(&mut self) -> Poll<(), io::Error> {
+        loop {
+            if self.start == self.end && !self.is_eos {
+                match self.reader.read(&mut self.buf) {
+                    Ok(0) => self.is_eos = true,
+                    Ok(size) => {
+                        self.start = 0;
+                        self.end = size;
+                    }
+                    Err(e) => try_nb!(e),
+                }
+            }
+            while self.start < self.end {
+                match self.writer.write(&self.buf[self.start..self.end]) {
+                    Ok(0) => {
+                        let e = io::Error::new(
+                            io::ErrorKind::WriteZero,
+                            "Failed to write whole buffer",
+                        );
+                        return Err(e);
+                    }
+                    Ok(size) => {
+                        self.start += size;
+                        self.copied += size as u64;
+                    }
+                    Err(e) => try_nb!(e),
+                }
+            }
+            if self.is_eos {
+                match self.writer.flush() {
+                    Ok(()) => return Ok(Async::Ready(())),
+                    Err(e) => try_nb!(e),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::{Async, Future, Stream};
+    use std::io::{self, Read, Write};
+    use std::net::Shutdown;
+
+    use super::*;
+    use executor::{Executor, InPlaceExecutor};
+    use fiber::Spawn;
+    use net::{TcpListener, TcpStream};
+
+    /// A stream which returns `WouldBlock` before every operation.
+    struct Choppy {
+        data: Vec<u8>,
+        blocked: bool,
+    }
+    impl Choppy {
+        fn new(data: &[u8]) -> Self {
+            Choppy {
+                data: data.to_owned(),
+                blocked: false,
+            }
+        }
+        fn block(&mut self) -> io::Result<()> {
+            self.blocked = !self.blocked;
+            if self.blocked {
+                Err(io::Error::new(io::ErrorKind::WouldBlock, "would block"))
+            } else {
+                Ok(())
+            }
+        }
+    }
+    impl Read for Choppy {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.block()?;
+            let size = if self.data.is_empty() { 0 } else { 1 };
+            buf[..size].copy_from_slice(&self.data[..size]);
+            self.data.drain(..size);
+            Ok(size)
+        }
+    }
+    impl Write for Choppy {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.block()?;
+            self.data.push(buf[0]);
+            Ok(1)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn read_exact_works() {
+        let mut future = read_exact(Choppy::new(b"foo"), [0; 2]);
+        let mut polls = 1;
+        while future.poll().ok().unwrap().is_not_ready() {
+            polls += 1;
+        }
+        assert_eq!(polls, 3);
+
+        let mut future = read_exact(Choppy::new(b"foo"), [0; 4]);
+        let error = loop {
+            match future.poll() {
+                Ok(Async::NotReady) => {}
+                Ok(Async::Ready(_)) => unreachable!(),
+                Err((_, buf, e)) => {
+                    assert_eq!(&buf[..3], b"foo");
+                    break e;
+                }
+            }
+        };
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn read_to_end_works() {
+        let data = (0..100).collect::<Vec<u8>>();
+        let mut future = read_to_end(Choppy::new(&data), b"head".to_vec());
+        let buf = loop {
+            if let Async::Ready((_, buf)) = future.poll().ok().unwrap() {
+                break buf;
+            }
+        };
+        assert_eq!(&buf[..4], b"head");
+        assert_eq!(&buf[4..], &data[..]);
+
+        // Exactly `max_size` bytes are allowed
+        let future = read_to_end(&data[..], Vec::new()).max_size(100);
+        assert_eq!(future.wait().ok().unwrap().1, data);
+    }
+
+    #[test]
+    fn read_to_end_fails_if_max_size_is_exceeded() {
+        let data = (0..100).collect::<Vec<u8>>();
+        let mut future = read_to_end(Choppy::new(&data), Vec::new()).max_size(10);
+        let (buf, error) = loop {
+            match future.poll() {
+                Ok(Async::NotReady) => {}
+                Ok(Async::Ready(_)) => unreachable!(),
+                Err((_, buf, e)) => break (buf, e),
+            }
+        };
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(buf, &data[..11]);
+    }
+
+    #[test]
+    fn copy_works() {
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let monitor = executor.spawn_monitor(TcpListener::bind("127.0.0.1:0".parse().unwrap()));
+        let listener = executor.run_fiber(monitor).unwrap().unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // Sends more bytes than the internal buffer of `copy` can hold at once
+        let data = (0..100_000).map(|i| i as u8).collect::<Vec<_>>();
+        let client = TcpStream::connect(addr).and_then(move |stream| {
+            write_all(stream, data)
+                .map_err(|(_, _, e)| e)
+                .and_then(|(stream, _)| stream.shutdown(Shutdown::Write))
+        });
+        let server = listener
+            .incoming()
+            .into_future()
+            .map_err(|(e, _)| e)
+            .and_then(|(client, _)| client.unwrap().0)
+            .and_then(|stream| copy(stream, Vec::new()).map_err(|(_, _, e)| e));
+        let client = executor.spawn_monitor(client);
+        let server = executor.spawn_monitor(server);
+        executor.run_fiber(client).unwrap().unwrap();
+
+        let (_, copied, size) = executor.run_fiber(server).unwrap().unwrap();
+        assert_eq!(size, 100_000);
+        assert!(copied.iter().enumerate().all(|(i, b)| *b == i as u8));
+    }
+
+    #[test]
+    fn write_all_works() {
+        let mut future = write_all(Choppy::new(b""), b"bar");
+        let stream = loop {
+            if let Async::Ready((stream, _)) = future.poll().ok().unwrap() {
+                break stream;
+            }
+        };
+        assert_eq!(stream.data, b"bar");
+    }
+}
@@ -80,7 +80,7 @@
 //! use fibers::{Spawn, Executor, ThreadPoolExecutor};
 //! use futures::Future;
 //!
-//! fn fibonacci<H: Spawn + Clone>(n: usize, handle: H) -> Box<Future<Item=usize, Error=()> + Send> {
+//! fn fibonacci<H: Spawn + Clone>(n: usize, handle: H) -> Box<dyn Future<Item=usize, Error=()> + Send> {
 //!     if n < 2 {
 //!         Box::new(futures::finished(n))
 //!     } else {
@@ -186,8 +186,12 @@
 #![warn(missing_docs)]
 
 extern crate futures;
+extern crate iovec;
+#[cfg(unix)]
+extern crate libc;
 extern crate mio;
 extern crate nbchan;
+extern crate net2;
 extern crate num_cpus;
 extern crate splay_tree;
 
@@ -203,15 +207,18 @@ macro_rules! assert_some {
 }
 
 #[doc(inline)]
-pub use self::executor::{Executor, InPlaceExecutor, ThreadPoolExecutor};
+pub use self::executor::{block_on, scope, Executor, InPlaceExecutor, ThreadPoolExecutor};
 
 #[doc(inline)]
-pub use self::fiber::{BoxSpawn, Spawn};
+pub use self::fiber::{AbortHandle, BoxSpawn, Spawn};
 
 pub mod executor;
 pub mod fiber;
+pub mod fs;
 pub mod io;
 pub mod net;
+#[cfg(unix)]
+pub mod signal;
 pub mod sync;
 pub mod time;
 
@@ -0,0 +1,247 @@
+// Copyright (c) 2016 DWANGO Co., Ltd. All Rights Reserved.
+// See the LICENSE file at the top-level directory of this distribution.
+
+use futures::{Async, Future, Poll};
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+use std::vec;
+
+use super::into_io_error;
+use super::resolve::{resolve, Resolve};
+use super::tcp::Connect;
+use super::TcpStream;
+use time::timer::{self, Timeout};
+
+/// The delay between the starts of two successive connection attempts (RFC 8305 recommends 250ms).
+const CONNECTION_ATTEMPT_DELAY_MS: u64 = 250;
+
+/// Makes a future to open a TCP connection to the remote host named `host`
+/// in the "Happy Eyeballs" ([RFC 8305]) fashion.
+///
+/// Unlike `TcpStream::connect_host`, this does not wait for an attempt to fail before
+/// trying the next address.
+/// The resolved addresses are sorted so that IPv6 and IPv4 addresses alternate,
+/// and a new attempt is started every 250 milliseconds (or immediately after an attempt fails)
+/// while the previous attempts are still in progress.
+/// The future will resolve to the first established connection and
+/// the other attempts will be cancelled.
+///
+/// If all attempts fail, the error of the last failed attempt will be returned.
+///
+/// [RFC 8305]: https://tools.ietf.org/html/rfc8305
+///
+/// # Examples
+///
+/// ```
+/// # extern crate fibers;
+/// # extern crate futures;
+/// use fibers::{Executor, InPlaceExecutor, Spawn};
+/// use fibers::net;
+/// use futures::Future;
+///
+/// # fn main() {
+/// let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+/// let port = listener.local_addr().unwrap().port();
+///
+/// let mut executor = InPlaceExecutor::new().unwrap();
+/// let future = net::connect_happy_eyeballs("localhost", port).map(|s| s.peer_addr().ok());
+/// let monitor = executor.spawn_monitor(future);
+/// assert_eq!(executor.run_fiber(monitor).unwrap().unwrap(), listener.local_addr().ok());
+/// # }
+/// ```
+pub fn connect_happy_eyeballs(host: &str, port: u16) -> ConnectHappyEyeballs {
+    ConnectHappyEyeballs {
+        resolve: Some(resolve(host, port)),
+        addrs: Vec::new().into_iter(),
+        attempts: Vec::new(),
+        attempt_delay: Duration::from_millis(CONNECTION_ATTEMPT_DELAY_MS),
+        next_attempt: None,
+        last_error: None,
+    }
+}
+
+/// A future which will open a TCP connection by racing attempts to the resolved addresses.
+///
+/// This is created by calling `fibers::net::connect_happy_eyeballs` function.
+/// It is permitted to move the future across fibers.
+///
+/// # Panics
+///
+/// If the future is polled on the outside of a fiber, it may crash.
+#[derive(Debug)]
+pub struct ConnectHappyEyeballs {
+    resolve: Option<Resolve>,
+    addrs: vec::IntoIter<SocketAddr>,
+    attempts: Vec<Connect>,
+    attempt_delay: Duration,
+    next_attempt: Option<Timeout>,
+    last_error: Option<io::Error>,
+}
+impl ConnectHappyEyeballs {
+    fn start_connecting(&mut self, addrs: Vec<SocketAddr>) -> io::Result<()> {
+        if addrs.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "No addresses are associated with the hostname",
+            ));
+        }
+        self.addrs = interleave_families(addrs).into_iter();
+        self.start_next_attempt();
+        Ok(())
+    }
+    fn start_next_attempt(&mut self) -> bool {
+        if let Some(addr) = self.addrs.next() {
+            self.attempts.push(TcpStream::connect(addr));
+            self.next_attempt = if self.addrs.len() > 0 {
+                Some(timer::timeout(self.attempt_delay))
+            } else {
+                None
+            };
+            true
+        } else {
+            self.next_attempt = None;
+            false
+        }
+    }
+}
+impl Future for ConnectHappyEyeballs {
+    type Item = TcpStream;
+    type Error = io::Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if let Some(mut future) = self.resolve.take() {
+            if let Async::Ready(addrs) = future.poll()? {
+                self.start_connecting(addrs)?;
+            } else {
+                self.resolve = Some(future);
+                return Ok(Async::NotReady);
+            }
+        }
+        loop {
+            let mut failed = false;
+            let mut i = 0;
+            while i < self.attempts.len() {
+                match self.attempts[i].poll() {
+                    Ok(Async::NotReady) => i += 1,
+                    Ok(Async::Ready(stream)) => {
+                        // NOTE: The other attempts are cancelled by dropping them
+                        self.attempts.clear();
+                        self.next_attempt = None;
+                        return Ok(Async::Ready(stream));
+                    }
+                    Err(e) => {
+                        self.attempts.swap_remove(i);
+                        self.last_error = Some(e);
+                        failed = true;
+                    }
+                }
+            }
+            if failed && self.start_next_attempt() {
+                continue;
+            }
+            if self.attempts.is_empty() {
+                return Err(self
+                    .last_error
+                    .take()
+                    .expect("Cannot poll ConnectHappyEyeballs twice"));
+            }
+
+            let elapsed = match self.next_attempt {
+                None => false,
+                Some(ref mut timeout) => timeout.poll().map_err(into_io_error)?.is_ready(),
+            };
+            if elapsed && self.start_next_attempt() {
+                continue;
+            }
+            return Ok(Async::NotReady);
+        }
+    }
+}
+
+/// Reorders `addrs` so that the address families alternate, starting with the first address.
+fn interleave_families(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let first_is_ipv6 = addrs[0].is_ipv6();
+    let (mut primary, mut secondary): (Vec<_>, Vec<_>) = addrs
+        .into_iter()
+        .partition(|a| a.is_ipv6() == first_is_ipv6);
+    let mut interleaved = Vec::with_capacity(primary.len() + secondary.len());
+    primary.reverse();
+    secondary.reverse();
+    loop {
+        match (primary.pop(), secondary.pop()) {
+            (None, None) => return interleaved,
+            (a, b) => interleaved.extend(a.into_iter().chain(b)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures;
+    use net2::TcpBuilder;
+    use std::net;
+    use std::time::Instant;
+
+    use super::*;
+    use executor::{Executor, InPlaceExecutor};
+    use fiber::Spawn;
+
+    fn connect_addrs(addrs: Vec<SocketAddr>) -> ConnectHappyEyeballs {
+        let mut future = ConnectHappyEyeballs {
+            resolve: None,
+            addrs: Vec::new().into_iter(),
+            attempts: Vec::new(),
+            attempt_delay: Duration::from_millis(50),
+            next_attempt: None,
+            last_error: None,
+        };
+        future.start_connecting(addrs).unwrap();
+        future
+    }
+
+    #[test]
+    fn interleave_families_works() {
+        let addrs = [
+            "[::1]:1",
+            "[::2]:1",
+            "[::3]:1",
+            "127.0.0.1:1",
+            "127.0.0.2:1",
+        ]
+        .iter()
+        .map(|a| a.parse().unwrap())
+        .collect::<Vec<SocketAddr>>();
+        let expected = vec![addrs[0], addrs[3], addrs[1], addrs[4], addrs[2]];
+        assert_eq!(interleave_families(addrs), expected);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn blackholed_address_does_not_delay_connection() {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut executor = InPlaceExecutor::new().unwrap();
+
+        // Linux silently drops SYNs to a listener whose accept queue is full,
+        // so the connections to it never complete like a blackholed address
+        let blackhole = TcpBuilder::new_v4()
+            .unwrap()
+            .bind("127.0.0.1:0")
+            .unwrap()
+            .listen(0)
+            .unwrap();
+        let blackholed = blackhole.local_addr().unwrap();
+        let _backlog = (0..2)
+            .filter_map(|_| {
+                net::TcpStream::connect_timeout(&blackholed, Duration::from_millis(100)).ok()
+            })
+            .collect::<Vec<_>>();
+
+        let start = Instant::now();
+        let monitor = executor.spawn_monitor(futures::lazy(move || {
+            connect_addrs(vec![blackholed, addr]).map(|stream| stream.peer_addr().ok())
+        }));
+        assert_eq!(executor.run_fiber(monitor).unwrap().unwrap(), Some(addr));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+}
@@ -25,40 +25,64 @@ use std::io;
 use std::mem;
 use std::net::SocketAddr;
 
-pub use self::tcp::{TcpListener, TcpStream};
-pub use self::udp::UdpSocket;
+pub use self::happy_eyeballs::connect_happy_eyeballs;
+pub use self::resolve::resolve;
+pub use self::tcp::{DrainHandle, TcpListener, TcpListenerBuilder, TcpStream};
+pub use self::udp::{UdpSocket, UdpSocketBuilder};
+
+#[cfg(unix)]
+pub use self::pipe::{pipe, PipeReader, PipeWriter};
 
 use fiber;
 use io::poll::{EventedHandle, Register};
 
 pub mod futures {
     //! Implementations of `futures::Future` trait.
-    pub use super::tcp::{Connect, Connected, TcpListenerBind};
-    pub use super::udp::{RecvFrom, SendTo, UdpSocketBind};
+    pub use super::happy_eyeballs::ConnectHappyEyeballs;
+    pub use super::resolve::Resolve;
+    pub use super::tcp::{
+        Connect, ConnectHost, ConnectTimeout, Connected, TcpListenerBind, WriteBufs,
+    };
+    pub use super::udp::{
+        RecvFrom, SendTo, UdpSocketBind, UdpSocketConnect, UdpSocketRecv, UdpSocketSend,
+    };
+
+    #[cfg(unix)]
+    pub use super::pipe::Pipe;
 }
 pub mod streams {
     //! Implementations of `futures::Stream` trait.
     pub use super::tcp::Incoming;
 }
 
+pub mod socks5;
+#[cfg(unix)]
+pub mod unix;
+
+mod happy_eyeballs;
+#[cfg(unix)]
+mod pipe;
+mod resolve;
 mod tcp;
 mod udp;
 
-enum Bind<F, T> {
-    Bind(SocketAddr, F),
+type BindFn<T> = Box<dyn FnOnce(&SocketAddr) -> io::Result<T> + Send>;
+
+enum Bind<A, F, T> {
+    Start(A, F),
     Registering(Register<T>),
     Polled,
 }
-impl<F, T> Future for Bind<F, T>
+impl<A, F, T> Future for Bind<A, F, T>
 where
-    F: FnOnce(&SocketAddr) -> io::Result<T>,
+    F: FnOnce(&A) -> io::Result<T>,
     T: mio::Evented + Send + 'static,
 {
     type Item = EventedHandle<T>;
     type Error = io::Error;
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
         match mem::replace(self, Bind::Polled) {
-            Bind::Bind(addr, bind) => {
+            Bind::Start(addr, bind) => {
                 let socket = bind(&addr)?;
                 let register = assert_some!(fiber::with_current_context(
                     |mut c| c.poller().register(socket),
@@ -67,7 +91,7 @@ where
                 self.poll()
             }
             Bind::Registering(mut future) => {
-                if let Async::Ready(handle) = future.poll().map_err(into_io_error)? {
+                if let Async::Ready(handle) = future.poll()? {
                     Ok(Async::Ready(handle))
                 } else {
                     *self = Bind::Registering(future);
@@ -78,16 +102,49 @@ where
         }
     }
 }
-impl<F, T> fmt::Debug for Bind<F, T> {
+impl<A: fmt::Debug, F, T> fmt::Debug for Bind<A, F, T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            Bind::Bind(addr, _) => write!(f, "Bind::Bind({:?}, _)", addr),
+            Bind::Start(ref addr, _) => write!(f, "Bind::Start({:?}, _)", addr),
             Bind::Registering(_) => write!(f, "Bind::Registering(_)"),
             Bind::Polled => write!(f, "Bind::Polled"),
         }
     }
 }
 
+#[cfg(not(all(unix, not(any(target_os = "solaris", target_os = "illumos")))))]
+fn reuse_port_unsupported() -> io::Error {
+    io::Error::other("SO_REUSEPORT is not supported on this platform")
+}
+
+/// Calls `f` with a `V` (e.g., `std::net::TcpStream`) which shares the socket of `socket`.
+///
+/// mio does not provide the accessors for some `SOL_SOCKET` level options
+/// (e.g., `SO_SNDBUF`) of `TcpListener` and `UdpSocket`,
+/// so they are accessed through this temporary view via net2.
+/// The view must be of the same protocol as `socket`, and it never closes the socket.
+#[cfg(unix)]
+fn with_socket_view<S, V, F, T>(socket: &S, f: F) -> T
+where
+    S: std::os::unix::io::AsRawFd,
+    V: std::os::unix::io::FromRawFd,
+    F: FnOnce(&V) -> T,
+{
+    let view = unsafe { V::from_raw_fd(socket.as_raw_fd()) };
+    f(&mem::ManuallyDrop::new(view))
+}
+
+#[cfg(windows)]
+fn with_socket_view<S, V, F, T>(socket: &S, f: F) -> T
+where
+    S: std::os::windows::io::AsRawSocket,
+    V: std::os::windows::io::FromRawSocket,
+    F: FnOnce(&V) -> T,
+{
+    let view = unsafe { V::from_raw_socket(socket.as_raw_socket()) };
+    f(&mem::ManuallyDrop::new(view))
+}
+
 fn into_io_error<E: error::Error + Send + Sync + 'static>(error: E) -> io::Error {
-    io::Error::new(io::ErrorKind::Other, Box::new(error))
+    io::Error::other(Box::new(error))
 }
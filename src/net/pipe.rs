@@ -0,0 +1,259 @@
+// Copyright (c) 2016 DWANGO Co., Ltd. All Rights Reserved.
+// See the LICENSE file at the top-level directory of this distribution.
+
+use futures::{Async, Future, Poll};
+use libc;
+use mio::unix::EventedFd;
+use mio::{self, Evented};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+
+use fiber;
+use io::poll::{EventedHandle, Interest, RegisterAll};
+use io::set_nonblocking;
+use sync::oneshot::Monitor;
+
+/// Makes a future to create an anonymous pipe.
+///
+/// The future will resolve to the read end and the write end of the pipe,
+/// both of which are registered to the I/O poller of the current fiber.
+///
+/// Pipes are handy for waking up a fiber from another thread (or a signal handler),
+/// and for handling the standard streams of subprocesses.
+///
+/// # Examples
+///
+/// ```
+/// // See also: fibers/examples/pipe_wakeup.rs
+/// # extern crate fibers;
+/// # extern crate futures;
+/// use fibers::{Executor, InPlaceExecutor, Spawn};
+/// use fibers::net;
+/// use futures::Future;
+/// use std::io::{Read, Write};
+///
+/// # fn main() {
+/// let mut executor = InPlaceExecutor::new().unwrap();
+/// let monitor = executor.spawn_monitor(net::pipe().and_then(|(mut reader, mut writer)| {
+///     writer.write_all(b"hello")?;
+///     let mut buf = [0; 5];
+///     reader.read_exact(&mut buf)?;
+///     Ok(buf)
+/// }));
+/// assert_eq!(&executor.run_fiber(monitor).unwrap().unwrap(), b"hello");
+/// # }
+/// ```
+pub fn pipe() -> Pipe {
+    Pipe(None)
+}
+
+/// A future which will create an anonymous pipe.
+///
+/// This is created by calling `pipe` function.
+/// It is permitted to move the future across fibers.
+///
+/// # Panics
+///
+/// If the future is polled on the outside of a fiber, it may crash.
+#[derive(Debug)]
+pub struct Pipe(Option<RegisterAll<EventedPipe>>);
+impl Future for Pipe {
+    type Item = (PipeReader, PipeWriter);
+    type Error = io::Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if self.0.is_none() {
+            let (reader, writer) = anonymous_pipe()?;
+            let ends = vec![EventedPipe::new(reader)?, EventedPipe::new(writer)?];
+            let register = |mut c: fiber::Context| c.poller().register_all(ends);
+            self.0 = Some(assert_some!(fiber::with_current_context(register)));
+        }
+        let future = self.0.as_mut().expect("Never fails");
+        if let Async::Ready(mut handles) = future.poll()? {
+            let writer = PipeWriter(PipeEnd::new(assert_some!(handles.pop())));
+            let reader = PipeReader(PipeEnd::new(assert_some!(handles.pop())));
+            Ok(Async::Ready((reader, writer)))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+/// The read end of a pipe.
+///
+/// This is created by calling `pipe` function.
+///
+/// # Note
+///
+/// Non blocking mode is always enabled on this pipe.
+/// If a read would block, it returns the `std::io::ErrorKind::WouldBlock` error and
+/// current fiber is suspended until the pipe becomes readable.
+/// A read returns `Ok(0)` after the write end is closed (i.e., dropped and deregistered)
+/// and the buffered data is consumed.
+#[derive(Debug)]
+pub struct PipeReader(PipeEnd);
+impl AsRawFd for PipeReader {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.handle.inner().0.as_raw_fd()
+    }
+}
+impl Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.operate(Interest::Read, |inner| inner.read(buf))
+    }
+}
+
+/// The write end of a pipe.
+///
+/// This is created by calling `pipe` function.
+///
+/// Unlike `PipeReader`, this can be used on the outside of a fiber as long as
+/// the pipe has enough room for the data (e.g., writing a byte to wake up the reader).
+///
+/// # Note
+///
+/// Non blocking mode is always enabled on this pipe.
+/// If a write would block, it returns the `std::io::ErrorKind::WouldBlock` error and
+/// current fiber is suspended until the pipe becomes writable.
+#[derive(Debug)]
+pub struct PipeWriter(PipeEnd);
+impl AsRawFd for PipeWriter {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.handle.inner().0.as_raw_fd()
+    }
+}
+impl Write for PipeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.operate(Interest::Write, |inner| inner.write(buf))
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+struct PipeEnd {
+    handle: EventedHandle<EventedPipe>,
+    monitor: Option<Monitor<(), io::Error>>,
+}
+impl PipeEnd {
+    fn new(handle: EventedHandle<EventedPipe>) -> Self {
+        PipeEnd {
+            handle,
+            monitor: None,
+        }
+    }
+    fn operate<F, T>(&mut self, interest: Interest, mut f: F) -> io::Result<T>
+    where
+        F: FnMut(&mut File) -> io::Result<T>,
+    {
+        self.handle
+            .operate(&mut self.monitor, interest, |inner| f(&mut inner.0))
+    }
+}
+impl fmt::Debug for PipeEnd {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PipeEnd {{ token:{:?}, .. }}", self.handle.token())
+    }
+}
+
+#[derive(Debug)]
+struct EventedPipe(File);
+impl EventedPipe {
+    fn new(fd: OwnedFd) -> io::Result<Self> {
+        let file = File::from(fd);
+        set_nonblocking(file.as_raw_fd())?;
+        Ok(EventedPipe(file))
+    }
+}
+impl Evented for EventedPipe {
+    fn register(
+        &self,
+        poll: &mio::Poll,
+        token: mio::Token,
+        interest: mio::Ready,
+        opts: mio::PollOpt,
+    ) -> io::Result<()> {
+        EventedFd(&self.0.as_raw_fd()).register(poll, token, interest, opts)
+    }
+    fn reregister(
+        &self,
+        poll: &mio::Poll,
+        token: mio::Token,
+        interest: mio::Ready,
+        opts: mio::PollOpt,
+    ) -> io::Result<()> {
+        EventedFd(&self.0.as_raw_fd()).reregister(poll, token, interest, opts)
+    }
+    fn deregister(&self, poll: &mio::Poll) -> io::Result<()> {
+        EventedFd(&self.0.as_raw_fd()).deregister(poll)
+    }
+}
+
+fn anonymous_pipe() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0; 2];
+    unsafe {
+        if libc::pipe(fds.as_mut_ptr()) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let ends = (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1]));
+        for &fd in &fds {
+            if libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(ends)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::future;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+    use executor::{Executor, InPlaceExecutor};
+    use fiber::Spawn;
+
+    #[test]
+    fn write_from_another_thread_wakes_up_reader() {
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let monitor = executor.spawn_monitor(pipe());
+        let (mut reader, mut writer) = executor.run_fiber(monitor).unwrap().unwrap();
+
+        let monitor = executor.spawn_monitor(future::poll_fn(move || {
+            let mut buf = [0; 1];
+            match reader.read(&mut buf) {
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(Async::NotReady),
+                Err(e) => Err(e),
+                Ok(size) => Ok(Async::Ready((size, buf[0]))),
+            }
+        }));
+        let thread = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            writer.write_all(&[42]).unwrap();
+        });
+        assert_eq!(executor.run_fiber(monitor).unwrap().unwrap(), (1, 42));
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn reader_gets_eof_if_writer_is_dropped() {
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let monitor = executor.spawn_monitor(pipe());
+        let (mut reader, writer) = executor.run_fiber(monitor).unwrap().unwrap();
+
+        // NOTE: The pipe is closed after the poller handles the deregistration of the writer
+        std::mem::drop(writer);
+        let monitor = executor.spawn_monitor(future::poll_fn(move || {
+            let mut buf = [0; 1];
+            match reader.read(&mut buf) {
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(Async::NotReady),
+                Err(e) => Err(e),
+                Ok(size) => Ok(Async::Ready(size)),
+            }
+        }));
+        assert_eq!(executor.run_fiber(monitor).unwrap().unwrap(), 0);
+    }
+}
@@ -0,0 +1,54 @@
+// Copyright (c) 2016 DWANGO Co., Ltd. All Rights Reserved.
+// See the LICENSE file at the top-level directory of this distribution.
+
+use futures::{Async, Future, Poll};
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+
+use super::into_io_error;
+use executor::{shared_blocking_pool, SpawnBlocking};
+
+/// Makes a future to resolve `host` to the socket addresses which have the port `port`.
+///
+/// There is no way to resolve hostnames on the poller without blocking,
+/// so the lookup (i.e., `getaddrinfo`) is offloaded to the process-wide blocking pool.
+/// Thus the worker threads of executors are never blocked by this function.
+///
+/// The addresses are returned in the order reported by the system resolver.
+///
+/// # Examples
+///
+/// ```
+/// use fibers::{Executor, InPlaceExecutor, Spawn};
+/// use fibers::net;
+///
+/// let mut executor = InPlaceExecutor::new().unwrap();
+/// let monitor = executor.spawn_monitor(net::resolve("localhost", 80));
+/// let addrs = executor.run_fiber(monitor).unwrap().unwrap();
+/// assert!(addrs.iter().all(|a| a.ip().is_loopback() && a.port() == 80));
+/// ```
+pub fn resolve(host: &str, port: u16) -> Resolve {
+    let host = host.to_owned();
+    Resolve(shared_blocking_pool().spawn(move || {
+        (host.as_str(), port)
+            .to_socket_addrs()
+            .map(|addrs| addrs.collect())
+    }))
+}
+
+/// A future which will resolve a hostname to socket addresses.
+///
+/// This is created by calling `fibers::net::resolve` function.
+/// It is permitted to move the future across fibers.
+#[derive(Debug)]
+pub struct Resolve(SpawnBlocking<io::Result<Vec<SocketAddr>>>);
+impl Future for Resolve {
+    type Item = Vec<SocketAddr>;
+    type Error = io::Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.0.poll().map_err(into_io_error)? {
+            Async::Ready(result) => result.map(Async::Ready),
+            Async::NotReady => Ok(Async::NotReady),
+        }
+    }
+}
@@ -0,0 +1,427 @@
+// Copyright (c) 2016 DWANGO Co., Ltd. All Rights Reserved.
+// See the LICENSE file at the top-level directory of this distribution.
+
+//! SOCKS5 proxy client.
+//!
+//! Only the `CONNECT` command is supported.
+//! As the authentication method,
+//! "NO AUTHENTICATION REQUIRED" and "USERNAME/PASSWORD" ([RFC 1929]) are supported.
+//!
+//! See [RFC 1928] for the details of the protocol.
+//!
+//! [RFC 1928]: https://tools.ietf.org/html/rfc1928
+//! [RFC 1929]: https://tools.ietf.org/html/rfc1929
+//!
+//! # Examples
+//!
+//! ```no_run
+//! # extern crate fibers;
+//! # extern crate futures;
+//! use fibers::{Executor, InPlaceExecutor, Spawn};
+//! use fibers::net::socks5;
+//! use std::io::Write;
+//! use futures::Future;
+//!
+//! # fn main() {
+//! let mut executor = InPlaceExecutor::new().unwrap();
+//! let proxy_addr = "127.0.0.1:1080".parse().unwrap();
+//! let future = socks5::connect(proxy_addr, "example.com", 80).and_then(|mut stream| {
+//!     stream.write_all(b"GET / HTTP/1.0\r\n\r\n")?;
+//!     Ok(stream)
+//! });
+//! let monitor = executor.spawn_monitor(future);
+//! executor.run_fiber(monitor).unwrap().unwrap();
+//! # }
+//! ```
+use futures::{Async, Future, Poll};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+
+use super::tcp::Connect;
+use super::TcpStream;
+use io::{read_exact, write_all, ReadExact, WriteAll};
+
+const VERSION: u8 = 5;
+const METHOD_NO_AUTH: u8 = 0x00;
+const METHOD_PASSWORD: u8 = 0x02;
+const METHOD_NOT_ACCEPTABLE: u8 = 0xFF;
+const PASSWORD_VERSION: u8 = 1;
+const COMMAND_CONNECT: u8 = 1;
+const ADDR_TYPE_IPV4: u8 = 1;
+const ADDR_TYPE_DOMAIN: u8 = 3;
+const ADDR_TYPE_IPV6: u8 = 4;
+
+macro_rules! try_ready_io {
+    ($e:expr) => {
+        match $e {
+            Err(e) => return Err(e),
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Ok(Async::Ready(v)) => v,
+        }
+    };
+}
+
+/// Makes a future to open a TCP connection to `target_host:target_port`
+/// through the SOCKS5 proxy listening on `proxy_addr`.
+///
+/// `target_host` can be either an IP address or a domain name.
+/// Domain names are resolved by the proxy.
+///
+/// The future will resolve to a stream tunneled to the target
+/// (i.e., the data written to the stream is relayed to the target by the proxy).
+pub fn connect(proxy_addr: SocketAddr, target_host: &str, target_port: u16) -> Socks5Connect {
+    Socks5Connect::new(proxy_addr, target_host, target_port, None)
+}
+
+/// Makes a future to open a TCP connection to `target_host:target_port`
+/// through the SOCKS5 proxy which requires the username/password authentication.
+///
+/// The proxy may also choose not to authenticate the client.
+/// Other than that, this is the same as `connect` function.
+pub fn connect_with_password(
+    proxy_addr: SocketAddr,
+    target_host: &str,
+    target_port: u16,
+    username: &str,
+    password: &str,
+) -> Socks5Connect {
+    Socks5Connect::new(
+        proxy_addr,
+        target_host,
+        target_port,
+        Some((username, password)),
+    )
+}
+
+/// A future which will open a TCP connection through a SOCKS5 proxy.
+///
+/// This is created by calling `connect` or `connect_with_password` function.
+/// It is permitted to move the future across fibers.
+///
+/// # Panics
+///
+/// If the future is polled on the outside of a fiber, it may crash.
+#[derive(Debug)]
+pub struct Socks5Connect {
+    auth: Option<Vec<u8>>,
+    request: Vec<u8>,
+    phase: Phase,
+}
+impl Socks5Connect {
+    fn new(
+        proxy_addr: SocketAddr,
+        target_host: &str,
+        target_port: u16,
+        credentials: Option<(&str, &str)>,
+    ) -> Self {
+        let encoded = credentials
+            .map(|(username, password)| encode_auth(username, password))
+            .map_or(Ok(None), |r| r.map(Some))
+            .and_then(|auth| Ok((auth, encode_request(target_host, target_port)?)));
+        match encoded {
+            Err(e) => Socks5Connect {
+                auth: None,
+                request: Vec::new(),
+                phase: Phase::Failed(Some(e)),
+            },
+            Ok((auth, request)) => Socks5Connect {
+                auth,
+                request,
+                phase: Phase::Connect(TcpStream::connect(proxy_addr)),
+            },
+        }
+    }
+}
+impl Future for Socks5Connect {
+    type Item = TcpStream;
+    type Error = io::Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.phase {
+                Phase::Failed(ref mut e) => {
+                    return Err(e.take().expect("Cannot poll Socks5Connect twice"));
+                }
+                Phase::Connect(ref mut f) => {
+                    let stream = try_ready_io!(f.poll());
+                    let greeting = if self.auth.is_some() {
+                        vec![VERSION, 2, METHOD_NO_AUTH, METHOD_PASSWORD]
+                    } else {
+                        vec![VERSION, 1, METHOD_NO_AUTH]
+                    };
+                    Phase::SendGreeting(write_all(stream, greeting))
+                }
+                Phase::SendGreeting(ref mut f) => {
+                    let (stream, _) = try_ready_io!(f.poll().map_err(|(_, _, e)| e));
+                    Phase::RecvMethod(read_exact(stream, [0; 2]))
+                }
+                Phase::RecvMethod(ref mut f) => {
+                    let (stream, [version, method]) =
+                        try_ready_io!(f.poll().map_err(|(_, _, e)| e));
+                    check_version(version, VERSION)?;
+                    match (method, self.auth.take()) {
+                        (METHOD_NO_AUTH, _) => {
+                            let request = self.request.clone();
+                            Phase::SendRequest(write_all(stream, request))
+                        }
+                        (METHOD_PASSWORD, Some(auth)) => Phase::SendAuth(write_all(stream, auth)),
+                        (METHOD_NOT_ACCEPTABLE, _) => {
+                            return Err(io::Error::new(
+                                io::ErrorKind::PermissionDenied,
+                                "No acceptable authentication method",
+                            ));
+                        }
+                        _ => {
+                            let message = format!("Unexpected authentication method: {}", method);
+                            return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+                        }
+                    }
+                }
+                Phase::SendAuth(ref mut f) => {
+                    let (stream, _) = try_ready_io!(f.poll().map_err(|(_, _, e)| e));
+                    Phase::RecvAuthStatus(read_exact(stream, [0; 2]))
+                }
+                Phase::RecvAuthStatus(ref mut f) => {
+                    let (stream, [version, status]) =
+                        try_ready_io!(f.poll().map_err(|(_, _, e)| e));
+                    check_version(version, PASSWORD_VERSION)?;
+                    if status != 0 {
+                        return Err(io::Error::new(
+                            io::ErrorKind::PermissionDenied,
+                            "Username/password authentication failed",
+                        ));
+                    }
+                    let request = self.request.clone();
+                    Phase::SendRequest(write_all(stream, request))
+                }
+                Phase::SendRequest(ref mut f) => {
+                    let (stream, _) = try_ready_io!(f.poll().map_err(|(_, _, e)| e));
+                    Phase::RecvReply(read_exact(stream, [0; 5]))
+                }
+                Phase::RecvReply(ref mut f) => {
+                    // NOTE: The last byte is the first byte of the bound address
+                    let (stream, [version, reply, _, addr_type, addr_head]) =
+                        try_ready_io!(f.poll().map_err(|(_, _, e)| e));
+                    check_version(version, VERSION)?;
+                    if reply != 0 {
+                        return Err(reply_error(reply));
+                    }
+                    let rest = match addr_type {
+                        ADDR_TYPE_IPV4 => 3,
+                        ADDR_TYPE_DOMAIN => addr_head as usize,
+                        ADDR_TYPE_IPV6 => 15,
+                        _ => {
+                            let message = format!("Unknown address type: {}", addr_type);
+                            return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+                        }
+                    };
+                    Phase::RecvBoundAddr(read_exact(stream, vec![0; rest + 2]))
+                }
+                Phase::RecvBoundAddr(ref mut f) => {
+                    let (stream, _) = try_ready_io!(f.poll().map_err(|(_, _, e)| e));
+                    self.phase = Phase::Failed(None);
+                    return Ok(Async::Ready(stream));
+                }
+            };
+            self.phase = next;
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Phase {
+    Failed(Option<io::Error>),
+    Connect(Connect),
+    SendGreeting(WriteAll<TcpStream, Vec<u8>>),
+    RecvMethod(ReadExact<TcpStream, [u8; 2]>),
+    SendAuth(WriteAll<TcpStream, Vec<u8>>),
+    RecvAuthStatus(ReadExact<TcpStream, [u8; 2]>),
+    SendRequest(WriteAll<TcpStream, Vec<u8>>),
+    RecvReply(ReadExact<TcpStream, [u8; 5]>),
+    RecvBoundAddr(ReadExact<TcpStream, Vec<u8>>),
+}
+
+fn encode_auth(username: &str, password: &str) -> io::Result<Vec<u8>> {
+    let mut buf = vec![PASSWORD_VERSION];
+    for field in &[username, password] {
+        if field.len() > 255 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Too long username or password",
+            ));
+        }
+        buf.push(field.len() as u8);
+        buf.extend_from_slice(field.as_bytes());
+    }
+    Ok(buf)
+}
+
+fn encode_request(host: &str, port: u16) -> io::Result<Vec<u8>> {
+    let mut buf = vec![VERSION, COMMAND_CONNECT, 0];
+    match host.parse() {
+        Ok(IpAddr::V4(addr)) => {
+            buf.push(ADDR_TYPE_IPV4);
+            buf.extend_from_slice(&addr.octets());
+        }
+        Ok(IpAddr::V6(addr)) => {
+            buf.push(ADDR_TYPE_IPV6);
+            buf.extend_from_slice(&addr.octets());
+        }
+        Err(_) => {
+            if host.is_empty() || host.len() > 255 {
+                let message = format!("Invalid domain name: {:?}", host);
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+            }
+            buf.push(ADDR_TYPE_DOMAIN);
+            buf.push(host.len() as u8);
+            buf.extend_from_slice(host.as_bytes());
+        }
+    }
+    buf.extend_from_slice(&port.to_be_bytes());
+    Ok(buf)
+}
+
+fn check_version(actual: u8, expected: u8) -> io::Result<()> {
+    if actual == expected {
+        Ok(())
+    } else {
+        let message = format!("Unexpected SOCKS version: {}", actual);
+        Err(io::Error::new(io::ErrorKind::InvalidData, message))
+    }
+}
+
+fn reply_error(reply: u8) -> io::Error {
+    let (kind, reason) = match reply {
+        1 => (io::ErrorKind::Other, "general SOCKS server failure"),
+        2 => (
+            io::ErrorKind::PermissionDenied,
+            "connection not allowed by ruleset",
+        ),
+        3 => (io::ErrorKind::Other, "network unreachable"),
+        4 => (io::ErrorKind::Other, "host unreachable"),
+        5 => (io::ErrorKind::ConnectionRefused, "connection refused"),
+        6 => (io::ErrorKind::TimedOut, "TTL expired"),
+        7 => (io::ErrorKind::Other, "command not supported"),
+        8 => (io::ErrorKind::Other, "address type not supported"),
+        _ => (io::ErrorKind::Other, "unknown error"),
+    };
+    let message = format!("SOCKS5 request failed: {} (reply={})", reason, reply);
+    io::Error::new(kind, message)
+}
+
+#[cfg(test)]
+mod test {
+    use futures::Future;
+    use std::io::{Read, Write};
+    use std::net::{self, Shutdown};
+    use std::thread::{self, JoinHandle};
+
+    use super::*;
+    use executor::{Executor, InPlaceExecutor};
+    use fiber::Spawn;
+    use io::read_to_end;
+    use sync::oneshot::MonitorError;
+
+    /// Spawns a SOCKS5 server which serves a client and echoes back the relayed data.
+    ///
+    /// The thread returns the address part of the request.
+    fn spawn_proxy(
+        credentials: Option<(&'static [u8], &'static [u8])>,
+    ) -> (SocketAddr, JoinHandle<io::Result<Vec<u8>>>) {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let thread = thread::spawn(move || {
+            let (mut stream, _) = listener.accept()?;
+            let mut buf = [0; 2];
+            stream.read_exact(&mut buf)?;
+            let mut methods = vec![0; buf[1] as usize];
+            stream.read_exact(&mut methods)?;
+
+            if let Some((username, password)) = credentials {
+                assert!(methods.contains(&METHOD_PASSWORD));
+                stream.write_all(&[VERSION, METHOD_PASSWORD])?;
+
+                let mut auth = vec![0; 2];
+                stream.read_exact(&mut auth)?;
+                let mut field = vec![0; auth[1] as usize + 1];
+                stream.read_exact(&mut field)?;
+                let mut rest = vec![0; field.pop().unwrap() as usize];
+                stream.read_exact(&mut rest)?;
+                if (&field[..], &rest[..]) != (username, password) {
+                    stream.write_all(&[PASSWORD_VERSION, 1])?;
+                    return Ok(Vec::new());
+                }
+                stream.write_all(&[PASSWORD_VERSION, 0])?;
+            } else {
+                stream.write_all(&[VERSION, METHOD_NO_AUTH])?;
+            }
+
+            let mut head = [0; 5];
+            stream.read_exact(&mut head)?;
+            assert_eq!(&head[..3], &[VERSION, COMMAND_CONNECT, 0]);
+            let rest = match head[3] {
+                ADDR_TYPE_IPV4 => 3,
+                ADDR_TYPE_DOMAIN => head[4] as usize,
+                _ => 15,
+            };
+            let mut target = head[3..].to_vec();
+            target.resize(target.len() + rest + 2, 0);
+            stream.read_exact(&mut target[2..])?;
+            stream.write_all(&[VERSION, 0, 0, ADDR_TYPE_IPV4, 127, 0, 0, 1, 0, 80])?;
+
+            let mut data = Vec::new();
+            stream.read_to_end(&mut data)?;
+            stream.write_all(&data)?;
+            Ok(target)
+        });
+        (addr, thread)
+    }
+
+    fn echo(stream: TcpStream) -> impl Future<Item = Vec<u8>, Error = io::Error> {
+        write_all(stream, b"hello")
+            .map_err(|(_, _, e)| e)
+            .and_then(|(stream, _)| {
+                stream.shutdown(Shutdown::Write)?;
+                Ok(stream)
+            })
+            .and_then(|stream| read_to_end(stream, Vec::new()).map_err(|(_, _, e)| e))
+            .map(|(_, buf)| buf)
+    }
+
+    #[test]
+    fn connect_works() {
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let (proxy_addr, proxy) = spawn_proxy(None);
+
+        let future = connect(proxy_addr, "example.com", 80).and_then(echo);
+        let monitor = executor.spawn_monitor(future);
+        assert_eq!(executor.run_fiber(monitor).unwrap().unwrap(), b"hello");
+        assert_eq!(
+            proxy.join().unwrap().unwrap(),
+            b"\x03\x0bexample.com\x00\x50"
+        );
+    }
+
+    #[test]
+    fn connect_with_password_works() {
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let (proxy_addr, proxy) = spawn_proxy(Some((b"foo", b"bar")));
+
+        let future = connect_with_password(proxy_addr, "127.0.0.1", 8080, "foo", "bar");
+        let monitor = executor.spawn_monitor(future.and_then(echo));
+        assert_eq!(executor.run_fiber(monitor).unwrap().unwrap(), b"hello");
+        assert_eq!(
+            proxy.join().unwrap().unwrap(),
+            b"\x01\x7f\x00\x00\x01\x1f\x90"
+        );
+
+        // Wrong password
+        let (proxy_addr, proxy) = spawn_proxy(Some((b"foo", b"bar")));
+        let future = connect_with_password(proxy_addr, "127.0.0.1", 8080, "foo", "baz");
+        let monitor = executor.spawn_monitor(future.map_err(|e| e.kind()));
+        assert_eq!(
+            executor.run_fiber(monitor).unwrap().err(),
+            Some(MonitorError::Failed(io::ErrorKind::PermissionDenied))
+        );
+        proxy.join().unwrap().unwrap();
+    }
+}
@@ -2,17 +2,25 @@
 // See the LICENSE file at the top-level directory of this distribution.
 
 use futures::{Async, Future, Poll, Stream};
-use mio;
+use iovec::IoVec;
 use mio::net::{TcpListener as MioTcpListener, TcpStream as MioTcpStream};
+use net2::{TcpBuilder, TcpStreamExt};
 use std::fmt;
 use std::io;
 use std::mem;
-use std::net::SocketAddr;
+use std::net::{self, Shutdown, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use std::vec;
 
-use super::{into_io_error, Bind};
+use super::resolve::{resolve, Resolve};
+use super::{into_io_error, Bind, BindFn};
 use fiber::{self, Context};
-use io::poll::{EventedHandle, Interest, Register};
+use io::poll::{EventedHandle, Interest, ReadinessStream, Register};
 use sync::oneshot::Monitor;
+use sync::Notifier;
+use time::timer::{self, Timeout};
 
 /// A structure representing a socket server.
 ///
@@ -63,17 +71,38 @@ use sync::oneshot::Monitor;
 /// ```
 pub struct TcpListener {
     handle: EventedHandle<MioTcpListener>,
-    monitor: Option<Monitor<(), io::Error>>,
 }
 impl TcpListener {
     /// Makes a future to create a new `TcpListener` which will be bound to the specified address.
     pub fn bind(addr: SocketAddr) -> TcpListenerBind {
-        TcpListenerBind(Bind::Bind(addr, MioTcpListener::bind))
+        TcpListenerBind(Bind::Start(addr, Box::new(MioTcpListener::bind)))
+    }
+
+    /// Makes a builder to create a `TcpListener` with the socket options applied before binding.
+    pub fn builder(addr: SocketAddr) -> TcpListenerBuilder {
+        TcpListenerBuilder {
+            addr,
+            reuse_address: false,
+            reuse_port: false,
+        }
     }
 
     /// Makes a stream of the connections which will be accepted by this listener.
+    ///
+    /// Each item is a pair of the future of a connected stream and the address of the peer.
+    /// The stream accepts all the pending connections before waiting for
+    /// the listener to become readable again.
+    ///
+    /// See `fibers/examples/tcp_echo_srv.rs` for an echo server built on this stream.
     pub fn incoming(self) -> Incoming {
-        Incoming(self)
+        Incoming {
+            readiness: self.handle.readiness_stream(Interest::Read),
+            waiting: false,
+            drain: DrainHandle {
+                drained: Arc::new(AtomicBool::new(false)),
+                notifier: Notifier::new(),
+            },
+        }
     }
 
     /// Returns the local socket address of this listener.
@@ -90,12 +119,62 @@ impl TcpListener {
         self.handle.inner().take_error()
     }
 
+    /// Gets the value of the `IP_TTL` option on this socket.
+    pub fn ttl(&self) -> io::Result<u32> {
+        self.handle.inner().ttl()
+    }
+
+    /// Sets the value of the `IP_TTL` option on this socket.
+    ///
+    /// This value is inherited by the accepted streams.
+    pub fn set_ttl(&self, ttl: u32) -> io::Result<()> {
+        self.handle.inner().set_ttl(ttl)
+    }
+
+    /// Gets the value of the `SO_SNDBUF` option on this socket.
+    pub fn send_buffer_size(&self) -> io::Result<usize> {
+        self.with_socket_view(|s| s.send_buffer_size())
+    }
+
+    /// Sets the value of the `SO_SNDBUF` option on this socket.
+    ///
+    /// This value is inherited by the accepted streams.
+    pub fn set_send_buffer_size(&self, size: usize) -> io::Result<()> {
+        self.with_socket_view(|s| s.set_send_buffer_size(size))
+    }
+
+    /// Gets the value of the `SO_RCVBUF` option on this socket.
+    pub fn recv_buffer_size(&self) -> io::Result<usize> {
+        self.with_socket_view(|s| s.recv_buffer_size())
+    }
+
+    /// Sets the value of the `SO_RCVBUF` option on this socket.
+    ///
+    /// This value is inherited by the accepted streams.
+    /// Note that the TCP window scale is negotiated based on this value,
+    /// so it should be set before accepting connections.
+    pub fn set_recv_buffer_size(&self, size: usize) -> io::Result<()> {
+        self.with_socket_view(|s| s.set_recv_buffer_size(size))
+    }
+
     /// Calls `f` with the reference to the inner socket.
+    ///
+    /// # Safety
+    ///
+    /// Operations performed on the inner socket must not break
+    /// the registration state managed by the I/O poller.
     pub unsafe fn with_inner<F, T>(&self, f: F) -> T
     where
         F: FnOnce(&MioTcpListener) -> T,
     {
-        f(&*self.handle.inner())
+        f(&self.handle.inner())
+    }
+
+    fn with_socket_view<F, T>(&self, f: F) -> T
+    where
+        F: FnOnce(&net::TcpStream) -> T,
+    {
+        super::with_socket_view(&*self.handle.inner(), f)
     }
 }
 impl fmt::Debug for TcpListener {
@@ -109,28 +188,110 @@ impl fmt::Debug for TcpListener {
     }
 }
 
+/// A builder for `TcpListener`.
+///
+/// This is created by calling `TcpListener::builder` function.
+///
+/// # Platform Differences
+///
+/// `SO_REUSEPORT` is only available on Unix platforms (except Solaris and illumos).
+/// On the other platforms, enabling `reuse_port` will make the binding fail.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate fibers;
+/// # extern crate futures;
+/// use fibers::{Executor, InPlaceExecutor, Spawn};
+/// use fibers::net::TcpListener;
+///
+/// # fn main() {
+/// let mut executor = InPlaceExecutor::new().unwrap();
+/// let future = TcpListener::builder("127.0.0.1:0".parse().unwrap())
+///     .reuse_address(true)
+///     .bind();
+/// let monitor = executor.spawn_monitor(future);
+/// let listener = executor.run_fiber(monitor).unwrap().unwrap();
+/// println!("# Start listening: {}", listener.local_addr().unwrap());
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct TcpListenerBuilder {
+    addr: SocketAddr,
+    reuse_address: bool,
+    reuse_port: bool,
+}
+impl TcpListenerBuilder {
+    /// Sets the value of the `SO_REUSEADDR` option of the socket.
+    ///
+    /// The default value is `false`.
+    pub fn reuse_address(mut self, reuse: bool) -> Self {
+        self.reuse_address = reuse;
+        self
+    }
+
+    /// Sets the value of the `SO_REUSEPORT` option of the socket.
+    ///
+    /// The default value is `false`.
+    pub fn reuse_port(mut self, reuse: bool) -> Self {
+        self.reuse_port = reuse;
+        self
+    }
+
+    /// Makes a future to create a new `TcpListener` which will be bound to the specified address.
+    pub fn bind(self) -> TcpListenerBind {
+        let addr = self.addr;
+        TcpListenerBind(Bind::Start(addr, Box::new(move |addr| self.build(addr))))
+    }
+
+    fn build(&self, addr: &SocketAddr) -> io::Result<MioTcpListener> {
+        let builder = if addr.is_ipv4() {
+            TcpBuilder::new_v4()?
+        } else {
+            TcpBuilder::new_v6()?
+        };
+        builder.reuse_address(self.reuse_address)?;
+        if self.reuse_port {
+            set_reuse_port(&builder)?;
+        }
+        let listener = builder.bind(addr)?.listen(1024)?;
+        MioTcpListener::from_std(listener)
+    }
+}
+
+#[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+fn set_reuse_port(builder: &TcpBuilder) -> io::Result<()> {
+    use net2::unix::UnixTcpBuilderExt;
+    builder.reuse_port(true).map(|_| ())
+}
+
+#[cfg(not(all(unix, not(any(target_os = "solaris", target_os = "illumos")))))]
+fn set_reuse_port(_builder: &TcpBuilder) -> io::Result<()> {
+    Err(super::reuse_port_unsupported())
+}
+
 /// A future which will create a new `TcpListener` which will be bound to the specified address.
 ///
-/// This is created by calling `TcpListener::bind` function.
+/// This is created by calling `TcpListener::bind` function or `TcpListenerBuilder::bind` method.
 /// It is permitted to move the future across fibers.
 ///
 /// # Panics
 ///
 /// If the future is polled on the outside of a fiber, it may crash.
 #[derive(Debug)]
-pub struct TcpListenerBind(Bind<fn(&SocketAddr) -> io::Result<MioTcpListener>, MioTcpListener>);
+pub struct TcpListenerBind(Bind<SocketAddr, BindFn<MioTcpListener>, MioTcpListener>);
 impl Future for TcpListenerBind {
     type Item = TcpListener;
     type Error = io::Error;
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        Ok(self.0.poll()?.map(|handle| TcpListener {
-            handle,
-            monitor: None,
-        }))
+        Ok(self.0.poll()?.map(|handle| TcpListener { handle }))
     }
 }
 
-/// An infinite stream of the connections which will be accepted by the listener.
+/// A stream of the connections which will be accepted by the listener.
+///
+/// The stream is infinite unless it is drained
+/// by `Incoming::stop_accepting` method or `DrainHandle::drain` method.
 ///
 /// This is created by calling `TcpListener::incoming` method.
 /// It is permitted to move the future across fibers.
@@ -139,19 +300,75 @@ impl Future for TcpListenerBind {
 ///
 /// If the stream is polled on the outside of a fiber, it may crash.
 #[derive(Debug)]
-pub struct Incoming(TcpListener);
+pub struct Incoming {
+    readiness: ReadinessStream<MioTcpListener>,
+    waiting: bool,
+    drain: DrainHandle,
+}
+impl Incoming {
+    /// Stops accepting new connections.
+    ///
+    /// The stream will terminate at the next polling.
+    /// The connections which have already been yielded by the stream are not affected,
+    /// while the pending ones which have not been accepted yet
+    /// are reset when the listener is dropped.
+    pub fn stop_accepting(&mut self) {
+        self.drain.drain();
+    }
+
+    /// Returns a handle to stop accepting new connections from another fiber (or thread).
+    ///
+    /// This is useful for draining a server gracefully:
+    /// stop accepting new connections by the handle, and then wait for
+    /// the completion of the existing handlers (e.g., by `fibers::sync::WaitGroup`).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate fibers;
+    /// # extern crate futures;
+    /// use fibers::{Executor, InPlaceExecutor, Spawn};
+    /// use fibers::net::TcpListener;
+    /// use futures::{Future, Stream};
+    ///
+    /// # fn main() {
+    /// let mut executor = InPlaceExecutor::new().unwrap();
+    /// let monitor = executor.spawn_monitor(TcpListener::bind("127.0.0.1:0".parse().unwrap()));
+    /// let incoming = executor.run_fiber(monitor).unwrap().unwrap().incoming();
+    ///
+    /// let drain = incoming.drain_handle();
+    /// let server = executor.spawn_monitor(incoming.for_each(|_| Ok(())));
+    /// executor.run_once().unwrap();
+    ///
+    /// drain.drain();
+    /// assert!(executor.run_fiber(server).unwrap().is_ok());
+    /// # }
+    /// ```
+    pub fn drain_handle(&self) -> DrainHandle {
+        self.drain.clone()
+    }
+}
 impl Stream for Incoming {
     type Item = (Connected, SocketAddr);
     type Error = io::Error;
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
         loop {
-            if let Some(mut monitor) = self.0.monitor.take() {
-                if let Async::NotReady = monitor.poll().map_err(into_io_error)? {
-                    self.0.monitor = Some(monitor);
+            if self.drain.is_drained() {
+                return Ok(Async::Ready(None));
+            }
+            if self.waiting {
+                if let Async::NotReady = self.readiness.poll()? {
+                    // NOTE: Re-checks the flag after registering the current fiber
+                    // to avoid missing a wakeup
+                    self.drain.notifier.await();
+                    if self.drain.is_drained() {
+                        return Ok(Async::Ready(None));
+                    }
                     return Ok(Async::NotReady);
                 }
+                self.waiting = false;
             } else {
-                match self.0.handle.inner().accept() {
+                match self.readiness.handle().inner().accept() {
                     Ok((stream, addr)) => {
                         let register = |mut c: Context| c.poller().register(stream);
                         let future = assert_some!(fiber::with_current_context(register));
@@ -160,7 +377,7 @@ impl Stream for Incoming {
                     }
                     Err(e) => {
                         if e.kind() == io::ErrorKind::WouldBlock {
-                            self.0.monitor = Some(self.0.handle.monitor(Interest::Read));
+                            self.waiting = true;
                         } else {
                             return Err(e);
                         }
@@ -171,6 +388,30 @@ impl Stream for Incoming {
     }
 }
 
+/// A handle to stop an `Incoming` stream from accepting new connections.
+///
+/// This is created by calling `Incoming::drain_handle` method.
+#[derive(Debug, Clone)]
+pub struct DrainHandle {
+    drained: Arc<AtomicBool>,
+    notifier: Notifier,
+}
+impl DrainHandle {
+    /// Stops the corresponding `Incoming` stream from accepting new connections.
+    ///
+    /// If the stream is waiting for a connection, it is woken up and terminates.
+    /// See `Incoming::stop_accepting` for more details.
+    pub fn drain(&self) {
+        self.drained.store(true, Ordering::SeqCst);
+        self.notifier.notify();
+    }
+
+    /// Returns `true` if the stream has been drained, otherwise `false`.
+    pub fn is_drained(&self) -> bool {
+        self.drained.load(Ordering::SeqCst)
+    }
+}
+
 /// A future which represents a `TcpStream` connected to a `TcpListener`.
 ///
 /// This is produced by `Incoming` stream.
@@ -186,7 +427,7 @@ impl Future for Connected {
     type Error = io::Error;
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
         let mut future = self.0.take().expect("Cannot poll Connected twice");
-        if let Async::Ready(handle) = future.poll().map_err(into_io_error)? {
+        if let Async::Ready(handle) = future.poll()? {
             Ok(Async::Ready(TcpStream::new(handle)))
         } else {
             self.0 = Some(future);
@@ -287,7 +528,28 @@ impl TcpStream {
         Connect(ConnectInner::Connect(addr))
     }
 
-    /// Returns the local socket address of this listener.
+    /// Makes a future to open a TCP connection to the remote host named `host`.
+    ///
+    /// The hostname is resolved by `fibers::net::resolve` function and
+    /// the resolved addresses are tried in order until a connection is established.
+    /// If all attempts fail, the error of the last attempt will be returned.
+    pub fn connect_host(host: &str, port: u16) -> ConnectHost {
+        ConnectHost(ConnectHostInner::Resolving(resolve(host, port)))
+    }
+
+    /// Makes a future to open a TCP connection to a remote host within the specified duration.
+    ///
+    /// If the connection is not established before `timeout` passes,
+    /// the future will fail with an error of the kind `io::ErrorKind::TimedOut`
+    /// and the half-open socket will be closed.
+    pub fn connect_timeout(addr: SocketAddr, timeout: Duration) -> ConnectTimeout {
+        ConnectTimeout {
+            connect: TcpStream::connect(addr),
+            timeout: timer::timeout(timeout),
+        }
+    }
+
+    /// Returns the socket address of the local half of this TCP connection.
     pub fn local_addr(&self) -> io::Result<SocketAddr> {
         self.handle.inner().local_addr()
     }
@@ -306,6 +568,17 @@ impl TcpStream {
         self.handle.inner().take_error()
     }
 
+    /// Shuts down the read, write, or both halves of this connection.
+    ///
+    /// Shutting down only one direction keeps the other one available.
+    /// For example, `Shutdown::Write` signals EOF to the peer while
+    /// this stream can still read the data sent by the peer.
+    ///
+    /// The socket remains registered to the poller until all the clones of this stream are dropped.
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        self.handle.inner().shutdown(how)
+    }
+
     /// Gets the value of the `TCP_NODELAY` option on this socket.
     pub fn nodelay(&self) -> io::Result<bool> {
         self.handle.inner().nodelay()
@@ -316,12 +589,111 @@ impl TcpStream {
         self.handle.inner().set_nodelay(nodelay)
     }
 
+    /// Gets the keepalive interval of this socket.
+    ///
+    /// `None` means that the `SO_KEEPALIVE` option is disabled.
+    pub fn keepalive(&self) -> io::Result<Option<Duration>> {
+        self.handle.inner().keepalive()
+    }
+
+    /// Sets the keepalive interval of this socket.
+    ///
+    /// If `keepalive` is `None`, the `SO_KEEPALIVE` option will be disabled.
+    pub fn set_keepalive(&self, keepalive: Option<Duration>) -> io::Result<()> {
+        self.handle.inner().set_keepalive(keepalive)
+    }
+
+    /// Gets the value of the `IP_TTL` option on this socket.
+    pub fn ttl(&self) -> io::Result<u32> {
+        self.handle.inner().ttl()
+    }
+
+    /// Sets the value of the `IP_TTL` option on this socket.
+    ///
+    /// This indicates the time-to-live value of the outgoing packets.
+    pub fn set_ttl(&self, ttl: u32) -> io::Result<()> {
+        self.handle.inner().set_ttl(ttl)
+    }
+
+    /// Gets the value of the `SO_SNDBUF` option on this socket.
+    pub fn send_buffer_size(&self) -> io::Result<usize> {
+        self.handle.inner().send_buffer_size()
+    }
+
+    /// Sets the value of the `SO_SNDBUF` option on this socket.
+    ///
+    /// Note that the system may adjust the value (e.g., Linux doubles it).
+    pub fn set_send_buffer_size(&self, size: usize) -> io::Result<()> {
+        self.handle.inner().set_send_buffer_size(size)
+    }
+
+    /// Gets the value of the `SO_RCVBUF` option on this socket.
+    pub fn recv_buffer_size(&self) -> io::Result<usize> {
+        self.handle.inner().recv_buffer_size()
+    }
+
+    /// Sets the value of the `SO_RCVBUF` option on this socket.
+    ///
+    /// Note that the system may adjust the value (e.g., Linux doubles it).
+    pub fn set_recv_buffer_size(&self, size: usize) -> io::Result<()> {
+        self.handle.inner().set_recv_buffer_size(size)
+    }
+
+    /// Attempts to read some bytes from this stream into `buf`.
+    ///
+    /// This is equivalent to `io::Read::read` except that `WouldBlock` is
+    /// converted to `Async::NotReady`, which is handy for implementing futures.
+    ///
+    /// If `Async::NotReady` is returned, the current fiber is guaranteed to be woken up
+    /// (i.e., polled again) when the stream becomes readable.
+    /// The pending monitor is kept in the stream,
+    /// so polling repeatedly before that does not send redundant requests to the poller.
+    ///
+    /// `Async::Ready(0)` means that the peer has closed its write half of the connection.
+    ///
+    /// Note that the wakeup is only guaranteed when this method is called in a fiber.
+    pub fn poll_read(&mut self, buf: &mut [u8]) -> Poll<usize, io::Error> {
+        into_poll(io::Read::read(self, buf))
+    }
+
+    /// Attempts to write some bytes in `buf` to this stream.
+    ///
+    /// This is equivalent to `io::Write::write` except that `WouldBlock` is
+    /// converted to `Async::NotReady`.
+    ///
+    /// The same contract as `poll_read` applies to `Async::NotReady`
+    /// (the fiber will be woken up when the stream becomes writable).
+    pub fn poll_write(&mut self, buf: &[u8]) -> Poll<usize, io::Error> {
+        into_poll(io::Write::write(self, buf))
+    }
+
+    /// Makes a future to write all the data in `bufs` to this stream.
+    ///
+    /// The buffers are written with a vectored write (i.e., `writev`),
+    /// so a small header and a large body can be sent without concatenating them
+    /// or issuing a system call for each of them.
+    /// If the buffers are partially written, the remaining part is written on the next try.
+    ///
+    /// The future will resolve to the stream and the total number of written bytes.
+    pub fn write_bufs(self, bufs: Vec<Vec<u8>>) -> WriteBufs {
+        WriteBufs(Some(WriteBufsInner {
+            stream: self,
+            cursor: BufsCursor::new(bufs),
+            written: 0,
+        }))
+    }
+
     /// Calls `f` with the reference to the inner socket.
+    ///
+    /// # Safety
+    ///
+    /// Operations performed on the inner socket must not break
+    /// the registration state managed by the I/O poller.
     pub unsafe fn with_inner<F, T>(&self, f: F) -> T
     where
         F: FnOnce(&MioTcpStream) -> T,
     {
-        f(&*self.handle.inner())
+        f(&self.handle.inner())
     }
 
     fn monitor(&mut self, interest: Interest) -> &mut Option<Monitor<(), io::Error>> {
@@ -336,7 +708,7 @@ impl TcpStream {
             *self.monitor(interest) = Some(self.handle.monitor(interest));
             if let Err(e) = self.monitor(interest).poll() {
                 return Err(e.unwrap_or_else(|| {
-                    io::Error::new(io::ErrorKind::Other, "Monitor channel disconnected")
+                    io::Error::other("Monitor channel disconnected")
                 }));
             }
             Ok(true)
@@ -344,32 +716,26 @@ impl TcpStream {
             Ok(false)
         }
     }
-    fn operate<F, T>(&mut self, interest: Interest, mut f: F) -> io::Result<T>
+    fn operate<F, T>(&mut self, interest: Interest, f: F) -> io::Result<T>
     where
         F: FnMut(&mut MioTcpStream) -> io::Result<T>,
     {
-        loop {
-            if let Some(mut monitor) = self.monitor(interest).take() {
-                if let Async::NotReady = monitor.poll().map_err(into_io_error)? {
-                    *self.monitor(interest) = Some(monitor);
-                    return Err(mio::would_block());
-                }
-            } else {
-                let result = f(&mut *self.handle.inner());
-                match result {
-                    Err(e) => {
-                        if e.kind() == io::ErrorKind::WouldBlock {
-                            *self.monitor(interest) = Some(self.handle.monitor(interest));
-                        } else {
-                            return Err(e);
-                        }
-                    }
-                    Ok(v) => return Ok(v),
-                }
-            }
-        }
+        let monitor = if interest == Interest::Read {
+            &mut self.read_monitor
+        } else {
+            &mut self.write_monitor
+        };
+        self.handle.operate(monitor, interest, f)
     }
 }
+fn into_poll<T>(result: io::Result<T>) -> Poll<T, io::Error> {
+    match result {
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(Async::NotReady),
+        Err(e) => Err(e),
+        Ok(v) => Ok(Async::Ready(v)),
+    }
+}
+
 impl io::Read for TcpStream {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         self.operate(Interest::Read, |inner| inner.read(buf))
@@ -397,6 +763,99 @@ impl fmt::Debug for TcpStream {
     }
 }
 
+/// A future which will write all the data in the buffers to a TCP stream.
+///
+/// This is created by calling `TcpStream::write_bufs` method.
+/// It is permitted to move the future across fibers.
+///
+/// # Panics
+///
+/// If the future is polled on the outside of a fiber, it may crash.
+#[derive(Debug)]
+pub struct WriteBufs(Option<WriteBufsInner>);
+impl Future for WriteBufs {
+    type Item = (TcpStream, usize);
+    type Error = io::Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        {
+            let inner = self.0.as_mut().expect("Cannot poll WriteBufs twice");
+            while !inner.cursor.is_empty() {
+                let cursor = &mut inner.cursor;
+                let size = match inner.stream.operate(Interest::Write, |stream| {
+                    stream.write_bufs(&cursor.slices())
+                }) {
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        return Ok(Async::NotReady);
+                    }
+                    Err(e) => return Err(e),
+                    Ok(0) => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::WriteZero,
+                            "Failed to write the whole buffers",
+                        ));
+                    }
+                    Ok(size) => size,
+                };
+                cursor.advance(size);
+                inner.written += size;
+            }
+        }
+        let inner = self.0.take().expect("Never fails");
+        Ok(Async::Ready((inner.stream, inner.written)))
+    }
+}
+
+#[derive(Debug)]
+struct WriteBufsInner {
+    stream: TcpStream,
+    cursor: BufsCursor,
+    written: usize,
+}
+
+#[derive(Debug)]
+struct BufsCursor {
+    bufs: Vec<Vec<u8>>,
+    index: usize,
+    offset: usize,
+}
+impl BufsCursor {
+    fn new(bufs: Vec<Vec<u8>>) -> Self {
+        let mut this = BufsCursor {
+            bufs,
+            index: 0,
+            offset: 0,
+        };
+        this.advance(0);
+        this
+    }
+    fn is_empty(&self) -> bool {
+        self.index == self.bufs.len()
+    }
+    fn slices(&self) -> Vec<&IoVec> {
+        self.bufs[self.index..]
+            .iter()
+            .enumerate()
+            .filter_map(|(i, b)| {
+                let offset = if i == 0 { self.offset } else { 0 };
+                IoVec::from_bytes(&b[offset..])
+            })
+            .collect()
+    }
+    fn advance(&mut self, mut size: usize) {
+        while self.index < self.bufs.len() {
+            let remaining = self.bufs[self.index].len() - self.offset;
+            if size < remaining {
+                self.offset += size;
+                return;
+            }
+            size -= remaining;
+            self.index += 1;
+            self.offset = 0;
+        }
+        assert_eq!(size, 0, "Advanced beyond the end of the buffers");
+    }
+}
+
 /// A future which will open a TCP connection to a remote host.
 ///
 /// This is created by calling `TcpStream::connect` function.
@@ -415,6 +874,95 @@ impl Future for Connect {
     }
 }
 
+/// A future which will open a TCP connection to a remote host within a time limit.
+///
+/// This is created by calling `TcpStream::connect_timeout` function.
+/// It is permitted to move the future across fibers.
+///
+/// # Panics
+///
+/// If the future is polled on the outside of a fiber, it may crash.
+#[derive(Debug)]
+pub struct ConnectTimeout {
+    connect: Connect,
+    timeout: Timeout,
+}
+impl Future for ConnectTimeout {
+    type Item = TcpStream;
+    type Error = io::Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if let Async::Ready(stream) = self.connect.poll()? {
+            return Ok(Async::Ready(stream));
+        }
+        match self.timeout.poll() {
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Ok(Async::Ready(())) => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "Connection timed out",
+            )),
+            Err(e) => Err(into_io_error(e)),
+        }
+    }
+}
+
+/// A future which will open a TCP connection to a remote host specified by its name.
+///
+/// This is created by calling `TcpStream::connect_host` function.
+/// It is permitted to move the future across fibers.
+///
+/// # Panics
+///
+/// If the future is polled on the outside of a fiber, it may crash.
+#[derive(Debug)]
+pub struct ConnectHost(ConnectHostInner);
+impl Future for ConnectHost {
+    type Item = TcpStream;
+    type Error = io::Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.0 {
+                ConnectHostInner::Resolving(ref mut future) => {
+                    if let Async::Ready(addrs) = future.poll()? {
+                        let mut addrs = addrs.into_iter();
+                        if let Some(addr) = addrs.next() {
+                            ConnectHostInner::Connecting(TcpStream::connect(addr), addrs)
+                        } else {
+                            return Err(io::Error::new(
+                                io::ErrorKind::NotFound,
+                                "No addresses are associated with the hostname",
+                            ));
+                        }
+                    } else {
+                        return Ok(Async::NotReady);
+                    }
+                }
+                ConnectHostInner::Connecting(ref mut future, ref mut addrs) => {
+                    match future.poll() {
+                        Ok(polled) => return Ok(polled),
+                        Err(e) => {
+                            if let Some(addr) = addrs.next() {
+                                ConnectHostInner::Connecting(
+                                    TcpStream::connect(addr),
+                                    mem::replace(addrs, Vec::new().into_iter()),
+                                )
+                            } else {
+                                return Err(e);
+                            }
+                        }
+                    }
+                }
+            };
+            self.0 = next;
+        }
+    }
+}
+
+#[derive(Debug)]
+enum ConnectHostInner {
+    Resolving(Resolve),
+    Connecting(Connect, vec::IntoIter<SocketAddr>),
+}
+
 #[derive(Debug)]
 enum ConnectInner {
     Connect(SocketAddr),
@@ -436,7 +984,7 @@ impl Future for ConnectInner {
                 self.poll()
             }
             ConnectInner::Registering(mut future) => {
-                if let Async::Ready(handle) = future.poll().map_err(into_io_error)? {
+                if let Async::Ready(handle) = future.poll()? {
                     *self = ConnectInner::Connecting(TcpStream::new(handle));
                     self.poll()
                 } else {
@@ -467,3 +1015,420 @@ impl Future for ConnectInner {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use futures::{self, Async, Future, Stream};
+    use std::cmp;
+    use std::io::{self, Read, Write};
+    use std::net;
+    use std::sync::atomic::AtomicUsize;
+    use std::time::Duration;
+
+    use super::*;
+    use executor::{Executor, InPlaceExecutor};
+    use fiber::Spawn;
+    use io::{read_exact, write_all};
+    use sync::oneshot::MonitorError;
+    use sync::WaitGroup;
+
+    #[test]
+    fn connect_timeout_works() {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut executor = InPlaceExecutor::new().unwrap();
+
+        let future = TcpStream::connect_timeout(addr, Duration::from_secs(10));
+        let monitor = executor.spawn_monitor(future.map(|stream| stream.peer_addr().ok()));
+        assert_eq!(executor.run_fiber(monitor).unwrap().unwrap(), Some(addr));
+
+        // The deadline has passed before the socket is registered to the poller
+        let future = TcpStream::connect_timeout(addr, Duration::from_secs(0));
+        let monitor = executor.spawn_monitor(future.map_err(|e| e.kind()));
+        assert_eq!(
+            executor.run_fiber(monitor).unwrap().err(),
+            Some(MonitorError::Failed(io::ErrorKind::TimedOut))
+        );
+    }
+
+    #[test]
+    fn connect_host_works() {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut executor = InPlaceExecutor::new().unwrap();
+
+        // "localhost" may be resolved to "::1" first, which is not listened on
+        let future = TcpStream::connect_host("localhost", addr.port());
+        let monitor = executor.spawn_monitor(future.map(|stream| stream.peer_addr().ok()));
+        assert_eq!(executor.run_fiber(monitor).unwrap().unwrap(), Some(addr));
+    }
+
+    #[test]
+    fn listener_options_work() {
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let monitor = executor.spawn_monitor(TcpListener::bind("127.0.0.1:0".parse().unwrap()));
+        let listener = executor.run_fiber(monitor).unwrap().unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        listener.set_ttl(42).unwrap();
+        assert_eq!(listener.ttl().unwrap(), 42);
+        listener.set_send_buffer_size(64 * 1024).unwrap();
+        assert!(listener.send_buffer_size().unwrap() >= 64 * 1024);
+        listener.set_recv_buffer_size(64 * 1024).unwrap();
+        assert!(listener.recv_buffer_size().unwrap() >= 64 * 1024);
+
+        // The listener is still usable after accessing the options
+        let client = net::TcpStream::connect(addr).unwrap();
+        let future = listener.incoming().into_future().map_err(|(e, _)| e);
+        let monitor = executor.spawn_monitor(future.map(|(client, _)| client.map(|c| c.1)));
+        let peer = executor.run_fiber(monitor).unwrap().unwrap();
+        assert_eq!(peer, client.local_addr().ok());
+    }
+
+    #[test]
+    fn drained_listener_stops_accepting_but_handlers_complete() {
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let monitor = executor.spawn_monitor(TcpListener::bind("127.0.0.1:0".parse().unwrap()));
+        let listener = executor.run_fiber(monitor).unwrap().unwrap();
+        let addr = listener.local_addr().unwrap();
+        let incoming = listener.incoming();
+        let drain = incoming.drain_handle();
+
+        // Spawns an echo handler for each accepted connection
+        let handle = executor.handle();
+        let handlers = WaitGroup::new();
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let server = {
+            let handlers = handlers.clone();
+            let accepted = Arc::clone(&accepted);
+            incoming.for_each(move |(connected, _)| {
+                accepted.fetch_add(1, Ordering::SeqCst);
+                let token = handlers.token();
+                handle.spawn(
+                    connected
+                        .and_then(|stream| read_exact(stream, [0; 4]).map_err(|(_, _, e)| e))
+                        .and_then(|(stream, buf)| write_all(stream, buf).map_err(|(_, _, e)| e))
+                        .then(move |_| {
+                            mem::drop(token);
+                            Ok(())
+                        }),
+                );
+                Ok(())
+            })
+        };
+        let server = executor.spawn_monitor(server);
+
+        let monitor = executor.spawn_monitor(TcpStream::connect(addr));
+        let client = executor.run_fiber(monitor).unwrap().unwrap();
+        while handlers.count() == 0 {
+            executor.run_once().unwrap();
+        }
+
+        // The connections made after draining are never accepted
+        drain.drain();
+        let _late_client = net::TcpStream::connect(addr);
+        executor.run_fiber(server).unwrap().unwrap();
+        assert_eq!(accepted.load(Ordering::SeqCst), 1);
+        assert!(drain.is_drained());
+
+        // The in-flight connection still works
+        let client = write_all(client, *b"ping")
+            .and_then(|(client, _)| read_exact(client, [0; 4]))
+            .map(|(_, buf)| buf)
+            .map_err(|(_, _, e)| e);
+        let client = executor.spawn_monitor(client);
+        assert_eq!(&executor.run_fiber(client).unwrap().unwrap(), b"ping");
+
+        let monitor = executor.spawn_monitor(handlers.wait());
+        executor.run_fiber(monitor).unwrap().unwrap();
+    }
+
+    #[test]
+    fn incoming_accepts_all_pending_connections() {
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let monitor = executor.spawn_monitor(TcpListener::bind("127.0.0.1:0".parse().unwrap()));
+        let listener = executor.run_fiber(monitor).unwrap().unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let clients = (0..3)
+            .map(|_| net::TcpStream::connect(addr).unwrap())
+            .collect::<Vec<_>>();
+        let future = listener
+            .incoming()
+            .and_then(|(connected, peer)| connected.map(move |stream| (stream, peer)))
+            .take(clients.len() as u64)
+            .map(|(stream, peer)| (stream.peer_addr().unwrap(), peer))
+            .collect();
+        let monitor = executor.spawn_monitor(future);
+        let mut peers = executor.run_fiber(monitor).unwrap().unwrap();
+        peers.sort();
+
+        let mut expected = clients
+            .iter()
+            .map(|c| {
+                let addr = c.local_addr().unwrap();
+                (addr, addr)
+            })
+            .collect::<Vec<_>>();
+        expected.sort();
+        assert_eq!(peers, expected);
+    }
+
+    #[test]
+    #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+    fn listeners_can_share_a_port() {
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let bind = |addr| {
+            TcpListener::builder(addr)
+                .reuse_address(true)
+                .reuse_port(true)
+                .bind()
+        };
+        let monitor = executor.spawn_monitor(bind("127.0.0.1:0".parse().unwrap()));
+        let listener0 = executor.run_fiber(monitor).unwrap().unwrap();
+        let addr = listener0.local_addr().unwrap();
+
+        let monitor = executor.spawn_monitor(bind(addr));
+        let listener1 = executor.run_fiber(monitor).unwrap().unwrap();
+        assert_eq!(listener1.local_addr().unwrap(), addr);
+
+        // Without `SO_REUSEPORT`, the port cannot be shared
+        let monitor = executor.spawn_monitor(TcpListener::bind(addr).map_err(|e| e.kind()));
+        assert_eq!(
+            executor.run_fiber(monitor).unwrap().err(),
+            Some(MonitorError::Failed(io::ErrorKind::AddrInUse))
+        );
+    }
+
+    #[test]
+    fn addresses_work() {
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let monitor = executor.spawn_monitor(TcpListener::bind("127.0.0.1:0".parse().unwrap()));
+        let listener = executor.run_fiber(monitor).unwrap().unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = listener
+            .incoming()
+            .into_future()
+            .map_err(|(e, _)| e)
+            .and_then(|(client, _)| {
+                let (connected, peer) = client.unwrap();
+                connected.map(move |stream| (stream, peer))
+            });
+        let server = executor.spawn_monitor(server);
+        let client = executor.spawn_monitor(TcpStream::connect(addr));
+        let client = executor.run_fiber(client).unwrap().unwrap();
+        let (server, peer) = executor.run_fiber(server).unwrap().unwrap();
+
+        assert_eq!(client.peer_addr().unwrap(), addr);
+        assert_eq!(server.local_addr().unwrap(), addr);
+        assert_eq!(client.local_addr().unwrap(), peer);
+        assert_eq!(server.peer_addr().unwrap(), peer);
+    }
+
+    #[test]
+    fn half_close_works() {
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let monitor = executor.spawn_monitor(TcpListener::bind("127.0.0.1:0".parse().unwrap()));
+        let listener = executor.run_fiber(monitor).unwrap().unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // Reads until EOF, then replies
+        let server = listener
+            .incoming()
+            .into_future()
+            .map_err(|(e, _)| e)
+            .and_then(|(client, _)| client.unwrap().0)
+            .and_then(|stream| read_to_end(stream, Vec::new()))
+            .and_then(|(mut stream, buf)| {
+                stream.write_all(b"bye")?;
+                Ok(buf)
+            });
+        let client = TcpStream::connect(addr).and_then(|mut stream| {
+            stream.write_all(b"hello")?;
+            stream.shutdown(Shutdown::Write)?;
+            Ok(read_to_end(stream, Vec::new()).map(|(_, buf)| buf))
+        });
+        let server = executor.spawn_monitor(server);
+        let client = executor.spawn_monitor(client.flatten());
+        assert_eq!(executor.run_fiber(server).unwrap().unwrap(), b"hello");
+        assert_eq!(executor.run_fiber(client).unwrap().unwrap(), b"bye");
+    }
+
+    #[test]
+    fn poll_read_and_poll_write_work() {
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let monitor = executor.spawn_monitor(TcpListener::bind("127.0.0.1:0".parse().unwrap()));
+        let listener = executor.run_fiber(monitor).unwrap().unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // Echoes back all the data until EOF
+        let server = listener
+            .incoming()
+            .into_future()
+            .map_err(|(e, _)| e)
+            .and_then(|(client, _)| client.unwrap().0)
+            .and_then(|mut stream| {
+                let mut buf = [0; 4];
+                let mut filled = 0;
+                let mut written = 0;
+                futures::future::poll_fn(move || loop {
+                    if written < filled {
+                        match stream.poll_write(&buf[written..filled])? {
+                            Async::NotReady => return Ok(Async::NotReady),
+                            Async::Ready(size) => written += size,
+                        }
+                    } else {
+                        match stream.poll_read(&mut buf)? {
+                            Async::NotReady => return Ok(Async::NotReady),
+                            Async::Ready(0) => return Ok(Async::Ready(())),
+                            Async::Ready(size) => {
+                                filled = size;
+                                written = 0;
+                            }
+                        }
+                    }
+                })
+            });
+        let client = TcpStream::connect(addr).and_then(|mut stream| {
+            stream.write_all(b"hello world")?;
+            stream.shutdown(Shutdown::Write)?;
+            Ok(read_to_end(stream, Vec::new()).map(|(_, buf)| buf))
+        });
+        let server = executor.spawn_monitor(server);
+        let client = executor.spawn_monitor(client.flatten());
+        assert_eq!(executor.run_fiber(client).unwrap().unwrap(), b"hello world");
+        assert!(executor.run_fiber(server).unwrap().is_ok());
+    }
+
+    #[test]
+    fn write_bufs_works() {
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let monitor = executor.spawn_monitor(TcpListener::bind("127.0.0.1:0".parse().unwrap()));
+        let listener = executor.run_fiber(monitor).unwrap().unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = listener
+            .incoming()
+            .into_future()
+            .map_err(|(e, _)| e)
+            .and_then(|(client, _)| client.unwrap().0)
+            .and_then(|stream| read_to_end(stream, Vec::new()))
+            .map(|(_, buf)| buf);
+
+        // The large body cannot be written at once, so the writes will be partial
+        let body = (0..4 * 1024 * 1024).map(|i| i as u8).collect::<Vec<_>>();
+        let bufs = vec![
+            b"header".to_vec(),
+            Vec::new(),
+            body.clone(),
+            b"footer".to_vec(),
+        ];
+        let client = TcpStream::connect(addr)
+            .and_then(|stream| stream.write_bufs(bufs))
+            .and_then(|(stream, size)| {
+                stream.shutdown(Shutdown::Write)?;
+                Ok(size)
+            });
+        let server = executor.spawn_monitor(server);
+        let client = executor.spawn_monitor(client);
+
+        let mut expected = b"header".to_vec();
+        expected.extend_from_slice(&body);
+        expected.extend_from_slice(b"footer");
+        assert_eq!(executor.run_fiber(client).unwrap().unwrap(), expected.len());
+        assert_eq!(executor.run_fiber(server).unwrap().unwrap(), expected);
+    }
+
+    #[test]
+    fn bufs_cursor_advances_across_buffers() {
+        let bufs = vec![
+            b"abc".to_vec(),
+            Vec::new(),
+            b"de".to_vec(),
+            b"fghi".to_vec(),
+        ];
+        let mut cursor = BufsCursor::new(bufs);
+
+        // Simulates a writer which accepts at most 4 bytes at a time
+        let mut written = Vec::new();
+        while !cursor.is_empty() {
+            let mut size = 0;
+            for slice in cursor.slices() {
+                let n = cmp::min(4 - size, slice.len());
+                written.extend_from_slice(&slice[..n]);
+                size += n;
+            }
+            cursor.advance(size);
+        }
+        assert_eq!(written, b"abcdefghi");
+        assert!(BufsCursor::new(vec![Vec::new()]).is_empty());
+    }
+
+    fn read_to_end(
+        mut stream: TcpStream,
+        mut buf: Vec<u8>,
+    ) -> impl Future<Item = (TcpStream, Vec<u8>), Error = io::Error> {
+        futures::future::poll_fn(move || loop {
+            let mut bytes = [0; 16];
+            match stream.read(&mut bytes) {
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(Async::NotReady),
+                Err(e) => return Err(e),
+                Ok(0) => return Ok(Async::Ready((stream.clone(), mem::take(&mut buf)))),
+                Ok(size) => buf.extend_from_slice(&bytes[..size]),
+            }
+        })
+    }
+
+    #[test]
+    fn socket_options_work() {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut executor = InPlaceExecutor::new().unwrap();
+
+        let future = TcpStream::connect(addr).and_then(|stream| {
+            stream.set_nodelay(true)?;
+            stream.set_keepalive(Some(Duration::from_secs(30)))?;
+            let enabled = (stream.nodelay()?, stream.keepalive()?);
+            stream.set_nodelay(false)?;
+            stream.set_keepalive(None)?;
+            let disabled = (stream.nodelay()?, stream.keepalive()?);
+            Ok((enabled, disabled))
+        });
+        let monitor = executor.spawn_monitor(future);
+        let (enabled, disabled) = executor.run_fiber(monitor).unwrap().unwrap();
+        assert_eq!(enabled, (true, Some(Duration::from_secs(30))));
+        assert_eq!(disabled, (false, None));
+
+        let future = TcpStream::connect(addr).and_then(|stream| {
+            stream.set_ttl(42)?;
+            stream.set_send_buffer_size(64 * 1024)?;
+            stream.set_recv_buffer_size(64 * 1024)?;
+            Ok((
+                stream.ttl()?,
+                stream.send_buffer_size()?,
+                stream.recv_buffer_size()?,
+            ))
+        });
+        let monitor = executor.spawn_monitor(future);
+        let (ttl, send_buffer_size, recv_buffer_size) =
+            executor.run_fiber(monitor).unwrap().unwrap();
+        assert_eq!(ttl, 42);
+        assert!(send_buffer_size >= 64 * 1024);
+        assert!(recv_buffer_size >= 64 * 1024);
+    }
+
+    #[test]
+    #[ignore] // Requires a network which silently drops packets to the non-routable address
+    fn connect_to_blackholed_address_times_out() {
+        let addr = "10.255.255.1:80".parse().unwrap();
+        let mut executor = InPlaceExecutor::new().unwrap();
+
+        let future = TcpStream::connect_timeout(addr, Duration::from_millis(100));
+        let monitor = executor.spawn_monitor(future.map_err(|e| e.kind()));
+        assert_eq!(
+            executor.run_fiber(monitor).unwrap().err(),
+            Some(MonitorError::Failed(io::ErrorKind::TimedOut))
+        );
+    }
+}
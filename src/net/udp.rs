@@ -3,11 +3,12 @@
 
 use futures::{Async, Future, Poll};
 use mio::net::UdpSocket as MioUdpSocket;
+use net2::{UdpBuilder, UdpSocketExt};
 use std::fmt;
 use std::io;
-use std::net::SocketAddr;
+use std::net::{self, Ipv4Addr, Ipv6Addr, SocketAddr};
 
-use super::{into_io_error, Bind};
+use super::{into_io_error, Bind, BindFn};
 use io::poll::{EventedHandle, Interest};
 use sync::oneshot::Monitor;
 
@@ -63,26 +64,53 @@ pub struct UdpSocket {
 impl UdpSocket {
     /// Makes a future to create a UDP socket binded to the given address.
     pub fn bind(addr: SocketAddr) -> UdpSocketBind {
-        UdpSocketBind(Bind::Bind(addr, MioUdpSocket::bind))
+        UdpSocketBind(Bind::Start(addr, Box::new(MioUdpSocket::bind)))
+    }
+
+    /// Makes a builder to create a UDP socket with the socket options applied before binding.
+    pub fn builder(addr: SocketAddr) -> UdpSocketBuilder {
+        UdpSocketBuilder {
+            addr,
+            reuse_address: false,
+            reuse_port: false,
+        }
     }
 
     /// Makes a future to send data on the socket to the given address.
     pub fn send_to<B: AsRef<[u8]>>(self, buf: B, target: SocketAddr) -> SendTo<B> {
-        SendTo(Some(SendToInner {
-            socket: self,
-            buf,
+        SendTo {
+            io: UdpIo::new(self, buf, false),
             target,
-            monitor: None,
-        }))
+        }
     }
 
     /// Makes a future to receive data from the socket.
     pub fn recv_from<B: AsMut<[u8]>>(self, buf: B) -> RecvFrom<B> {
-        RecvFrom(Some(RecvFromInner {
-            socket: self,
-            buf,
-            monitor: None,
-        }))
+        RecvFrom(UdpIo::new(self, buf, false))
+    }
+
+    /// Makes a future to connect the socket to the given remote address.
+    ///
+    /// After connecting, `send` and `recv` methods can be used to exchange data with the peer,
+    /// and the datagrams from the other addresses are discarded by the system.
+    pub fn connect(self, addr: SocketAddr) -> UdpSocketConnect {
+        UdpSocketConnect(Some((self, addr)))
+    }
+
+    /// Makes a future to send data on the socket to the connected peer.
+    ///
+    /// If the socket has not been connected by `connect` method,
+    /// the future will fail with `io::ErrorKind::NotConnected`.
+    pub fn send<B: AsRef<[u8]>>(self, buf: B) -> UdpSocketSend<B> {
+        UdpSocketSend(UdpIo::new(self, buf, true))
+    }
+
+    /// Makes a future to receive data on the socket from the connected peer.
+    ///
+    /// If the socket has not been connected by `connect` method,
+    /// the future will fail with `io::ErrorKind::NotConnected`.
+    pub fn recv<B: AsMut<[u8]>>(self, buf: B) -> UdpSocketRecv<B> {
+        UdpSocketRecv(UdpIo::new(self, buf, true))
     }
 
     /// Returns the socket address that this socket was created from.
@@ -90,6 +118,14 @@ impl UdpSocket {
         self.handle.inner().local_addr()
     }
 
+    /// Returns the socket address of the peer that this socket is connected to.
+    ///
+    /// If the socket has not been connected by `connect` method,
+    /// this will return an error of `io::ErrorKind::NotConnected`.
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.with_socket_view(|s| s.peer_addr())
+    }
+
     /// Get the value of the `SO_ERROR` option on this socket.
     ///
     /// This will retrieve the stored error in the underlying socket,
@@ -99,12 +135,125 @@ impl UdpSocket {
         self.handle.inner().take_error()
     }
 
+    /// Gets the value of the `SO_BROADCAST` option on this socket.
+    pub fn broadcast(&self) -> io::Result<bool> {
+        self.handle.inner().broadcast()
+    }
+
+    /// Sets the value of the `SO_BROADCAST` option on this socket.
+    ///
+    /// If enabled, this socket is allowed to send packets to a broadcast address.
+    pub fn set_broadcast(&self, on: bool) -> io::Result<()> {
+        self.handle.inner().set_broadcast(on)
+    }
+
+    /// Gets the value of the `IP_TTL` option on this socket.
+    pub fn ttl(&self) -> io::Result<u32> {
+        self.handle.inner().ttl()
+    }
+
+    /// Sets the value of the `IP_TTL` option on this socket.
+    ///
+    /// This indicates the time-to-live value of the outgoing unicast packets.
+    pub fn set_ttl(&self, ttl: u32) -> io::Result<()> {
+        self.handle.inner().set_ttl(ttl)
+    }
+
+    /// Gets the value of the `SO_SNDBUF` option on this socket.
+    pub fn send_buffer_size(&self) -> io::Result<usize> {
+        self.with_socket_view(|s| s.send_buffer_size())
+    }
+
+    /// Sets the value of the `SO_SNDBUF` option on this socket.
+    ///
+    /// Note that the system may adjust the value (e.g., Linux doubles it).
+    pub fn set_send_buffer_size(&self, size: usize) -> io::Result<()> {
+        self.with_socket_view(|s| s.set_send_buffer_size(size))
+    }
+
+    /// Gets the value of the `SO_RCVBUF` option on this socket.
+    pub fn recv_buffer_size(&self) -> io::Result<usize> {
+        self.with_socket_view(|s| s.recv_buffer_size())
+    }
+
+    /// Sets the value of the `SO_RCVBUF` option on this socket.
+    ///
+    /// Note that the system may adjust the value (e.g., Linux doubles it).
+    pub fn set_recv_buffer_size(&self, size: usize) -> io::Result<()> {
+        self.with_socket_view(|s| s.set_recv_buffer_size(size))
+    }
+
+    /// Joins the multicast group `multiaddr` on the interface `interface`.
+    ///
+    /// If `interface` is `Ipv4Addr::UNSPECIFIED`, an appropriate interface is chosen by the system.
+    pub fn join_multicast_v4(&self, multiaddr: &Ipv4Addr, interface: &Ipv4Addr) -> io::Result<()> {
+        self.handle.inner().join_multicast_v4(multiaddr, interface)
+    }
+
+    /// Joins the multicast group `multiaddr` on the interface specified by the index `interface`.
+    ///
+    /// If `interface` is `0`, an appropriate interface is chosen by the system.
+    pub fn join_multicast_v6(&self, multiaddr: &Ipv6Addr, interface: u32) -> io::Result<()> {
+        self.handle.inner().join_multicast_v6(multiaddr, interface)
+    }
+
+    /// Leaves the multicast group `multiaddr` on the interface `interface`.
+    ///
+    /// See also: `join_multicast_v4` method.
+    pub fn leave_multicast_v4(&self, multiaddr: &Ipv4Addr, interface: &Ipv4Addr) -> io::Result<()> {
+        self.handle.inner().leave_multicast_v4(multiaddr, interface)
+    }
+
+    /// Leaves the multicast group `multiaddr` on the interface specified by the index `interface`.
+    ///
+    /// See also: `join_multicast_v6` method.
+    pub fn leave_multicast_v6(&self, multiaddr: &Ipv6Addr, interface: u32) -> io::Result<()> {
+        self.handle.inner().leave_multicast_v6(multiaddr, interface)
+    }
+
+    /// Gets the value of the `IP_MULTICAST_LOOP` option on this socket.
+    pub fn multicast_loop_v4(&self) -> io::Result<bool> {
+        self.handle.inner().multicast_loop_v4()
+    }
+
+    /// Sets the value of the `IP_MULTICAST_LOOP` option on this socket.
+    ///
+    /// If enabled, the multicast packets sent from this socket will be looped back to
+    /// the local sockets (including this one) which have joined the destination group.
+    pub fn set_multicast_loop_v4(&self, on: bool) -> io::Result<()> {
+        self.handle.inner().set_multicast_loop_v4(on)
+    }
+
+    /// Gets the value of the `IP_MULTICAST_TTL` option on this socket.
+    pub fn multicast_ttl_v4(&self) -> io::Result<u32> {
+        self.handle.inner().multicast_ttl_v4()
+    }
+
+    /// Sets the value of the `IP_MULTICAST_TTL` option on this socket.
+    ///
+    /// This indicates the time-to-live value of the outgoing multicast packets.
+    pub fn set_multicast_ttl_v4(&self, ttl: u32) -> io::Result<()> {
+        self.handle.inner().set_multicast_ttl_v4(ttl)
+    }
+
     /// Calls `f` with the reference to the inner socket.
+    ///
+    /// # Safety
+    ///
+    /// Operations performed on the inner socket must not break
+    /// the registration state managed by the I/O poller.
     pub unsafe fn with_inner<F, T>(&self, f: F) -> T
     where
         F: FnOnce(&MioUdpSocket) -> T,
     {
-        f(&*self.handle.inner())
+        f(&self.handle.inner())
+    }
+
+    fn with_socket_view<F, T>(&self, f: F) -> T
+    where
+        F: FnOnce(&net::UdpSocket) -> T,
+    {
+        super::with_socket_view(&*self.handle.inner(), f)
     }
 }
 impl fmt::Debug for UdpSocket {
@@ -113,21 +262,86 @@ impl fmt::Debug for UdpSocket {
         if let Ok(addr) = self.local_addr() {
             write!(f, "local_addr:{:?}, ", addr)?;
         }
+        if let Ok(addr) = self.peer_addr() {
+            write!(f, "peer_addr:{:?}, ", addr)?;
+        }
         write!(f, ".. }}")?;
         Ok(())
     }
 }
 
+/// A builder for `UdpSocket`.
+///
+/// This is created by calling `UdpSocket::builder` function.
+///
+/// # Platform Differences
+///
+/// `SO_REUSEPORT` is only available on Unix platforms (except Solaris and illumos).
+/// On the other platforms, enabling `reuse_port` will make the binding fail.
+#[derive(Debug, Clone)]
+pub struct UdpSocketBuilder {
+    addr: SocketAddr,
+    reuse_address: bool,
+    reuse_port: bool,
+}
+impl UdpSocketBuilder {
+    /// Sets the value of the `SO_REUSEADDR` option of the socket.
+    ///
+    /// The default value is `false`.
+    pub fn reuse_address(mut self, reuse: bool) -> Self {
+        self.reuse_address = reuse;
+        self
+    }
+
+    /// Sets the value of the `SO_REUSEPORT` option of the socket.
+    ///
+    /// The default value is `false`.
+    pub fn reuse_port(mut self, reuse: bool) -> Self {
+        self.reuse_port = reuse;
+        self
+    }
+
+    /// Makes a future to create a UDP socket binded to the specified address.
+    pub fn bind(self) -> UdpSocketBind {
+        let addr = self.addr;
+        UdpSocketBind(Bind::Start(addr, Box::new(move |addr| self.build(addr))))
+    }
+
+    fn build(&self, addr: &SocketAddr) -> io::Result<MioUdpSocket> {
+        let builder = if addr.is_ipv4() {
+            UdpBuilder::new_v4()?
+        } else {
+            UdpBuilder::new_v6()?
+        };
+        builder.reuse_address(self.reuse_address)?;
+        if self.reuse_port {
+            set_reuse_port(&builder)?;
+        }
+        MioUdpSocket::from_socket(builder.bind(addr)?)
+    }
+}
+
+#[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+fn set_reuse_port(builder: &UdpBuilder) -> io::Result<()> {
+    use net2::unix::UnixUdpBuilderExt;
+    builder.reuse_port(true).map(|_| ())
+}
+
+#[cfg(not(all(unix, not(any(target_os = "solaris", target_os = "illumos")))))]
+fn set_reuse_port(_builder: &UdpBuilder) -> io::Result<()> {
+    Err(super::reuse_port_unsupported())
+}
+
 /// A future which will create a UDP socket binded to the given address.
 ///
-/// This is created by calling `UdpSocket::bind` function.
+/// This is created by calling `UdpSocket::bind` function or `UdpSocketBuilder::bind` method.
 /// It is permitted to move the future across fibers.
 ///
 /// # Panics
 ///
 /// If the future is polled on the outside of a fiber, it may crash.
 #[derive(Debug)]
-pub struct UdpSocketBind(Bind<fn(&SocketAddr) -> io::Result<MioUdpSocket>, MioUdpSocket>);
+pub struct UdpSocketBind(Bind<SocketAddr, BindFn<MioUdpSocket>, MioUdpSocket>);
 impl Future for UdpSocketBind {
     type Item = UdpSocket;
     type Error = io::Error;
@@ -145,52 +359,21 @@ impl Future for UdpSocketBind {
 ///
 /// If the future is polled on the outside of a fiber, it may crash.
 #[derive(Debug)]
-pub struct SendTo<B>(Option<SendToInner<B>>);
+pub struct SendTo<B> {
+    io: Option<UdpIo<B>>,
+    target: SocketAddr,
+}
 impl<B: AsRef<[u8]>> Future for SendTo<B> {
     type Item = (UdpSocket, B, usize);
     type Error = (UdpSocket, B, io::Error);
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        let mut state = self.0.take().expect("Cannot poll SendTo twice");
-        loop {
-            if let Some(mut monitor) = state.monitor.take() {
-                match monitor.poll() {
-                    Err(e) => return Err((state.socket, state.buf, into_io_error(e))),
-                    Ok(Async::NotReady) => {
-                        state.monitor = Some(monitor);
-                        self.0 = Some(state);
-                        return Ok(Async::NotReady);
-                    }
-                    Ok(Async::Ready(())) => {}
-                }
-            } else {
-                let result = state
-                    .socket
-                    .handle
-                    .inner()
-                    .send_to(state.buf.as_ref(), &state.target);
-                match result {
-                    Err(e) => {
-                        if e.kind() == io::ErrorKind::WouldBlock {
-                            state.monitor = Some(state.socket.handle.monitor(Interest::Write));
-                        } else {
-                            return Err((state.socket, state.buf, e));
-                        }
-                    }
-                    Ok(size) => return Ok(Async::Ready((state.socket, state.buf, size))),
-                }
-            }
-        }
+        let target = &self.target;
+        poll_io(&mut self.io, Interest::Write, |socket, buf| {
+            socket.send_to(buf.as_ref(), target)
+        })
     }
 }
 
-#[derive(Debug)]
-struct SendToInner<B> {
-    socket: UdpSocket,
-    buf: B,
-    target: SocketAddr,
-    monitor: Option<Monitor<(), io::Error>>,
-}
-
 /// A future which will receive data from the socket.
 ///
 /// This is created by calling `UdpSocket::recv_from` method.
@@ -200,47 +383,360 @@ struct SendToInner<B> {
 ///
 /// If the future is polled on the outside of a fiber, it may crash.
 #[derive(Debug)]
-pub struct RecvFrom<B>(Option<RecvFromInner<B>>);
+pub struct RecvFrom<B>(Option<UdpIo<B>>);
 impl<B: AsMut<[u8]>> Future for RecvFrom<B> {
     type Item = (UdpSocket, B, usize, SocketAddr);
     type Error = (UdpSocket, B, io::Error);
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        let mut state = self.0.take().expect("Cannot poll RecvFrom twice");
-        loop {
-            if let Some(mut monitor) = state.monitor.take() {
-                match monitor.poll() {
-                    Err(e) => return Err((state.socket, state.buf, into_io_error(e))),
-                    Ok(Async::NotReady) => {
-                        state.monitor = Some(monitor);
-                        self.0 = Some(state);
-                        return Ok(Async::NotReady);
-                    }
-                    Ok(Async::Ready(())) => {}
-                }
-            } else {
-                let mut buf = state.buf;
-                let result = state.socket.handle.inner().recv_from(buf.as_mut());
-                state.buf = buf;
-                match result {
-                    Err(e) => {
-                        if e.kind() == io::ErrorKind::WouldBlock {
-                            state.monitor = Some(state.socket.handle.monitor(Interest::Read));
-                        } else {
-                            return Err((state.socket, state.buf, e));
-                        }
-                    }
-                    Ok((size, addr)) => {
-                        return Ok(Async::Ready((state.socket, state.buf, size, addr)))
-                    }
-                }
-            }
+        let polled = poll_io(&mut self.0, Interest::Read, |socket, buf| {
+            socket.recv_from(buf.as_mut())
+        })?;
+        Ok(polled.map(|(socket, buf, (size, addr))| (socket, buf, size, addr)))
+    }
+}
+
+/// A future which will connect a UDP socket to the given remote address.
+///
+/// This is created by calling `UdpSocket::connect` method.
+/// It is permitted to move the future across fibers.
+#[derive(Debug)]
+pub struct UdpSocketConnect(Option<(UdpSocket, SocketAddr)>);
+impl Future for UdpSocketConnect {
+    type Item = UdpSocket;
+    type Error = (UdpSocket, io::Error);
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let (socket, addr) = self.0.take().expect("Cannot poll UdpSocketConnect twice");
+        let result = socket.handle.inner().connect(addr);
+        match result {
+            Err(e) => Err((socket, e)),
+            Ok(()) => Ok(Async::Ready(socket)),
         }
     }
 }
 
+/// A future which will send data `B` on the socket to the connected peer.
+///
+/// This is created by calling `UdpSocket::send` method.
+/// It is permitted to move the future across fibers.
+///
+/// # Panics
+///
+/// If the future is polled on the outside of a fiber, it may crash.
+#[derive(Debug)]
+pub struct UdpSocketSend<B>(Option<UdpIo<B>>);
+impl<B: AsRef<[u8]>> Future for UdpSocketSend<B> {
+    type Item = (UdpSocket, B, usize);
+    type Error = (UdpSocket, B, io::Error);
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        poll_io(&mut self.0, Interest::Write, |socket, buf| {
+            socket.send(buf.as_ref())
+        })
+    }
+}
+
+/// A future which will receive data on the socket from the connected peer.
+///
+/// This is created by calling `UdpSocket::recv` method.
+/// It is permitted to move the future across fibers.
+///
+/// # Panics
+///
+/// If the future is polled on the outside of a fiber, it may crash.
+#[derive(Debug)]
+pub struct UdpSocketRecv<B>(Option<UdpIo<B>>);
+impl<B: AsMut<[u8]>> Future for UdpSocketRecv<B> {
+    type Item = (UdpSocket, B, usize);
+    type Error = (UdpSocket, B, io::Error);
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        poll_io(&mut self.0, Interest::Read, |socket, buf| {
+            socket.recv(buf.as_mut())
+        })
+    }
+}
+
 #[derive(Debug)]
-struct RecvFromInner<B> {
+struct UdpIo<B> {
     socket: UdpSocket,
     buf: B,
     monitor: Option<Monitor<(), io::Error>>,
+
+    // NOTE: If `true`, the socket is checked whether it has been connected before the first I/O
+    requires_peer: bool,
+}
+impl<B> UdpIo<B> {
+    fn new(socket: UdpSocket, buf: B, requires_peer: bool) -> Option<Self> {
+        Some(UdpIo {
+            socket,
+            buf,
+            monitor: None,
+            requires_peer,
+        })
+    }
+}
+
+/// Polls the I/O operation `f` on the socket of `io`,
+/// and waits until the socket becomes ready for `interest` if the operation would block.
+fn poll_io<B, F, T>(
+    io: &mut Option<UdpIo<B>>,
+    interest: Interest,
+    mut f: F,
+) -> Poll<(UdpSocket, B, T), (UdpSocket, B, io::Error)>
+where
+    F: FnMut(&MioUdpSocket, &mut B) -> io::Result<T>,
+{
+    let mut state = io.take().expect("Cannot poll a UDP socket future twice");
+    if state.requires_peer {
+        if let Err(e) = state.socket.peer_addr() {
+            return Err((state.socket, state.buf, e));
+        }
+        state.requires_peer = false;
+    }
+    loop {
+        if let Some(mut monitor) = state.monitor.take() {
+            match monitor.poll() {
+                Err(e) => return Err((state.socket, state.buf, into_io_error(e))),
+                Ok(Async::NotReady) => {
+                    state.monitor = Some(monitor);
+                    *io = Some(state);
+                    return Ok(Async::NotReady);
+                }
+                Ok(Async::Ready(())) => {}
+            }
+        } else {
+            let result = f(&state.socket.handle.inner(), &mut state.buf);
+            match result {
+                Err(e) => {
+                    if e.kind() == io::ErrorKind::WouldBlock {
+                        state.monitor = Some(state.socket.handle.monitor(interest));
+                    } else {
+                        return Err((state.socket, state.buf, e));
+                    }
+                }
+                Ok(v) => return Ok(Async::Ready((state.socket, state.buf, v))),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::Future;
+
+    use super::*;
+    use executor::{Executor, InPlaceExecutor};
+    use fiber::Spawn;
+    use sync::oneshot::MonitorError;
+
+    #[test]
+    fn round_trip_works() {
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let bind = || UdpSocket::bind("127.0.0.1:0".parse().unwrap());
+        let monitor = executor.spawn_monitor(bind().join(bind()));
+        let (client, server) = executor.run_fiber(monitor).unwrap().unwrap();
+        let client_addr = client.local_addr().unwrap();
+        let server_addr = server.local_addr().unwrap();
+
+        // Echoes back a datagram to its sender
+        let server = server
+            .recv_from(vec![0; 32])
+            .map_err(|(_, _, e)| e)
+            .and_then(|(socket, mut buf, len, peer)| {
+                buf.truncate(len);
+                socket
+                    .send_to(buf, peer)
+                    .map(move |(_, buf, _)| (buf, peer))
+                    .map_err(|(_, _, e)| e)
+            });
+        let client = client
+            .send_to(b"ping", server_addr)
+            .map_err(|(_, _, e)| e)
+            .and_then(|(socket, _, sent)| {
+                socket
+                    .recv_from([0; 32])
+                    .map(move |(_, buf, len, peer)| (buf[..len].to_vec(), sent, peer))
+                    .map_err(|(_, _, e)| e)
+            });
+        let server = executor.spawn_monitor(server);
+        let client = executor.spawn_monitor(client);
+
+        let (buf, peer) = executor.run_fiber(server).unwrap().unwrap();
+        assert_eq!(buf, b"ping");
+        assert_eq!(peer, client_addr);
+
+        let (buf, sent, peer) = executor.run_fiber(client).unwrap().unwrap();
+        assert_eq!(buf, b"ping");
+        assert_eq!(sent, 4);
+        assert_eq!(peer, server_addr);
+    }
+
+    #[test]
+    fn connected_round_trip_works() {
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let bind = || UdpSocket::bind("127.0.0.1:0".parse().unwrap());
+        let monitor = executor.spawn_monitor(bind().join3(bind(), bind()));
+        let (client, server, stranger) = executor.run_fiber(monitor).unwrap().unwrap();
+        let client_addr = client.local_addr().unwrap();
+        let server_addr = server.local_addr().unwrap();
+
+        // The connectionless sockets cannot use `send` and `recv`
+        let monitor = executor.spawn_monitor(stranger.send(b"hello").then(Ok::<_, ()>));
+        let (stranger, _, e) = executor.run_fiber(monitor).unwrap().unwrap().err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::NotConnected);
+        assert_eq!(
+            stranger.peer_addr().err().map(|e| e.kind()),
+            Some(io::ErrorKind::NotConnected)
+        );
+
+        let client_clone = client.clone();
+        let connect = client
+            .connect(server_addr)
+            .join(server.connect(client_addr))
+            .map_err(|(_, e)| e);
+        let monitor = executor.spawn_monitor(connect);
+        let (client, server) = executor.run_fiber(monitor).unwrap().unwrap();
+        assert_eq!(client.peer_addr().unwrap(), server_addr);
+        assert_eq!(server.peer_addr().unwrap(), client_addr);
+
+        // The clones share the same connected socket
+        assert_eq!(client_clone.peer_addr().unwrap(), server_addr);
+
+        // The datagrams from the sockets other than the peer are discarded
+        let monitor = executor.spawn_monitor(stranger.send_to(b"spoofed", server_addr));
+        executor.run_fiber(monitor).unwrap().ok().unwrap();
+
+        // Echoes back a datagram to the peer
+        let server =
+            server
+                .recv(vec![0; 32])
+                .map_err(|(_, _, e)| e)
+                .and_then(|(socket, mut buf, len)| {
+                    buf.truncate(len);
+                    socket
+                        .send(buf)
+                        .map(|(_, buf, _)| buf)
+                        .map_err(|(_, _, e)| e)
+                });
+        let client = client
+            .send(b"ping")
+            .map_err(|(_, _, e)| e)
+            .and_then(|(socket, _, _)| socket.recv([0; 32]).map_err(|(_, _, e)| e))
+            .map(|(_, buf, len)| buf[..len].to_vec());
+        let server = executor.spawn_monitor(server);
+        let client = executor.spawn_monitor(client);
+        assert_eq!(executor.run_fiber(server).unwrap().unwrap(), b"ping");
+        assert_eq!(executor.run_fiber(client).unwrap().unwrap(), b"ping");
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn connection_refused_is_reported_by_recv() {
+        // NOTE: The port is closed after the socket is dropped
+        let closed_addr = std::net::UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let mut executor = InPlaceExecutor::new().unwrap();
+
+        let future = UdpSocket::bind("127.0.0.1:0".parse().unwrap())
+            .and_then(move |socket| socket.connect(closed_addr).map_err(|(_, e)| e));
+        let monitor = executor.spawn_monitor(future);
+        let socket = executor.run_fiber(monitor).unwrap().unwrap();
+
+        // Waits for a reply before sending a datagram,
+        // so that the ICMP port unreachable message is reported to the waiting receiver
+        let mut receiver =
+            executor.spawn_monitor(socket.clone().recv([0; 8]).map_err(|(_, _, e)| e.kind()));
+        for _ in 0..10 {
+            executor.run_once().unwrap();
+        }
+        assert!(receiver.poll().unwrap().is_not_ready());
+
+        let monitor = executor.spawn_monitor(socket.send(b"ping"));
+        executor.run_fiber(monitor).unwrap().ok().unwrap();
+        assert_eq!(
+            executor.run_fiber(receiver).unwrap().err(),
+            Some(MonitorError::Failed(io::ErrorKind::ConnectionRefused))
+        );
+    }
+
+    #[test]
+    fn socket_options_work() {
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let monitor = executor.spawn_monitor(UdpSocket::bind("127.0.0.1:0".parse().unwrap()));
+        let socket = executor.run_fiber(monitor).unwrap().unwrap();
+
+        socket.set_ttl(42).unwrap();
+        assert_eq!(socket.ttl().unwrap(), 42);
+
+        socket.set_broadcast(true).unwrap();
+        assert!(socket.broadcast().unwrap());
+
+        socket.set_send_buffer_size(64 * 1024).unwrap();
+        assert!(socket.send_buffer_size().unwrap() >= 64 * 1024);
+        socket.set_recv_buffer_size(64 * 1024).unwrap();
+        assert!(socket.recv_buffer_size().unwrap() >= 64 * 1024);
+
+        // The socket is still usable after accessing the options
+        let addr = socket.local_addr().unwrap();
+        let future = socket
+            .send_to(b"hello", addr)
+            .map_err(|(_, _, e)| e)
+            .and_then(|(socket, _, _)| socket.recv_from([0; 8]).map_err(|(_, _, e)| e))
+            .map(|(_, _, len, _)| len);
+        let monitor = executor.spawn_monitor(future);
+        assert_eq!(executor.run_fiber(monitor).unwrap().unwrap(), 5);
+    }
+
+    #[test]
+    fn multicast_loopback_works() {
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let monitor = executor.spawn_monitor(UdpSocket::bind("0.0.0.0:0".parse().unwrap()));
+        let socket = executor.run_fiber(monitor).unwrap().unwrap();
+        let port = socket.local_addr().unwrap().port();
+
+        let group = Ipv4Addr::new(239, 255, 0, 1);
+        socket
+            .join_multicast_v4(&group, &Ipv4Addr::UNSPECIFIED)
+            .unwrap();
+        socket.set_multicast_loop_v4(true).unwrap();
+        socket.set_multicast_ttl_v4(1).unwrap();
+        assert!(socket.multicast_loop_v4().unwrap());
+        assert_eq!(socket.multicast_ttl_v4().unwrap(), 1);
+
+        let future = socket
+            .clone()
+            .send_to(b"hello", SocketAddr::new(group.into(), port))
+            .map_err(|(_, _, e)| e)
+            .and_then(|(socket, _, _)| socket.recv_from(vec![0; 32]).map_err(|(_, _, e)| e))
+            .map(|(socket, mut buf, len, _)| {
+                buf.truncate(len);
+                (socket, buf)
+            });
+        let monitor = executor.spawn_monitor(future);
+        let (socket, buf) = executor.run_fiber(monitor).unwrap().unwrap();
+        assert_eq!(buf, b"hello");
+
+        socket
+            .leave_multicast_v4(&group, &Ipv4Addr::UNSPECIFIED)
+            .unwrap();
+    }
+
+    #[test]
+    #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+    fn sockets_can_share_a_port() {
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let bind = |addr| {
+            UdpSocket::builder(addr)
+                .reuse_address(true)
+                .reuse_port(true)
+                .bind()
+        };
+        let monitor = executor.spawn_monitor(bind("127.0.0.1:0".parse().unwrap()));
+        let socket0 = executor.run_fiber(monitor).unwrap().unwrap();
+        let addr = socket0.local_addr().unwrap();
+
+        let monitor = executor.spawn_monitor(bind(addr));
+        let socket1 = executor.run_fiber(monitor).unwrap().unwrap();
+        assert_eq!(socket1.local_addr().unwrap(), addr);
+    }
 }
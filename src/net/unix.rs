@@ -0,0 +1,483 @@
+// Copyright (c) 2016 DWANGO Co., Ltd. All Rights Reserved.
+// See the LICENSE file at the top-level directory of this distribution.
+
+//! Unix domain socket primitives.
+//!
+//! The structures in this module mirror the TCP counterparts in `fibers::net`.
+//!
+//! # Examples
+//!
+//! ```
+//! // See also: fibers/examples/unix_echo.rs
+//! # extern crate fibers;
+//! # extern crate futures;
+//! use fibers::{Executor, InPlaceExecutor, Spawn};
+//! use fibers::net::unix::{UnixListener, UnixStream};
+//! use futures::{Future, Stream};
+//! use std::io::Write;
+//!
+//! # fn main() {
+//! let mut executor = InPlaceExecutor::new().unwrap();
+//! let path = std::env::temp_dir().join(format!("fibers-doctest-{}.sock", std::process::id()));
+//! let _ = std::fs::remove_file(&path);
+//!
+//! // Binds a listener to the path and accepts a client
+//! let monitor = executor.spawn_monitor(UnixListener::bind(&path));
+//! let listener = executor.run_fiber(monitor).unwrap().unwrap();
+//! let server = executor.spawn_monitor(
+//!     listener.incoming().into_future().map_err(|(e, _)| e).and_then(|(client, _)| {
+//!         client.unwrap().0
+//!     }),
+//! );
+//!
+//! // Connects to the listener
+//! let client = executor.spawn_monitor(UnixStream::connect(&path).and_then(|mut stream| {
+//!     stream.write_all(b"hello")?;
+//!     Ok(stream)
+//! }));
+//!
+//! assert!(executor.run_fiber(client).unwrap().is_ok());
+//! assert!(executor.run_fiber(server).unwrap().is_ok());
+//! std::fs::remove_file(&path).unwrap();
+//! # }
+//! ```
+use futures::{Async, Future, Poll, Stream};
+use mio::unix::EventedFd;
+use mio::{self, Evented};
+use std::fmt;
+use std::io;
+use std::net::Shutdown;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{self, SocketAddr};
+use std::path::{Path, PathBuf};
+
+use super::Bind;
+use fiber::{self, Context};
+use io::poll::{EventedHandle, Interest, ReadinessStream, Register};
+use sync::oneshot::Monitor;
+
+type BindPath<T> = Bind<PathBuf, fn(&PathBuf) -> io::Result<T>, T>;
+
+/// A structure representing a Unix domain socket server.
+///
+/// The socket file will not be removed when the listener is dropped.
+pub struct UnixListener {
+    handle: EventedHandle<EventedListener>,
+}
+impl UnixListener {
+    /// Makes a future to create a new `UnixListener` which will be bound to the specified path.
+    pub fn bind<P: AsRef<Path>>(path: P) -> UnixListenerBind {
+        let path = path.as_ref().to_path_buf();
+        UnixListenerBind(Bind::Start(path, EventedListener::bind))
+    }
+
+    /// Makes a stream of the connections which will be accepted by this listener.
+    ///
+    /// Each item is a pair of the future of a connected stream and the address of the peer.
+    pub fn incoming(self) -> Incoming {
+        Incoming {
+            readiness: self.handle.readiness_stream(Interest::Read),
+            waiting: false,
+        }
+    }
+
+    /// Returns the local socket address of this listener.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.handle.inner().0.local_addr()
+    }
+
+    /// Get the value of the `SO_ERROR` option on this socket.
+    ///
+    /// This will retrieve the stored error in the underlying socket,
+    /// clearing the field in the process.
+    /// This can be useful for checking errors between calls.
+    pub fn take_error(&self) -> io::Result<Option<io::Error>> {
+        self.handle.inner().0.take_error()
+    }
+
+    /// Calls `f` with the reference to the inner socket.
+    ///
+    /// # Safety
+    ///
+    /// Operations performed on the inner socket must not break
+    /// the registration state managed by the I/O poller.
+    pub unsafe fn with_inner<F, T>(&self, f: F) -> T
+    where
+        F: FnOnce(&net::UnixListener) -> T,
+    {
+        f(&self.handle.inner().0)
+    }
+}
+impl fmt::Debug for UnixListener {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "UnixListener {{ ")?;
+        if let Ok(addr) = self.local_addr() {
+            write!(f, "local_addr:{:?}, ", addr)?;
+        }
+        write!(f, ".. }}")?;
+        Ok(())
+    }
+}
+
+/// A future which will create a new `UnixListener` which will be bound to the specified path.
+///
+/// This is created by calling `UnixListener::bind` function.
+/// It is permitted to move the future across fibers.
+///
+/// # Panics
+///
+/// If the future is polled on the outside of a fiber, it may crash.
+#[derive(Debug)]
+pub struct UnixListenerBind(BindPath<EventedListener>);
+impl Future for UnixListenerBind {
+    type Item = UnixListener;
+    type Error = io::Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        Ok(self.0.poll()?.map(|handle| UnixListener { handle }))
+    }
+}
+
+/// An infinite stream of the connections which will be accepted by the listener.
+///
+/// This is created by calling `UnixListener::incoming` method.
+/// It is permitted to move the future across fibers.
+///
+/// # Panics
+///
+/// If the stream is polled on the outside of a fiber, it may crash.
+#[derive(Debug)]
+pub struct Incoming {
+    readiness: ReadinessStream<EventedListener>,
+    waiting: bool,
+}
+impl Stream for Incoming {
+    type Item = (Connected, SocketAddr);
+    type Error = io::Error;
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            if self.waiting {
+                if let Async::NotReady = self.readiness.poll()? {
+                    return Ok(Async::NotReady);
+                }
+                self.waiting = false;
+            } else {
+                match self.readiness.handle().inner().0.accept() {
+                    Ok((stream, addr)) => {
+                        let stream = EventedStream::new(stream)?;
+                        let register = |mut c: Context| c.poller().register(stream);
+                        let future = assert_some!(fiber::with_current_context(register));
+                        let stream = Connected(Some(future));
+                        return Ok(Async::Ready(Some((stream, addr))));
+                    }
+                    Err(e) => {
+                        if e.kind() == io::ErrorKind::WouldBlock {
+                            self.waiting = true;
+                        } else {
+                            return Err(e);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A future which represents a `UnixStream` connected to a `UnixListener`.
+///
+/// This is produced by `Incoming` stream.
+/// It is permitted to move the future across fibers.
+///
+/// # Panics
+///
+/// If the future is polled on the outside of a fiber, it may crash.
+#[derive(Debug)]
+pub struct Connected(Option<Register<EventedStream>>);
+impl Future for Connected {
+    type Item = UnixStream;
+    type Error = io::Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut future = self.0.take().expect("Cannot poll Connected twice");
+        if let Async::Ready(handle) = future.poll()? {
+            Ok(Async::Ready(UnixStream::new(handle)))
+        } else {
+            self.0 = Some(future);
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+/// A structure which represents a Unix domain stream socket.
+///
+/// The socket will be closed when the value is dropped.
+///
+/// # Note
+///
+/// Non blocking mode is always enabled on this socket.
+/// Roughly speaking, if an operation (read or write) for a socket would block,
+/// it returns the `std::io::ErrorKind::WouldBlock` error and
+/// current fiber is suspended until the socket becomes available.
+pub struct UnixStream {
+    handle: EventedHandle<EventedStream>,
+    read_monitor: Option<Monitor<(), io::Error>>,
+    write_monitor: Option<Monitor<(), io::Error>>,
+}
+impl Clone for UnixStream {
+    fn clone(&self) -> Self {
+        UnixStream {
+            handle: self.handle.clone(),
+            read_monitor: None,
+            write_monitor: None,
+        }
+    }
+}
+impl UnixStream {
+    fn new(handle: EventedHandle<EventedStream>) -> Self {
+        UnixStream {
+            handle,
+            read_monitor: None,
+            write_monitor: None,
+        }
+    }
+
+    /// Makes a future to connect to the socket bound to the specified path.
+    ///
+    /// Connecting to a Unix domain socket completes immediately unless
+    /// the backlog of the listener is full.
+    /// In that case, the current thread may be blocked until the listener accepts a connection.
+    pub fn connect<P: AsRef<Path>>(path: P) -> Connect {
+        Connect(Bind::Start(
+            path.as_ref().to_path_buf(),
+            EventedStream::connect,
+        ))
+    }
+
+    /// Returns the socket address of the local half of this connection.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.handle.inner().0.local_addr()
+    }
+
+    /// Returns the socket address of the remote half of this connection.
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.handle.inner().0.peer_addr()
+    }
+
+    /// Shuts down the read, write, or both halves of this connection.
+    ///
+    /// See also: `TcpStream::shutdown` method.
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        self.handle.inner().0.shutdown(how)
+    }
+
+    /// Get the value of the `SO_ERROR` option on this socket.
+    ///
+    /// This will retrieve the stored error in the underlying socket,
+    /// clearing the field in the process.
+    /// This can be useful for checking errors between calls.
+    pub fn take_error(&self) -> io::Result<Option<io::Error>> {
+        self.handle.inner().0.take_error()
+    }
+
+    /// Calls `f` with the reference to the inner socket.
+    ///
+    /// # Safety
+    ///
+    /// Operations performed on the inner socket must not break
+    /// the registration state managed by the I/O poller.
+    pub unsafe fn with_inner<F, T>(&self, f: F) -> T
+    where
+        F: FnOnce(&net::UnixStream) -> T,
+    {
+        f(&self.handle.inner().0)
+    }
+
+    fn operate<F, T>(&mut self, interest: Interest, mut f: F) -> io::Result<T>
+    where
+        F: FnMut(&mut net::UnixStream) -> io::Result<T>,
+    {
+        let monitor = if interest == Interest::Read {
+            &mut self.read_monitor
+        } else {
+            &mut self.write_monitor
+        };
+        self.handle
+            .operate(monitor, interest, |inner| f(&mut inner.0))
+    }
+}
+impl io::Read for UnixStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.operate(Interest::Read, |inner| inner.read(buf))
+    }
+}
+impl io::Write for UnixStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.operate(Interest::Write, |inner| inner.write(buf))
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.operate(Interest::Write, |inner| inner.flush())
+    }
+}
+impl fmt::Debug for UnixStream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "UnixStream {{ ")?;
+        if let Ok(addr) = self.local_addr() {
+            write!(f, "local_addr:{:?}, ", addr)?;
+        }
+        if let Ok(addr) = self.peer_addr() {
+            write!(f, "peer_addr:{:?}, ", addr)?;
+        }
+        write!(f, ".. }}")?;
+        Ok(())
+    }
+}
+
+/// A future which will connect to the socket bound to a path.
+///
+/// This is created by calling `UnixStream::connect` function.
+/// It is permitted to move the future across fibers.
+///
+/// # Panics
+///
+/// If the future is polled on the outside of a fiber, it may crash.
+#[derive(Debug)]
+pub struct Connect(BindPath<EventedStream>);
+impl Future for Connect {
+    type Item = UnixStream;
+    type Error = io::Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        Ok(self.0.poll()?.map(UnixStream::new))
+    }
+}
+
+#[derive(Debug)]
+struct EventedListener(net::UnixListener);
+impl EventedListener {
+    fn bind(path: &PathBuf) -> io::Result<Self> {
+        let listener = net::UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        Ok(EventedListener(listener))
+    }
+}
+impl Evented for EventedListener {
+    fn register(
+        &self,
+        poll: &mio::Poll,
+        token: mio::Token,
+        interest: mio::Ready,
+        opts: mio::PollOpt,
+    ) -> io::Result<()> {
+        EventedFd(&self.0.as_raw_fd()).register(poll, token, interest, opts)
+    }
+    fn reregister(
+        &self,
+        poll: &mio::Poll,
+        token: mio::Token,
+        interest: mio::Ready,
+        opts: mio::PollOpt,
+    ) -> io::Result<()> {
+        EventedFd(&self.0.as_raw_fd()).reregister(poll, token, interest, opts)
+    }
+    fn deregister(&self, poll: &mio::Poll) -> io::Result<()> {
+        EventedFd(&self.0.as_raw_fd()).deregister(poll)
+    }
+}
+
+#[derive(Debug)]
+struct EventedStream(net::UnixStream);
+impl EventedStream {
+    fn new(stream: net::UnixStream) -> io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        Ok(EventedStream(stream))
+    }
+    fn connect(path: &PathBuf) -> io::Result<Self> {
+        EventedStream::new(net::UnixStream::connect(path)?)
+    }
+}
+impl Evented for EventedStream {
+    fn register(
+        &self,
+        poll: &mio::Poll,
+        token: mio::Token,
+        interest: mio::Ready,
+        opts: mio::PollOpt,
+    ) -> io::Result<()> {
+        EventedFd(&self.0.as_raw_fd()).register(poll, token, interest, opts)
+    }
+    fn reregister(
+        &self,
+        poll: &mio::Poll,
+        token: mio::Token,
+        interest: mio::Ready,
+        opts: mio::PollOpt,
+    ) -> io::Result<()> {
+        EventedFd(&self.0.as_raw_fd()).reregister(poll, token, interest, opts)
+    }
+    fn deregister(&self, poll: &mio::Poll) -> io::Result<()> {
+        EventedFd(&self.0.as_raw_fd()).deregister(poll)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::{Future, Stream};
+    use std::fs;
+    use std::io::{Read, Write};
+    use std::process;
+
+    use super::*;
+    use executor::{Executor, InPlaceExecutor};
+    use fiber::Spawn;
+
+    #[test]
+    fn echo_works() {
+        let path = ::std::env::temp_dir().join(format!("fibers-test-{}.sock", process::id()));
+        let _ = fs::remove_file(&path);
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let monitor = executor.spawn_monitor(UnixListener::bind(&path));
+        let listener = executor.run_fiber(monitor).unwrap().unwrap();
+        assert_eq!(
+            listener.local_addr().unwrap().as_pathname(),
+            Some(path.as_ref())
+        );
+
+        // Echoes back the data sent by the first client
+        let server = listener
+            .incoming()
+            .into_future()
+            .map_err(|(e, _)| e)
+            .and_then(|(client, _)| client.unwrap().0)
+            .and_then(|mut stream| {
+                futures::future::poll_fn(move || {
+                    let mut buf = [0; 5];
+                    match stream.read_exact(&mut buf) {
+                        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(Async::NotReady),
+                        Err(e) => Err(e),
+                        Ok(()) => stream.write_all(&buf).map(Async::Ready),
+                    }
+                })
+            });
+        let client = UnixStream::connect(&path).and_then(|mut stream| {
+            stream.write_all(b"hello")?;
+            let mut buf = Vec::new();
+            Ok(futures::future::poll_fn(move || {
+                let mut bytes = [0; 5];
+                match stream.read(&mut bytes) {
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(Async::NotReady),
+                    Err(e) => Err(e),
+                    Ok(size) => {
+                        buf.extend_from_slice(&bytes[..size]);
+                        if buf.len() < 5 {
+                            Ok(Async::NotReady)
+                        } else {
+                            Ok(Async::Ready(buf.clone()))
+                        }
+                    }
+                }
+            }))
+        });
+        let server = executor.spawn_monitor(server);
+        let client = executor.spawn_monitor(client.flatten());
+        assert_eq!(executor.run_fiber(client).unwrap().unwrap(), b"hello");
+        assert!(executor.run_fiber(server).unwrap().is_ok());
+        fs::remove_file(&path).unwrap();
+    }
+}
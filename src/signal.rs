@@ -0,0 +1,383 @@
+// Copyright (c) 2016 DWANGO Co., Ltd. All Rights Reserved.
+// See the LICENSE file at the top-level directory of this distribution.
+
+//! Unix signal handling functionalities.
+//!
+//! # Implementation Details
+//!
+//! Each `SignalStream` owns an anonymous pipe (see `fibers::net::pipe`).
+//! The signal handler installed by this module only writes a byte to
+//! the write ends of the pipes interested in the signal (which is async-signal-safe),
+//! and the fibers waiting on the read ends are woken up by the I/O poller.
+//! When a stream is dropped, the write end of its pipe is closed only after
+//! the handlers running at that time return, so they never write to a reused descriptor.
+//!
+//! Once a handler is installed for a signal, it is never uninstalled.
+//! Thus, the default action of the signal (e.g., terminating the process) is no longer taken
+//! even after all the streams for the signal are dropped.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! // See also: fibers/examples/graceful_shutdown.rs
+//! # extern crate fibers;
+//! # extern crate futures;
+//! use fibers::{Executor, InPlaceExecutor, Spawn};
+//! use fibers::signal;
+//!
+//! # fn main() {
+//! let mut executor = InPlaceExecutor::new().unwrap();
+//! let monitor = executor.spawn_monitor(signal::ctrl_c());
+//! executor.run_fiber(monitor).unwrap().unwrap();
+//! println!("Interrupted");
+//! # }
+//! ```
+use futures::{Async, Future, Poll, Stream};
+use libc;
+use std::io::{self, Read};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicUsize, Ordering};
+use std::{fmt, mem, ptr, thread};
+
+use net::futures::Pipe;
+use net::{self, PipeReader, PipeWriter};
+
+/// The maximum number of the alive `SignalStream`s per signal.
+pub const MAX_STREAMS_PER_SIGNAL: usize = 16;
+
+const MAX_SIGNAL: usize = 64;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_SLOT: AtomicI32 = AtomicI32::new(-1);
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_SLOTS: [AtomicI32; MAX_STREAMS_PER_SIGNAL] = [EMPTY_SLOT; MAX_STREAMS_PER_SIGNAL];
+#[allow(clippy::declare_interior_mutable_const)]
+const NOT_INSTALLED: AtomicBool = AtomicBool::new(false);
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_HANDLERS: AtomicUsize = AtomicUsize::new(0);
+
+// NOTE: These are the only states touched by the signal handler
+static SLOTS: [[AtomicI32; MAX_STREAMS_PER_SIGNAL]; MAX_SIGNAL + 1] = [EMPTY_SLOTS; MAX_SIGNAL + 1];
+static INSTALLED: [AtomicBool; MAX_SIGNAL + 1] = [NOT_INSTALLED; MAX_SIGNAL + 1];
+
+// NOTE: The number of the running signal handlers (per signal),
+// which may be still writing to the file descriptors they loaded from `SLOTS`
+static RUNNING_HANDLERS: [AtomicUsize; MAX_SIGNAL + 1] = [NO_HANDLERS; MAX_SIGNAL + 1];
+
+/// The kind of a Unix signal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SignalKind(libc::c_int);
+impl SignalKind {
+    /// Makes a `SignalKind` from the raw signal number.
+    pub fn from_raw(signum: libc::c_int) -> Self {
+        SignalKind(signum)
+    }
+
+    /// Returns the raw signal number.
+    pub fn as_raw(&self) -> libc::c_int {
+        self.0
+    }
+
+    /// `SIGINT` (e.g., Ctrl-C on the terminal).
+    pub fn interrupt() -> Self {
+        SignalKind(libc::SIGINT)
+    }
+
+    /// `SIGTERM` (e.g., the default signal of the `kill` command).
+    pub fn terminate() -> Self {
+        SignalKind(libc::SIGTERM)
+    }
+
+    /// `SIGHUP`.
+    pub fn hangup() -> Self {
+        SignalKind(libc::SIGHUP)
+    }
+
+    /// `SIGQUIT`.
+    pub fn quit() -> Self {
+        SignalKind(libc::SIGQUIT)
+    }
+
+    /// `SIGUSR1`.
+    pub fn user_defined1() -> Self {
+        SignalKind(libc::SIGUSR1)
+    }
+
+    /// `SIGUSR2`.
+    pub fn user_defined2() -> Self {
+        SignalKind(libc::SIGUSR2)
+    }
+}
+
+/// Makes a stream which yields an item each time the signal `kind` is delivered to the process.
+///
+/// The signal handler is installed when the stream is polled for the first time,
+/// so the signals delivered before that are not reported.
+/// Consecutive deliveries may be coalesced into an item if the stream is not polled in between.
+///
+/// The stream fails if `kind` cannot be handled
+/// (e.g., `SIGKILL`, or more than `MAX_STREAMS_PER_SIGNAL` streams are alive for it).
+///
+/// # Panics
+///
+/// If the stream is polled on the outside of a fiber, it may crash.
+pub fn unix(kind: SignalKind) -> SignalStream {
+    SignalStream {
+        kind,
+        pipe: None,
+        listener: None,
+    }
+}
+
+/// Makes a future which will complete when `SIGINT` is delivered to the process.
+///
+/// This is equivalent to taking the first item of `unix(SignalKind::interrupt())`.
+pub fn ctrl_c() -> CtrlC {
+    CtrlC(unix(SignalKind::interrupt()))
+}
+
+/// A stream of the deliveries of a signal.
+///
+/// This is created by calling `unix` function.
+/// It is permitted to move the stream across fibers.
+///
+/// # Panics
+///
+/// If the stream is polled on the outside of a fiber, it may crash.
+pub struct SignalStream {
+    kind: SignalKind,
+    pipe: Option<Pipe>,
+    listener: Option<Listener>,
+}
+impl Stream for SignalStream {
+    type Item = ();
+    type Error = io::Error;
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if self.listener.is_none() {
+            let mut pipe = self.pipe.take().unwrap_or_else(net::pipe);
+            if let Async::Ready((reader, writer)) = pipe.poll()? {
+                self.listener = Some(Listener::new(self.kind, reader, writer)?);
+            } else {
+                self.pipe = Some(pipe);
+                return Ok(Async::NotReady);
+            }
+        }
+
+        let listener = self.listener.as_mut().expect("Never fails");
+        let mut buf = [0; 1];
+        match listener.reader.read(&mut buf) {
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(Async::NotReady),
+            Err(e) => Err(e),
+            Ok(0) => Ok(Async::Ready(None)),
+            Ok(_) => Ok(Async::Ready(Some(()))),
+        }
+    }
+}
+impl fmt::Debug for SignalStream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SignalStream {{ kind:{:?}, .. }}", self.kind)
+    }
+}
+
+/// A future which will complete when `SIGINT` is delivered to the process.
+///
+/// This is created by calling `ctrl_c` function.
+/// It is permitted to move the future across fibers.
+///
+/// # Panics
+///
+/// If the future is polled on the outside of a fiber, it may crash.
+#[derive(Debug)]
+pub struct CtrlC(SignalStream);
+impl Future for CtrlC {
+    type Item = ();
+    type Error = io::Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.0.poll()? {
+            Async::NotReady => Ok(Async::NotReady),
+            Async::Ready(Some(())) => Ok(Async::Ready(())),
+            Async::Ready(None) => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Signal stream terminated",
+            )),
+        }
+    }
+}
+
+struct Listener {
+    signum: usize,
+    slot: usize,
+    reader: PipeReader,
+
+    // NOTE: This keeps the file descriptor stored in `SLOTS` open
+    _writer: PipeWriter,
+}
+impl Listener {
+    fn new(kind: SignalKind, reader: PipeReader, writer: PipeWriter) -> io::Result<Self> {
+        let signum = kind.as_raw();
+        if signum <= 0 || signum as usize > MAX_SIGNAL {
+            let message = format!("Unsupported signal number: {}", signum);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+        }
+        let signum = signum as usize;
+
+        let fd = writer.as_raw_fd();
+        let slot = SLOTS[signum]
+            .iter()
+            .position(|s| {
+                s.compare_exchange(-1, fd, Ordering::SeqCst, Ordering::SeqCst)
+                    .is_ok()
+            })
+            .ok_or_else(|| io::Error::other("Too many signal streams for the same signal"))?;
+        let listener = Listener {
+            signum,
+            slot,
+            reader,
+            _writer: writer,
+        };
+        install_handler(signum)?;
+        Ok(listener)
+    }
+}
+impl Drop for Listener {
+    fn drop(&mut self) {
+        SLOTS[self.signum][self.slot].store(-1, Ordering::SeqCst);
+
+        // NOTE: The file descriptor of `_writer` must not be closed (and reused by others)
+        // while a handler which has loaded it from `SLOTS` is running
+        while RUNNING_HANDLERS[self.signum].load(Ordering::SeqCst) > 0 {
+            thread::yield_now();
+        }
+    }
+}
+
+fn install_handler(signum: usize) -> io::Result<()> {
+    if INSTALLED[signum]
+        .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+        .is_err()
+    {
+        return Ok(());
+    }
+    unsafe {
+        let mut action: libc::sigaction = mem::zeroed();
+        action.sa_sigaction = handle_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        if libc::sigaction(signum as libc::c_int, &action, ptr::null_mut()) == -1 {
+            INSTALLED[signum].store(false, Ordering::SeqCst);
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+extern "C" fn handle_signal(signum: libc::c_int) {
+    // NOTE: Only async-signal-safe operations are allowed here
+    //
+    // `write(2)` may overwrite the `errno` of the interrupted code, so it is restored on return.
+    let errno = unsafe { errno_location() };
+    let saved_errno = if errno.is_null() {
+        0
+    } else {
+        unsafe { *errno }
+    };
+
+    let signum = signum as usize;
+    RUNNING_HANDLERS[signum].fetch_add(1, Ordering::SeqCst);
+    for slot in SLOTS[signum].iter() {
+        let fd: RawFd = slot.load(Ordering::SeqCst);
+        if fd >= 0 {
+            // NOTE: The pipe is non-blocking, so this never blocks even if the pipe is full
+            // (in which case the reader has not yet consumed the previous notification).
+            unsafe {
+                libc::write(fd, b"\x01".as_ptr() as *const libc::c_void, 1);
+            }
+        }
+    }
+    RUNNING_HANDLERS[signum].fetch_sub(1, Ordering::SeqCst);
+
+    if !errno.is_null() {
+        unsafe {
+            *errno = saved_errno;
+        }
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "emscripten", target_os = "fuchsia"))]
+unsafe fn errno_location() -> *mut libc::c_int {
+    libc::__errno_location()
+}
+
+#[cfg(any(target_os = "android", target_os = "netbsd", target_os = "openbsd"))]
+unsafe fn errno_location() -> *mut libc::c_int {
+    libc::__errno()
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd"))]
+unsafe fn errno_location() -> *mut libc::c_int {
+    libc::__error()
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "emscripten",
+    target_os = "fuchsia",
+    target_os = "android",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd"
+)))]
+unsafe fn errno_location() -> *mut libc::c_int {
+    ptr::null_mut()
+}
+
+#[cfg(test)]
+mod test {
+    use futures::future;
+
+    use super::*;
+    use executor::{Executor, InPlaceExecutor};
+    use fiber::Spawn;
+
+    #[test]
+    fn signal_stream_works() {
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let mut streams = vec![
+            unix(SignalKind::user_defined2()),
+            unix(SignalKind::user_defined2()),
+        ];
+        let mut raised = false;
+        let monitor = executor.spawn_monitor(future::poll_fn(move || -> Poll<(), io::Error> {
+            let mut count = 0;
+            for stream in &mut streams {
+                if let Async::Ready(item) = stream.poll()? {
+                    assert_eq!(item, Some(()));
+                    count += 1;
+                }
+            }
+            if streams.iter().all(|s| s.listener.is_some()) && !raised {
+                raised = true;
+                unsafe {
+                    libc::raise(libc::SIGUSR2);
+                }
+            }
+            if count == streams.len() {
+                Ok(Async::Ready(()))
+            } else {
+                Ok(Async::NotReady)
+            }
+        }));
+        executor.run_fiber(monitor).unwrap().unwrap();
+    }
+
+    #[test]
+    fn unsupported_signal_fails() {
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let monitor =
+            executor.spawn_monitor(unix(SignalKind::from_raw(libc::SIGKILL)).into_future());
+        assert!(executor.run_fiber(monitor).unwrap().is_err());
+    }
+}
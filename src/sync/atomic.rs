@@ -0,0 +1,217 @@
+// Copyright (c) 2016 DWANGO Co., Ltd. All Rights Reserved.
+// See the LICENSE file at the top-level directory of this distribution.
+
+//! Shared mutable slots which fibers can wait on.
+//!
+//! # Note
+//!
+//! Unlike `fibers::net` module, the structures in this module
+//! can be used on both inside and outside of a fiber.
+use futures::{Async, Future, Poll};
+use std::fmt;
+use std::mem;
+use std::sync::Arc;
+
+use super::WaitQueue;
+use sync_atomic;
+
+/// A shared mutable slot.
+///
+/// The clones of a cell share the same slot,
+/// so a cell can be passed to another fiber by cloning it.
+///
+/// Operations on the slot never block the current thread with an OS-level lock,
+/// and each of them is performed as a single atomic step.
+/// A fiber which observes a value (by `load` or `WaitUntil`) also observes
+/// all the memory writes made before the value was stored.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate fibers;
+/// # extern crate futures;
+/// use fibers::{Executor, InPlaceExecutor, Spawn};
+/// use fibers::sync::atomic::AtomicCell;
+///
+/// # fn main() {
+/// let mut executor = InPlaceExecutor::new().unwrap();
+/// let cell = AtomicCell::new(0);
+///
+/// // Spawns a fiber which waits until the value becomes greater than `2`.
+/// let monitor = executor.spawn_monitor(cell.wait_until(|v| *v > 2));
+///
+/// for i in 1..5 {
+///     cell.store(i);
+/// }
+/// assert_eq!(executor.run_fiber(monitor).unwrap(), Ok(4));
+/// # }
+/// ```
+pub struct AtomicCell<T> {
+    inner: Arc<Inner<T>>,
+}
+impl<T> AtomicCell<T> {
+    /// Makes a new cell which holds `value`.
+    pub fn new(value: T) -> Self {
+        AtomicCell {
+            inner: Arc::new(Inner {
+                value: sync_atomic::AtomicCell::new(value),
+                waiters: WaitQueue::new(),
+            }),
+        }
+    }
+
+    /// Stores `value` into the cell.
+    ///
+    /// The fibers waiting on the cell will be woken up.
+    pub fn store(&self, value: T) {
+        let _ = self.swap(value);
+    }
+
+    /// Stores `value` into the cell, returning the old value.
+    ///
+    /// The fibers waiting on the cell will be woken up.
+    pub fn swap(&self, value: T) -> T {
+        let old = self.inner.with_value(|v| mem::replace(v, value));
+        self.inner.waiters.notify_all();
+        old
+    }
+
+    /// Makes a future which waits until the value of the cell satisfies `predicate`.
+    ///
+    /// The future will complete with the clone of the value which satisfied `predicate`.
+    /// The predicate is evaluated when the future is polled and
+    /// every time a new value is stored into the cell.
+    /// It is applied to a snapshot (i.e., a clone) of the value,
+    /// so the cell can be updated by others while the predicate is running.
+    pub fn wait_until<F>(&self, predicate: F) -> WaitUntil<T, F>
+    where
+        F: FnMut(&T) -> bool,
+    {
+        WaitUntil {
+            cell: self.clone(),
+            predicate,
+        }
+    }
+}
+impl<T: Copy> AtomicCell<T> {
+    /// Loads the value of the cell.
+    pub fn load(&self) -> T {
+        self.inner.with_value(|v| *v)
+    }
+}
+impl<T> Clone for AtomicCell<T> {
+    fn clone(&self) -> Self {
+        AtomicCell {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+impl<T> fmt::Debug for AtomicCell<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "AtomicCell {{ .. }}")
+    }
+}
+
+/// A future which waits until the value of a cell satisfies a predicate.
+///
+/// This is created by calling `AtomicCell::wait_until` method.
+pub struct WaitUntil<T, F> {
+    cell: AtomicCell<T>,
+    predicate: F,
+}
+impl<T, F> Future for WaitUntil<T, F>
+where
+    T: Clone,
+    F: FnMut(&T) -> bool,
+{
+    type Item = T;
+    type Error = ();
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if let Some(value) = self.check() {
+            return Ok(Async::Ready(value));
+        }
+
+        // NOTE: Rechecks after registering the current fiber to avoid missing a wakeup
+        self.cell.inner.waiters.await();
+        if let Some(value) = self.check() {
+            return Ok(Async::Ready(value));
+        }
+        Ok(Async::NotReady)
+    }
+}
+impl<T: Clone, F: FnMut(&T) -> bool> WaitUntil<T, F> {
+    fn check(&mut self) -> Option<T> {
+        // NOTE: The predicate is evaluated after releasing the lock of the slot,
+        // because it may take time or even access the cell
+        let value = self.cell.inner.with_value(|v| v.clone());
+        if (self.predicate)(&value) {
+            Some(value)
+        } else {
+            None
+        }
+    }
+}
+impl<T, F> fmt::Debug for WaitUntil<T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "WaitUntil {{ .. }}")
+    }
+}
+
+struct Inner<T> {
+    value: sync_atomic::AtomicCell<T>,
+    waiters: WaitQueue,
+}
+impl<T> Inner<T> {
+    fn with_value<F, U>(&self, f: F) -> U
+    where
+        F: FnOnce(&mut T) -> U,
+    {
+        loop {
+            if let Some(mut value) = self.value.try_borrow_mut() {
+                return f(&mut value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::{Async, Future};
+
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let cell = AtomicCell::new(1);
+        assert_eq!(cell.load(), 1);
+        assert_eq!(cell.swap(2), 1);
+        cell.clone().store(3);
+        assert_eq!(cell.load(), 3);
+    }
+
+    #[test]
+    fn wait_until_works() {
+        let cell = AtomicCell::new(0);
+        let mut future = cell.wait_until(|v| *v % 2 == 1);
+        assert_eq!(future.poll(), Ok(Async::NotReady));
+        cell.store(2);
+        assert_eq!(future.poll(), Ok(Async::NotReady));
+        cell.store(3);
+        assert_eq!(future.poll(), Ok(Async::Ready(3)));
+    }
+
+    #[test]
+    fn predicate_can_access_the_cell() {
+        let cell = AtomicCell::new(0);
+        let mut future = cell.wait_until({
+            let cell = cell.clone();
+            move |v| {
+                cell.store(*v + 1);
+                *v >= 2
+            }
+        });
+        assert_eq!(future.poll(), Ok(Async::NotReady));
+        assert_eq!(cell.load(), 2);
+        assert_eq!(future.poll(), Ok(Async::Ready(2)));
+    }
+}
@@ -0,0 +1,298 @@
+// Copyright (c) 2016 DWANGO Co., Ltd. All Rights Reserved.
+// See the LICENSE file at the top-level directory of this distribution.
+
+//! Multi-producer, multi-consumer broadcast communication primitives.
+//!
+//! Each value sent on a channel is delivered to all the receivers which exist at that time.
+//!
+//! # Examples
+//!
+//! ```
+//! # extern crate futures;
+//! # extern crate fibers;
+//! use fibers::{Executor, InPlaceExecutor, Spawn};
+//! use fibers::sync::broadcast;
+//! use futures::{Future, Stream};
+//!
+//! # fn main() {
+//! let mut executor = InPlaceExecutor::new().unwrap();
+//! let (tx, rx0) = broadcast::channel(8);
+//! let rx1 = rx0.clone();
+//!
+//! // Spanws receivers
+//! let monitor0 = executor.spawn_monitor(rx0.collect());
+//! let monitor1 = executor.spawn_monitor(rx1.collect());
+//!
+//! // Sends values and disconnects the channel
+//! tx.send("foo").unwrap();
+//! tx.send("bar").unwrap();
+//! std::mem::drop(tx);
+//!
+//! assert_eq!(executor.run_fiber(monitor0).unwrap(), Ok(vec!["foo", "bar"]));
+//! assert_eq!(executor.run_fiber(monitor1).unwrap(), Ok(vec!["foo", "bar"]));
+//! # }
+//! ```
+//!
+//! # Note
+//!
+//! Unlike `fibers::net` module, the structures in this module
+//! can be used on both inside and outside of a fiber.
+//!
+//! # Implementation Details
+//!
+//! The sent values are stored in a ring buffer shared by the senders and the receivers.
+//! If a receiver falls behind so that the values it has not received yet are overwritten,
+//! the next receiving attempt will fail with `RecvError::Lagged`.
+use futures::{Async, Poll, Stream};
+use std::error;
+use std::fmt;
+use std::sync::mpsc::SendError;
+use std::sync::Arc;
+
+use super::WaitQueue;
+use sync_atomic::AtomicCell;
+
+/// Creates a new broadcast channel, returning the sender/receiver halves.
+///
+/// The channel retains the last `capacity` values for the receivers which have not
+/// received them yet.
+///
+/// More receivers can be created by calling `Receiver::clone` or `Sender::subscribe` methods.
+///
+/// # Panics
+///
+/// If `capacity` is `0`, this function will panic.
+pub fn channel<T: Clone>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0);
+    let shared = Arc::new(Shared {
+        state: AtomicCell::new(State {
+            buffer: (0..capacity).map(|_| None).collect(),
+            next_seq: 0,
+            senders: 1,
+            receivers: 1,
+        }),
+        receivers: WaitQueue::new(),
+    });
+    (
+        Sender {
+            shared: Arc::clone(&shared),
+        },
+        Receiver { shared, next_seq: 0 },
+    )
+}
+
+/// The sending-half of a broadcast channel.
+///
+/// This structure can be used on both inside and outside of a fiber.
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+impl<T: Clone> Sender<T> {
+    /// Sends a value to all the receivers of this channel.
+    ///
+    /// This method will never block the current thread.
+    /// If the buffer is full, the oldest value in it will be overwritten.
+    ///
+    /// On success, the number of the receivers which will receive the value is returned.
+    /// If there are no receivers, the value is returned as an error.
+    pub fn send(&self, t: T) -> Result<usize, SendError<T>> {
+        let receivers = self.shared.with_state(|state| {
+            if state.receivers == 0 {
+                return Err(SendError(t));
+            }
+            let i = state.index(state.next_seq);
+            state.buffer[i] = Some(t);
+            state.next_seq += 1;
+            Ok(state.receivers)
+        })?;
+        self.shared.receivers.notify_all();
+        Ok(receivers)
+    }
+
+    /// Creates a new receiver which will receive the values sent after this call.
+    pub fn subscribe(&self) -> Receiver<T> {
+        Receiver::new(Arc::clone(&self.shared))
+    }
+}
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.with_state(|state| state.senders += 1);
+        Sender {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.shared.with_state(|state| state.senders -= 1);
+        self.shared.receivers.notify_all();
+    }
+}
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Sender {{ .. }}")
+    }
+}
+
+/// The receiving-half of a broadcast channel.
+///
+/// The clone of a receiver will receive the values sent after the clone is created.
+///
+/// This structure can be used on both inside and outside of a fiber.
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+    next_seq: u64,
+}
+impl<T> Receiver<T> {
+    fn new(shared: Arc<Shared<T>>) -> Self {
+        let next_seq = shared.with_state(|state| {
+            state.receivers += 1;
+            state.next_seq
+        });
+        Receiver { shared, next_seq }
+    }
+}
+impl<T: Clone> Receiver<T> {
+    fn try_recv(&mut self) -> Result<Option<Option<T>>, RecvError> {
+        let next_seq = &mut self.next_seq;
+        self.shared.with_state(|state| {
+            let oldest_seq = state.oldest_seq();
+            if *next_seq < oldest_seq {
+                let skipped = oldest_seq - *next_seq;
+                *next_seq = oldest_seq;
+                Err(RecvError::Lagged(skipped))
+            } else if *next_seq < state.next_seq {
+                let i = state.index(*next_seq);
+                *next_seq += 1;
+                Ok(Some(state.buffer[i].clone()))
+            } else if state.senders == 0 {
+                Ok(Some(None))
+            } else {
+                Ok(None)
+            }
+        })
+    }
+}
+impl<T: Clone> Stream for Receiver<T> {
+    type Item = T;
+    type Error = RecvError;
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let mut result = self.try_recv()?;
+        if result.is_none() {
+            self.shared.receivers.await();
+            result = self.try_recv()?;
+        }
+        Ok(result.map_or(Async::NotReady, Async::Ready))
+    }
+}
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        Receiver::new(Arc::clone(&self.shared))
+    }
+}
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.with_state(|state| state.receivers -= 1);
+    }
+}
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Receiver {{ next_seq: {}, .. }}", self.next_seq)
+    }
+}
+
+/// The error type of receiving values from a broadcast channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecvError {
+    /// The receiver has fallen behind so that some values were overwritten.
+    ///
+    /// The value represents the number of the skipped values.
+    /// The receiver can continue receiving from the oldest value retained in the channel.
+    Lagged(u64),
+}
+impl error::Error for RecvError {
+    fn description(&self) -> &str {
+        match *self {
+            RecvError::Lagged(_) => "Receiver lagged behind",
+        }
+    }
+}
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RecvError::Lagged(n) => write!(f, "Receiver lagged behind ({} values skipped)", n),
+        }
+    }
+}
+
+struct Shared<T> {
+    state: AtomicCell<State<T>>,
+    receivers: WaitQueue,
+}
+impl<T> Shared<T> {
+    fn with_state<F, U>(&self, f: F) -> U
+    where
+        F: FnOnce(&mut State<T>) -> U,
+    {
+        loop {
+            if let Some(mut state) = self.state.try_borrow_mut() {
+                return f(&mut state);
+            }
+        }
+    }
+}
+
+struct State<T> {
+    buffer: Vec<Option<T>>,
+    next_seq: u64,
+    senders: usize,
+    receivers: usize,
+}
+impl<T> State<T> {
+    fn oldest_seq(&self) -> u64 {
+        self.next_seq.saturating_sub(self.buffer.len() as u64)
+    }
+    fn index(&self, seq: u64) -> usize {
+        (seq % self.buffer.len() as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::{Async, Stream};
+
+    use super::*;
+
+    #[test]
+    fn lagged_receiver_works() {
+        let (tx, mut rx) = channel(2);
+        for i in 0..5 {
+            assert_eq!(tx.send(i), Ok(1));
+        }
+        assert_eq!(rx.poll(), Err(RecvError::Lagged(3)));
+        assert_eq!(rx.poll(), Ok(Async::Ready(Some(3))));
+        assert_eq!(rx.poll(), Ok(Async::Ready(Some(4))));
+        assert_eq!(rx.poll(), Ok(Async::NotReady));
+
+        std::mem::drop(tx);
+        assert_eq!(rx.poll(), Ok(Async::Ready(None)));
+    }
+
+    #[test]
+    fn late_subscribers_only_see_subsequent_values() {
+        let (tx, mut rx0) = channel(4);
+        tx.send(0).unwrap();
+        let mut rx1 = rx0.clone();
+        let mut rx2 = tx.subscribe();
+        assert_eq!(tx.send(1), Ok(3));
+
+        assert_eq!(rx0.poll(), Ok(Async::Ready(Some(0))));
+        assert_eq!(rx0.poll(), Ok(Async::Ready(Some(1))));
+        assert_eq!(rx1.poll(), Ok(Async::Ready(Some(1))));
+        assert_eq!(rx2.poll(), Ok(Async::Ready(Some(1))));
+        assert_eq!(rx2.poll(), Ok(Async::NotReady));
+
+        std::mem::drop((rx0, rx1, rx2));
+        assert_eq!(tx.send(2), Err(SendError(2)));
+    }
+}
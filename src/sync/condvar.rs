@@ -0,0 +1,275 @@
+// Copyright (c) 2016 DWANGO Co., Ltd. All Rights Reserved.
+// See the LICENSE file at the top-level directory of this distribution.
+
+use futures::{Async, Future, Poll};
+use std::fmt;
+use std::mem;
+
+use super::mutex::{Lock, Mutex, MutexGuard};
+use super::{WaitQueue, WaitTicket};
+
+/// A condition variable for fibers.
+///
+/// This is used together with `Mutex` to suspend fibers until some condition holds.
+/// Unlike `std::sync::Condvar`, waiting never blocks the current thread;
+/// the waiting fibers are suspended and resumed by their schedulers.
+///
+/// As with `std::sync::Condvar`, the condition should be re-checked after each wakeup.
+///
+/// The clones of a condition variable share the same waiters.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate fibers;
+/// # extern crate futures;
+/// use fibers::{Executor, InPlaceExecutor, Spawn};
+/// use fibers::sync::{Condvar, Mutex};
+/// use futures::{future, Future};
+///
+/// # fn main() {
+/// let mut executor = InPlaceExecutor::new().unwrap();
+/// let mutex = Mutex::new(false);
+/// let condvar = Condvar::new();
+///
+/// let waiter = {
+///     let condvar = condvar.clone();
+///     mutex.lock().and_then(move |guard| {
+///         future::loop_fn(guard, move |guard| {
+///             if *guard {
+///                 future::Either::A(future::ok(future::Loop::Break(())))
+///             } else {
+///                 future::Either::B(condvar.wait(guard).map(future::Loop::Continue))
+///             }
+///         })
+///     })
+/// };
+/// let monitor = executor.spawn_monitor(waiter);
+///
+/// executor.spawn(mutex.lock().map(move |mut guard| {
+///     *guard = true;
+///     condvar.notify_all();
+/// }));
+/// assert_eq!(executor.run_fiber(monitor).unwrap(), Ok(()));
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Condvar {
+    waiters: WaitQueue,
+}
+impl Condvar {
+    /// Makes a new condition variable.
+    pub fn new() -> Self {
+        Condvar {
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Makes a future which releases the lock held by `guard` and waits for a notification.
+    ///
+    /// When notified, the future reacquires the lock and completes with a new guard.
+    /// If the future is dropped after being notified but before reacquiring the lock,
+    /// the notification is passed to the next waiter.
+    pub fn wait<T>(&self, guard: MutexGuard<T>) -> Wait<T> {
+        Wait {
+            condvar: self.clone(),
+            phase: WaitPhase::Start(guard),
+            ticket: WaitTicket::default(),
+        }
+    }
+
+    /// Wakes up one fiber waiting on this condition variable.
+    pub fn notify_one(&self) {
+        self.waiters.notify_one();
+    }
+
+    /// Wakes up all fibers waiting on this condition variable.
+    pub fn notify_all(&self) {
+        self.waiters.notify_all();
+    }
+}
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl fmt::Debug for Condvar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Condvar {{ .. }}")
+    }
+}
+
+/// A future which will wait for a notification of a condition variable.
+///
+/// This is created by calling `Condvar::wait` method.
+pub struct Wait<T> {
+    condvar: Condvar,
+    phase: WaitPhase<T>,
+    ticket: WaitTicket,
+}
+impl<T> Future for Wait<T> {
+    type Item = MutexGuard<T>;
+    type Error = ();
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match mem::replace(&mut self.phase, WaitPhase::Done) {
+                WaitPhase::Start(guard) => {
+                    // NOTE: The fiber is registered before releasing the lock,
+                    // so the notifications made after that are never missed
+                    self.condvar.waiters.await_turn(&mut self.ticket);
+                    let mutex = MutexGuard::mutex(&guard).clone();
+                    mem::drop(guard);
+                    WaitPhase::Waiting(mutex)
+                }
+                WaitPhase::Waiting(mutex) => {
+                    if self.ticket.is_notified() {
+                        self.condvar.waiters.leave(&mut self.ticket);
+                        WaitPhase::Locking(mutex.lock())
+                    } else {
+                        self.phase = WaitPhase::Waiting(mutex);
+                        return Ok(Async::NotReady);
+                    }
+                }
+                WaitPhase::Locking(mut lock) => {
+                    if let Async::Ready(guard) = lock.poll()? {
+                        return Ok(Async::Ready(guard));
+                    }
+                    self.phase = WaitPhase::Locking(lock);
+                    return Ok(Async::NotReady);
+                }
+                WaitPhase::Done => panic!("Cannot poll Wait twice"),
+            };
+            self.phase = next;
+        }
+    }
+}
+impl<T> Drop for Wait<T> {
+    fn drop(&mut self) {
+        // NOTE: The notification for this future is passed to the next waiter
+        self.condvar.waiters.cancel(&mut self.ticket);
+    }
+}
+impl<T> fmt::Debug for Wait<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Wait {{ .. }}")
+    }
+}
+
+enum WaitPhase<T> {
+    Start(MutexGuard<T>),
+    Waiting(Mutex<T>),
+    Locking(Lock<T>),
+    Done,
+}
+
+#[cfg(test)]
+mod test {
+    use futures::future::{self, Either, Loop};
+    use futures::{self, Future};
+    use std::collections::VecDeque;
+
+    use super::*;
+    use executor::{Executor, InPlaceExecutor, ThreadPoolExecutor};
+    use fiber::Spawn;
+    use sync::oneshot;
+
+    #[test]
+    fn notification_is_passed_on_when_notified_waiter_is_dropped() {
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let mutex = Mutex::new(());
+        let condvar = Condvar::new();
+
+        // The first waiter is dropped as soon as its fiber is resumed
+        let (cancel_tx, cancel_rx) = oneshot::channel();
+        let waiter = {
+            let condvar = condvar.clone();
+            mutex.lock().and_then(move |guard| condvar.wait(guard))
+        };
+        executor.spawn(cancel_rx.select2(waiter).then(|_| Ok(())));
+        let waiter = {
+            let condvar = condvar.clone();
+            mutex.lock().and_then(move |guard| condvar.wait(guard))
+        };
+        let mut monitor = executor.spawn_monitor(waiter.map(|_| ()));
+        for _ in 0..10 {
+            executor.run_once().unwrap();
+        }
+
+        condvar.notify_one();
+        cancel_tx.send(()).unwrap();
+        for _ in 0..10 {
+            executor.run_once().unwrap();
+        }
+        assert_eq!(monitor.poll(), Ok(Async::Ready(())));
+    }
+
+    #[test]
+    fn bounded_queue_works() {
+        const CAPACITY: usize = 3;
+        const ITEMS_PER_FIBER: usize = 50;
+        const FIBERS: usize = 4;
+
+        let mut executor = ThreadPoolExecutor::with_thread_count(4).unwrap();
+        let queue = Mutex::new(VecDeque::new());
+        let not_empty = Condvar::new();
+        let not_full = Condvar::new();
+
+        let mut monitors = Vec::new();
+        for p in 0..FIBERS {
+            let (queue, not_empty, not_full) = (queue.clone(), not_empty.clone(), not_full.clone());
+            let producer = future::loop_fn(0, move |i| {
+                let (not_empty, not_full) = (not_empty.clone(), not_full.clone());
+                queue.lock().and_then(move |guard| {
+                    future::loop_fn(guard, move |mut guard| {
+                        if guard.len() < CAPACITY {
+                            guard.push_back(p * ITEMS_PER_FIBER + i);
+                            not_empty.notify_one();
+                            Either::A(future::ok(Loop::Break(())))
+                        } else {
+                            Either::B(not_full.wait(guard).map(Loop::Continue))
+                        }
+                    })
+                    .map(move |()| {
+                        if i + 1 < ITEMS_PER_FIBER {
+                            Loop::Continue(i + 1)
+                        } else {
+                            Loop::Break(0)
+                        }
+                    })
+                })
+            });
+            monitors.push(executor.spawn_monitor(producer));
+        }
+        for _ in 0..FIBERS {
+            let (queue, not_empty, not_full) = (queue.clone(), not_empty.clone(), not_full.clone());
+            let consumer = future::loop_fn((0, 0), move |(i, sum)| {
+                let (not_empty, not_full) = (not_empty.clone(), not_full.clone());
+                queue.lock().and_then(move |guard| {
+                    future::loop_fn(guard, move |mut guard| {
+                        if let Some(item) = guard.pop_front() {
+                            assert!(guard.len() < CAPACITY);
+                            not_full.notify_one();
+                            Either::A(future::ok(Loop::Break(item)))
+                        } else {
+                            Either::B(not_empty.wait(guard).map(Loop::Continue))
+                        }
+                    })
+                    .map(move |item| {
+                        if i + 1 < ITEMS_PER_FIBER {
+                            Loop::Continue((i + 1, sum + item))
+                        } else {
+                            Loop::Break(sum + item)
+                        }
+                    })
+                })
+            });
+            monitors.push(executor.spawn_monitor(consumer));
+        }
+
+        let all = futures::future::join_all(monitors);
+        let sums = executor.run_future(all).unwrap().unwrap();
+        let total = FIBERS * ITEMS_PER_FIBER;
+        assert_eq!(sums.iter().sum::<usize>(), total * (total - 1) / 2);
+        assert!(queue.try_lock().unwrap().is_empty());
+    }
+}
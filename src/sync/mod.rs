@@ -2,16 +2,31 @@
 // See the LICENSE file at the top-level directory of this distribution.
 
 //! Synchronization primitives.
+use std::collections::VecDeque;
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
 use fiber;
 use sync_atomic::AtomicCell;
 
+pub use self::condvar::{Condvar, Wait};
+pub use self::mutex::{Lock, Mutex, MutexGuard};
+pub use self::semaphore::{Acquire, Permit, Semaphore};
+pub use self::wait_group::{WaitAll, WaitGroup, WaitGroupToken};
+
+pub mod atomic;
+pub mod broadcast;
 pub mod mpsc;
 pub mod oneshot;
 
+mod condvar;
+mod mutex;
+mod semaphore;
+mod wait_group;
+
 #[derive(Debug, Clone)]
-struct Notifier {
+pub(crate) struct Notifier {
     unpark: Arc<AtomicCell<Option<fiber::Unpark>>>,
 }
 impl Notifier {
@@ -40,3 +55,173 @@ impl Notifier {
         }
     }
 }
+
+/// A FIFO queue of the fibers waiting for an event.
+///
+/// Unlike `Notifier`, this can hold multiple waiting fibers at once.
+///
+/// The fibers which take turns (e.g., the ones waiting for a lock) register themselves by
+/// `await_turn`, and each of them holds a `WaitTicket` which identifies its entry in the queue.
+/// The fibers which all have to observe an event can simply register by `await`,
+/// and they are expected to be woken up by `notify_all`.
+///
+/// Note that both `await` and `await_turn` must be called in a fiber context.
+/// On the outside of a fiber, it returns without registering anything,
+/// so the caller will never be woken up by the queue.
+#[derive(Debug, Clone)]
+struct WaitQueue {
+    waiters: Arc<AtomicCell<VecDeque<Waiter>>>,
+}
+impl WaitQueue {
+    pub fn new() -> Self {
+        WaitQueue {
+            waiters: Arc::new(AtomicCell::new(VecDeque::new())),
+        }
+    }
+
+    /// Registers the current fiber to the queue, unless it is already waiting in it.
+    pub fn await(&self) {
+        let context_id = match fiber::with_current_context(|c| c.context_id()) {
+            None => return,
+            Some(context_id) => context_id,
+        };
+        self.with_waiters(|waiters| {
+            if waiters.iter().all(|w| w.unpark.context_id() != context_id) {
+                waiters.extend(fiber::with_current_context(|mut c| Waiter {
+                    notified: Arc::new(AtomicBool::new(false)),
+                    unpark: c.park(),
+                }));
+            }
+        });
+    }
+
+    /// Registers the current fiber to the queue, unless `ticket` is already waiting in it.
+    ///
+    /// A waiter which has been woken up but could not proceed (e.g., the lock was taken by
+    /// another fiber in the meantime) is requeued at the front to keep the FIFO order.
+    pub fn await_turn(&self, ticket: &mut WaitTicket) {
+        let context_id = match fiber::with_current_context(|c| c.context_id()) {
+            None => return,
+            Some(context_id) => context_id,
+        };
+        if let Some(ref t) = ticket.0 {
+            if t.context_id == context_id && !t.notified.load(Ordering::SeqCst) {
+                return;
+            }
+        }
+        let was_notified = self.leave(ticket);
+
+        let notified = Arc::new(AtomicBool::new(false));
+        let waiter = Waiter {
+            notified: Arc::clone(&notified),
+            unpark: assert_some!(fiber::with_current_context(|mut c| c.park())),
+        };
+        self.with_waiters(|waiters| {
+            if was_notified {
+                waiters.push_front(waiter);
+            } else {
+                waiters.push_back(waiter);
+            }
+        });
+        ticket.0 = Some(Ticket {
+            context_id,
+            notified,
+        });
+    }
+
+    /// Wakes up the first waiter in the queue.
+    pub fn notify_one(&self) {
+        let waiter = self.with_waiters(|waiters| {
+            let waiter = waiters.pop_front();
+            if let Some(ref waiter) = waiter {
+                waiter.notified.store(true, Ordering::SeqCst);
+            }
+            waiter
+        });
+
+        // NOTE: The fiber is unparked after releasing the lock
+        if let Some(waiter) = waiter {
+            mem::drop(waiter.unpark);
+        }
+    }
+
+    /// Wakes up all the waiters in the queue.
+    pub fn notify_all(&self) {
+        let waiters = self.with_waiters(|waiters| {
+            for waiter in waiters.iter() {
+                waiter.notified.store(true, Ordering::SeqCst);
+            }
+            mem::take(waiters)
+        });
+
+        // NOTE: The fibers are unparked after releasing the lock
+        for waiter in waiters {
+            mem::drop(waiter.unpark);
+        }
+    }
+
+    /// Removes the entry of `ticket` from the queue.
+    ///
+    /// Returns `true` if the entry has already been notified.
+    pub fn leave(&self, ticket: &mut WaitTicket) -> bool {
+        let ticket = match ticket.0.take() {
+            None => return false,
+            Some(ticket) => ticket,
+        };
+        let removed = self.with_waiters(|waiters| {
+            if ticket.notified.load(Ordering::SeqCst) {
+                return None;
+            }
+            let i = waiters
+                .iter()
+                .position(|w| Arc::ptr_eq(&w.notified, &ticket.notified));
+            i.and_then(|i| waiters.remove(i))
+        });
+        // NOTE: Dropping the removed entry may wake up the fiber spuriously, but it is harmless
+        removed.is_none()
+    }
+
+    /// Cancels the waiting of `ticket`.
+    ///
+    /// If the entry has already been notified, the notification is passed to the next waiter.
+    pub fn cancel(&self, ticket: &mut WaitTicket) {
+        if self.leave(ticket) {
+            self.notify_one();
+        }
+    }
+
+    fn with_waiters<F, T>(&self, f: F) -> T
+    where
+        F: FnOnce(&mut VecDeque<Waiter>) -> T,
+    {
+        loop {
+            if let Some(mut waiters) = self.waiters.try_borrow_mut() {
+                return f(&mut waiters);
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Waiter {
+    notified: Arc<AtomicBool>,
+    unpark: fiber::Unpark,
+}
+
+/// An entry of a future in a `WaitQueue`.
+#[derive(Debug, Default)]
+struct WaitTicket(Option<Ticket>);
+impl WaitTicket {
+    /// Returns `true` if the entry of this ticket has been notified.
+    pub fn is_notified(&self) -> bool {
+        self.0
+            .as_ref()
+            .is_some_and(|t| t.notified.load(Ordering::SeqCst))
+    }
+}
+
+#[derive(Debug)]
+struct Ticket {
+    context_id: fiber::ContextId,
+    notified: Arc<AtomicBool>,
+}
@@ -61,12 +61,14 @@
 //! an object shared with the senders.
 //! If a corresponding sender finds there is a waiting receiver,
 //! it will resume (reschedule) the fiber, after sending a message.
-use futures::{Async, AsyncSink, Poll, Sink, StartSend, Stream};
+use futures::{Async, AsyncSink, Future, Poll, Sink, StartSend, Stream};
 use nbchan::mpsc as nb_mpsc;
 use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::mpsc::{SendError, TryRecvError, TrySendError};
+use std::sync::Arc;
 
-use super::Notifier;
+use super::{Notifier, WaitQueue, WaitTicket};
 
 /// Creates a new asynchronous channel, returning the sender/receiver halves.
 ///
@@ -122,35 +124,216 @@ pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
         Receiver {
             inner: rx,
             notifier,
+            bound: None,
         },
     )
 }
 
 /// Creates a new synchronous, bounded channel.
-#[deprecated]
+///
+/// At most `bound` messages can be buffered in the channel.
+/// If the buffer is full, the senders will wait until the receiver consumes a message.
+///
+/// If `bound` is `0`, the channel becomes a "rendezvous" channel, i.e.,
+/// a message can be sent only when the receiver is waiting for it.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate futures;
+/// # extern crate fibers;
+/// use fibers::{Executor, InPlaceExecutor, Spawn};
+/// use fibers::sync::mpsc;
+/// use futures::{Future, Stream};
+///
+/// # fn main() {
+/// let mut executor = InPlaceExecutor::new().unwrap();
+/// let (tx, rx) = mpsc::sync_channel(1);
+///
+/// // Spawns sender
+/// executor.spawn(tx.send(0)
+///     .and_then(move |tx| tx.send(1))
+///     .and_then(move |tx| tx.send(2))
+///     .map(|_| ())
+///     .map_err(|_| ()));
+/// std::mem::drop(tx);
+///
+/// // Runs `executor` until the receiver exits (i.e., channel is disconnected)
+/// let result = executor.run_future(rx.collect()).unwrap();
+/// assert_eq!(result, Ok(vec![0, 1, 2]));
+/// # }
+/// ```
 pub fn sync_channel<T>(bound: usize) -> (SyncSender<T>, Receiver<T>) {
     let notifier = Notifier::new();
-    let (tx, rx) = nb_mpsc::sync_channel(bound);
+    let (tx, rx) = nb_mpsc::channel();
+    let bound = Arc::new(Bound::new(bound));
     (
         SyncSender {
             inner: tx,
             notifier: notifier.clone(),
+            bound: Arc::clone(&bound),
+            ticket: WaitTicket::default(),
         },
         Receiver {
             inner: rx,
             notifier,
+            bound: Some(bound),
         },
     )
 }
 
+/// Makes a stream which receives messages from any of `receivers`.
+///
+/// The stream yields pairs of the index of the receiver within `receivers` and
+/// the received message.
+/// To avoid starvation, the receivers are polled in a round robin fashion.
+///
+/// The disconnected receivers are removed from the stream,
+/// and the stream terminates when all of them are disconnected.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate futures;
+/// # extern crate fibers;
+/// use fibers::{Executor, InPlaceExecutor};
+/// use fibers::sync::mpsc;
+/// use futures::Stream;
+///
+/// # fn main() {
+/// let mut executor = InPlaceExecutor::new().unwrap();
+/// let (tx0, rx0) = mpsc::channel();
+/// let (tx1, rx1) = mpsc::channel();
+/// tx0.send("foo").unwrap();
+/// tx1.send("bar").unwrap();
+/// std::mem::drop((tx0, tx1));
+///
+/// let mut messages = executor.run_future(mpsc::select(vec![rx0, rx1]).collect())
+///     .unwrap()
+///     .unwrap();
+/// messages.sort();
+/// assert_eq!(messages, [(0, "foo"), (1, "bar")]);
+/// # }
+/// ```
+pub fn select<T>(receivers: Vec<Receiver<T>>) -> SelectRecv<T> {
+    SelectRecv {
+        receivers: receivers.into_iter().enumerate().collect(),
+        next: 0,
+    }
+}
+
+/// A stream which receives messages from multiple receivers.
+///
+/// This is created by calling `select` function.
+#[derive(Debug)]
+pub struct SelectRecv<T> {
+    receivers: Vec<(usize, Receiver<T>)>,
+    next: usize,
+}
+impl<T> Stream for SelectRecv<T> {
+    type Item = (usize, T);
+    type Error = ();
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let mut result = None;
+        let mut disconnected = Vec::new();
+        let len = self.receivers.len();
+        for k in 0..len {
+            let i = (self.next + k) % len;
+            let (index, ref mut receiver) = self.receivers[i];
+            match receiver.poll() {
+                Err(()) => unreachable!(),
+                Ok(Async::NotReady) => {}
+                Ok(Async::Ready(None)) => disconnected.push(i),
+                Ok(Async::Ready(Some(t))) => {
+                    self.next = i + 1;
+                    result = Some((index, t));
+                    break;
+                }
+            }
+        }
+        for i in disconnected.into_iter().rev() {
+            self.receivers.remove(i);
+            if i < self.next {
+                self.next -= 1;
+            }
+        }
+        if !self.receivers.is_empty() {
+            self.next %= self.receivers.len();
+        }
+
+        if let Some(item) = result {
+            Ok(Async::Ready(Some(item)))
+        } else if self.receivers.is_empty() {
+            Ok(Async::Ready(None))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+}
+
 /// The receiving-half of a mpsc channel.
 ///
 /// This receving stream will never fail.
 ///
+/// The stream terminates (i.e., yields `None`) only after all the senders have dropped
+/// and all the messages buffered in the channel have been received.
+/// That is, the messages sent before the disconnection are never lost.
+///
 /// This structure can be used on both inside and outside of a fiber.
 pub struct Receiver<T> {
     inner: nb_mpsc::Receiver<T>,
     notifier: Notifier,
+    bound: Option<Arc<Bound>>,
+}
+impl<T> Receiver<T> {
+    /// Attempts to receive a message from the channel without waiting.
+    ///
+    /// Unlike `Stream::poll`, this method never registers the current fiber for wakeup.
+    /// The semantics of the result are the same as `std::sync::mpsc::Receiver::try_recv`.
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        self.try_recv_inner()
+    }
+
+    /// Makes a future which receives a single message from the channel.
+    ///
+    /// The future returns the received message (or `None` if the channel is disconnected
+    /// and drained) together with this receiver, so that it can be used to receive more messages.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate fibers;
+    /// use fibers::{Executor, InPlaceExecutor, Spawn};
+    /// use fibers::sync::mpsc;
+    /// use futures::Future;
+    ///
+    /// # fn main() {
+    /// let mut executor = InPlaceExecutor::new().unwrap();
+    /// let (tx, rx) = mpsc::channel();
+    /// tx.send(1).unwrap();
+    /// std::mem::drop(tx);
+    ///
+    /// let future = rx.recv().and_then(|(m0, rx)| rx.recv().map(move |(m1, _)| (m0, m1)));
+    /// let monitor = executor.spawn_monitor(future);
+    /// assert_eq!(executor.run_fiber(monitor).unwrap(), Ok((Some(1), None)));
+    /// # }
+    /// ```
+    pub fn recv(self) -> Recv<T> {
+        Recv {
+            receiver: Some(self),
+        }
+    }
+
+    fn try_recv_inner(&mut self) -> Result<T, TryRecvError> {
+        let t = self.inner.try_recv()?;
+        if let Some(ref bound) = self.bound {
+            bound.is_receiver_waiting.store(false, Ordering::SeqCst);
+            bound.received.fetch_add(1, Ordering::SeqCst);
+            bound.senders.notify_one();
+        }
+        Ok(t)
+    }
 }
 impl<T> Stream for Receiver<T> {
     /// # Note
@@ -159,10 +342,15 @@ impl<T> Stream for Receiver<T> {
     type Error = ();
     type Item = T;
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
-        let mut result = self.inner.try_recv();
+        let mut result = self.try_recv_inner();
         if let Err(TryRecvError::Empty) = result {
             self.notifier.await();
-            result = self.inner.try_recv();
+            if let Some(ref bound) = self.bound {
+                // Allows the senders of a rendezvous channel to send messages
+                bound.is_receiver_waiting.store(true, Ordering::SeqCst);
+                bound.senders.notify_one();
+            }
+            result = self.try_recv_inner();
         }
         match result {
             Err(TryRecvError::Empty) => Ok(Async::NotReady),
@@ -174,6 +362,9 @@ impl<T> Stream for Receiver<T> {
 impl<T> Drop for Receiver<T> {
     fn drop(&mut self) {
         self.notifier.notify();
+        if let Some(ref bound) = self.bound {
+            bound.senders.notify_all();
+        }
     }
 }
 impl<T> fmt::Debug for Receiver<T> {
@@ -182,6 +373,34 @@ impl<T> fmt::Debug for Receiver<T> {
     }
 }
 
+/// A future which will receive a single message from a channel.
+///
+/// This is created by calling `Receiver::recv` method.
+/// The future returns the receiver when it completes.
+pub struct Recv<T> {
+    receiver: Option<Receiver<T>>,
+}
+impl<T> Future for Recv<T> {
+    type Item = (Option<T>, Receiver<T>);
+    type Error = ();
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let received = {
+            let receiver = self.receiver.as_mut().expect("Cannot poll Recv twice");
+            receiver.poll()?
+        };
+        if let Async::Ready(m) = received {
+            Ok(Async::Ready((m, assert_some!(self.receiver.take()))))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+}
+impl<T> fmt::Debug for Recv<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Recv {{ .. }}")
+    }
+}
+
 /// The sending-half of a asynchronous channel.
 ///
 /// This structure can be used on both inside and outside of a fiber.
@@ -228,21 +447,80 @@ impl<T> fmt::Debug for Sender<T> {
 ///
 /// This structure can be used on both inside and outside of a fiber.
 pub struct SyncSender<T> {
-    inner: nb_mpsc::SyncSender<T>,
+    inner: nb_mpsc::Sender<T>,
     notifier: Notifier,
+    bound: Arc<Bound>,
+    ticket: WaitTicket,
+}
+impl<T> SyncSender<T> {
+    /// Makes a future which sends a value on this synchronous channel.
+    ///
+    /// The future will complete when the value is buffered in the channel
+    /// (or received by the receiver if the channel is a rendezvous one).
+    /// While the buffer is full, the current fiber is suspended.
+    ///
+    /// If the receiver has dropped, the future will fail and return the value as an error.
+    pub fn send(&self, t: T) -> SyncSend<T> {
+        SyncSend {
+            sender: Some(self.clone()),
+            item: Some(t),
+        }
+    }
+
+    /// Attempts to send a value on this synchronous channel without waiting.
+    ///
+    /// If the buffer of the channel is full, this will return `TrySendError::Full` immediately.
+    /// Note that a rendezvous channel accepts a value only when the receiver is waiting for it.
+    pub fn try_send(&self, t: T) -> Result<(), TrySendError<T>> {
+        match self.try_send_inner(t) {
+            Err(SendError(t)) => Err(TrySendError::Disconnected(t)),
+            Ok(Some(t)) => Err(TrySendError::Full(t)),
+            Ok(None) => Ok(()),
+        }
+    }
+
+    /// Returns `true` if the receiver has dropped, otherwise `false`.
+    pub fn is_disconnected(&self) -> bool {
+        self.inner.is_disconnected()
+    }
+
+    fn try_send_inner(&self, t: T) -> Result<Option<T>, SendError<T>> {
+        if self.inner.is_disconnected() {
+            return Err(SendError(t));
+        }
+        if !self.bound.try_reserve() {
+            return Ok(Some(t));
+        }
+        self.inner.send(t)?;
+        self.notifier.notify();
+        Ok(None)
+    }
 }
 impl<T> Sink for SyncSender<T> {
     type SinkItem = T;
     type SinkError = SendError<T>;
     fn start_send(&mut self, item: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
-        match self.inner.try_send(item) {
-            Err(TrySendError::Full(item)) => Ok(AsyncSink::NotReady(item)),
-            Err(TrySendError::Disconnected(item)) => Err(SendError(item)),
-            Ok(()) => {
-                self.notifier.notify();
-                Ok(AsyncSink::Ready)
+        let mut item = match self.try_send_inner(item)? {
+            None => {
+                self.bound.senders.leave(&mut self.ticket);
+                return Ok(AsyncSink::Ready);
             }
+            Some(item) => item,
+        };
+
+        // NOTE: Retries after registering the current fiber to avoid missing a wakeup
+        self.bound.senders.await_turn(&mut self.ticket);
+        if self.inner.is_disconnected() {
+            return Err(SendError(item));
         }
+        item = match self.try_send_inner(item)? {
+            None => {
+                self.bound.senders.leave(&mut self.ticket);
+                return Ok(AsyncSink::Ready);
+            }
+            Some(item) => item,
+        };
+        Ok(AsyncSink::NotReady(item))
     }
     fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
         Ok(Async::Ready(()))
@@ -254,12 +532,17 @@ impl<T> Clone for SyncSender<T> {
         SyncSender {
             inner: self.inner.clone(),
             notifier: self.notifier.clone(),
+            bound: Arc::clone(&self.bound),
+            ticket: WaitTicket::default(),
         }
     }
 }
 impl<T> Drop for SyncSender<T> {
     fn drop(&mut self) {
         self.notifier.notify();
+
+        // NOTE: The wakeup for this sender is passed to the next waiting one
+        self.bound.senders.cancel(&mut self.ticket);
     }
 }
 impl<T> fmt::Debug for SyncSender<T> {
@@ -267,3 +550,275 @@ impl<T> fmt::Debug for SyncSender<T> {
         write!(f, "SyncSender {{ .. }}")
     }
 }
+
+/// A future which will send a value on a synchronous channel.
+///
+/// This is created by calling `SyncSender::send` method.
+/// The future returns the sender when it completes.
+pub struct SyncSend<T> {
+    sender: Option<SyncSender<T>>,
+    item: Option<T>,
+}
+impl<T> Future for SyncSend<T> {
+    type Item = SyncSender<T>;
+    type Error = SendError<T>;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let item = self.item.take().expect("Cannot poll SyncSend twice");
+        let sender = assert_some!(self.sender.as_mut());
+        match sender.start_send(item)? {
+            AsyncSink::Ready => Ok(Async::Ready(assert_some!(self.sender.take()))),
+            AsyncSink::NotReady(item) => {
+                self.item = Some(item);
+                Ok(Async::NotReady)
+            }
+        }
+    }
+}
+impl<T> fmt::Debug for SyncSend<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SyncSend {{ .. }}")
+    }
+}
+
+#[derive(Debug)]
+struct Bound {
+    capacity: usize,
+    sent: AtomicUsize,
+    received: AtomicUsize,
+    is_receiver_waiting: AtomicBool,
+    senders: WaitQueue,
+}
+impl Bound {
+    fn new(capacity: usize) -> Self {
+        Bound {
+            capacity,
+            sent: AtomicUsize::new(0),
+            received: AtomicUsize::new(0),
+            is_receiver_waiting: AtomicBool::new(false),
+            senders: WaitQueue::new(),
+        }
+    }
+    fn try_reserve(&self) -> bool {
+        loop {
+            let sent = self.sent.load(Ordering::SeqCst);
+            let received = self.received.load(Ordering::SeqCst);
+            let len = sent.wrapping_sub(received);
+            let is_available = if self.capacity == 0 {
+                len == 0 && self.is_receiver_waiting.load(Ordering::SeqCst)
+            } else {
+                len < self.capacity
+            };
+            if !is_available {
+                return false;
+            }
+            if self
+                .sent
+                .compare_exchange(
+                    sent,
+                    sent.wrapping_add(1),
+                    Ordering::SeqCst,
+                    Ordering::SeqCst,
+                )
+                .is_ok()
+            {
+                return true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::{self, Future, Stream};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+    use executor::{Executor, InPlaceExecutor};
+    use fiber::Spawn;
+    use sync::oneshot;
+
+    #[test]
+    fn rendezvous_channel_works() {
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let (tx, mut rx) = sync_channel(0);
+        let sent = Arc::new(AtomicUsize::new(0));
+        let sent_clone = Arc::clone(&sent);
+        executor.spawn(
+            futures::stream::iter_ok::<_, ()>(0..3)
+                .fold(tx, move |tx, i| {
+                    let sent = Arc::clone(&sent_clone);
+                    SyncSender::send(&tx, i)
+                        .map(move |tx| {
+                            sent.fetch_add(1, Ordering::SeqCst);
+                            tx
+                        })
+                        .map_err(|_| ())
+                })
+                .map(|_| ()),
+        );
+
+        // No messages can be sent until the receiver waits for them
+        for _ in 0..10 {
+            executor.run_once().unwrap();
+        }
+        assert_eq!(sent.load(Ordering::SeqCst), 0);
+
+        for i in 0..3 {
+            let mut received = None;
+            while received.is_none() {
+                if let Async::Ready(m) = rx.poll().unwrap() {
+                    received = m;
+                } else {
+                    executor.run_once().unwrap();
+                }
+            }
+            assert_eq!(received, Some(i));
+            for _ in 0..10 {
+                executor.run_once().unwrap();
+            }
+            assert_eq!(sent.load(Ordering::SeqCst), i + 1);
+        }
+    }
+
+    #[test]
+    fn try_recv_and_try_send_work() {
+        let (tx, mut rx) = sync_channel(1);
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+        assert_eq!(tx.try_send(0), Ok(()));
+        assert_eq!(tx.try_send(1), Err(TrySendError::Full(1)));
+        assert_eq!(rx.try_recv(), Ok(0));
+        assert_eq!(tx.try_send(1), Ok(()));
+        std::mem::drop(tx);
+        assert_eq!(rx.try_recv(), Ok(1));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+
+        let (tx, rx) = sync_channel(1);
+        std::mem::drop(rx);
+        assert_eq!(tx.try_send(0), Err(TrySendError::Disconnected(0)));
+
+        let (tx, mut rx) = channel();
+        tx.send(0).unwrap();
+        assert_eq!(rx.try_recv(), Ok(0));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+    }
+
+    #[test]
+    fn wakeup_of_dropped_sender_is_passed_to_next_one() {
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let (tx, mut rx) = sync_channel(1);
+        assert_eq!(tx.try_send(0), Ok(()));
+
+        let (cancel_tx, cancel_rx) = oneshot::channel::<()>();
+        let canceled = executor.spawn_monitor(
+            cancel_rx
+                .select2(SyncSender::send(&tx, 1))
+                .then(|_| Ok::<_, ()>(())),
+        );
+        let waiting = executor.spawn_monitor(SyncSender::send(&tx, 2).map(|_| ()).map_err(|_| ()));
+        for _ in 0..10 {
+            executor.run_once().unwrap();
+        }
+
+        // The first sender is woken up, but drops its `SyncSend` without sending the value
+        assert_eq!(rx.try_recv(), Ok(0));
+        cancel_tx.send(()).unwrap();
+        executor.run_fiber(canceled).unwrap().unwrap();
+        executor.run_fiber(waiting).unwrap().unwrap();
+        assert_eq!(rx.try_recv(), Ok(2));
+    }
+
+    #[test]
+    fn select_is_fair() {
+        let channels = (0..3).map(|_| channel()).collect::<Vec<_>>();
+        for (tx, _) in &channels {
+            for i in 0..100 {
+                tx.send(i).unwrap();
+            }
+        }
+        let (senders, receivers): (Vec<_>, Vec<_>) = channels.into_iter().unzip();
+        let mut select = select(receivers);
+
+        let mut counts = [0; 3];
+        for _ in 0..30 {
+            if let Ok(Async::Ready(Some((index, _)))) = select.poll() {
+                counts[index] += 1;
+            } else {
+                panic!();
+            }
+        }
+        assert_eq!(counts, [10, 10, 10]);
+
+        // Disconnected receivers are removed
+        let mut senders = senders.into_iter();
+        let tx0 = senders.next().unwrap();
+        std::mem::drop(senders);
+        let mut received = Vec::new();
+        while let Ok(Async::Ready(Some((index, _)))) = select.poll() {
+            received.push(index);
+        }
+        assert_eq!(received.len(), 270);
+        assert_eq!(received.iter().filter(|&&i| i == 0).count(), 90);
+        assert_eq!(select.poll(), Ok(Async::NotReady));
+        std::mem::drop(tx0);
+        assert_eq!(select.poll(), Ok(Async::Ready(None)));
+    }
+
+    #[test]
+    fn buffered_messages_are_drained_before_termination() {
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let (tx, rx) = channel();
+        for i in 0..3 {
+            tx.send(i).unwrap();
+        }
+        std::mem::drop(tx);
+        let future = futures::future::loop_fn((rx, Vec::new()), |(rx, mut received)| {
+            rx.recv().map(move |(m, rx)| {
+                received.push(m);
+                if received.last() == Some(&None) {
+                    futures::future::Loop::Break(received)
+                } else {
+                    futures::future::Loop::Continue((rx, received))
+                }
+            })
+        });
+        let monitor = executor.spawn_monitor(future);
+        let received = executor.run_fiber(monitor).unwrap().unwrap();
+        assert_eq!(received, [Some(0), Some(1), Some(2), None]);
+
+        let (tx, mut rx) = sync_channel(2);
+        tx.try_send(0).unwrap();
+        tx.try_send(1).unwrap();
+        std::mem::drop(tx);
+        assert_eq!(rx.poll(), Ok(Async::Ready(Some(0))));
+        assert_eq!(rx.poll(), Ok(Async::Ready(Some(1))));
+        assert_eq!(rx.poll(), Ok(Async::Ready(None)));
+    }
+
+    #[test]
+    fn bounded_channel_keeps_ordering() {
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let (tx, rx) = sync_channel(2);
+        for sender in 0..2 {
+            executor.spawn(
+                futures::stream::iter_ok::<_, ()>(0..10)
+                    .fold(tx.clone(), move |tx, i| {
+                        SyncSender::send(&tx, (sender, i)).map_err(|_| ())
+                    })
+                    .map(|_| ()),
+            );
+        }
+        std::mem::drop(tx);
+
+        let messages = executor.run_future(rx.collect()).unwrap().unwrap();
+        assert_eq!(messages.len(), 20);
+        for sender in 0..2 {
+            let sent = messages
+                .iter()
+                .filter(|m| m.0 == sender)
+                .map(|m| m.1)
+                .collect::<Vec<_>>();
+            assert_eq!(sent, (0..10).collect::<Vec<_>>());
+        }
+    }
+}
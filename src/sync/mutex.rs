@@ -0,0 +1,309 @@
+// Copyright (c) 2016 DWANGO Co., Ltd. All Rights Reserved.
+// See the LICENSE file at the top-level directory of this distribution.
+
+use futures::{Async, Future, Poll};
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::ops;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use super::{WaitQueue, WaitTicket};
+
+/// A mutual exclusion primitive for fibers.
+///
+/// Unlike `std::sync::Mutex`, acquiring this lock never blocks the current thread.
+/// If the lock is held by another fiber, the current fiber is suspended
+/// until the lock is released.
+///
+/// The clones of a mutex share the same lock and the same protected data,
+/// so a mutex can be passed to another fiber by cloning it.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate fibers;
+/// # extern crate futures;
+/// use fibers::{Executor, InPlaceExecutor, Spawn};
+/// use fibers::sync::Mutex;
+/// use futures::Future;
+///
+/// # fn main() {
+/// let mut executor = InPlaceExecutor::new().unwrap();
+/// let mutex = Mutex::new(0);
+/// for _ in 0..10 {
+///     executor.spawn(mutex.lock().map(|mut guard| *guard += 1));
+/// }
+///
+/// let monitor = executor.spawn_monitor(mutex.lock().map(|guard| *guard));
+/// assert_eq!(executor.run_fiber(monitor).unwrap(), Ok(10));
+/// # }
+/// ```
+pub struct Mutex<T> {
+    inner: Arc<Inner<T>>,
+}
+impl<T> Mutex<T> {
+    /// Makes a new mutex which protects `value`.
+    pub fn new(value: T) -> Self {
+        Mutex {
+            inner: Arc::new(Inner {
+                is_locked: AtomicBool::new(false),
+                value: UnsafeCell::new(value),
+                waiters: WaitQueue::new(),
+            }),
+        }
+    }
+
+    /// Makes a future which acquires the lock of this mutex.
+    ///
+    /// The future will complete with a guard when the lock is acquired.
+    /// While the guard is alive, no other fibers can acquire the lock.
+    pub fn lock(&self) -> Lock<T> {
+        Lock {
+            mutex: Some(self.clone()),
+            ticket: WaitTicket::default(),
+        }
+    }
+
+    /// Attempts to acquire the lock of this mutex without waiting.
+    ///
+    /// If the lock is held by another one, this will return `None`.
+    pub fn try_lock(&self) -> Option<MutexGuard<T>> {
+        if self
+            .inner
+            .is_locked
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+        {
+            Some(MutexGuard {
+                mutex: self.clone(),
+            })
+        } else {
+            None
+        }
+    }
+}
+impl<T> Clone for Mutex<T> {
+    fn clone(&self) -> Self {
+        Mutex {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+impl<T> fmt::Debug for Mutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Mutex {{ .. }}")
+    }
+}
+
+/// A future which will acquire the lock of a mutex.
+///
+/// This is created by calling `Mutex::lock` method.
+pub struct Lock<T> {
+    mutex: Option<Mutex<T>>,
+    ticket: WaitTicket,
+}
+impl<T> Future for Lock<T> {
+    type Item = MutexGuard<T>;
+    type Error = ();
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let guard = {
+            let mutex = self.mutex.as_ref().expect("Cannot poll Lock twice");
+            let ticket = &mut self.ticket;
+            mutex.try_lock().or_else(|| {
+                // NOTE: Retries after registering the current fiber to avoid missing a wakeup
+                mutex.inner.waiters.await_turn(ticket);
+                mutex.try_lock()
+            })
+        };
+        if let Some(guard) = guard {
+            let mutex = self.mutex.take().expect("Never fails");
+            mutex.inner.waiters.leave(&mut self.ticket);
+            Ok(Async::Ready(guard))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+}
+impl<T> Drop for Lock<T> {
+    fn drop(&mut self) {
+        if let Some(ref mutex) = self.mutex {
+            // NOTE: The wakeup for this future is passed to the next waiter
+            mutex.inner.waiters.cancel(&mut self.ticket);
+        }
+    }
+}
+impl<T> fmt::Debug for Lock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Lock {{ .. }}")
+    }
+}
+
+/// A guard which releases the lock of a mutex when dropped.
+///
+/// The protected data can be accessed through this guard.
+pub struct MutexGuard<T> {
+    mutex: Mutex<T>,
+}
+impl<T> MutexGuard<T> {
+    pub(super) fn mutex(this: &Self) -> &Mutex<T> {
+        &this.mutex
+    }
+}
+impl<T> ops::Deref for MutexGuard<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.inner.value.get() }
+    }
+}
+impl<T> ops::DerefMut for MutexGuard<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.inner.value.get() }
+    }
+}
+impl<T> Drop for MutexGuard<T> {
+    fn drop(&mut self) {
+        self.mutex.inner.is_locked.store(false, Ordering::SeqCst);
+        self.mutex.inner.waiters.notify_one();
+    }
+}
+impl<T: fmt::Debug> fmt::Debug for MutexGuard<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MutexGuard({:?})", &**self)
+    }
+}
+
+struct Inner<T> {
+    is_locked: AtomicBool,
+    value: UnsafeCell<T>,
+    waiters: WaitQueue,
+}
+unsafe impl<T: Send> Send for Inner<T> {}
+unsafe impl<T: Send> Sync for Inner<T> {}
+
+#[cfg(test)]
+mod test {
+    use futures::{self, Future};
+    use std::sync::atomic::AtomicUsize;
+
+    use super::*;
+    use executor::{Executor, InPlaceExecutor, ThreadPoolExecutor};
+    use fiber::{self, Spawn};
+    use sync::oneshot;
+
+    #[test]
+    fn try_lock_works() {
+        let mutex = Mutex::new(());
+        let guard = mutex.try_lock();
+        assert!(guard.is_some());
+        assert!(mutex.try_lock().is_none());
+        std::mem::drop(guard);
+        assert!(mutex.try_lock().is_some());
+    }
+
+    #[test]
+    fn no_updates_are_lost() {
+        let mut executor = ThreadPoolExecutor::with_thread_count(4).unwrap();
+        let mutex = Mutex::new(0);
+        let monitors = (0..100)
+            .map(|_| {
+                let mutex = mutex.clone();
+                let future = futures::future::loop_fn(0, move |i| {
+                    mutex.lock().and_then(move |mut guard| {
+                        // Yields while holding the lock to cause contention
+                        let value = *guard;
+                        fiber::yield_now().map(move |()| {
+                            *guard = value + 1;
+                            if i < 9 {
+                                futures::future::Loop::Continue(i + 1)
+                            } else {
+                                futures::future::Loop::Break(())
+                            }
+                        })
+                    })
+                });
+                executor.spawn_monitor(future)
+            })
+            .collect::<Vec<_>>();
+        let all = futures::future::join_all(monitors);
+        executor.run_future(all).unwrap().unwrap();
+        assert_eq!(*mutex.try_lock().unwrap(), 1000);
+    }
+
+    #[test]
+    fn unlock_wakes_up_only_first_waiter() {
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let mutex = Mutex::new(());
+        let guard = mutex.try_lock().unwrap();
+
+        let polls = (0..3)
+            .map(|_| Arc::new(AtomicUsize::new(0)))
+            .collect::<Vec<_>>();
+        let monitors = polls
+            .iter()
+            .map(|polls| {
+                let polls = Arc::clone(polls);
+                let mut lock = mutex.lock();
+                executor.spawn_monitor(futures::future::poll_fn(move || {
+                    polls.fetch_add(1, Ordering::SeqCst);
+                    lock.poll()
+                }))
+            })
+            .collect::<Vec<_>>();
+        for _ in 0..10 {
+            executor.run_once().unwrap();
+        }
+        let counts = || {
+            polls
+                .iter()
+                .map(|p| p.load(Ordering::SeqCst))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(counts(), [1, 1, 1]);
+
+        std::mem::drop(guard);
+        let mut monitors = monitors.into_iter();
+        let first = executor
+            .run_fiber(monitors.next().unwrap())
+            .unwrap()
+            .unwrap();
+        for _ in 0..10 {
+            executor.run_once().unwrap();
+        }
+        assert_eq!(counts(), [2, 1, 1]);
+
+        // The waiters acquire the lock in FIFO order
+        std::mem::drop(first);
+        let second = executor
+            .run_fiber(monitors.next().unwrap())
+            .unwrap()
+            .unwrap();
+        assert_eq!(counts(), [2, 2, 1]);
+        std::mem::drop(second);
+        assert!(executor
+            .run_fiber(monitors.next().unwrap())
+            .unwrap()
+            .is_ok());
+    }
+
+    #[test]
+    fn wakeup_of_dropped_waiter_is_passed_to_next_one() {
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let mutex = Mutex::new(());
+        let guard = mutex.try_lock().unwrap();
+
+        let (cancel_tx, cancel_rx) = oneshot::channel::<()>();
+        let canceled =
+            executor.spawn_monitor(cancel_rx.select2(mutex.lock()).then(|_| Ok::<_, ()>(())));
+        let waiting = executor.spawn_monitor(mutex.lock().map(|_| ()));
+        for _ in 0..10 {
+            executor.run_once().unwrap();
+        }
+
+        // The first waiter is woken up, but drops its `Lock` without acquiring the lock
+        std::mem::drop(guard);
+        cancel_tx.send(()).unwrap();
+        executor.run_fiber(canceled).unwrap().unwrap();
+        assert!(executor.run_fiber(waiting).unwrap().is_ok());
+    }
+}
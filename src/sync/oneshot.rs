@@ -114,6 +114,38 @@ pub struct Receiver<T> {
     inner: nbchan::oneshot::Receiver<T>,
     notifier: Notifier,
 }
+impl<T> Receiver<T> {
+    /// Attempts to receive the value from the channel without waiting.
+    ///
+    /// This returns `Ok(None)` if the value has not been sent yet, and
+    /// `Err(RecvError)` if the sender has been dropped without sending a value.
+    ///
+    /// Unlike `Future::poll`, this method never registers the current fiber for wakeup,
+    /// so it does not affect the notification of the fiber waiting on this receiver.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fibers::sync::oneshot;
+    ///
+    /// let (tx, mut rx) = oneshot::channel();
+    /// assert_eq!(rx.try_recv(), Ok(None));
+    ///
+    /// tx.send(1).unwrap();
+    /// assert_eq!(rx.try_recv(), Ok(Some(1)));
+    ///
+    /// let (tx, mut rx) = oneshot::channel::<()>();
+    /// std::mem::drop(tx);
+    /// assert!(rx.try_recv().is_err());
+    /// ```
+    pub fn try_recv(&mut self) -> Result<Option<T>, RecvError> {
+        match self.inner.try_recv() {
+            Err(nbchan::oneshot::TryRecvError::Empty) => Ok(None),
+            Err(nbchan::oneshot::TryRecvError::Disconnected) => Err(RecvError),
+            Ok(t) => Ok(Some(t)),
+        }
+    }
+}
 impl<T> Future for Receiver<T> {
     type Item = T;
     type Error = RecvError;
@@ -336,7 +368,7 @@ impl<E: error::Error> error::Error for MonitorError<E> {
             MonitorError::Failed(_) => "Monitor target failed: {}",
         }
     }
-    fn cause(&self) -> Option<&error::Error> {
+    fn cause(&self) -> Option<&dyn error::Error> {
         match *self {
             MonitorError::Aborted => None,
             MonitorError::Failed(ref e) => Some(e),
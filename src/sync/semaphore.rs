@@ -0,0 +1,255 @@
+// Copyright (c) 2016 DWANGO Co., Ltd. All Rights Reserved.
+// See the LICENSE file at the top-level directory of this distribution.
+
+use futures::{Async, Future, Poll};
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use super::{WaitQueue, WaitTicket};
+
+/// A counting semaphore for fibers.
+///
+/// This is useful to limit the number of fibers which execute a section concurrently.
+/// If no permits are available, the acquiring fiber is suspended (not the thread)
+/// until another fiber releases its permit.
+/// The waiting fibers are woken up in FIFO order when permits are released.
+///
+/// The clones of a semaphore share the same permits.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate fibers;
+/// # extern crate futures;
+/// use fibers::{Executor, InPlaceExecutor, Spawn};
+/// use fibers::sync::Semaphore;
+/// use futures::Future;
+///
+/// # fn main() {
+/// let mut executor = InPlaceExecutor::new().unwrap();
+/// let semaphore = Semaphore::new(1);
+///
+/// let permit = semaphore.try_acquire().unwrap();
+/// let monitor = executor.spawn_monitor(semaphore.acquire().map(|_permit| "acquired"));
+/// executor.run_once().unwrap();
+/// assert_eq!(semaphore.available_permits(), 0);
+///
+/// std::mem::drop(permit);
+/// assert_eq!(executor.run_fiber(monitor).unwrap(), Ok("acquired"));
+/// assert_eq!(semaphore.available_permits(), 1);
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Semaphore {
+    inner: Arc<Inner>,
+}
+impl Semaphore {
+    /// Makes a new semaphore which has `permits` permits.
+    pub fn new(permits: usize) -> Self {
+        Semaphore {
+            inner: Arc::new(Inner {
+                permits: AtomicUsize::new(permits),
+                waiters: WaitQueue::new(),
+            }),
+        }
+    }
+
+    /// Makes a future which acquires a permit of this semaphore.
+    ///
+    /// The future will complete with a `Permit` when a permit is acquired.
+    /// If the future is dropped before completion, its place in the queue is abandoned.
+    pub fn acquire(&self) -> Acquire {
+        Acquire {
+            semaphore: Some(self.clone()),
+            ticket: WaitTicket::default(),
+        }
+    }
+
+    /// Attempts to acquire a permit of this semaphore without waiting.
+    ///
+    /// If no permits are available, this will return `None`.
+    pub fn try_acquire(&self) -> Option<Permit> {
+        self.inner
+            .permits
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            .ok()
+            .map(|_| Permit {
+                semaphore: self.clone(),
+            })
+    }
+
+    /// Returns the number of the permits currently available.
+    pub fn available_permits(&self) -> usize {
+        self.inner.permits.load(Ordering::SeqCst)
+    }
+
+    fn release(&self) {
+        self.inner.permits.fetch_add(1, Ordering::SeqCst);
+        self.inner.waiters.notify_one();
+    }
+}
+impl fmt::Debug for Semaphore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Semaphore {{ available_permits: {}, .. }}",
+            self.available_permits()
+        )
+    }
+}
+
+/// A future which will acquire a permit of a semaphore.
+///
+/// This is created by calling `Semaphore::acquire` method.
+pub struct Acquire {
+    semaphore: Option<Semaphore>,
+    ticket: WaitTicket,
+}
+impl Future for Acquire {
+    type Item = Permit;
+    type Error = ();
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let permit = {
+            let semaphore = self.semaphore.as_ref().expect("Cannot poll Acquire twice");
+            let ticket = &mut self.ticket;
+            semaphore.try_acquire().or_else(|| {
+                // NOTE: Retries after registering the current fiber to avoid missing a wakeup
+                semaphore.inner.waiters.await_turn(ticket);
+                semaphore.try_acquire()
+            })
+        };
+        if let Some(permit) = permit {
+            let semaphore = self.semaphore.take().expect("Never fails");
+            semaphore.inner.waiters.leave(&mut self.ticket);
+            Ok(Async::Ready(permit))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+}
+impl Drop for Acquire {
+    fn drop(&mut self) {
+        if let Some(ref semaphore) = self.semaphore {
+            // NOTE: The wakeup for this future is passed to the next waiter
+            semaphore.inner.waiters.cancel(&mut self.ticket);
+        }
+    }
+}
+impl fmt::Debug for Acquire {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Acquire {{ .. }}")
+    }
+}
+
+/// A permit of a semaphore.
+///
+/// The permit is released when this is dropped.
+pub struct Permit {
+    semaphore: Semaphore,
+}
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.semaphore.release();
+    }
+}
+impl fmt::Debug for Permit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Permit {{ .. }}")
+    }
+}
+
+struct Inner {
+    permits: AtomicUsize,
+    waiters: WaitQueue,
+}
+
+#[cfg(test)]
+mod test {
+    use futures::{self, Future};
+    use std::mem;
+    use std::time::Duration;
+
+    use super::*;
+    use executor::{Executor, InPlaceExecutor, ThreadPoolExecutor};
+    use fiber::Spawn;
+    use sync::oneshot;
+    use time::timer;
+
+    #[test]
+    fn concurrency_is_limited() {
+        let mut executor = ThreadPoolExecutor::with_thread_count(4).unwrap();
+        let semaphore = Semaphore::new(3);
+        let active = Arc::new(AtomicUsize::new(0));
+        let max_active = Arc::new(AtomicUsize::new(0));
+        let monitors = (0..30)
+            .map(|_| {
+                let active = Arc::clone(&active);
+                let max_active = Arc::clone(&max_active);
+                let future = semaphore.acquire().and_then(move |permit| {
+                    let n = active.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_active.fetch_max(n, Ordering::SeqCst);
+                    timer::timeout(Duration::from_millis(5))
+                        .map_err(|_| ())
+                        .map(move |()| {
+                            active.fetch_sub(1, Ordering::SeqCst);
+                            mem::drop(permit);
+                        })
+                });
+                executor.spawn_monitor(future)
+            })
+            .collect::<Vec<_>>();
+        let all = futures::future::join_all(monitors);
+        executor.run_future(all).unwrap().unwrap();
+
+        assert!(max_active.load(Ordering::SeqCst) <= 3);
+        assert_eq!(semaphore.available_permits(), 3);
+    }
+
+    #[test]
+    fn abandoned_waiters_are_skipped() {
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let semaphore = Semaphore::new(1);
+        let permit = semaphore.try_acquire().unwrap();
+
+        let abandoned = executor.spawn_monitor(
+            semaphore
+                .acquire()
+                .select(futures::future::err(()))
+                .map(|_| ())
+                .map_err(|_| ()),
+        );
+        let waiter = executor.spawn_monitor(semaphore.acquire().map(|_| ()));
+        assert!(executor.run_fiber(abandoned).unwrap().is_err());
+        assert!(semaphore.try_acquire().is_none());
+
+        mem::drop(permit);
+        assert_eq!(executor.run_fiber(waiter).unwrap(), Ok(()));
+        assert_eq!(semaphore.available_permits(), 1);
+    }
+
+    #[test]
+    fn wakeup_of_dropped_waiter_is_passed_to_next_one() {
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let semaphore = Semaphore::new(1);
+        let permit = semaphore.try_acquire().unwrap();
+
+        let (cancel_tx, cancel_rx) = oneshot::channel::<()>();
+        let canceled = executor.spawn_monitor(
+            cancel_rx
+                .select2(semaphore.acquire())
+                .then(|_| Ok::<_, ()>(())),
+        );
+        let waiting = executor.spawn_monitor(semaphore.acquire().map(|_| ()));
+        for _ in 0..10 {
+            executor.run_once().unwrap();
+        }
+
+        // The first waiter is woken up, but drops its `Acquire` without acquiring a permit
+        mem::drop(permit);
+        cancel_tx.send(()).unwrap();
+        executor.run_fiber(canceled).unwrap().unwrap();
+        assert!(executor.run_fiber(waiting).unwrap().is_ok());
+        assert_eq!(semaphore.available_permits(), 1);
+    }
+}
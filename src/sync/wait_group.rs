@@ -0,0 +1,197 @@
+// Copyright (c) 2016 DWANGO Co., Ltd. All Rights Reserved.
+// See the LICENSE file at the top-level directory of this distribution.
+
+use futures::{Async, Future, Poll};
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use super::WaitQueue;
+
+/// A counter for waiting for the completion of a dynamic set of fibers.
+///
+/// The counter is incremented by `WaitGroup::add` (or `WaitGroup::token`) and
+/// decremented by `WaitGroup::done` (or dropping a token).
+/// The future returned by `WaitGroup::wait` completes when the counter reaches zero.
+///
+/// The clones of a wait group share the same counter.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate fibers;
+/// # extern crate futures;
+/// use fibers::{Executor, InPlaceExecutor, Spawn};
+/// use fibers::sync::WaitGroup;
+/// use futures::future;
+///
+/// # fn main() {
+/// let mut executor = InPlaceExecutor::new().unwrap();
+/// let group = WaitGroup::new();
+/// for _ in 0..3 {
+///     let token = group.token();
+///     executor.spawn(future::lazy(move || {
+///         // Do some work, and then drop the token
+///         std::mem::drop(token);
+///         Ok(())
+///     }));
+/// }
+/// let monitor = executor.spawn_monitor(group.wait());
+/// assert_eq!(executor.run_fiber(monitor).unwrap(), Ok(()));
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct WaitGroup {
+    inner: Arc<Inner>,
+}
+impl WaitGroup {
+    /// Makes a new wait group whose counter is zero.
+    pub fn new() -> Self {
+        WaitGroup {
+            inner: Arc::new(Inner {
+                count: AtomicUsize::new(0),
+                waiters: WaitQueue::new(),
+            }),
+        }
+    }
+
+    /// Increments the counter by `n`.
+    pub fn add(&self, n: usize) {
+        self.inner.count.fetch_add(n, Ordering::SeqCst);
+    }
+
+    /// Decrements the counter by one.
+    ///
+    /// # Panics
+    ///
+    /// If the counter is zero, this method will panic.
+    pub fn done(&self) {
+        let old = self.inner.count.fetch_sub(1, Ordering::SeqCst);
+        assert_ne!(old, 0, "WaitGroup counter underflow");
+        if old == 1 {
+            self.inner.waiters.notify_all();
+        }
+    }
+
+    /// Increments the counter by one, and returns a token which decrements it when dropped.
+    ///
+    /// Cloning the token also increments the counter.
+    pub fn token(&self) -> WaitGroupToken {
+        self.add(1);
+        WaitGroupToken {
+            group: self.clone(),
+        }
+    }
+
+    /// Returns the current value of the counter.
+    pub fn count(&self) -> usize {
+        self.inner.count.load(Ordering::SeqCst)
+    }
+
+    /// Makes a future which will complete when the counter reaches zero.
+    pub fn wait(&self) -> WaitAll {
+        WaitAll {
+            group: self.clone(),
+        }
+    }
+}
+impl Default for WaitGroup {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl fmt::Debug for WaitGroup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "WaitGroup {{ count: {} }}", self.count())
+    }
+}
+
+/// A token which decrements the counter of a wait group when dropped.
+///
+/// This is created by calling `WaitGroup::token` method.
+pub struct WaitGroupToken {
+    group: WaitGroup,
+}
+impl Clone for WaitGroupToken {
+    fn clone(&self) -> Self {
+        self.group.token()
+    }
+}
+impl Drop for WaitGroupToken {
+    fn drop(&mut self) {
+        self.group.done();
+    }
+}
+impl fmt::Debug for WaitGroupToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "WaitGroupToken {{ .. }}")
+    }
+}
+
+/// A future which will complete when the counter of a wait group reaches zero.
+///
+/// This is created by calling `WaitGroup::wait` method.
+#[derive(Debug)]
+pub struct WaitAll {
+    group: WaitGroup,
+}
+impl Future for WaitAll {
+    type Item = ();
+    type Error = ();
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if self.group.count() == 0 {
+            return Ok(Async::Ready(()));
+        }
+
+        // NOTE: Retries after registering the current fiber to avoid missing a wakeup
+        self.group.inner.waiters.await();
+        if self.group.count() == 0 {
+            Ok(Async::Ready(()))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+struct Inner {
+    count: AtomicUsize,
+    waiters: WaitQueue,
+}
+
+#[cfg(test)]
+mod test {
+    use futures::Future;
+    use std::sync::atomic::AtomicUsize;
+    use std::time::Duration;
+
+    use super::*;
+    use executor::{Executor, ThreadPoolExecutor};
+    use fiber::Spawn;
+    use time::timer;
+
+    #[test]
+    fn wait_group_works() {
+        let mut executor = ThreadPoolExecutor::with_thread_count(4).unwrap();
+        let group = WaitGroup::new();
+        let finished = Arc::new(AtomicUsize::new(0));
+        for i in 0..20 {
+            let token = group.token();
+            let finished = Arc::clone(&finished);
+            let worker = timer::timeout(Duration::from_millis(i % 5 * 3))
+                .map_err(|_| ())
+                .map(move |()| {
+                    finished.fetch_add(1, Ordering::SeqCst);
+                    std::mem::drop(token);
+                });
+            executor.spawn(worker);
+        }
+
+        // The counter can also be updated by hand
+        group.add(1);
+        let monitor = executor.spawn_monitor(group.wait());
+        group.done();
+        executor.run_fiber(monitor).unwrap().unwrap();
+        assert_eq!(finished.load(Ordering::SeqCst), 20);
+        assert_eq!(group.count(), 0);
+    }
+}
@@ -2,6 +2,7 @@
 // See the LICENSE file at the top-level directory of this distribution.
 
 #![allow(dead_code)]
+use std::mem;
 use std::ops;
 use std::ptr;
 use std::sync::atomic::{self, AtomicPtr};
@@ -17,7 +18,7 @@ impl<T> AtomicCell<T> {
             inner: AtomicPtr::new(Box::into_raw(boxed)),
         }
     }
-    pub fn try_borrow_mut(&self) -> Option<AtomicBorrowMut<T>> {
+    pub fn try_borrow_mut(&self) -> Option<AtomicBorrowMut<'_, T>> {
         let old = self.inner.swap(ptr::null_mut(), atomic::Ordering::SeqCst);
         if old.is_null() {
             None
@@ -26,9 +27,15 @@ impl<T> AtomicCell<T> {
             Some(AtomicBorrowMut::new(self, inner))
         }
     }
-    pub fn try_borrow(&self) -> Option<AtomicBorrowRef<T>> {
+    pub fn try_borrow(&self) -> Option<AtomicBorrowRef<'_, T>> {
         self.try_borrow_mut().map(AtomicBorrowRef)
     }
+    pub fn into_inner(self) -> T {
+        let inner = self.inner.load(atomic::Ordering::SeqCst);
+        assert_ne!(inner, ptr::null_mut());
+        mem::forget(self);
+        *unsafe { Box::from_raw(inner) }
+    }
 }
 impl<T> Drop for AtomicCell<T> {
     fn drop(&mut self) {
@@ -53,12 +60,12 @@ impl<'a, T> AtomicBorrowMut<'a, T> {
 impl<'a, T> ops::Deref for AtomicBorrowMut<'a, T> {
     type Target = T;
     fn deref(&self) -> &T {
-        &*assert_some!(self.inner.as_ref())
+        assert_some!(self.inner.as_ref())
     }
 }
 impl<'a, T> ops::DerefMut for AtomicBorrowMut<'a, T> {
     fn deref_mut(&mut self) -> &mut T {
-        &mut *assert_some!(self.inner.as_mut())
+        assert_some!(self.inner.as_mut())
     }
 }
 impl<'a, T> Drop for AtomicBorrowMut<'a, T> {
@@ -2,10 +2,15 @@
 // See the LICENSE file at the top-level directory of this distribution.
 
 //! Time related functionalities.
+use futures::{Async, Future, Poll, Stream};
+use std::sync::{Arc, Mutex};
+use std::time;
+
+use io::poll;
+
 pub mod timer {
     //! Timer
-    use futures::{Async, Future, Poll};
-    use std::sync::mpsc::RecvError;
+    use futures::{Async, Future, Poll, Stream};
     use std::time;
 
     use fiber::{self, Context};
@@ -20,6 +25,13 @@ pub mod timer {
                 timeout: timeout(duration),
             }
         }
+
+        /// Adds the specified deadline to this future.
+        ///
+        /// This is equivalent to `timer::at(self, deadline)`.
+        fn timeout_at(self, deadline: time::Instant) -> Deadline<Self> {
+            at(self, deadline)
+        }
     }
     impl<T: Future> TimerExt for T {}
 
@@ -27,6 +39,9 @@ pub mod timer {
     ///
     /// If the timeout duration passes, it will return `Err(None)`.
     /// If an error occurres before the expiration time, this will result in `Err(Some(T::Error))`.
+    ///
+    /// See also `fibers::time::with_timeout`, which reports the expiration as
+    /// `TimeoutResult::TimedOut` instead of an error.
     pub struct TimeoutAfter<T> {
         future: T,
         timeout: Timeout,
@@ -45,6 +60,77 @@ pub mod timer {
         }
     }
 
+    /// Makes a future which will try executing `future` until the time instant `deadline`.
+    ///
+    /// If `deadline` passes before `future` completes, the future will be dropped
+    /// (thus, the poller registrations made by it are cancelled) and
+    /// the resulting future will return `Err(None)`.
+    ///
+    /// Unlike `TimerExt::timeout_after`, the time limit does not depend on
+    /// when this function is called, so the same deadline can be shared
+    /// by the futures making up a call chain.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate fibers;
+    /// # extern crate futures;
+    /// use fibers::{Executor, InPlaceExecutor, Spawn};
+    /// use fibers::time::timer;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// # fn main() {
+    /// let mut executor = InPlaceExecutor::new().unwrap();
+    /// let deadline = Instant::now() + Duration::from_millis(10);
+    /// let future = timer::at(futures::empty::<(), ()>(), deadline);
+    /// let monitor = executor.spawn_monitor(future);
+    /// assert!(executor.run_fiber(monitor).unwrap().is_err());
+    /// # }
+    /// ```
+    pub fn at<T: Future>(future: T, deadline: time::Instant) -> Deadline<T> {
+        let now = time::Instant::now();
+        Deadline {
+            future: Some(future),
+            timeout: Timeout {
+                start: now,
+                duration: deadline.saturating_duration_since(now),
+                inner: None,
+            },
+        }
+    }
+
+    /// A future which will try executing `T` until the specified time instant.
+    ///
+    /// If the deadline passes, it will return `Err(None)`.
+    /// If an error occurres before the deadline, this will result in `Err(Some(T::Error))`.
+    ///
+    /// This is created by calling `timer::at` function.
+    /// See also `fibers::time::with_timeout`, which reports the expiration as
+    /// `TimeoutResult::TimedOut` instead of an error.
+    #[derive(Debug)]
+    pub struct Deadline<T> {
+        future: Option<T>,
+        timeout: Timeout,
+    }
+    impl<T: Future> Future for Deadline<T> {
+        type Item = T::Item;
+        type Error = Option<T::Error>;
+        fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+            {
+                let future = self.future.as_mut().expect("Cannot poll Deadline twice");
+                if let Async::Ready(value) = future.poll().map_err(Some)? {
+                    return Ok(Async::Ready(value));
+                }
+            }
+            if let Ok(Async::NotReady) = self.timeout.poll() {
+                Ok(Async::NotReady)
+            } else {
+                self.future = None;
+                Err(None)
+            }
+        }
+    }
+
     /// A future which will expire at the specified time instant.
     ///
     /// If this object is dropped before expiration, the timer will be cancelled.
@@ -54,7 +140,7 @@ pub mod timer {
     pub struct Timeout {
         start: time::Instant,
         duration: time::Duration,
-        inner: Option<poll::poller::Timeout>,
+        inner: Option<poll::Timeout>,
     }
 
     /// Makes a future which will expire after `delay_from_now`.
@@ -67,7 +153,7 @@ pub mod timer {
     }
     impl Future for Timeout {
         type Item = ();
-        type Error = RecvError;
+        type Error = poll::Error;
         fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
             if let Some(ref mut inner) = self.inner {
                 inner.poll()
@@ -80,7 +166,7 @@ pub mod timer {
 
                 let set_timeout = |mut c: Context| {
                     let rest = duration - elapsed;
-                    poll::poller::set_timeout(c.poller(), rest)
+                    c.poller().set_timeout(rest)
                 };
                 if let Some(inner) = fiber::with_current_context(set_timeout) {
                     self.inner = Some(inner);
@@ -92,16 +178,196 @@ pub mod timer {
         }
     }
 
+    /// Makes a future which will sleep for `delay_from_now`.
+    ///
+    /// The timer is registered to the poller of the executing fiber,
+    /// so no helper threads are involved.
+    /// As with `Timeout`, dropping the future before expiration cancels the timer.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate fibers;
+    /// # extern crate futures;
+    /// use fibers::{Executor, InPlaceExecutor, Spawn};
+    /// use fibers::time::timer;
+    /// use futures::Future;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// # fn main() {
+    /// let mut executor = InPlaceExecutor::new().unwrap();
+    /// let start = Instant::now();
+    /// let monitor = executor.spawn_monitor(timer::sleep(Duration::from_millis(10)));
+    /// executor.run_fiber(monitor).unwrap().unwrap();
+    /// assert!(start.elapsed() >= Duration::from_millis(10));
+    /// # }
+    /// ```
+    pub fn sleep(delay_from_now: time::Duration) -> Sleep {
+        Sleep {
+            timeout: timeout(delay_from_now),
+        }
+    }
+
+    /// A future which will sleep for the specified time duration.
+    ///
+    /// This is created by calling `timer::sleep` function.
+    #[derive(Debug)]
+    pub struct Sleep {
+        timeout: Timeout,
+    }
+    impl Sleep {
+        /// Returns the time instant at which this future will expire.
+        pub fn deadline(&self) -> time::Instant {
+            self.timeout.start + self.timeout.duration
+        }
+
+        /// Resets this future so that it will expire after `delay_from_now`.
+        ///
+        /// The pending timer (if any) is cancelled,
+        /// and the future can be polled again even if it has already expired.
+        pub fn reset(&mut self, delay_from_now: time::Duration) {
+            self.timeout = timeout(delay_from_now);
+        }
+    }
+    impl Future for Sleep {
+        type Item = ();
+        type Error = poll::Error;
+        fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+            self.timeout.poll()
+        }
+    }
+
+    /// Makes a stream which fires every `period`.
+    ///
+    /// The deadlines are anchored to the time this function is called
+    /// (i.e., the `n`-th tick fires at `start + period * n`),
+    /// so the delays of wakeups do not accumulate.
+    /// If the stream is not polled for a while, the missed ticks fire immediately in succession.
+    ///
+    /// # Panics
+    ///
+    /// If `period` is zero, this function will panic.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate fibers;
+    /// # extern crate futures;
+    /// use fibers::{Executor, InPlaceExecutor, Spawn};
+    /// use fibers::time::timer;
+    /// use futures::Stream;
+    /// use std::time::Duration;
+    ///
+    /// # fn main() {
+    /// let mut executor = InPlaceExecutor::new().unwrap();
+    /// let ticks = timer::interval(Duration::from_millis(10)).take(3).collect();
+    /// let monitor = executor.spawn_monitor(ticks);
+    /// assert_eq!(executor.run_fiber(monitor).unwrap().unwrap(), vec![(), (), ()]);
+    /// # }
+    /// ```
+    pub fn interval(period: time::Duration) -> Interval {
+        assert!(period > time::Duration::from_secs(0));
+        let start = time::Instant::now();
+        Interval {
+            period,
+            next_deadline: start + period,
+            timeout: Timeout {
+                start,
+                duration: period,
+                inner: None,
+            },
+        }
+    }
+
+    /// A stream which fires periodically.
+    ///
+    /// This is created by calling `timer::interval` function.
+    #[derive(Debug)]
+    pub struct Interval {
+        period: time::Duration,
+
+        // The deadline of `timeout`
+        next_deadline: time::Instant,
+        timeout: Timeout,
+    }
+    impl Stream for Interval {
+        type Item = ();
+        type Error = poll::Error;
+        fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+            if let Async::NotReady = self.timeout.poll()? {
+                return Ok(Async::NotReady);
+            }
+            self.timeout = Timeout {
+                start: self.next_deadline,
+                duration: self.period,
+                inner: None,
+            };
+            self.next_deadline += self.period;
+            Ok(Async::Ready(Some(())))
+        }
+    }
+
     #[cfg(test)]
     mod test {
         use super::*;
-        use futures::{self, Async, Future};
-        use std::time::Duration;
+        use executor::{Executor, InPlaceExecutor};
+        use fiber::Spawn;
+        use futures::{self, Async, Future, Stream};
+        use std::thread;
+        use std::time::{Duration, Instant};
 
         #[test]
         fn it_works() {
             let mut timeout = timeout(Duration::from_secs(0));
-            assert_eq!(timeout.poll(), Ok(Async::Ready(())));
+            assert_eq!(timeout.poll().ok(), Some(Async::Ready(())));
+        }
+
+        #[test]
+        fn past_deadline_works() {
+            let deadline = Instant::now();
+            thread::sleep(Duration::from_millis(1));
+
+            let mut future = at(futures::empty::<(), ()>(), deadline);
+            assert_eq!(future.poll(), Err(None));
+            assert!(future.future.is_none());
+
+            // A future which has already completed wins over the deadline
+            let mut future = futures::finished::<(), ()>(()).timeout_at(deadline);
+            assert_eq!(future.poll(), Ok(Async::Ready(())));
+        }
+
+        #[test]
+        fn sleep_works() {
+            let mut executor = InPlaceExecutor::new().unwrap();
+            let start = Instant::now();
+            let future = sleep(Duration::from_millis(20)).and_then(|()| {
+                let mut sleep = sleep(Duration::from_secs(0));
+                sleep.reset(Duration::from_millis(20));
+                sleep
+            });
+            let monitor = executor.spawn_monitor(future);
+            executor.run_fiber(monitor).unwrap().unwrap();
+            assert!(start.elapsed() >= Duration::from_millis(40));
+
+            // The loser of the selection is dropped, and its timer is cancelled
+            let short = sleep(Duration::from_millis(10)).map(|()| "short");
+            let long = sleep(Duration::from_secs(60)).map(|()| "long");
+            let future = short.select(long).map(|(winner, _)| winner).map_err(|(e, _)| e);
+            let monitor = executor.spawn_monitor(future);
+            assert_eq!(executor.run_fiber(monitor).unwrap().ok(), Some("short"));
+        }
+
+        #[test]
+        fn interval_works() {
+            let mut executor = InPlaceExecutor::new().unwrap();
+            let period = Duration::from_millis(20);
+            let start = Instant::now();
+            let monitor = executor.spawn_monitor(interval(period).take(5).collect());
+            assert_eq!(executor.run_fiber(monitor).unwrap().unwrap().len(), 5);
+
+            let elapsed = start.elapsed();
+            assert!(elapsed >= period * 5);
+            assert!(elapsed < period * 5 + Duration::from_secs(1));
         }
 
         #[test]
@@ -117,3 +383,499 @@ pub mod timer {
         }
     }
 }
+
+/// A token bucket rate limiter.
+///
+/// Permits are granted at the rate of `permits_per_second`
+/// (plus the burst allowed by `RateLimiter::with_burst`).
+/// The limiter can be shared by multiple fibers via cloning,
+/// and the permits are granted in the order in which the `Acquire` futures are polled first.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate fibers;
+/// # extern crate futures;
+/// use fibers::{Executor, InPlaceExecutor, Spawn};
+/// use fibers::time::RateLimiter;
+/// use futures::Future;
+/// use std::time::{Duration, Instant};
+///
+/// # fn main() {
+/// let mut executor = InPlaceExecutor::new().unwrap();
+/// let limiter = RateLimiter::new(100);
+/// let start = Instant::now();
+/// let future = limiter.acquire().and_then(move |()| limiter.acquire());
+/// let monitor = executor.spawn_monitor(future);
+/// executor.run_fiber(monitor).unwrap().unwrap();
+/// assert!(start.elapsed() >= Duration::from_millis(10));
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    bucket: Arc<Mutex<TokenBucket>>,
+}
+impl RateLimiter {
+    /// Makes a new rate limiter which grants `permits_per_second` permits per second.
+    ///
+    /// This is equivalent to `RateLimiter::with_burst(permits_per_second, 1)`.
+    ///
+    /// # Panics
+    ///
+    /// If `permits_per_second` is zero, this function will panic.
+    pub fn new(permits_per_second: u32) -> Self {
+        Self::with_burst(permits_per_second, 1)
+    }
+
+    /// Makes a new rate limiter which grants `permits_per_second` permits per second,
+    /// and allows up to `burst` permits to be granted at once after an idle period.
+    ///
+    /// # Panics
+    ///
+    /// If either `permits_per_second` or `burst` is zero, this function will panic.
+    pub fn with_burst(permits_per_second: u32, burst: u32) -> Self {
+        assert_ne!(permits_per_second, 0);
+        assert_ne!(burst, 0);
+        let interval = time::Duration::from_secs(1) / permits_per_second;
+        let bucket = TokenBucket {
+            interval,
+            burst_window: interval * (burst - 1),
+            next_permit_time: time::Instant::now(),
+        };
+        RateLimiter {
+            bucket: Arc::new(Mutex::new(bucket)),
+        }
+    }
+
+    /// Makes a future which will complete when a permit is granted.
+    ///
+    /// The permit is reserved when the future is polled for the first time,
+    /// and the future sleeps on a timer of the poller until the reserved time comes.
+    /// Note that if the future is dropped before completion, the reserved permit is wasted.
+    pub fn acquire(&self) -> Acquire {
+        Acquire {
+            limiter: self.clone(),
+            timeout: None,
+        }
+    }
+
+    fn reserve(&self) -> time::Duration {
+        let mut bucket = self.bucket.lock().unwrap_or_else(|e| e.into_inner());
+        let now = time::Instant::now();
+        let permit_time = bucket
+            .next_permit_time
+            .checked_sub(bucket.burst_window)
+            .map_or(now, |t| t.max(now));
+        bucket.next_permit_time = bucket.next_permit_time.max(now) + bucket.interval;
+        permit_time - now
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    interval: time::Duration,
+    burst_window: time::Duration,
+
+    // The time at which the bucket will be full again if no more permits are granted
+    next_permit_time: time::Instant,
+}
+
+/// A future which will complete when a permit of a `RateLimiter` is granted.
+///
+/// This is created by calling `RateLimiter::acquire` method.
+#[derive(Debug)]
+pub struct Acquire {
+    limiter: RateLimiter,
+    timeout: Option<timer::Timeout>,
+}
+impl Future for Acquire {
+    type Item = ();
+    type Error = poll::Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if self.timeout.is_none() {
+            let delay = self.limiter.reserve();
+            if delay == time::Duration::from_secs(0) {
+                return Ok(Async::Ready(()));
+            }
+            self.timeout = Some(timer::timeout(delay));
+        }
+        self.timeout.as_mut().expect("Never fails").poll()
+    }
+}
+
+/// Makes a stream which emits the latest item of `stream` only after `quiet_period`
+/// has elapsed without new items.
+///
+/// Each arrival of an item resets the timer (which is registered to the poller of
+/// the executing fiber), and the preceding pending item is discarded.
+/// When `stream` terminates, the pending item (if any) is emitted immediately.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate fibers;
+/// # extern crate futures;
+/// use fibers::{Executor, InPlaceExecutor, Spawn};
+/// use fibers::time;
+/// use futures::{stream, Stream};
+/// use std::time::Duration;
+///
+/// # fn main() {
+/// let mut executor = InPlaceExecutor::new().unwrap();
+/// let burst = stream::iter_ok::<_, std::io::Error>(vec![1, 2, 3]);
+/// let debounced = time::debounce(burst, Duration::from_millis(10)).collect();
+/// let monitor = executor.spawn_monitor(debounced);
+/// assert_eq!(executor.run_fiber(monitor).unwrap().unwrap(), vec![3]);
+/// # }
+/// ```
+pub fn debounce<S: Stream>(stream: S, quiet_period: time::Duration) -> Debounce<S> {
+    Debounce {
+        stream: Some(stream),
+        quiet_period,
+        pending: None,
+        timeout: None,
+    }
+}
+
+/// A stream which emits an item only after the upstream stream has been quiet for a while.
+///
+/// This is created by calling `time::debounce` function.
+#[derive(Debug)]
+pub struct Debounce<S: Stream> {
+    stream: Option<S>,
+    quiet_period: time::Duration,
+    pending: Option<S::Item>,
+    timeout: Option<timer::Timeout>,
+}
+impl<S> Stream for Debounce<S>
+where
+    S: Stream,
+    S::Error: From<poll::Error>,
+{
+    type Item = S::Item;
+    type Error = S::Error;
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        while let Some(mut stream) = self.stream.take() {
+            match stream.poll()? {
+                Async::NotReady => {
+                    self.stream = Some(stream);
+                    break;
+                }
+                Async::Ready(None) => {
+                    self.timeout = None;
+                    return Ok(Async::Ready(self.pending.take()));
+                }
+                Async::Ready(Some(item)) => {
+                    self.stream = Some(stream);
+                    self.pending = Some(item);
+                    self.timeout = Some(timer::timeout(self.quiet_period));
+                }
+            }
+        }
+        if self.stream.is_none() {
+            return Ok(Async::Ready(None));
+        }
+        if let Some(mut timeout) = self.timeout.take() {
+            if let Async::NotReady = timeout.poll()? {
+                self.timeout = Some(timeout);
+                return Ok(Async::NotReady);
+            }
+            return Ok(Async::Ready(self.pending.take()));
+        }
+        Ok(Async::NotReady)
+    }
+}
+
+/// Makes a stream which passes at most one item of `stream` per `window`.
+///
+/// The first item is emitted immediately and opens a window (which is registered to
+/// the timer of the poller of the executing fiber).
+/// The items arriving while the window is open are dropped.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate fibers;
+/// # extern crate futures;
+/// use fibers::{Executor, InPlaceExecutor, Spawn};
+/// use fibers::time;
+/// use futures::{stream, Stream};
+/// use std::time::Duration;
+///
+/// # fn main() {
+/// let mut executor = InPlaceExecutor::new().unwrap();
+/// let burst = stream::iter_ok::<_, std::io::Error>(vec![1, 2, 3]);
+/// let throttled = time::throttle(burst, Duration::from_secs(1)).collect();
+/// let monitor = executor.spawn_monitor(throttled);
+/// assert_eq!(executor.run_fiber(monitor).unwrap().unwrap(), vec![1]);
+/// # }
+/// ```
+pub fn throttle<S: Stream>(stream: S, window: time::Duration) -> Throttle<S> {
+    Throttle {
+        stream,
+        window,
+        timeout: None,
+    }
+}
+
+/// A stream which passes at most one item of the upstream stream per window.
+///
+/// This is created by calling `time::throttle` function.
+#[derive(Debug)]
+pub struct Throttle<S> {
+    stream: S,
+    window: time::Duration,
+    timeout: Option<timer::Timeout>,
+}
+impl<S> Stream for Throttle<S>
+where
+    S: Stream,
+    S::Error: From<poll::Error>,
+{
+    type Item = S::Item;
+    type Error = S::Error;
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            let closed = match self.timeout {
+                None => true,
+                Some(ref mut timeout) => timeout.poll()?.is_ready(),
+            };
+            if closed {
+                self.timeout = None;
+            }
+            match self.stream.poll()? {
+                Async::NotReady => return Ok(Async::NotReady),
+                Async::Ready(None) => {
+                    self.timeout = None;
+                    return Ok(Async::Ready(None));
+                }
+                Async::Ready(Some(item)) => {
+                    if self.timeout.is_none() {
+                        self.timeout = Some(timer::timeout(self.window));
+                        return Ok(Async::Ready(Some(item)));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Makes a future which will try executing `future` within `duration`.
+///
+/// Unlike `TimerExt::timeout_after`, the expiration is not treated as an error
+/// but reported as `TimeoutResult::TimedOut`,
+/// so the errors of `future` can be propagated as they are.
+/// If `duration` passes before `future` completes, `future` will be dropped immediately
+/// (thus, the poller registrations made by it are cancelled).
+///
+/// # Examples
+///
+/// ```
+/// # extern crate fibers;
+/// # extern crate futures;
+/// use fibers::{Executor, InPlaceExecutor, Spawn};
+/// use fibers::time::{self, TimeoutResult};
+/// use std::io;
+/// use std::time::Duration;
+///
+/// # fn main() {
+/// let mut executor = InPlaceExecutor::new().unwrap();
+/// let future = time::with_timeout(futures::empty::<(), io::Error>(), Duration::from_millis(10));
+/// let monitor = executor.spawn_monitor(future);
+/// assert_eq!(executor.run_fiber(monitor).unwrap().unwrap(), TimeoutResult::TimedOut);
+/// # }
+/// ```
+pub fn with_timeout<F: Future>(future: F, duration: time::Duration) -> WithTimeout<F> {
+    WithTimeout {
+        future: Some(future),
+        timeout: timer::timeout(duration),
+    }
+}
+
+/// The outcome of a `WithTimeout` future.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum TimeoutResult<T> {
+    /// The inner future completed before the expiration.
+    Completed(T),
+
+    /// The time limit passed before the inner future completed.
+    TimedOut,
+}
+
+/// A future which will try executing `F` within the specified time duration.
+///
+/// This is created by calling `time::with_timeout` function.
+#[derive(Debug)]
+pub struct WithTimeout<F> {
+    future: Option<F>,
+    timeout: timer::Timeout,
+}
+impl<F> Future for WithTimeout<F>
+where
+    F: Future,
+    F::Error: From<poll::Error>,
+{
+    type Item = TimeoutResult<F::Item>;
+    type Error = F::Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        {
+            let future = self.future.as_mut().expect("Cannot poll WithTimeout twice");
+            if let Async::Ready(item) = future.poll()? {
+                return Ok(Async::Ready(TimeoutResult::Completed(item)));
+            }
+        }
+        if let Async::NotReady = self.timeout.poll()? {
+            Ok(Async::NotReady)
+        } else {
+            self.future = None;
+            Ok(Async::Ready(TimeoutResult::TimedOut))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::{future, stream};
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use executor::{Executor, InPlaceExecutor};
+    use fiber::Spawn;
+    use time::timer::TimerExt;
+
+    #[test]
+    fn rate_limiter_works() {
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let limiter = RateLimiter::new(100);
+        let start = Instant::now();
+        let monitors = (0..20)
+            .map(|_| {
+                let limiter = limiter.clone();
+                executor.spawn_monitor(future::lazy(move || limiter.acquire()))
+            })
+            .collect::<Vec<_>>();
+        let monitor = executor.spawn_monitor(future::join_all(monitors));
+        executor.run_fiber(monitor).unwrap().unwrap();
+
+        // The first permit is granted immediately, and the rest are granted every 10ms
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(190), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(300), "{:?}", elapsed);
+    }
+
+    #[test]
+    #[ignore] // Takes about 10 seconds
+    fn rate_limiter_keeps_rate_for_long_run() {
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let limiter = RateLimiter::new(10);
+        let start = Instant::now();
+        let monitors = (0..100)
+            .map(|_| {
+                let limiter = limiter.clone();
+                executor.spawn_monitor(future::lazy(move || limiter.acquire()))
+            })
+            .collect::<Vec<_>>();
+        let monitor = executor.spawn_monitor(future::join_all(monitors));
+        executor.run_fiber(monitor).unwrap().unwrap();
+
+        // The first permit is granted immediately, and the rest are granted every 100ms
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(9900), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(10500), "{:?}", elapsed);
+    }
+
+    #[test]
+    fn debounce_works() {
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let quiet_period = Duration::from_millis(50);
+
+        // Five items arrive every 5ms, and then the upstream keeps silent
+        let upstream = timer::interval(Duration::from_millis(5))
+            .take(5)
+            .zip(stream::iter_ok(0..5))
+            .map(|((), i)| i)
+            .chain(stream::poll_fn(|| Ok(Async::NotReady)));
+
+        let start = Instant::now();
+        let monitor = executor.spawn_monitor(debounce(upstream, quiet_period).into_future());
+        let (item, rest) = executor.run_fiber(monitor).unwrap().ok().unwrap();
+        assert_eq!(item, Some(4));
+        assert!(start.elapsed() >= Duration::from_millis(25) + quiet_period);
+
+        // No more items are emitted
+        let future = rest.into_future().timeout_after(Duration::from_millis(200));
+        let monitor = executor.spawn_monitor(future.map(|(item, _)| item).map_err(|_| ()));
+        assert!(executor.run_fiber(monitor).unwrap().is_err());
+    }
+
+    #[test]
+    fn throttle_works() {
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let window = Duration::from_millis(50);
+
+        // Forty items arrive every 5ms
+        let upstream = timer::interval(Duration::from_millis(5))
+            .zip(stream::iter_ok(0..40))
+            .map(|((), i)| i);
+        let throttled = throttle(upstream, window).map(|i| (i, Instant::now()));
+        let monitor = executor.spawn_monitor(throttled.collect());
+        let outputs = executor.run_fiber(monitor).unwrap().ok().unwrap();
+
+        assert_eq!(outputs[0].0, 0);
+        assert!(outputs.len() >= 3 && outputs.len() <= 5, "{:?}", outputs);
+        for pair in outputs.windows(2) {
+            assert!(pair[0].0 < pair[1].0);
+            assert!(pair[1].1 - pair[0].1 >= window);
+        }
+    }
+
+    #[test]
+    fn with_timeout_works() {
+        let mut executor = InPlaceExecutor::new().unwrap();
+
+        // Completed
+        let future = timer::timeout(Duration::from_millis(10)).map(|()| "done");
+        let future = with_timeout(future, Duration::from_secs(60));
+        let monitor = executor.spawn_monitor(future);
+        assert_eq!(
+            executor.run_fiber(monitor).unwrap().ok(),
+            Some(TimeoutResult::Completed("done"))
+        );
+
+        let future = future::err::<(), _>(poll::Error::Deregistered);
+        let monitor = executor.spawn_monitor(with_timeout(future, Duration::from_secs(60)));
+        assert!(executor.run_fiber(monitor).unwrap().is_err());
+
+        // Timed out: the inner future is dropped as soon as the time limit passes
+        let resource = Arc::new(());
+        let inner = {
+            let resource = Arc::clone(&resource);
+            future::empty::<(), poll::Error>().map(move |()| drop(resource))
+        };
+        let future = with_timeout(inner, Duration::from_millis(10))
+            .map(move |outcome| (outcome, Arc::strong_count(&resource)));
+        let monitor = executor.spawn_monitor(future);
+        assert_eq!(
+            executor.run_fiber(monitor).unwrap().ok(),
+            Some((TimeoutResult::TimedOut, 1))
+        );
+    }
+
+    #[test]
+    fn burst_works() {
+        let mut executor = InPlaceExecutor::new().unwrap();
+        let limiter = RateLimiter::with_burst(10, 5);
+        let start = Instant::now();
+        let monitors = (0..5)
+            .map(|_| executor.spawn_monitor(limiter.acquire()))
+            .collect::<Vec<_>>();
+        let monitor = executor.spawn_monitor(future::join_all(monitors));
+        executor.run_fiber(monitor).unwrap().unwrap();
+        assert!(start.elapsed() < Duration::from_millis(100));
+
+        // The burst has been consumed
+        let monitor = executor.spawn_monitor(limiter.acquire());
+        executor.run_fiber(monitor).unwrap().unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+}